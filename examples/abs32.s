@@ -0,0 +1,20 @@
+# R_X86_64_32 and R_X86_64_32S fields. The immediate of the movq is sign extended, the
+# .long is zero extended when loaded.
+    .text
+    .globl _start
+_start:
+    movq $answer, %rax
+    movl address(%rip), %ecx
+    cmpq %rax, %rcx
+    jne 1f
+    movzbl (%rax), %edi
+    movl $60, %eax
+    syscall
+1:
+    ud2
+
+    .data
+answer:
+    .byte 42
+address:
+    .long answer
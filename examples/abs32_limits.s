@@ -0,0 +1,12 @@
+# 32 bit fields referring to the absolute symbols of big_symbols.s that just fit.
+    .text
+    .globl _start
+_start:
+    ud2
+
+    .data
+    .long max_u32
+    .reloc ., R_X86_64_32S, max_i32
+    .long 0
+    .reloc ., R_X86_64_32S, min_i32
+    .long 0
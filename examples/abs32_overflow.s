@@ -0,0 +1,10 @@
+# 32 bit fields referring to the absolute symbols of big_symbols.s, none of which fit.
+    .text
+    .globl _start
+_start:
+    movq $above_2g, %rax
+    movq $below_2g, %rax
+
+    .data
+    .long above_4g
+    .long below_2g
@@ -0,0 +1,17 @@
+# R_X86_64_64 fields with and without addends. The program exits with the byte the first
+# one points at.
+    .text
+    .globl _start
+_start:
+    movq pointers(%rip), %rax
+    movzbl (%rax), %edi
+    movl $60, %eax
+    syscall
+
+    .data
+answer:
+    .byte 0, 42
+pointers:
+    .quad answer+1
+    .quad _start
+    .quad answer-0x100
@@ -0,0 +1,9 @@
+@ A literal with the offset of the GOT entry of `table` from the literal, which the code
+@ loads the address of `table` through.
+    .syntax unified
+    .arch armv7-a
+
+    .text
+    .globl got_offset
+got_offset:
+    .word table(GOT_PREL)
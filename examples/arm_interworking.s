@@ -0,0 +1,47 @@
+@ Calls between ARM and Thumb code. BL to the other instruction set has to become BLX,
+@ and addresses of Thumb code have the lowest bit set. The untyped labels only have the
+@ $a and $t mapping symbols to tell which instruction set they are in. $é is an ordinary
+@ local symbol that only looks like one.
+    .syntax unified
+    .arch armv7-a
+
+    .section .text.arm, "ax", %progbits
+    .arm
+    .globl _start
+    .type _start, %function
+_start:
+    bl thumb_func
+    bl arm_func
+    b .
+
+    .globl arm_func
+    .type arm_func, %function
+"$é":
+arm_func:
+    bx lr
+
+    .globl arm_label
+arm_label:
+    bx lr
+
+    .section .text.thumb, "ax", %progbits
+    .thumb
+    .globl thumb_func
+    .type thumb_func, %function
+    .thumb_func
+thumb_func:
+    bl arm_func
+    bl thumb_label
+    bx lr
+
+    .globl thumb_label
+thumb_label:
+    bx lr
+
+    .data
+    .globl table
+table:
+    .word thumb_func
+    .word arm_func
+    .word thumb_label
+    .word arm_label
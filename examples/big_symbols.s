@@ -0,0 +1,10 @@
+# Absolute symbols outside of the range of 32 bit fields.
+    .globl above_4g, above_2g, below_2g
+    .set above_4g, 0x100000000
+    .set above_2g, 0x80000000
+    .set below_2g, -0x80000001
+# The limits of the fields
+    .globl max_u32, max_i32, min_i32
+    .set max_u32, 0xffffffff
+    .set max_i32, 0x7fffffff
+    .set min_i32, -0x80000000
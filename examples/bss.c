@@ -0,0 +1,20 @@
+// Uninitialized data goes into .bss which takes up no space in the file but has to read
+// as zero at runtime.
+static char buffer[1 << 20];
+int counter;
+
+void _start() {
+    int exit = 42;
+    for (unsigned long i = 0; i < sizeof(buffer); i++) {
+        if (buffer[i] != 0) {
+            exit = 1;
+        }
+        buffer[i] = 1;
+    }
+    counter += exit;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(counter));
+}
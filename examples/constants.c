@@ -0,0 +1,12 @@
+// Both objects load the same floating point constants from .rodata.cst8 which only
+// end up once in the output.
+double scale(double x);
+
+void _start() {
+    int exit = (scale(2.0) + 2.0 * 1.5 - 2.25 == 6.0) ? 42 : 1;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
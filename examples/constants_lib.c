@@ -0,0 +1,3 @@
+double scale(double x) {
+    return x * 1.5 + 2.25;
+}
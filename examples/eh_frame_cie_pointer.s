@@ -0,0 +1,30 @@
+# An FDE whose CIE pointer a relocation moves far before the start of .eh_frame.
+    .text
+    .globl _start
+_start:
+    ret
+
+    .section .eh_frame, "a", @progbits
+cie:
+    .long cie_end - cie_id
+cie_id:
+    .long 0
+    .byte 1
+    .asciz "zR"
+    .uleb128 1
+    .sleb128 -8
+    .byte 16
+    .uleb128 1
+    .byte 0x1b
+    .balign 4
+cie_end:
+fde:
+    .long fde_end - fde_pointer
+fde_pointer:
+    .reloc ., R_X86_64_32, _start + 0x7fff0000
+    .long fde_pointer - cie
+    .long _start - .
+    .long 1
+    .uleb128 0
+    .balign 4
+fde_end:
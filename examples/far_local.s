@@ -0,0 +1,9 @@
+# A PC8 field against a local symbol in .text, which the assembler turns into a relocation
+# against the section.
+    .text
+    ud2
+far:
+    ud2
+
+    .data
+    .byte far - .
@@ -0,0 +1,17 @@
+// Built with -ffunction-sections -fdata-sections so that --gc-sections can drop
+// everything that _start does not use.
+const char* unused_string = "this string is never used";
+int unused_table[4096] = {1};
+int used = 42;
+
+int unused_function() {
+    return unused_table[used];
+}
+
+void _start() {
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(used));
+}
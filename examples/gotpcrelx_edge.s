@@ -0,0 +1,7 @@
+# A GOTPCRELX relocation at the very start of .text, so the instruction it belongs to
+# would start before the section.
+    .text
+    .globl _start
+_start:
+    .reloc ., R_X86_64_REX_GOTPCRELX, _start-4
+    .long 0
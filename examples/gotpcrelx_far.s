@@ -0,0 +1,31 @@
+# GOT accesses to symbols that a direct access might not reach. far is beyond 2 GiB so
+# its GOT entry stays, small fits in the immediate of the relaxed add. far_func is out
+# of reach when .far is placed at 4 GiB. The program exits with 42.
+    .section .far,"ax",@progbits
+far_func:
+    ret
+
+    .text
+    .globl _start
+_start:
+    movq far@GOTPCREL(%rip), %rax
+    movabsq $0x7fff00000000, %rdx
+    cmpq %rdx, %rax
+    jne fail
+    xorl %eax, %eax
+    addq small@GOTPCREL(%rip), %rax
+    cmpq $0x1000, %rax
+    jne fail
+    call *far_func@GOTPCREL(%rip)
+    movl $42, %edi
+    jmp exit
+fail:
+    movl $1, %edi
+exit:
+    movl $60, %eax
+    syscall
+
+    # Defined after their uses so that they are only resolved by the linker
+    .globl far, small
+    far = 0x7fff00000000
+    small = 0x1000
@@ -0,0 +1,15 @@
+# A function that starts with a tail call through the GOT, like gcc -O2 -fPIC -fno-plt
+# -ffunction-sections emits. The R_X86_64_GOTPCRELX of the jmp is at offset 2 of its
+# section. The program exits with 42.
+    .section .text.tail,"ax",@progbits
+tail:
+    jmp *exit42@GOTPCREL(%rip)
+
+    .text
+    .globl _start
+_start:
+    call tail
+exit42:
+    movl $42, %edi
+    movl $60, %eax
+    syscall
@@ -0,0 +1,18 @@
+// Built with -m32 to get a 32-bit executable. The exit status comes from data reached
+// through an absolute address and a call.
+int value = 40;
+int* pointer = &value;
+const char message[] = "two";
+
+int add(int x) {
+    return x + sizeof(message) - 2;
+}
+
+void _start() {
+    int status = add(*pointer);
+    asm("movl %0, %%ebx;"
+        "movl $1, %%eax;"
+        "int $0x80"
+        :
+        : "r"(status));
+}
@@ -0,0 +1,9 @@
+int answer();
+
+void _start() {
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(answer()));
+}
@@ -0,0 +1,8 @@
+// Built with different values of ANSWER to change the object without moving anything.
+// LARGE grows the code beyond the room an incremental link leaves after it.
+int answer() {
+#ifdef LARGE
+    asm(".skip 4096, 0x90");
+#endif
+    return ANSWER;
+}
@@ -0,0 +1,27 @@
+// Without a C runtime _start runs the constructors and destructors itself, which
+// have to come out in priority order.
+typedef void (*func)(void);
+extern func __init_array_start[], __init_array_end[];
+extern func __fini_array_start[], __fini_array_end[];
+
+int order = 0;
+
+__attribute__((constructor)) static void last() { order = order * 10 + 3; }
+__attribute__((constructor(200))) static void second() { order = order * 10 + 2; }
+__attribute__((constructor(101))) static void first() { order = order * 10 + 1; }
+__attribute__((destructor)) static void finish() { order = order * 10 + 4; }
+
+void _start() {
+    for (func* f = __init_array_start; f < __init_array_end; f++) {
+        (*f)();
+    }
+    for (func* f = __fini_array_start; f < __fini_array_end; f++) {
+        (*f)();
+    }
+    int exit = order == 1234 ? 42 : 1;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
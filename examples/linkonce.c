@@ -0,0 +1,19 @@
+// get_value is emitted the way compilers did before COMDAT groups, in a .gnu.linkonce
+// section of its own. linkonce_lib.c has a different copy that must be discarded.
+asm(".section .gnu.linkonce.t.get_value, \"ax\", @progbits\n"
+    ".globl get_value\n"
+    "get_value:\n"
+    "movl $40, %eax\n"
+    "ret\n"
+    ".previous");
+
+int get_value(void);
+int lib_value(void);
+
+void _start() {
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(get_value() + lib_value()));
+}
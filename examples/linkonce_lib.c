@@ -0,0 +1,12 @@
+asm(".section .gnu.linkonce.t.get_value, \"ax\", @progbits\n"
+    ".globl get_value\n"
+    "get_value:\n"
+    "movl $1, %eax\n"
+    "ret\n"
+    ".previous");
+
+int get_value(void);
+
+int lib_value(void) {
+    return get_value() - 38;
+}
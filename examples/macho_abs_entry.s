@@ -0,0 +1,3 @@
+# An entry point that is an absolute symbol below the text base.
+    .globl _main
+_main = 0x1000
@@ -0,0 +1,23 @@
+// Assembled with llvm-mc -triple arm64-apple-macos11 -filetype=obj.
+    .text
+    .globl _main
+    .p2align 2
+_main:
+    adrp x8, _value@PAGE
+    add x8, x8, _value@PAGEOFF
+    adrp x9, _counter@GOTPAGE
+    ldr x9, [x9, _counter@GOTPAGEOFF]
+    ldr w0, [x8]
+    b _helper
+
+    .globl _helper
+    .p2align 2
+_helper:
+    ret
+
+    .data
+    .globl _value
+_value:
+    .long 42
+
+    .comm _counter, 4, 2
@@ -0,0 +1,7 @@
+# Linked with macho_x86_64.s, assembled with llvm-mc -triple x86_64-apple-macos11
+# -filetype=obj.
+    .text
+    .globl _helper
+_helper:
+    movl $1, %eax
+    retq
@@ -0,0 +1,21 @@
+# Assembled with llvm-mc -triple x86_64-apple-macos11 -filetype=obj.
+    .text
+    .globl _main
+_main:
+    leaq _value(%rip), %rax
+    movq _counter@GOTPCREL(%rip), %rcx
+    movl (%rax), %eax
+    addl (%rcx), %eax
+    callq _helper
+    retq
+
+    .data
+    .globl _value
+_value:
+    .long 42
+_pointer:
+    .quad _value+4
+_distance:
+    .quad _pointer - _value
+
+    .comm _counter, 4, 2
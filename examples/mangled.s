@@ -0,0 +1,14 @@
+# A C++ function with a mangled name, foo::bar(int).
+    .text
+    .globl _ZN3foo3barEi
+    .type _ZN3foo3barEi, @function
+_ZN3foo3barEi:
+    ret
+
+    .globl _start
+    .type _start, @function
+_start:
+    call _ZN3foo3barEi
+    movl $42, %edi
+    movl $60, %eax
+    syscall
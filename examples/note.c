@@ -0,0 +1,16 @@
+// Contains an alloc .note.ABI-tag note, compiled with -fcf-protection it also gets a
+// .note.gnu.property note with an alignment of 8.
+asm(".section .note.ABI-tag, \"a\", @note\n"
+    ".balign 4\n"
+    ".long 4\n"
+    ".long 16\n"
+    ".long 1\n"
+    ".asciz \"GNU\"\n"
+    ".long 0, 3, 2, 0\n"
+    ".previous");
+
+void _start() {
+    asm("movl $42, %edi;"
+        "movq $60, %rax;"
+        "syscall");
+}
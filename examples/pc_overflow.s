@@ -0,0 +1,12 @@
+# PC-relative fields too narrow to reach _start, which is more than 64KiB away.
+    .text
+    .globl _start
+_start:
+    ud2
+    .space 0x10000
+
+    .data
+    .reloc ., R_X86_64_PC8, _start
+    .byte 0
+    .reloc ., R_X86_64_PC16, _start
+    .word 0
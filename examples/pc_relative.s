@@ -0,0 +1,19 @@
+# PC-relative fields of each width against a symbol in the same section and one in .text.
+    .text
+    .globl _start
+_start:
+    ud2
+
+    .data
+    .globl target
+    .byte 0
+target:
+    .byte 0
+    .reloc ., R_X86_64_PC8, target
+    .byte 0
+    .reloc ., R_X86_64_PC16, target-2
+    .word 0
+    .reloc ., R_X86_64_PC32, target+4
+    .long 0
+    .reloc ., R_X86_64_PC64, _start
+    .quad 0
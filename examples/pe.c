@@ -0,0 +1,17 @@
+// Linked as a PE executable, it is turned into a COFF object with objcopy. Built with
+// -mcmodel=large because objcopy does not convert PC-relative data accesses correctly.
+typedef void __attribute__((ms_abi)) (*exit_process)(unsigned int);
+extern exit_process __imp_ExitProcess;
+int value = 40;
+int counter;
+int* pointer = &value;
+
+void fail();
+
+void _start() {
+    counter = 2;
+    if (*pointer == 40) {
+        __imp_ExitProcess(*pointer + counter);
+    }
+    fail();
+}
@@ -0,0 +1,6 @@
+// The call goes through the thunk for the import.
+void __attribute__((ms_abi)) ExitProcess(unsigned int);
+
+void fail() {
+    ExitProcess(1);
+}
@@ -0,0 +1,11 @@
+# A .note.gnu.property note with a descriptor size past the end of the section.
+    .text
+    .globl _start
+_start:
+    ret
+
+    .section .note.gnu.property,"a",@note
+    .p2align 3
+    .long 4, 32, 5
+    .asciz "GNU"
+    .long 0xc0000002, 4, 3, 0
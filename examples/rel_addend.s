@@ -0,0 +1,12 @@
+# i386 objects use SHT_REL, the addends of these relocations are stored in the fields.
+    .text
+    .globl _start
+_start:
+    ud2
+
+    .data
+target:
+    .long 0
+    .long target+5
+    .long target-0x20
+    .long _start-.+8
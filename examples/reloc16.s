@@ -0,0 +1,12 @@
+# The .word gets an R_X86_64_16 relocation, which the linker only applies with a
+# registered relocation handler.
+    .text
+    .globl _start
+_start:
+    movzwl value(%rip), %edi
+    movl $60, %eax
+    syscall
+
+    .data
+value:
+    .word answer
@@ -0,0 +1,16 @@
+// Laid out by script.ld which defines the bounds of .data.
+extern char data_start[], data_end[];
+
+long values[] = {40, 2};
+const char message[] = "script";
+
+void _start() {
+    int exit = (unsigned long)data_start % 0x1000 == 0 && data_end - data_start >= 16
+                   ? values[0] + values[1]
+                   : 1;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
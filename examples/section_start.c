@@ -0,0 +1,11 @@
+// Linked with .boot pinned to a fixed address by --section-start.
+__attribute__((section(".boot"))) long boot_value = 40;
+
+void _start() {
+    int exit = (unsigned long)&boot_value == 0x600000 ? boot_value + 2 : 1;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
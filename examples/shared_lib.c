@@ -0,0 +1,27 @@
+#include <stddef.h>
+
+static void print(int fd, const void* buf, size_t count) {
+    asm("movq $1, %%rax;"
+        "movl %[fd], %%edi;"
+        "movq %[buf], %%rsi;"
+        "movq %[count], %%rdx;"
+        "syscall"
+        :
+        : [fd] "r" (fd), [buf] "r" (buf), [count] "r" (count)
+    );
+}
+
+static void hello() {
+    print(1, "wuhu\n", 5);
+}
+
+// The pointers in the table and the GOT entry of the counter are relocated by the
+// dynamic loader.
+void (*const handlers[])() = { hello };
+
+int counter;
+
+void extern_call() {
+    counter++;
+    handlers[counter - 1]();
+}
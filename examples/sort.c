@@ -0,0 +1,14 @@
+// The order of these sections in the input differs from their order by name and from
+// their order by alignment.
+__attribute__((section(".rodata.b"))) const char b = 1;
+__attribute__((section(".rodata.c"), aligned(16))) const char c = 2;
+__attribute__((section(".rodata.a"), aligned(8))) const char a = 3;
+
+void _start() {
+    int exit = 36 + a + b + c;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
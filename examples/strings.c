@@ -0,0 +1,13 @@
+// The same string literal in two objects ends up only once in the output so both
+// pointers are equal.
+const char* lib_greeting();
+
+void _start() {
+    const char* greeting = "shared greeting\n";
+    int exit = lib_greeting() == greeting ? 42 : 1;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
@@ -0,0 +1,3 @@
+const char* lib_greeting() {
+    return "shared greeting\n";
+}
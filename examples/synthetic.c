@@ -0,0 +1,23 @@
+#include <stddef.h>
+
+// Defined in a section that the test adds with Linker::add_section.
+extern const char version[];
+extern const char version_end[];
+
+static void print(int fd, const void* buf, size_t count) {
+    asm("movq $1, %%rax;"
+        "movl %[fd], %%edi;"
+        "movq %[buf], %%rsi;"
+        "movq %[count], %%rdx;"
+        "syscall"
+        :
+        : [fd] "r" (fd), [buf] "r" (buf), [count] "r" (count)
+    );
+}
+
+void _start() {
+    print(1, version, version_end - version);
+    asm("movl $0, %edi;"
+        "movq $60, %rax;"
+        "syscall");
+}
@@ -0,0 +1,8 @@
+__thread int gd_var;
+static __thread int ld_var;
+
+int add_tls(int x) {
+    gd_var += x;
+    ld_var += x;
+    return gd_var + ld_var;
+}
@@ -0,0 +1,14 @@
+# A TLS symbol in .text, which lies before the TLS segment.
+    .text
+    .globl _start
+_start:
+    ret
+
+    .section .text.misplaced, "ax", @progbits
+    .globl misplaced
+    .type misplaced, @tls_object
+misplaced:
+    .long 0
+
+    .section .tdata, "awT", @progbits
+    .long 1
@@ -0,0 +1,27 @@
+extern __thread int gd_var;
+static __thread long le_var = 3;
+
+int add_tls(int x);
+
+// Thread control block followed by the static TLS block below it. There is no libc to set
+// up the thread pointer for us.
+static long tls_area[16] __attribute__((aligned(16))) = {1};
+
+void _start() {
+    void* tp = &tls_area[8];
+    *(void**)tp = tp;
+    asm volatile("movq $158, %%rax;" /* arch_prctl */
+                 "movq $0x1002, %%rdi;" /* ARCH_SET_FS */
+                 "movq %0, %%rsi;"
+                 "syscall"
+                 :
+                 : "r"(tp)
+                 : "rax", "rdi", "rsi", "rcx", "r11", "memory");
+    le_var = 2;
+    int exit = add_tls(10) + gd_var + le_var;
+    asm("movl %0, %%edi;"
+        "movq $60, %%rax;"
+        "syscall"
+        :
+        : "r"(exit));
+}
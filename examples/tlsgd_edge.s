@@ -0,0 +1,12 @@
+# A general-dynamic TLS access whose call to __tls_get_addr is cut off by the end of the
+# section, so the sequence the relaxation rewrites does not fit.
+    .text
+    .globl _start
+_start:
+    .byte 0x66
+    leaq x@tlsgd(%rip), %rdi
+    .byte 0x66, 0x66, 0x48, 0xe8
+
+    .section .tbss,"awT",@nobits
+x:
+    .long 0
@@ -0,0 +1,9 @@
+# A relocation type that is missing from the x86-64 howto table.
+    .text
+    .globl _start
+_start:
+    ud2
+
+    .data
+    .reloc ., R_X86_64_GOTOFF64, _start
+    .quad 0
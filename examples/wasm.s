@@ -0,0 +1,46 @@
+# Assembled with llvm-mc -triple wasm32 -filetype=obj. The code clang emits for
+# print(helper(value)) and print(pointer(7)) with a stack frame.
+    .functype print (i32) -> ()
+    .functype helper (i32) -> (i32)
+    .import_module print, env
+    .import_name print, print
+    .globaltype __stack_pointer, i32
+
+    .text
+    .globl _start
+    .type _start,@function
+_start:
+    .functype _start () -> ()
+    .local i32
+    global.get __stack_pointer
+    i32.const 16
+    i32.sub
+    local.tee 0
+    global.set __stack_pointer
+    i32.const 0
+    i32.load value
+    call helper
+    call print
+    i32.const 7
+    i32.const 0
+    i32.load pointer
+    call_indirect (i32) -> (i32)
+    call print
+    local.get 0
+    i32.const 16
+    i32.add
+    global.set __stack_pointer
+    end_function
+
+    .section .data.value,"",@
+    .globl value
+    .p2align 2
+value:
+    .int32 42
+    .size value, 4
+
+    .section .data.pointer,"",@
+    .p2align 2
+pointer:
+    .int32 helper
+    .size pointer, 4
@@ -0,0 +1,10 @@
+# Linked with wasm.s, assembled with llvm-mc -triple wasm32 -filetype=obj.
+    .text
+    .globl helper
+    .type helper,@function
+helper:
+    .functype helper (i32) -> (i32)
+    local.get 0
+    i32.const 1
+    i32.add
+    end_function
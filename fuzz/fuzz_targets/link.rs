@@ -0,0 +1,11 @@
+#![no_main]
+// Links the input as an object file, which must give an error or an output but never
+// panic. Run with `cargo fuzz run link`.
+use clap::Clap;
+use libfuzzer_sys::fuzz_target;
+use toy_linker::{link_in_memory, Opts};
+
+fuzz_target!(|data: &[u8]| {
+    let opts = Opts::parse_from(["toy-linker", "-o", "a.out"]);
+    let _ = link_in_memory(&[data], &opts);
+});
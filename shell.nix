@@ -1,13 +1,17 @@
-{pkgs ? import (fetchTarball "https://github.com/NixOS/nixpkgs/archive/536fe36e23ab0fc8b7f35c24603422eee9fc17a2.tar.gz") {}
+{pkgs ? import (fetchTarball "https://github.com/NixOS/nixpkgs/archive/nixos-25.11.tar.gz") {}
 }:
 with pkgs;
 
 stdenv.mkDerivation {
   name = "env";
   nativeBuildInputs = [
-    rustc cargo rls racer pkgconfig
-#    clang
-#    llvm
+    rustc cargo rust-analyzer pkg-config
+    # llvm-mc assembles the Mach-O and ARM test inputs. Only the unwrapped clang, the wrapped
+    # one would take over cc from gcc.
+    llvm
+    llvmPackages.clang-unwrapped
+    # Runs the WebAssembly test output.
+    nodejs
     gdb
     gcc
     valgrind
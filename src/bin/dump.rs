@@ -1,16 +1,2839 @@
 use clap::Clap;
+use goblin::elf::program_header::*;
+use goblin::elf::section_header::*;
+use goblin::elf::sym::{
+    bind_to_str, type_to_str, visibility_to_str, Sym, Symtab, STB_GLOBAL, STB_LOCAL, STB_WEAK,
+    STT_FILE,
+};
 use goblin::error;
+use goblin::strtab::Strtab;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
 #[derive(Clap, Debug)]
 struct Opts {
-    input: String,
+    /// The file to dump, not needed with a subcommand. Without options only the ELF header
+    /// is printed.
+    input: Option<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Print the ELF header.
+    #[clap(long = "file-header")]
+    file_header: bool,
+    /// Print the ELF header, section and program headers, symbols, relocations, the
+    /// dynamic section, notes and symbol versions.
+    #[clap(short = 'a', long = "all")]
+    all: bool,
+    /// Print everything goblin parsed in its Debug format, which is very long for large
+    /// files.
+    #[clap(long = "debug")]
+    debug: bool,
+    /// Do not highlight the output, which is also the default if NO_COLOR is set or the
+    /// output is not a terminal.
+    #[clap(long = "no-color")]
+    no_color: bool,
+    /// Do not page the output through $PAGER (`less` by default) on a terminal.
+    #[clap(long = "no-pager")]
+    no_pager: bool,
+    /// Print the section headers as a table.
+    #[clap(short = 'S')]
+    section_headers: bool,
+    /// Print the program headers and the sections in each segment.
+    #[clap(short = 'l')]
+    program_headers: bool,
+    /// Print the symbols of .symtab and .dynsym.
+    #[clap(short = 's')]
+    symbols: bool,
+    /// Print the relocations of each relocation section.
+    #[clap(short = 'r')]
+    relocations: bool,
+    /// Print the entries of the dynamic section.
+    #[clap(short = 'd')]
+    dynamic: bool,
+    /// Only print the shared libraries the file needs, one per line.
+    #[clap(long = "needed")]
+    needed: bool,
+    /// Print the shared libraries the file needs and the ones they need in turn, with the
+    /// paths the dynamic loader would find them at.
+    #[clap(long = "tree")]
+    tree: bool,
+    /// Report the hardening of the file like checksec: PIE, RELRO, NX stack, stack canaries
+    /// and BIND_NOW.
+    #[clap(long = "checksec")]
+    checksec: bool,
+    /// Print the symbol versions of .gnu.version_d and .gnu.version_r and the version each
+    /// dynamic symbol binds to.
+    #[clap(short = 'V')]
+    versions: bool,
+    /// Decode the notes of the SHT_NOTE sections.
+    #[clap(short = 'n')]
+    notes: bool,
+    /// Print the contents of the section with this name or index as hex and ASCII.
+    #[clap(short = 'x', number_of_values = 1)]
+    hex_dump: Vec<String>,
+    /// Write the raw contents of a section to a file, e.g. `--extract .text=text.bin`.
+    #[clap(long = "extract", value_name = "SECTION=PATH", number_of_values = 1)]
+    extract: Vec<Extract>,
+    /// Print the strings in the section with this name or index.
+    #[clap(short = 'p', number_of_values = 1)]
+    string_dump: Vec<String>,
+    /// Attribute the file and VM size to sections, symbols and source objects.
+    #[clap(long = "size")]
+    size: bool,
+    /// The number of rows in each table of --size, the rest are summed up.
+    #[clap(long = "top", default_value = "10")]
+    top: usize,
+    /// Check the structure of the file, e.g. that segments are inside the file and
+    /// congruent to their alignment, and exit with 1 if there are problems.
+    #[clap(long = "verify")]
+    verify: bool,
+    /// Dump this member of an archive instead of listing its members.
+    #[clap(long = "member")]
+    member: Option<String>,
+    /// Decode the CIEs and FDEs of .eh_frame with the CFA rules of each FDE, check them and
+    /// .eh_frame_hdr, and exit with 1 if there are problems.
+    #[clap(long = "eh-frame")]
+    eh_frame: bool,
+    /// Print the compilation units of the DWARF debug info.
+    #[clap(long = "dwarf")]
+    dwarf: bool,
+    /// Print the source file and line of the code at this address from .debug_line.
+    #[clap(long = "addr2line", value_name = "ADDRESS", parse(try_from_str = parse_address))]
+    addr2line: Option<u64>,
+    /// Disassemble the x86 code sections, or only the function SYMBOL with
+    /// --disassemble=SYMBOL.
+    #[clap(
+        long = "disassemble",
+        value_name = "SYMBOL",
+        min_values = 0,
+        require_equals = true
+    )]
+    disassemble: Option<Option<String>>,
+    /// Only print the symbols with this binding, e.g. GLOBAL.
+    #[clap(long = "binding")]
+    binding: Option<String>,
+    /// Only print the symbols of this type, e.g. FUNC.
+    #[clap(long = "type")]
+    symbol_type: Option<String>,
+    /// Only print the symbols whose name matches this regular expression. Demangled names
+    /// are matched with --demangle.
+    #[clap(long = "name", parse(try_from_str = Regex::new))]
+    name: Option<Regex>,
+    /// Sort the symbols by `address` or `size` instead of their index.
+    #[clap(long = "sort")]
+    sort: Option<SortKey>,
+    /// Demangle C++ and Rust symbol names, also in the relocations.
+    #[clap(short = 'C', long = "demangle")]
+    demangle: bool,
 }
 
-fn main() -> Result<(), error::Error> {
+#[derive(Clap, Debug)]
+enum Command {
+    /// Compare the headers, sections and symbols of two ELF files, e.g. the outputs of two
+    /// linker versions. Exits with 1 if they differ.
+    Diff { old: String, new: String },
+    /// Print a Graphviz graph of which objects define the undefined symbols of the others.
+    /// The members of archives are nodes of their own, grouped by archive.
+    Graph {
+        #[clap(required = true)]
+        inputs: Vec<String>,
+    },
+}
+
+fn parse_address(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid address: {}", s))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Address,
+    Size,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "address" => Ok(SortKey::Address),
+            "size" => Ok(SortKey::Size),
+            _ => Err(format!("invalid --sort: {}", s)),
+        }
+    }
+}
+
+// A section to write to a file with --extract.
+#[derive(Clone, Debug)]
+struct Extract {
+    section: String,
+    path: PathBuf,
+}
+
+impl std::str::FromStr for Extract {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((section, path)) if !section.is_empty() && !path.is_empty() => Ok(Extract {
+                section: section.to_string(),
+                path: PathBuf::from(path),
+            }),
+            _ => Err(format!("invalid --extract, expected SECTION=PATH: {}", s)),
+        }
+    }
+}
+
+// goblin does not know these GNU extensions.
+const SHF_EXCLUDE: u32 = 0x8000_0000;
+const PT_GNU_PROPERTY: u32 = 0x6474_e553;
+
+// The ANSI colors of the highlighted parts of the output.
+#[derive(Clone, Copy)]
+enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+    Blue = 34,
+    Magenta = 35,
+    Cyan = 36,
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static PAGER: Mutex<Option<std::process::Child>> = Mutex::new(None);
+
+// `text` in `color` if the output is highlighted. Pad before painting, the escape codes
+// would count towards the width.
+fn paint(text: &str, color: Color) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", color as u8, text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Pipe stdout into $PAGER. LESS defaults to FRX like git does, so that output that fits
+// on the screen is not paged and colors pass through.
+fn start_pager() {
+    use std::os::unix::io::AsRawFd;
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| String::from("less"));
+    if pager.is_empty() || pager == "cat" {
+        return;
+    }
+    let mut command = std::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&pager)
+        .stdin(std::process::Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(_) => return,
+    };
+    if let Some(stdin) = child.stdin.take() {
+        if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) } != -1 {
+            *PAGER.lock().unwrap() = Some(child);
+        }
+    }
+}
+
+// Exit once the pager is done with the output.
+fn exit(code: i32) -> ! {
+    if let Some(mut pager) = PAGER.lock().unwrap().take() {
+        let _ = io::stdout().flush();
+        unsafe { libc::close(libc::STDOUT_FILENO) };
+        let _ = pager.wait();
+    }
+    std::process::exit(code)
+}
+
+fn print_file_header(elf: &goblin::elf::Elf) {
+    use goblin::elf::header::*;
+    let header = &elf.header;
+    let os_abi = match header.e_ident[EI_OSABI] {
+        0 => String::from("UNIX - System V"),
+        3 => String::from("UNIX - GNU"),
+        9 => String::from("UNIX - FreeBSD"),
+        abi => format!("{:#x}", abi),
+    };
+    let field = |name: &str, value: String| println!("  {:<35}{}", format!("{}:", name), value);
+    println!("ELF Header:");
+    field(
+        "Class",
+        String::from(if elf.is_64 { "ELF64" } else { "ELF32" }),
+    );
+    field(
+        "Data",
+        String::from(if elf.little_endian {
+            "little endian"
+        } else {
+            "big endian"
+        }),
+    );
+    field("OS/ABI", os_abi);
+    field("Type", et_to_str(header.e_type).to_string());
+    field(
+        "Machine",
+        machine_to_str(header.e_machine)
+            .trim_start_matches("EM_")
+            .to_string(),
+    );
+    field("Entry point address", format!("{:#x}", header.e_entry));
+    field(
+        "Start of program headers",
+        format!("{} (bytes into file)", header.e_phoff),
+    );
+    field(
+        "Start of section headers",
+        format!("{} (bytes into file)", header.e_shoff),
+    );
+    field("Flags", format!("{:#x}", header.e_flags));
+    field("Number of program headers", header.e_phnum.to_string());
+    field("Number of section headers", header.e_shnum.to_string());
+    field(
+        "Section header string table index",
+        header.e_shstrndx.to_string(),
+    );
+    if let Some(interpreter) = elf.interpreter {
+        field("Interpreter", interpreter.to_string());
+    }
+}
+
+// The flags as the letters readelf uses.
+fn section_flags(flags: u64) -> String {
+    let letters = [
+        (SHF_WRITE, 'W'),
+        (SHF_ALLOC, 'A'),
+        (SHF_EXECINSTR, 'X'),
+        (SHF_MERGE, 'M'),
+        (SHF_STRINGS, 'S'),
+        (SHF_INFO_LINK, 'I'),
+        (SHF_LINK_ORDER, 'L'),
+        (SHF_OS_NONCONFORMING, 'O'),
+        (SHF_GROUP, 'G'),
+        (SHF_TLS, 'T'),
+        (SHF_EXCLUDE, 'E'),
+    ];
+    letters
+        .iter()
+        .filter(|(flag, _)| flags & u64::from(*flag) != 0)
+        .map(|(_, letter)| *letter)
+        .collect()
+}
+
+fn section_type(sh_type: u32) -> String {
+    match sht_to_str(sh_type) {
+        "UNKNOWN_SHT" => format!("{:#x}", sh_type),
+        name => name.trim_start_matches("SHT_").to_string(),
+    }
+}
+
+fn print_section_headers(elf: &goblin::elf::Elf) {
+    let width = if elf.is_64 { 16 } else { 8 };
+    println!(
+        "  [Nr] {:<20} {:<14} {:<5} {:<width$} {:<8} {:<8} Al",
+        "Name",
+        "Type",
+        "Flg",
+        "Address",
+        "Off",
+        "Size",
+        width = width
+    );
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        let name = elf
+            .shdr_strtab
+            .get(sec.sh_name)
+            .and_then(Result::ok)
+            .unwrap_or("");
+        let kind = format!("{:<14}", section_type(sec.sh_type));
+        let kind = match sec.sh_type {
+            SHT_PROGBITS => paint(&kind, Color::Green),
+            SHT_NOBITS => paint(&kind, Color::Blue),
+            SHT_SYMTAB | SHT_DYNSYM | SHT_STRTAB => paint(&kind, Color::Magenta),
+            SHT_REL | SHT_RELA => paint(&kind, Color::Yellow),
+            SHT_NOTE | SHT_DYNAMIC => paint(&kind, Color::Cyan),
+            _ => kind,
+        };
+        println!(
+            "  [{:>2}] {:<20} {} {:<5} {:0width$x} {:08x} {:08x} {}",
+            idx,
+            name,
+            kind,
+            section_flags(sec.sh_flags),
+            sec.sh_addr,
+            sec.sh_offset,
+            sec.sh_size,
+            sec.sh_addralign,
+            width = width
+        );
+    }
+    println!("Key to Flags:");
+    println!("  W (write), A (alloc), X (execute), M (merge), S (strings), I (info),");
+    println!("  L (link order), O (extra OS processing required), G (group), T (TLS),");
+    println!("  E (exclude)");
+}
+
+fn segment_type(p_type: u32) -> String {
+    match (p_type, pt_to_str(p_type)) {
+        (PT_GNU_PROPERTY, _) => "GNU_PROPERTY".to_string(),
+        (_, "UNKNOWN_PT") => format!("{:#x}", p_type),
+        (_, name) => name.trim_start_matches("PT_").to_string(),
+    }
+}
+
+// Whether readelf lists the section in the segment. Allocated sections have to be inside
+// it in memory and the ones with contents in the file too. TLS sections only go into the
+// segments that can hold them, and .tbss takes no room in a PT_LOAD.
+fn section_in_segment(sec: &SectionHeader, ph: &ProgramHeader) -> bool {
+    let tls = sec.sh_flags & u64::from(SHF_TLS) != 0;
+    let nobits = sec.sh_type == SHT_NOBITS;
+    let allowed = if tls {
+        matches!(ph.p_type, PT_TLS | PT_GNU_RELRO | PT_LOAD) && !(nobits && ph.p_type != PT_TLS)
+    } else {
+        !matches!(ph.p_type, PT_TLS | PT_PHDR)
+    };
+    if !allowed || sec.sh_type == SHT_NULL || sec.sh_flags & u64::from(SHF_ALLOC) == 0 {
+        return false;
+    }
+    // Empty sections count if they start before the end, unless the segment is empty too.
+    let within = |start: u64, size: u64, base: u64, len: u64| {
+        start >= base
+            && start - base <= len
+            && size <= len - (start - base)
+            && (size > 0 || start - base < len || len == 0)
+    };
+    within(sec.sh_addr, sec.sh_size, ph.p_vaddr, ph.p_memsz)
+        && (nobits || within(sec.sh_offset, sec.sh_size, ph.p_offset, ph.p_filesz))
+}
+
+// The structural problems of a file that loaders or tools would trip over.
+fn verify(elf: &goblin::elf::Elf, buf: &[u8]) -> Vec<String> {
+    use goblin::elf::header::{ET_DYN, ET_EXEC, ET_REL};
+    let mut problems = Vec::new();
+    let file_size = u64::try_from(buf.len()).unwrap();
+    let in_file =
+        |offset: u64, size: u64| offset.checked_add(size).is_some_and(|end| end <= file_size);
+    let aligned = |align: u64| align <= 1 || align.is_power_of_two();
+    let header = &elf.header;
+    let tables = [
+        (
+            "program",
+            header.e_phoff,
+            header.e_phnum,
+            header.e_phentsize,
+        ),
+        (
+            "section",
+            header.e_shoff,
+            header.e_shnum,
+            header.e_shentsize,
+        ),
+    ];
+    for (table, offset, count, size) in tables.iter() {
+        if !in_file(*offset, u64::from(*count) * u64::from(*size)) {
+            problems.push(format!(
+                "the {} header table ends past the end of the file",
+                table
+            ));
+        }
+    }
+
+    for (idx, ph) in elf.program_headers.iter().enumerate() {
+        let segment = format!("segment {} ({})", idx, segment_type(ph.p_type));
+        if !in_file(ph.p_offset, ph.p_filesz) {
+            problems.push(format!("{} ends past the end of the file", segment));
+        }
+        if ph.p_filesz > ph.p_memsz && ph.p_type == PT_LOAD {
+            problems.push(format!(
+                "{} is larger in the file ({:#x}) than in memory ({:#x})",
+                segment, ph.p_filesz, ph.p_memsz
+            ));
+        }
+        if !aligned(ph.p_align) {
+            problems.push(format!(
+                "{} has an alignment of {:#x}, not a power of two",
+                segment, ph.p_align
+            ));
+        } else if ph.p_align > 1 && ph.p_vaddr % ph.p_align != ph.p_offset % ph.p_align {
+            problems.push(format!(
+                "{} has an address {:#x} and offset {:#x} that differ modulo its alignment {:#x}",
+                segment, ph.p_vaddr, ph.p_offset, ph.p_align
+            ));
+        }
+    }
+    let loads: Vec<_> = elf
+        .program_headers
+        .iter()
+        .enumerate()
+        .filter(|(_, ph)| ph.p_type == PT_LOAD)
+        .collect();
+    for pair in loads.windows(2) {
+        let ((prev_idx, prev), (idx, ph)) = (pair[0], pair[1]);
+        if ph.p_vaddr < prev.p_vaddr.saturating_add(prev.p_memsz) {
+            problems.push(format!(
+                "segment {} (LOAD) at {:#x} is not after segment {} (LOAD) ending at {:#x}",
+                idx,
+                ph.p_vaddr,
+                prev_idx,
+                prev.p_vaddr.saturating_add(prev.p_memsz)
+            ));
+        }
+    }
+    if header.e_type == ET_EXEC || (header.e_type == ET_DYN && header.e_entry != 0) {
+        let executable = loads.iter().any(|(_, ph)| {
+            ph.is_executable()
+                && header.e_entry >= ph.p_vaddr
+                && header.e_entry - ph.p_vaddr < ph.p_memsz
+        });
+        if !executable {
+            problems.push(format!(
+                "the entry point {:#x} is not inside an executable LOAD segment",
+                header.e_entry
+            ));
+        }
+    }
+
+    let mut contents = Vec::new();
+    let mut memory = Vec::new();
+    for (idx, sec) in elf.section_headers.iter().enumerate().skip(1) {
+        let section = format!("section {} ({})", idx, section_name(elf, idx));
+        let nobits = sec.sh_type == SHT_NOBITS;
+        let alloc = sec.sh_flags & u64::from(SHF_ALLOC) != 0;
+        let tls = sec.sh_flags & u64::from(SHF_TLS) != 0;
+        if !nobits && !in_file(sec.sh_offset, sec.sh_size) {
+            problems.push(format!("{} ends past the end of the file", section));
+        }
+        if !aligned(sec.sh_addralign) {
+            problems.push(format!(
+                "{} has an alignment of {:#x}, not a power of two",
+                section, sec.sh_addralign
+            ));
+        } else if sec.sh_addralign > 1 && sec.sh_addr % sec.sh_addralign != 0 {
+            problems.push(format!(
+                "{} at {:#x} is not aligned to {:#x}",
+                section, sec.sh_addr, sec.sh_addralign
+            ));
+        }
+        if sec.sh_link >= u32::from(header.e_shnum) && header.e_shnum != 0 {
+            problems.push(format!(
+                "{} links to section {}, which does not exist",
+                section, sec.sh_link
+            ));
+        }
+        if sec.sh_size == 0 {
+            continue;
+        }
+        if !nobits {
+            contents.push((sec.sh_offset, sec.sh_size, section.clone()));
+        }
+        // .tbss takes no room, the sections after it overlap it. Objects have no addresses.
+        if alloc && !(tls && nobits) && header.e_type != ET_REL {
+            memory.push((sec.sh_addr, sec.sh_size, section.clone()));
+        }
+        if alloc && !loads.is_empty() {
+            let segment = |ph: &&ProgramHeader| {
+                (ph.p_type == PT_LOAD || (tls && ph.p_type == PT_TLS))
+                    && section_in_segment(sec, ph)
+            };
+            if !elf.program_headers.iter().any(|ph| segment(&ph)) {
+                problems.push(format!(
+                    "{} is allocated but not inside a LOAD segment",
+                    section
+                ));
+            }
+        }
+    }
+    for (ranges, space) in [(&mut contents, "file"), (&mut memory, "memory")] {
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            let ((start, size, prev), (next, _, section)) = (&pair[0], &pair[1]);
+            if *next < start.saturating_add(*size) {
+                problems.push(format!("{} overlaps {} in {}", section, prev, space));
+            }
+        }
+    }
+    problems
+}
+
+fn print_program_headers(elf: &goblin::elf::Elf) {
+    use goblin::elf::header::et_to_str;
+    let width = if elf.is_64 { 18 } else { 10 };
+    println!("Elf file type is {}", et_to_str(elf.header.e_type));
+    println!("Entry point {:#x}", elf.header.e_entry);
+    println!(
+        "There are {} program headers, starting at offset {}",
+        elf.program_headers.len(),
+        elf.header.e_phoff
+    );
+    println!();
+    println!("Program Headers:");
+    println!(
+        "  {:<14} {:<8} {:<width$} {:<width$} {:<8} {:<8} Flg Align",
+        "Type",
+        "Offset",
+        "VirtAddr",
+        "PhysAddr",
+        "FileSiz",
+        "MemSiz",
+        width = width
+    );
+    for ph in &elf.program_headers {
+        let flags: String = [(PF_R, 'R'), (PF_W, 'W'), (PF_X, 'E')]
+            .iter()
+            .map(|(flag, letter)| if ph.p_flags & flag != 0 { *letter } else { ' ' })
+            .collect();
+        println!(
+            "  {:<14} {:#08x} {:#0width$x} {:#0width$x} {:#08x} {:#08x} {} {:#x}",
+            segment_type(ph.p_type),
+            ph.p_offset,
+            ph.p_vaddr,
+            ph.p_paddr,
+            ph.p_filesz,
+            ph.p_memsz,
+            flags,
+            ph.p_align,
+            width = width
+        );
+    }
+    println!();
+    println!(" Section to Segment mapping:");
+    println!("  Segment Sections...");
+    for (idx, ph) in elf.program_headers.iter().enumerate() {
+        let names: Vec<&str> = elf
+            .section_headers
+            .iter()
+            .filter(|sec| section_in_segment(sec, ph))
+            .map(|sec| {
+                elf.shdr_strtab
+                    .get(sec.sh_name)
+                    .and_then(Result::ok)
+                    .unwrap_or("")
+            })
+            .collect();
+        println!("   {:02}     {}", idx, names.join(" "));
+    }
+}
+
+fn symbol_section(shndx: usize) -> String {
+    match u32::try_from(shndx) {
+        Ok(SHN_UNDEF) => "UND".to_string(),
+        Ok(SHN_ABS) => "ABS".to_string(),
+        Ok(SHN_COMMON) => "COM".to_string(),
+        _ => shndx.to_string(),
+    }
+}
+
+fn demangle(name: &str) -> Option<String> {
+    let symbol = cpp_demangle::Symbol::new(name).ok()?;
+    symbol.demangle(&Default::default()).ok()
+}
+
+fn print_symbols(opts: &Opts, table: &str, syms: &Symtab, strtab: &Strtab) {
+    let is_named = |name: &Option<String>, value: &str| {
+        name.as_ref()
+            .is_none_or(|name| name.eq_ignore_ascii_case(value))
+    };
+    let mut rows: Vec<(usize, Sym, String)> = syms
+        .iter()
+        .enumerate()
+        .map(|(idx, sym)| {
+            let name = strtab.get(sym.st_name).and_then(Result::ok).unwrap_or("");
+            let name = match opts.demangle.then(|| demangle(name)).flatten() {
+                Some(demangled) => demangled,
+                None => name.to_string(),
+            };
+            (idx, sym, name)
+        })
+        .filter(|(_, sym, name)| {
+            is_named(&opts.binding, bind_to_str(sym.st_bind()))
+                && is_named(&opts.symbol_type, type_to_str(sym.st_type()))
+                && opts.name.as_ref().is_none_or(|regex| regex.is_match(name))
+        })
+        .collect();
+    match opts.sort {
+        Some(SortKey::Address) => rows.sort_by_key(|(_, sym, _)| sym.st_value),
+        Some(SortKey::Size) => rows.sort_by_key(|(_, sym, _)| sym.st_size),
+        None => {}
+    }
+    println!(
+        "Symbol table '{}' contains {} entries, {} shown:",
+        table,
+        syms.len(),
+        rows.len()
+    );
+    println!("   Num:    Value          Size Type    Bind   Vis      Ndx Name");
+    for (idx, sym, name) in rows {
+        let bind = format!("{:<6}", bind_to_str(sym.st_bind()));
+        let bind = match sym.st_bind() {
+            STB_GLOBAL => paint(&bind, Color::Green),
+            STB_WEAK => paint(&bind, Color::Yellow),
+            STB_LOCAL => bind,
+            _ => paint(&bind, Color::Magenta),
+        };
+        println!(
+            "{:>6}: {:016x} {:>5} {:<7} {} {:<8} {:>3} {}",
+            idx,
+            sym.st_value,
+            sym.st_size,
+            type_to_str(sym.st_type()),
+            bind,
+            visibility_to_str(sym.st_visibility()),
+            symbol_section(sym.st_shndx),
+            name
+        );
+    }
+}
+
+fn section_name<'a>(elf: &'a goblin::elf::Elf, idx: usize) -> &'a str {
+    elf.section_headers
+        .get(idx)
+        .and_then(|sec| elf.shdr_strtab.get(sec.sh_name))
+        .and_then(Result::ok)
+        .unwrap_or("")
+}
+
+// The value and name of the symbol a relocation in `sec` refers to, and its addend as
+// readelf prints it.
+fn relocation_target(
+    opts: &Opts,
+    elf: &goblin::elf::Elf,
+    sec: &SectionHeader,
+    reloc: &goblin::elf::Reloc,
+) -> (u64, String, String) {
+    use goblin::elf::sym::STT_SECTION;
+    // Relocations in executables refer to .dynsym, the ones in objects to .symtab.
+    let linked = elf
+        .section_headers
+        .get(usize::try_from(sec.sh_link).unwrap());
+    let (syms, strtab) = match linked {
+        Some(linked) if linked.sh_type == SHT_DYNSYM => (&elf.dynsyms, &elf.dynstrtab),
+        _ => (&elf.syms, &elf.strtab),
+    };
+    let (value, name) = match syms.get(reloc.r_sym).filter(|_| reloc.r_sym != 0) {
+        // Section symbols have no name of their own.
+        Some(sym) if sym.st_type() == STT_SECTION => {
+            (sym.st_value, section_name(elf, sym.st_shndx).to_string())
+        }
+        Some(sym) => {
+            let name = strtab.get(sym.st_name).and_then(Result::ok).unwrap_or("");
+            let name = match opts.demangle.then(|| demangle(name)).flatten() {
+                Some(demangled) => demangled,
+                None => name.to_string(),
+            };
+            (sym.st_value, name)
+        }
+        None => (0, String::new()),
+    };
+    let addend = match reloc.r_addend {
+        Some(addend) if addend < 0 => format!(" - {:#x}", -i128::from(addend)),
+        Some(addend) => format!(" + {:#x}", addend),
+        None => String::new(),
+    };
+    (value, name, addend)
+}
+
+fn print_relocations(opts: &Opts, elf: &goblin::elf::Elf) {
+    use goblin::elf::reloc::r_to_str;
+    if elf.shdr_relocs.is_empty() {
+        println!("There are no relocations in this file.");
+    }
+    for (i, (idx, relocs)) in elf.shdr_relocs.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let sec = &elf.section_headers[*idx];
+        println!(
+            "Relocation section '{}' at offset {:#x} contains {} entries:",
+            section_name(elf, *idx),
+            sec.sh_offset,
+            relocs.len()
+        );
+        println!("  Offset           Type                 Sym. Value       Sym. Name + Addend");
+        for reloc in relocs.iter() {
+            let (value, name, addend) = relocation_target(opts, elf, sec, &reloc);
+            println!(
+                "  {:016x} {:<20} {:016x} {}{}",
+                reloc.r_offset,
+                r_to_str(reloc.r_type, elf.header.e_machine),
+                value,
+                name,
+                addend
+            );
+        }
+    }
+}
+
+fn print_dynamic(elf: &goblin::elf::Elf) {
+    use goblin::elf::dynamic::*;
+    let dynamic = match &elf.dynamic {
+        Some(dynamic) => dynamic,
+        None => {
+            println!("There is no dynamic section in this file.");
+            return;
+        }
+    };
+    let offset = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_DYNAMIC)
+        .map_or(0, |ph| ph.p_offset);
+    println!(
+        "Dynamic section at offset {:#x} contains {} entries:",
+        offset,
+        dynamic.dyns.len()
+    );
+    println!("  Tag                Type                 Name/Value");
+    for entry in &dynamic.dyns {
+        let string = || {
+            elf.dynstrtab
+                .get(usize::try_from(entry.d_val).unwrap_or(usize::MAX))
+                .and_then(Result::ok)
+                .unwrap_or("")
+        };
+        let value = match entry.d_tag {
+            DT_NEEDED => format!("Shared library: [{}]", string()),
+            DT_SONAME => format!("Library soname: [{}]", string()),
+            DT_RPATH => format!("Library rpath: [{}]", string()),
+            DT_RUNPATH => format!("Library runpath: [{}]", string()),
+            DT_PLTRELSZ | DT_RELASZ | DT_RELAENT | DT_STRSZ | DT_SYMENT | DT_RELSZ | DT_RELENT => {
+                format!("{} (bytes)", entry.d_val)
+            }
+            _ => format!("{:#x}", entry.d_val),
+        };
+        let tag = match tag_to_str(entry.d_tag) {
+            "UNKNOWN_TAG" => format!("{:#x}", entry.d_tag),
+            name => name.trim_start_matches("DT_").to_string(),
+        };
+        println!(
+            "  {:#018x} {:<20} {}",
+            entry.d_tag,
+            format!("({})", tag),
+            value
+        );
+    }
+}
+
+const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+const GNU_PROPERTY_STACK_SIZE: u32 = 1;
+const GNU_PROPERTY_NO_COPY_ON_PROTECTED: u32 = 2;
+const GNU_PROPERTY_AARCH64_FEATURE_1_AND: u32 = 0xc000_0000;
+const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc000_0002;
+const GNU_PROPERTY_X86_ISA_1_NEEDED: u32 = 0xc000_8002;
+
+// The names of the bits set in `value`, or the bits themselves if they have no name.
+fn feature_flags(value: u32, names: &[(u32, &str)]) -> String {
+    let mut flags: Vec<_> = names
+        .iter()
+        .filter(|(bit, _)| value & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let unknown = names.iter().fold(value, |value, (bit, _)| value & !bit);
+    if unknown != 0 {
+        flags.push(format!("{:#x}", unknown));
+    }
+    if flags.is_empty() {
+        String::from("<None>")
+    } else {
+        flags.join(", ")
+    }
+}
+
+// The properties of an NT_GNU_PROPERTY_TYPE_0 note, one per line. Each is padded to the
+// word size.
+fn gnu_properties(desc: &[u8], elf: &goblin::elf::Elf) -> Vec<String> {
+    let word = |bytes: &[u8]| {
+        let bytes = <[u8; 4]>::try_from(bytes).unwrap();
+        if elf.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let align = if elf.is_64 { 8 } else { 4 };
+    let mut properties = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = desc.get(offset..offset + 8) {
+        let (pr_type, size) = (word(&header[..4]), word(&header[4..]));
+        let data = match desc.get(offset + 8..offset + 8 + usize::try_from(size).unwrap()) {
+            Some(data) => data,
+            None => {
+                properties.push(format!("<corrupt property of size {:#x}>", size));
+                break;
+            }
+        };
+        let value = if data.len() >= 4 { word(&data[..4]) } else { 0 };
+        properties.push(match pr_type {
+            GNU_PROPERTY_X86_FEATURE_1_AND => format!(
+                "x86 feature: {}",
+                feature_flags(value, &[(1, "IBT"), (2, "SHSTK")])
+            ),
+            GNU_PROPERTY_X86_ISA_1_NEEDED => format!(
+                "x86 ISA needed: {}",
+                feature_flags(
+                    value,
+                    &[
+                        (1, "x86-64-baseline"),
+                        (2, "x86-64-v2"),
+                        (4, "x86-64-v3"),
+                        (8, "x86-64-v4")
+                    ]
+                )
+            ),
+            GNU_PROPERTY_AARCH64_FEATURE_1_AND => format!(
+                "AArch64 feature: {}",
+                feature_flags(value, &[(1, "BTI"), (2, "PAC")])
+            ),
+            GNU_PROPERTY_STACK_SIZE => format!("stack size: {:#x}", value),
+            GNU_PROPERTY_NO_COPY_ON_PROTECTED => String::from("no copy on protected"),
+            _ => format!("<property {:#x} of size {:#x}>", pr_type, size),
+        });
+        offset = (offset + 8 + data.len()).div_ceil(align) * align;
+    }
+    properties
+}
+
+// The type of a note and the lines describing its contents.
+fn describe_note(note: &goblin::elf::note::Note, elf: &goblin::elf::Elf) -> (String, Vec<String>) {
+    use goblin::elf::note::*;
+    let word = |idx: usize| {
+        let bytes = note.desc.get(idx * 4..idx * 4 + 4)?;
+        let bytes = <[u8; 4]>::try_from(bytes).unwrap();
+        Some(if elf.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let hex = || {
+        note.desc
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    };
+    if note.name != "GNU" {
+        return (
+            format!("{:#x}", note.n_type),
+            vec![format!("description data: {}", hex())],
+        );
+    }
+    match note.n_type {
+        NT_GNU_BUILD_ID => (
+            String::from("NT_GNU_BUILD_ID (unique build ID bitstring)"),
+            vec![format!("Build ID: {}", hex())],
+        ),
+        NT_GNU_ABI_TAG => {
+            let description = match (word(0), word(1), word(2), word(3)) {
+                (Some(os), Some(major), Some(minor), Some(patch)) => {
+                    let os = match os {
+                        ELF_NOTE_OS_LINUX => String::from("Linux"),
+                        ELF_NOTE_OS_GNU => String::from("Hurd"),
+                        ELF_NOTE_OS_SOLARIS2 => String::from("Solaris"),
+                        ELF_NOTE_OS_FREEBSD => String::from("FreeBSD"),
+                        _ => format!("{:#x}", os),
+                    };
+                    format!("OS: {}, ABI: {}.{}.{}", os, major, minor, patch)
+                }
+                _ => format!("<corrupt ABI tag: {}>", hex()),
+            };
+            (
+                String::from("NT_GNU_ABI_TAG (ABI version tag)"),
+                vec![description],
+            )
+        }
+        NT_GNU_GOLD_VERSION => (
+            String::from("NT_GNU_GOLD_VERSION (gold version)"),
+            vec![format!(
+                "Version: {}",
+                String::from_utf8_lossy(note.desc).trim_end_matches('\0')
+            )],
+        ),
+        NT_GNU_PROPERTY_TYPE_0 => (
+            String::from("NT_GNU_PROPERTY_TYPE_0"),
+            gnu_properties(note.desc, elf),
+        ),
+        _ => (
+            format!("{:#x}", note.n_type),
+            vec![format!("description data: {}", hex())],
+        ),
+    }
+}
+
+fn print_notes(elf: &goblin::elf::Elf, buf: &[u8]) -> Result<(), error::Error> {
+    let ctx = goblin::container::Ctx::new(
+        if elf.is_64 {
+            goblin::container::Container::Big
+        } else {
+            goblin::container::Container::Little
+        },
+        if elf.little_endian {
+            scroll::Endian::Little
+        } else {
+            scroll::Endian::Big
+        },
+    );
+    let mut first = true;
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        if sec.sh_type != SHT_NOTE {
+            continue;
+        }
+        let contents = section_contents(elf, buf, idx).unwrap_or(&[]);
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("Displaying notes found in: {}", section_name(elf, idx));
+        println!("  Owner                Data size \tDescription");
+        let notes = goblin::elf::note::NoteDataIterator {
+            data: contents,
+            size: contents.len(),
+            offset: 0,
+            ctx: (usize::try_from(sec.sh_addralign).unwrap_or(4), ctx),
+        };
+        for note in notes {
+            let note = note?;
+            let (kind, lines) = describe_note(&note, elf);
+            println!("  {:<20} {:#010x}\t{}", note.name, note.desc.len(), kind);
+            for line in lines {
+                println!("    {}", line);
+            }
+        }
+    }
+    if first {
+        println!("There are no notes in this file.");
+    }
+    Ok(())
+}
+
+// The directories of the DT_RPATH or DT_RUNPATH entries, with $ORIGIN replaced by the
+// directory of the file.
+fn search_path(elf: &goblin::elf::Elf, tag: u64, path: &Path) -> Vec<PathBuf> {
+    let origin = path.parent().unwrap_or_else(|| Path::new("."));
+    let origin = origin.to_string_lossy();
+    let dyns = elf
+        .dynamic
+        .as_ref()
+        .map_or(&[][..], |dynamic| &dynamic.dyns[..]);
+    dyns.iter()
+        .filter(|entry| entry.d_tag == tag)
+        .filter_map(|entry| {
+            let offset = usize::try_from(entry.d_val).ok()?;
+            elf.dynstrtab.get(offset).and_then(Result::ok)
+        })
+        .flat_map(|paths| paths.split(':'))
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| {
+            PathBuf::from(
+                dir.replace("${ORIGIN}", &origin)
+                    .replace("$ORIGIN", &origin),
+            )
+        })
+        .collect()
+}
+
+// The directories of /etc/ld.so.conf and the files it includes, which ldconfig puts into
+// the cache the dynamic loader searches.
+fn ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>) {
+    let conf = match fs::read_to_string(path) {
+        Ok(conf) => conf,
+        Err(_) => return,
+    };
+    for line in conf.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            // Only patterns like `dir/*.conf` are supported.
+            let pattern = Path::new(pattern.trim());
+            let suffix = pattern.file_name().map_or(String::new(), |name| {
+                name.to_string_lossy().replace('*', "")
+            });
+            let mut files: Vec<_> = pattern
+                .parent()
+                .and_then(|dir| fs::read_dir(dir).ok())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| file.to_string_lossy().ends_with(&suffix))
+                .collect();
+            files.sort();
+            for file in files {
+                ld_so_conf(&file, dirs);
+            }
+        } else if !line.is_empty() {
+            dirs.push(PathBuf::from(line));
+        }
+    }
+}
+
+// Where the dynamic loader finds a library needed by `elf`: in the DT_RPATH of it and the
+// files that need it unless it has a DT_RUNPATH, in LD_LIBRARY_PATH, in DT_RUNPATH, in the
+// ld.so.conf directories and in the default directories, skipping libraries for other
+// machines.
+fn find_library(
+    name: &str,
+    elf: &goblin::elf::Elf,
+    rpaths: &[PathBuf],
+    runpaths: &[PathBuf],
+) -> Option<PathBuf> {
+    let compatible = |path: &Path| {
+        let buf = fs::read(path).ok()?;
+        let lib = goblin::elf::Elf::parse(&buf).ok()?;
+        Some(lib.is_64 == elf.is_64 && lib.header.e_machine == elf.header.e_machine)
+    };
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+    let mut dirs = Vec::new();
+    if runpaths.is_empty() {
+        dirs.extend_from_slice(rpaths);
+    }
+    if let Some(paths) = std::env::var_os("LD_LIBRARY_PATH") {
+        dirs.extend(std::env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    dirs.extend_from_slice(runpaths);
+    ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs);
+    let defaults: &[&str] = if elf.is_64 {
+        &["/lib64", "/usr/lib64", "/lib", "/usr/lib"]
+    } else {
+        &["/lib", "/usr/lib"]
+    };
+    dirs.extend(defaults.iter().map(PathBuf::from));
+    dirs.into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| compatible(path).unwrap_or(false))
+}
+
+// Print the libraries `elf` needs below it and recurse into the ones not printed before.
+// The DT_RPATHs of the files that lead to a library are searched for it as well.
+fn print_tree(
+    elf: &goblin::elf::Elf,
+    path: &Path,
+    depth: usize,
+    rpaths: &mut Vec<PathBuf>,
+    seen: &mut BTreeSet<PathBuf>,
+) {
+    use goblin::elf::dynamic::{DT_RPATH, DT_RUNPATH};
+    let outer = rpaths.len();
+    rpaths.extend(search_path(elf, DT_RPATH, path));
+    let runpaths = search_path(elf, DT_RUNPATH, path);
+    for library in &elf.libraries {
+        let indent = "  ".repeat(depth);
+        let found = match find_library(library, elf, rpaths, &runpaths) {
+            Some(found) => found,
+            None => {
+                println!(
+                    "{}{} => {}",
+                    indent,
+                    library,
+                    paint("not found", Color::Red)
+                );
+                continue;
+            }
+        };
+        if !seen.insert(found.clone()) {
+            println!(
+                "{}{} => {} (listed above)",
+                indent,
+                library,
+                found.display()
+            );
+            continue;
+        }
+        println!("{}{} => {}", indent, library, found.display());
+        match fs::read(&found) {
+            Ok(buf) => match goblin::elf::Elf::parse(&buf) {
+                Ok(lib) => print_tree(&lib, &found, depth + 1, rpaths, seen),
+                Err(err) => println!("{}  <cannot parse: {}>", indent, err),
+            },
+            Err(err) => println!("{}  <cannot read: {}>", indent, err),
+        }
+    }
+    rpaths.truncate(outer);
+}
+
+fn print_checksec(elf: &goblin::elf::Elf) {
+    use goblin::elf::dynamic::*;
+    use goblin::elf::header::{ET_DYN, ET_EXEC};
+    let segment = |p_type| elf.program_headers.iter().find(|ph| ph.p_type == p_type);
+    let dyns = elf
+        .dynamic
+        .as_ref()
+        .map_or(&[][..], |dynamic| &dynamic.dyns[..]);
+    let flags = |tag| {
+        dyns.iter()
+            .filter(|entry| entry.d_tag == tag)
+            .fold(0, |flags, entry| flags | entry.d_val)
+    };
+    let bind_now = dyns.iter().any(|entry| entry.d_tag == DT_BIND_NOW)
+        || flags(DT_FLAGS) & DF_BIND_NOW != 0
+        || flags(DT_FLAGS_1) & DF_1_NOW != 0;
+    let symbols = || {
+        let dynsyms = elf.dynsyms.iter().map(move |sym| (sym, &elf.dynstrtab));
+        let syms = elf.syms.iter().map(move |sym| (sym, &elf.strtab));
+        dynsyms
+            .chain(syms)
+            .filter_map(|(sym, strtab)| strtab.get(sym.st_name)?.ok())
+    };
+    let yes_no = |yes| if yes { "Yes" } else { "No" };
+
+    let pie = match elf.header.e_type {
+        ET_DYN if elf.interpreter.is_some() || flags(DT_FLAGS_1) & DF_1_PIE != 0 => "Yes",
+        ET_DYN => "Yes (shared object)",
+        ET_EXEC => "No",
+        _ => "No (not an executable)",
+    };
+    let relro = match (segment(PT_GNU_RELRO), bind_now) {
+        (Some(_), true) => "Full",
+        (Some(_), false) => "Partial",
+        (None, _) => "No",
+    };
+    // Without PT_GNU_STACK the kernel maps the stack executable.
+    let nx = match segment(PT_GNU_STACK) {
+        Some(ph) => yes_no(!ph.is_executable()),
+        None => "No (no PT_GNU_STACK)",
+    };
+    let canary = symbols().any(|name| name == "__stack_chk_fail" || name == "__stack_chk_guard");
+    let mut fortified: Vec<_> = symbols()
+        .filter(|name| name.starts_with("__") && name.ends_with("_chk"))
+        .filter(|name| !name.starts_with("__stack_chk"))
+        .collect();
+    fortified.sort_unstable();
+    fortified.dedup();
+    println!("PIE:       {}", pie);
+    println!("RELRO:     {}", relro);
+    println!("NX stack:  {}", nx);
+    println!("Canary:    {}", yes_no(canary));
+    println!("BIND_NOW:  {}", yes_no(bind_now));
+    if fortified.is_empty() {
+        println!("Fortify:   No");
+    } else {
+        println!("Fortify:   Yes ({} functions)", fortified.len());
+    }
+}
+
+// The version name of each index and, for needed versions, the file that defines it.
+type VersionNames = BTreeMap<u16, (String, Option<String>)>;
+
+// Print .gnu.version_d and .gnu.version_r and return the names of their versions. goblin
+// does not parse these, the entries are chained by offsets relative to each entry.
+fn print_version_tables(elf: &goblin::elf::Elf, buf: &[u8]) -> VersionNames {
+    use scroll::Pread;
+    let endian = if elf.little_endian {
+        scroll::Endian::Little
+    } else {
+        scroll::Endian::Big
+    };
+    let u16_at = |data: &[u8], offset: usize| data.pread_with::<u16>(offset, endian).ok();
+    let u32_at = |data: &[u8], offset: usize| {
+        let value = data.pread_with::<u32>(offset, endian).ok()?;
+        usize::try_from(value).ok()
+    };
+    let string = |offset: Option<usize>| {
+        offset
+            .and_then(|offset| elf.dynstrtab.get(offset))
+            .and_then(Result::ok)
+            .unwrap_or("<corrupt>")
+            .to_string()
+    };
+    let mut names = VersionNames::new();
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        let data = section_contents(elf, buf, idx).unwrap_or(&[]);
+        let count = usize::try_from(sec.sh_info).unwrap();
+        match sec.sh_type {
+            SHT_GNU_VERDEF => {
+                println!(
+                    "Version definition section '{}' contains {} entries:",
+                    section_name(elf, idx),
+                    count
+                );
+                let mut offset = 0;
+                for _ in 0..count {
+                    let (flags, index, aux_count) = match (
+                        u16_at(data, offset + 2),
+                        u16_at(data, offset + 4),
+                        u16_at(data, offset + 6),
+                    ) {
+                        (Some(flags), Some(index), Some(aux_count)) => (flags, index, aux_count),
+                        _ => {
+                            println!("  <corrupt entry at {:#06x}>", offset);
+                            break;
+                        }
+                    };
+                    let mut aux = offset + u32_at(data, offset + 12).unwrap_or(0);
+                    let name = string(u32_at(data, aux));
+                    let flags = match flags {
+                        0 => String::from("none"),
+                        1 => String::from("BASE"),
+                        2 => String::from("WEAK"),
+                        _ => format!("{:#x}", flags),
+                    };
+                    println!(
+                        "  {:#06x}: Rev: 1  Flags: {}  Index: {}  Cnt: {}  Name: {}",
+                        offset, flags, index, aux_count, name
+                    );
+                    // The other names are the versions this one inherits from.
+                    for parent in 1..aux_count {
+                        aux += u32_at(data, aux + 4).unwrap_or(0);
+                        println!(
+                            "  {:#06x}: Parent {}: {}",
+                            aux,
+                            parent,
+                            string(u32_at(data, aux))
+                        );
+                    }
+                    names.insert(index, (name, None));
+                    match u32_at(data, offset + 16) {
+                        Some(0) | None => break,
+                        Some(next) => offset += next,
+                    }
+                }
+                println!();
+            }
+            SHT_GNU_VERNEED => {
+                println!(
+                    "Version needs section '{}' contains {} entries:",
+                    section_name(elf, idx),
+                    count
+                );
+                let mut offset = 0;
+                for _ in 0..count {
+                    let aux_count = match u16_at(data, offset + 2) {
+                        Some(aux_count) => aux_count,
+                        None => {
+                            println!("  <corrupt entry at {:#06x}>", offset);
+                            break;
+                        }
+                    };
+                    let file = string(u32_at(data, offset + 4));
+                    println!(
+                        "  {:#06x}: Version: 1  File: {}  Cnt: {}",
+                        offset, file, aux_count
+                    );
+                    let mut aux = offset + u32_at(data, offset + 8).unwrap_or(0);
+                    for _ in 0..aux_count {
+                        let name = string(u32_at(data, aux + 8));
+                        let flags = match u16_at(data, aux + 4) {
+                            Some(0) => String::from("none"),
+                            Some(2) => String::from("WEAK"),
+                            flags => format!("{:#x}", flags.unwrap_or(0)),
+                        };
+                        let index = u16_at(data, aux + 6).unwrap_or(0);
+                        println!(
+                            "  {:#06x}:   Name: {}  Flags: {}  Version: {}",
+                            aux, name, flags, index
+                        );
+                        names.insert(index, (name, Some(file.clone())));
+                        match u32_at(data, aux + 12) {
+                            Some(0) | None => break,
+                            Some(next) => aux += next,
+                        }
+                    }
+                    match u32_at(data, offset + 12) {
+                        Some(0) | None => break,
+                        Some(next) => offset += next,
+                    }
+                }
+                println!();
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+fn print_versions(elf: &goblin::elf::Elf, buf: &[u8]) {
+    let names = print_version_tables(elf, buf);
+    let versym = (0..elf.section_headers.len())
+        .find(|idx| elf.section_headers[*idx].sh_type == SHT_GNU_VERSYM);
+    let versym = match versym {
+        Some(idx) => section_contents(elf, buf, idx).unwrap_or(&[]),
+        None => {
+            println!("There is no .gnu.version section in this file.");
+            return;
+        }
+    };
+    println!("Dynamic symbol versions:");
+    println!("   Num: Ver Name");
+    for (idx, (sym, entry)) in elf.dynsyms.iter().zip(versym.chunks_exact(2)).enumerate() {
+        let entry = <[u8; 2]>::try_from(entry).unwrap();
+        let entry = if elf.little_endian {
+            u16::from_le_bytes(entry)
+        } else {
+            u16::from_be_bytes(entry)
+        };
+        // The top bit hides the version from symbols without one, so it is not the default.
+        let (index, hidden) = (entry & 0x7fff, entry & 0x8000 != 0);
+        let name = elf
+            .dynstrtab
+            .get(sym.st_name)
+            .and_then(Result::ok)
+            .unwrap_or("");
+        let version = match (index, names.get(&index)) {
+            (0, _) => String::from(" (*local*)"),
+            (1, _) => String::from(" (*global*)"),
+            (_, Some((version, Some(file)))) => format!("@{} ({})", version, file),
+            (_, Some((version, None))) if hidden || sym.is_import() => format!("@{}", version),
+            (_, Some((version, None))) => format!("@@{}", version),
+            (_, None) => String::from(" <unknown version>"),
+        };
+        println!("{:>6}: {:>3} {}{}", idx, index, name, version);
+    }
+}
+
+// The index of the section named `section`, or with that index if it is a number.
+fn find_section(elf: &goblin::elf::Elf, section: &str) -> Option<usize> {
+    match section.parse::<usize>() {
+        Ok(idx) => Some(idx).filter(|idx| *idx < elf.section_headers.len()),
+        Err(_) => (0..elf.section_headers.len()).find(|idx| section_name(elf, *idx) == section),
+    }
+}
+
+// The contents of a section, None if it has none in the file.
+fn section_contents<'a>(elf: &goblin::elf::Elf, buf: &'a [u8], idx: usize) -> Option<&'a [u8]> {
+    let sec = &elf.section_headers[idx];
+    let start = usize::try_from(sec.sh_offset).ok()?;
+    let size = usize::try_from(sec.sh_size).ok()?;
+    buf.get(start..start.checked_add(size)?)
+        .filter(|contents| sec.sh_type != SHT_NOBITS && !contents.is_empty())
+}
+
+fn print_hex_dump(elf: &goblin::elf::Elf, buf: &[u8], idx: usize) {
+    let sec = &elf.section_headers[idx];
+    let name = section_name(elf, idx);
+    let contents = match section_contents(elf, buf, idx) {
+        Some(contents) => contents,
+        None => {
+            println!("Section '{}' has no data to dump.", name);
+            return;
+        }
+    };
+    println!("Hex dump of section '{}':", name);
+    for (i, line) in contents.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, byte) in line.iter().enumerate() {
+            if j > 0 && j % 4 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let ascii: String = line
+            .iter()
+            .map(|byte| match byte {
+                0x20..=0x7e => char::from(*byte),
+                _ => '.',
+            })
+            .collect();
+        println!(
+            "  {:#010x} {:<35} {}",
+            sec.sh_addr + u64::try_from(i * 16).unwrap(),
+            hex,
+            ascii
+        );
+    }
+}
+
+// The functions and labels of a code section by address, for the names of branch targets.
+fn code_symbols(opts: &Opts, elf: &goblin::elf::Elf, idx: usize) -> Vec<(u64, u64, String)> {
+    use goblin::elf::sym::{STT_FUNC, STT_NOTYPE};
+    let mut symbols: Vec<(u64, u64, String)> = elf
+        .syms
+        .iter()
+        .filter(|sym| sym.st_shndx == idx && matches!(sym.st_type(), STT_FUNC | STT_NOTYPE))
+        .filter_map(|sym| {
+            let name = elf
+                .strtab
+                .get(sym.st_name)?
+                .ok()
+                .filter(|name| !name.is_empty())?;
+            let name = match opts.demangle.then(|| demangle(name)).flatten() {
+                Some(demangled) => demangled,
+                None => name.to_string(),
+            };
+            Some((sym.st_value, sym.st_size, name))
+        })
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+// `<symbol+offset>` for the closest symbol at or before `address`.
+fn symbolize(symbols: &[(u64, u64, String)], address: u64) -> Option<String> {
+    let (start, _, name) = symbols
+        .iter()
+        .take_while(|(start, _, _)| *start <= address)
+        .last()?;
+    Some(match address - start {
+        0 => format!("<{}>", name),
+        offset => format!("<{}+{:#x}>", name, offset),
+    })
+}
+
+fn print_disassembly(opts: &Opts, elf: &goblin::elf::Elf, buf: &[u8], symbol: Option<&str>) {
+    use goblin::elf::header::{EM_386, EM_X86_64};
+    use iced_x86::{Decoder, DecoderOptions, FlowControl, Formatter, GasFormatter, Instruction};
+    let bitness = match elf.header.e_machine {
+        EM_X86_64 => 64,
+        EM_386 => 32,
+        machine => {
+            eprintln!("dump: cannot disassemble code for machine {}", machine);
+            exit(1);
+        }
+    };
+    use goblin::elf::reloc::r_to_str;
+    let mut formatter = GasFormatter::new();
+    formatter.options_mut().set_uppercase_hex(false);
+    formatter.options_mut().set_branch_leading_zeros(false);
+    let mut found = false;
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        if sec.sh_flags & u64::from(SHF_EXECINSTR) == 0 || sec.sh_type != SHT_PROGBITS {
+            continue;
+        }
+        let symbols = code_symbols(opts, elf, idx);
+        // Relocations that are still in the file, as in objects, are shown instead of the
+        // target they would be resolved to.
+        let relocs: Vec<(u64, String)> = elf
+            .shdr_relocs
+            .iter()
+            .filter(|(reloc_idx, _)| {
+                usize::try_from(elf.section_headers[*reloc_idx].sh_info).ok() == Some(idx)
+            })
+            .flat_map(|(reloc_idx, relocs)| {
+                let reloc_sec = &elf.section_headers[*reloc_idx];
+                relocs.iter().map(move |reloc| {
+                    let (_, name, addend) = relocation_target(opts, elf, reloc_sec, &reloc);
+                    let r_type = r_to_str(reloc.r_type, elf.header.e_machine);
+                    (reloc.r_offset, format!("# {} {}{}", r_type, name, addend))
+                })
+            })
+            .collect();
+        // The whole section or only the function
+        let (start, size) = match symbol {
+            Some(symbol) => match symbols.iter().find(|(_, _, name)| name == symbol) {
+                Some((value, size, _)) => (*value, *size),
+                None => continue,
+            },
+            None => (sec.sh_addr, sec.sh_size),
+        };
+        let contents = usize::try_from(sec.sh_offset + (start - sec.sh_addr))
+            .ok()
+            .zip(usize::try_from(size).ok())
+            .and_then(|(offset, size)| buf.get(offset..offset.checked_add(size)?));
+        let contents = match contents {
+            Some(contents) => contents,
+            None => continue,
+        };
+        if found {
+            println!();
+        }
+        found = true;
+        println!("Disassembly of section {}:", section_name(elf, idx));
+        let mut decoder = Decoder::with_ip(bitness, contents, start, DecoderOptions::NONE);
+        let mut instruction = Instruction::default();
+        let mut text = String::new();
+        while decoder.can_decode() {
+            decoder.decode_out(&mut instruction);
+            let address = instruction.ip();
+            if let Some((_, _, name)) = symbols.iter().find(|(value, _, _)| *value == address) {
+                println!();
+                println!("{:016x} <{}>:", address, name);
+            }
+            text.clear();
+            formatter.format(&instruction, &mut text);
+            let is_branch = matches!(
+                instruction.flow_control(),
+                FlowControl::Call
+                    | FlowControl::UnconditionalBranch
+                    | FlowControl::ConditionalBranch
+            );
+            let end = address + u64::try_from(instruction.len()).unwrap();
+            let reloc = relocs
+                .iter()
+                .find(|(offset, _)| (address..end).contains(&(sec.sh_addr + offset)));
+            match reloc {
+                Some((_, reloc)) => {
+                    text.push_str("  ");
+                    text.push_str(reloc);
+                }
+                None if is_branch => {
+                    if let Some(target) = symbolize(&symbols, instruction.near_branch_target()) {
+                        text.push(' ');
+                        text.push_str(&target);
+                    }
+                }
+                None => {}
+            }
+            let offset = usize::try_from(address - start).unwrap();
+            let bytes: Vec<String> = contents[offset..offset + instruction.len()]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            println!("  {:>8x}:\t{:<24}\t{}", address, bytes.join(" "), text);
+        }
+    }
+    if let (Some(symbol), false) = (symbol, found) {
+        eprintln!("dump: there is no function {}", symbol);
+        exit(1);
+    }
+}
+
+// Each string with its offset and the characters that are not printable escaped, like
+// readelf -p.
+fn print_string_dump(elf: &goblin::elf::Elf, buf: &[u8], idx: usize) {
+    let name = section_name(elf, idx);
+    let contents = match section_contents(elf, buf, idx) {
+        Some(contents) => contents,
+        None => {
+            println!("Section '{}' has no data to dump.", name);
+            return;
+        }
+    };
+    println!("String dump of section '{}':", name);
+    let mut offset = 0;
+    for string in contents.split(|byte| *byte == 0) {
+        if !string.is_empty() {
+            let escaped: String = string
+                .iter()
+                .flat_map(|byte| match byte {
+                    0x20..=0x7e => vec![char::from(*byte)],
+                    _ => std::ascii::escape_default(*byte).map(char::from).collect(),
+                })
+                .collect();
+            println!("  [{:>6x}]  {}", offset, escaped);
+        }
+        offset += string.len() + 1;
+    }
+}
+
+type Dwarf<'a> = gimli::Dwarf<gimli::EndianSlice<'a, gimli::RunTimeEndian>>;
+
+// The debug sections by name, missing ones are empty. Relocations in objects are not
+// applied, so addresses are only right in linked files.
+fn load_dwarf<'a>(elf: &goblin::elf::Elf, buf: &'a [u8]) -> Result<Dwarf<'a>, gimli::Error> {
+    let endian = if elf.little_endian {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    gimli::Dwarf::load(|id| {
+        let contents = (0..elf.section_headers.len())
+            .find(|idx| section_name(elf, *idx) == id.name())
+            .and_then(|idx| section_contents(elf, buf, idx))
+            .unwrap_or(&[]);
+        Ok(gimli::EndianSlice::new(contents, endian))
+    })
+}
+
+fn print_dwarf_units(dwarf: &Dwarf) -> Result<(), gimli::Error> {
+    let string = |unit: &gimli::Unit<_>, value| -> Result<String, gimli::Error> {
+        Ok(dwarf
+            .attr_string(unit, value)?
+            .to_string_lossy()
+            .into_owned())
+    };
+    let mut headers = dwarf.units();
+    let mut count = 0;
+    while let Some(header) = headers.next()? {
+        let offset = header
+            .offset()
+            .as_debug_info_offset()
+            .map_or(0, |offset| offset.0);
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        let producer = match entries.next_dfs()? {
+            Some((_, root)) => match root.attr_value(gimli::DW_AT_producer)? {
+                Some(value) => string(&unit, value)?,
+                None => String::new(),
+            },
+            None => String::new(),
+        };
+        let lossy = |name: Option<gimli::EndianSlice<_>>| {
+            name.map_or(String::new(), |name| name.to_string_lossy().into_owned())
+        };
+        let rows = match &unit.line_program {
+            Some(program) => {
+                let mut rows = program.clone().rows();
+                let mut count = 0;
+                while rows.next_row()?.is_some() {
+                    count += 1;
+                }
+                count
+            }
+            None => 0,
+        };
+        println!(
+            "Compilation unit at offset {:#x} (DWARF {}):",
+            offset,
+            unit.header.version()
+        );
+        println!("  Name:      {}", lossy(unit.name));
+        println!("  Producer:  {}", producer);
+        println!("  Directory: {}", lossy(unit.comp_dir));
+        println!("  Line rows: {}", rows);
+        count += 1;
+    }
+    if count == 0 {
+        println!("There is no DWARF debug info in this file.");
+    }
+    Ok(())
+}
+
+// The file and line of the row in a line program that covers `address`.
+fn find_line(dwarf: &Dwarf, address: u64) -> Result<Option<(String, u64)>, gimli::Error> {
+    let mut headers = dwarf.units();
+    while let Some(header) = headers.next()? {
+        let unit = dwarf.unit(header)?;
+        let program = match &unit.line_program {
+            Some(program) => program.clone(),
+            None => continue,
+        };
+        let mut rows = program.rows();
+        let mut previous: Option<gimli::LineRow> = None;
+        while let Some((header, row)) = rows.next_row()? {
+            // A row covers the addresses up to the next one in its sequence.
+            if let Some(previous) = previous.filter(|previous| previous.address() <= address) {
+                if address < row.address() {
+                    // Relative paths are relative to the directory of the file, and that
+                    // one to the directory of the compilation.
+                    let file = match previous.file(header) {
+                        Some(file) => {
+                            let name = dwarf.attr_string(&unit, file.path_name())?;
+                            let mut path = PathBuf::from(name.to_string_lossy().as_ref());
+                            if let Some(dir) = file.directory(header) {
+                                let dir = dwarf.attr_string(&unit, dir)?;
+                                path = Path::new(dir.to_string_lossy().as_ref()).join(path);
+                            }
+                            if let Some(comp_dir) = unit.comp_dir {
+                                path = Path::new(comp_dir.to_string_lossy().as_ref()).join(path);
+                            }
+                            path.display().to_string()
+                        }
+                        None => String::from("??"),
+                    };
+                    let line = previous.line().map_or(0, |line| line.get());
+                    return Ok(Some((file, line)));
+                }
+            }
+            previous = if row.end_sequence() { None } else { Some(*row) };
+        }
+    }
+    Ok(None)
+}
+
+fn print_dwarf(opts: &Opts, elf: &goblin::elf::Elf, buf: &[u8]) -> Result<(), gimli::Error> {
+    let dwarf = load_dwarf(elf, buf)?;
+    if opts.dwarf {
+        print_dwarf_units(&dwarf)?;
+    }
+    if let Some(address) = opts.addr2line {
+        match find_line(&dwarf, address)? {
+            Some((file, line)) => println!("{:#x}: {}:{}", address, file, line),
+            None => println!("{:#x}: {}", address, paint("??:0", Color::Red)),
+        }
+    }
+    Ok(())
+}
+
+// The address ranges of the DWARF compilation units with their names.
+fn unit_ranges(dwarf: &Dwarf) -> Result<Vec<(u64, u64, String)>, gimli::Error> {
+    let mut ranges = Vec::new();
+    let mut headers = dwarf.units();
+    while let Some(header) = headers.next()? {
+        let unit = dwarf.unit(header)?;
+        let name = unit
+            .name
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let mut unit_ranges = dwarf.unit_ranges(&unit)?;
+        while let Some(range) = unit_ranges.next()? {
+            ranges.push((range.begin, range.end, name.clone()));
+        }
+    }
+    Ok(ranges)
+}
+
+// A table of names with their file and VM size. The largest `top` rows are printed,
+// the others are summed up in one row.
+fn print_size_table(title: &str, mut rows: Vec<(String, u64, u64)>, top: usize) {
+    let (file_total, vm_total) = rows
+        .iter()
+        .fold((0, 0), |(file, vm), row| (file + row.1, vm + row.2));
+    rows.retain(|(_, file, vm)| *file != 0 || *vm != 0);
+    rows.sort_by(|a, b| b.1.max(b.2).cmp(&a.1.max(a.2)).then_with(|| a.0.cmp(&b.0)));
+    if rows.len() > top {
+        let others = rows.split_off(top);
+        let (file, vm) = others
+            .iter()
+            .fold((0, 0), |(file, vm), row| (file + row.1, vm + row.2));
+        rows.push((format!("[{} others]", others.len()), file, vm));
+    }
+    let percent = |size: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            size as f64 * 100.0 / total as f64
+        }
+    };
+    println!("{}:", title);
+    println!("     File size         VM size  Name");
+    rows.push((String::from("TOTAL"), file_total, vm_total));
+    for (name, file, vm) in rows {
+        println!(
+            "  {:>8} {:>5.1}%  {:>8} {:>5.1}%  {}",
+            file,
+            percent(file, file_total),
+            vm,
+            percent(vm, vm_total),
+            name
+        );
+    }
+}
+
+fn print_sizes(opts: &Opts, elf: &goblin::elf::Elf, buf: &[u8]) {
+    let header = &elf.header;
+    let headers = u64::from(header.e_ehsize)
+        + u64::from(header.e_phnum) * u64::from(header.e_phentsize)
+        + u64::from(header.e_shnum) * u64::from(header.e_shentsize);
+    let sizes = |sec: &SectionHeader| {
+        let file = if sec.sh_type == SHT_NOBITS {
+            0
+        } else {
+            sec.sh_size
+        };
+        let vm = if sec.sh_flags & u64::from(SHF_ALLOC) != 0 {
+            sec.sh_size
+        } else {
+            0
+        };
+        (file, vm)
+    };
+    let sections: Vec<_> = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(idx, sec)| (section_name(elf, idx).to_string(), sizes(sec)))
+        .collect();
+    let contents: u64 = sections.iter().map(|(_, (file, _))| file).sum();
+    // Everything the headers and sections do not cover, e.g. alignment.
+    let padding = u64::try_from(buf.len())
+        .unwrap()
+        .saturating_sub(headers + contents);
+    let mut rows = vec![
+        (String::from("[ELF headers]"), headers, 0),
+        (String::from("[padding]"), padding, 0),
+    ];
+    rows.extend(
+        sections
+            .iter()
+            .map(|(name, (file, vm))| (name.clone(), *file, *vm)),
+    );
+    print_size_table("Sections", rows, opts.top);
+
+    // The sized symbols with their source object, from the DWARF compilation unit that
+    // covers them or the FILE symbol before a local symbol.
+    let (syms, strtab) = if elf.syms.is_empty() {
+        (&elf.dynsyms, &elf.dynstrtab)
+    } else {
+        (&elf.syms, &elf.strtab)
+    };
+    let ranges = load_dwarf(elf, buf)
+        .and_then(|dwarf| unit_ranges(&dwarf))
+        .unwrap_or_default();
+    let mut file = None;
+    let mut symbols = Vec::new();
+    for sym in syms.iter() {
+        let name = strtab.get(sym.st_name).and_then(Result::ok).unwrap_or("");
+        if sym.st_type() == STT_FILE {
+            file = Some(name.to_string());
+            continue;
+        }
+        if sym.st_bind() != STB_LOCAL {
+            file = None;
+        }
+        let shndx = u32::try_from(sym.st_shndx).unwrap_or(SHN_UNDEF);
+        if sym.st_size == 0 || shndx == SHN_UNDEF || sym.st_shndx > sections.len() {
+            continue;
+        }
+        let object = ranges
+            .iter()
+            .find(|(begin, end, _)| (*begin..*end).contains(&sym.st_value))
+            .map(|(_, _, name)| name.clone())
+            .or_else(|| file.clone());
+        let name = match opts.demangle.then(|| demangle(name)).flatten() {
+            Some(demangled) => demangled,
+            None => name.to_string(),
+        };
+        symbols.push((name, object, sym.st_shndx, sym.st_size));
+    }
+    // The bytes of each section that no symbol covers.
+    let mut uncovered: Vec<_> = sections.iter().map(|(_, sizes)| *sizes).collect();
+    for (_, _, shndx, size) in &symbols {
+        let (file, vm) = &mut uncovered[shndx - 1];
+        *file = file.saturating_sub(if *file == 0 { 0 } else { *size });
+        *vm = vm.saturating_sub(if *vm == 0 { 0 } else { *size });
+    }
+    // Symbols are only attributed within the loaded sections, the rest of the file is one
+    // row so that the totals still match the file.
+    let loaded = |(_, (_, vm)): &&(String, (u64, u64))| *vm != 0;
+    let unloaded: u64 = sections
+        .iter()
+        .filter(|section| !loaded(section))
+        .map(|(_, (file, _))| file)
+        .sum();
+    let mut rest = vec![(
+        String::from("[not loaded]"),
+        headers + padding + unloaded,
+        0,
+    )];
+    rest.extend(
+        sections
+            .iter()
+            .zip(uncovered)
+            .filter(|(section, _)| loaded(section))
+            .map(|((name, _), (file, vm))| (format!("[section {}]", name), file, vm)),
+    );
+    let sized = |shndx: usize, size: u64| {
+        let (file, vm) = sections[shndx - 1].1;
+        (
+            if file == 0 { 0 } else { size },
+            if vm == 0 { 0 } else { size },
+        )
+    };
+    let mut rows = rest.clone();
+    rows.extend(symbols.iter().map(|(name, _, shndx, size)| {
+        let (file, vm) = sized(*shndx, *size);
+        (name.clone(), file, vm)
+    }));
+    println!();
+    print_size_table("Symbols", rows, opts.top);
+
+    let mut objects = BTreeMap::<_, (u64, u64)>::new();
+    for (_, object, shndx, size) in &symbols {
+        let (file, vm) = sized(*shndx, *size);
+        let name = object.clone().unwrap_or_else(|| String::from("[unknown]"));
+        let sizes = objects.entry(name).or_default();
+        *sizes = (sizes.0 + file, sizes.1 + vm);
+    }
+    let mut rows = rest;
+    rows.extend(
+        objects
+            .into_iter()
+            .map(|(name, (file, vm))| (name, file, vm)),
+    );
+    println!();
+    print_size_table("Source objects", rows, opts.top);
+}
+
+// Group items by name, keeping the order of those with the same name so that they are
+// paired up in order.
+fn by_name<T>(items: impl Iterator<Item = (String, T)>) -> BTreeMap<String, Vec<T>> {
+    let mut map = BTreeMap::<_, Vec<_>>::new();
+    for (name, item) in items {
+        map.entry(name).or_default().push(item);
+    }
+    map
+}
+
+// The lines describing the differences between the items of two files with the same
+// name. `describe` is printed for added and removed ones, `compare` lists the changed
+// fields of a pair.
+fn diff_by_name<T>(
+    old: BTreeMap<String, Vec<T>>,
+    mut new: BTreeMap<String, Vec<T>>,
+    describe: impl Fn(&T) -> String,
+    compare: impl Fn(&T, &T) -> Vec<String>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut changes = |name: &str, old: &[T], new: &[T]| {
+        for (old, new) in old.iter().zip(new) {
+            let changed = compare(old, new);
+            if !changed.is_empty() {
+                lines.push(format!("~ {}: {}", name, changed.join(", ")));
+            }
+        }
+        for old in old.iter().skip(new.len()) {
+            lines.push(format!("- {} ({})", name, describe(old)));
+        }
+        for new in new.iter().skip(old.len()) {
+            lines.push(format!("+ {} ({})", name, describe(new)));
+        }
+    };
+    for (name, old) in &old {
+        let new = new.remove(name).unwrap_or_default();
+        changes(name, old, &new);
+    }
+    for (name, new) in &new {
+        changes(name, &[], new);
+    }
+    lines
+}
+
+// "field old -> new" if the values differ.
+fn changed<T: PartialEq + std::fmt::Display>(field: &str, old: T, new: T) -> Option<String> {
+    if old == new {
+        None
+    } else {
+        Some(format!("{} {} -> {}", field, old, new))
+    }
+}
+
+fn diff_headers(old: &goblin::elf::Elf, new: &goblin::elf::Elf) -> Vec<String> {
+    use goblin::elf::header::{et_to_str, machine_to_str};
+    let (old, new) = (&old.header, &new.header);
+    let hex = |value: u64| format!("{:#x}", value);
+    vec![
+        changed("Type:", et_to_str(old.e_type), et_to_str(new.e_type)),
+        changed(
+            "Machine:",
+            machine_to_str(old.e_machine),
+            machine_to_str(new.e_machine),
+        ),
+        changed("Entry point:", hex(old.e_entry), hex(new.e_entry)),
+        changed("Flags:", hex(old.e_flags.into()), hex(new.e_flags.into())),
+        changed("Number of program headers:", old.e_phnum, new.e_phnum),
+        changed("Number of section headers:", old.e_shnum, new.e_shnum),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn diff_sections(old: &goblin::elf::Elf, new: &goblin::elf::Elf) -> Vec<String> {
+    let sections = |elf: &goblin::elf::Elf| {
+        by_name(
+            elf.section_headers
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(idx, sec)| (section_name(elf, idx).to_string(), sec.clone())),
+        )
+    };
+    diff_by_name(
+        sections(old),
+        sections(new),
+        |sec| format!("{:#x} bytes", sec.sh_size),
+        |old, new| {
+            vec![
+                changed("type", section_type(old.sh_type), section_type(new.sh_type)),
+                changed(
+                    "flags",
+                    section_flags(old.sh_flags),
+                    section_flags(new.sh_flags),
+                ),
+                changed(
+                    "size",
+                    format!("{:#x}", old.sh_size),
+                    format!("{:#x}", new.sh_size),
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        },
+    )
+}
+
+// The symbols of a table by name with their section by name, since section indices
+// differ between linkers. Symbols without a name are left out.
+fn named_symbols(
+    elf: &goblin::elf::Elf,
+    syms: &Symtab,
+    strtab: &Strtab,
+) -> BTreeMap<String, Vec<(Sym, String)>> {
+    by_name(syms.iter().filter_map(|sym| {
+        let name = strtab.get(sym.st_name).and_then(Result::ok)?;
+        if name.is_empty() {
+            return None;
+        }
+        let shndx = u32::try_from(sym.st_shndx).unwrap_or(SHN_UNDEF);
+        let section = if shndx == SHN_UNDEF || shndx >= SHN_LORESERVE {
+            symbol_section(sym.st_shndx)
+        } else {
+            section_name(elf, sym.st_shndx).to_string()
+        };
+        Some((name.to_string(), (sym, section)))
+    }))
+}
+
+fn diff_symbols(
+    old: BTreeMap<String, Vec<(Sym, String)>>,
+    new: BTreeMap<String, Vec<(Sym, String)>>,
+) -> Vec<String> {
+    diff_by_name(
+        old,
+        new,
+        |(sym, section)| {
+            format!(
+                "{} {} in {}",
+                type_to_str(sym.st_type()),
+                bind_to_str(sym.st_bind()),
+                section
+            )
+        },
+        |(old, old_section), (new, new_section)| {
+            let hex = |value: u64| format!("{:#x}", value);
+            vec![
+                changed("value", hex(old.st_value), hex(new.st_value)),
+                changed("size", old.st_size, new.st_size),
+                changed(
+                    "type",
+                    type_to_str(old.st_type()),
+                    type_to_str(new.st_type()),
+                ),
+                changed(
+                    "binding",
+                    bind_to_str(old.st_bind()),
+                    bind_to_str(new.st_bind()),
+                ),
+                changed("section", old_section, new_section),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        },
+    )
+}
+
+// Print the differences between two files, returns whether there are any.
+fn print_diff(old_path: &str, new_path: &str) -> Result<bool, error::Error> {
+    let (old_buf, new_buf) = (fs::read(old_path)?, fs::read(new_path)?);
+    let old = goblin::elf::Elf::parse(&old_buf)?;
+    let new = goblin::elf::Elf::parse(&new_buf)?;
+    let groups = [
+        ("ELF header", diff_headers(&old, &new)),
+        ("Section headers", diff_sections(&old, &new)),
+        (
+            "Symbol table '.dynsym'",
+            diff_symbols(
+                named_symbols(&old, &old.dynsyms, &old.dynstrtab),
+                named_symbols(&new, &new.dynsyms, &new.dynstrtab),
+            ),
+        ),
+        (
+            "Symbol table '.symtab'",
+            diff_symbols(
+                named_symbols(&old, &old.syms, &old.strtab),
+                named_symbols(&new, &new.syms, &new.strtab),
+            ),
+        ),
+    ];
+    let mut differ = false;
+    for (title, lines) in groups.iter().filter(|(_, lines)| !lines.is_empty()) {
+        if !differ {
+            println!("--- {}", old_path);
+            println!("+++ {}", new_path);
+        }
+        differ = true;
+        println!();
+        println!("{}:", title);
+        for line in lines {
+            let line = match line.chars().next() {
+                Some('-') => paint(line, Color::Red),
+                Some('+') => paint(line, Color::Green),
+                _ => paint(line, Color::Yellow),
+            };
+            println!("  {}", line);
+        }
+    }
+    Ok(differ)
+}
+
+// A double quoted Graphviz string.
+fn dot_string(s: &str) -> String {
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped.replace('\n', "\\n"))
+}
+
+// The global symbols an object defines, with whether they are weak, and the ones it
+// leaves undefined.
+type ObjectSymbols = (Vec<(String, bool)>, Vec<String>);
+
+fn object_symbols(buf: &[u8]) -> Result<ObjectSymbols, error::Error> {
+    let elf = goblin::elf::Elf::parse(buf)?;
+    let (mut defined, mut undefined) = (Vec::new(), Vec::new());
+    for sym in elf.syms.iter() {
+        let bind = sym.st_bind();
+        if bind != STB_GLOBAL && bind != STB_WEAK {
+            continue;
+        }
+        let name = match elf.strtab.get(sym.st_name).and_then(Result::ok) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => continue,
+        };
+        if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() {
+            undefined.push(name);
+        } else {
+            defined.push((name, bind == STB_WEAK));
+        }
+    }
+    Ok((defined, undefined))
+}
+
+fn print_graph(inputs: &[String]) -> Result<(), error::Error> {
+    // The objects as node names, with the archive they are in.
+    let mut objects = Vec::new();
+    for input in inputs {
+        let file = fs::read(input)?;
+        if file.starts_with(goblin::archive::MAGIC) {
+            let archive = goblin::archive::Archive::parse(&file)?;
+            for (name, _, _) in archive.summarize() {
+                let symbols = object_symbols(archive.extract(name, &file)?)?;
+                objects.push((format!("{}({})", input, name), Some(input), symbols));
+            }
+        } else {
+            objects.push((input.clone(), None, object_symbols(&file)?));
+        }
+    }
+    // Like the linker, a global definition wins over a weak one and otherwise the first.
+    let mut definitions = BTreeMap::new();
+    for (idx, (_, _, (defined, _))) in objects.iter().enumerate() {
+        for (name, weak) in defined {
+            let entry = definitions.entry(name.as_str()).or_insert((idx, *weak));
+            if entry.1 && !weak {
+                *entry = (idx, false);
+            }
+        }
+    }
+
+    println!("digraph symbols {{");
+    println!("  rankdir=LR;");
+    println!("  node [shape=box];");
+    let mut archives = BTreeMap::<_, Vec<_>>::new();
+    for (node, archive, _) in &objects {
+        match archive {
+            Some(archive) => archives.entry(*archive).or_default().push(node),
+            None => println!("  {};", dot_string(node)),
+        }
+    }
+    for (idx, (archive, members)) in archives.iter().enumerate() {
+        println!("  subgraph cluster_{} {{", idx);
+        println!("    label={};", dot_string(archive));
+        for member in members {
+            println!("    {};", dot_string(member));
+        }
+        println!("  }}");
+    }
+    let mut unresolved = false;
+    for (node, _, (_, undefined)) in &objects {
+        let mut edges = BTreeMap::<_, Vec<_>>::new();
+        for name in undefined {
+            let target = definitions.get(name.as_str()).map(|(idx, _)| *idx);
+            edges.entry(target).or_default().push(name.as_str());
+        }
+        for (target, mut symbols) in edges {
+            let target = match target {
+                Some(idx) => &objects[idx].0,
+                None => {
+                    unresolved = true;
+                    "[undefined]"
+                }
+            };
+            // Long labels make the graph unreadable, the count says how many are left out.
+            let more = symbols.len().saturating_sub(5);
+            symbols.truncate(5);
+            let mut label = symbols.join("\n");
+            if more > 0 {
+                label.push_str(&format!("\n({} more)", more));
+            }
+            println!(
+                "  {} -> {} [label={}];",
+                dot_string(node),
+                dot_string(target),
+                dot_string(&label)
+            );
+        }
+    }
+    if unresolved {
+        println!("  {} [style=dashed];", dot_string("[undefined]"));
+    }
+    println!("}}");
+    Ok(())
+}
+
+type Slice<'a> = gimli::EndianSlice<'a, gimli::RunTimeEndian>;
+
+// The name of a DWARF register of the machine, or its number.
+fn register_name(elf: &goblin::elf::Elf, register: gimli::Register) -> String {
+    use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_RISCV, EM_X86_64};
+    let name = match elf.header.e_machine {
+        EM_X86_64 => gimli::X86_64::register_name(register),
+        EM_386 => gimli::X86::register_name(register),
+        EM_AARCH64 => gimli::AArch64::register_name(register),
+        EM_ARM => gimli::Arm::register_name(register),
+        EM_RISCV => gimli::RiscV::register_name(register),
+        _ => None,
+    };
+    name.map_or_else(|| format!("r{}", register.0), String::from)
+}
+
+fn format_offset(offset: i64) -> String {
+    if offset < 0 {
+        format!("-{}", offset.unsigned_abs())
+    } else {
+        format!("+{}", offset)
+    }
+}
+
+// The CFA rule and the register rules of a row of an unwind table.
+fn format_row(elf: &goblin::elf::Elf, row: &gimli::UnwindTableRow<Slice>) -> String {
+    use gimli::RegisterRule::*;
+    let mut rules = vec![match row.cfa() {
+        gimli::CfaRule::RegisterAndOffset { register, offset } => {
+            format!(
+                "CFA={}{}",
+                register_name(elf, *register),
+                format_offset(*offset)
+            )
+        }
+        gimli::CfaRule::Expression(_) => String::from("CFA=exp"),
+    }];
+    for (register, rule) in row.registers() {
+        let rule = match rule {
+            Undefined => String::from("u"),
+            SameValue => String::from("s"),
+            Offset(offset) => format!("[CFA{}]", format_offset(*offset)),
+            ValOffset(offset) => format!("CFA{}", format_offset(*offset)),
+            Register(other) => register_name(elf, *other),
+            Expression(_) => String::from("exp"),
+            ValExpression(_) => String::from("vexp"),
+            Constant(value) => format!("{:#x}", value),
+            _ => String::from("arch"),
+        };
+        rules.push(format!("{}={}", register_name(elf, *register), rule));
+    }
+    rules.join(" ")
+}
+
+// Print the entries of .eh_frame and return the problems with it and .eh_frame_hdr.
+fn print_eh_frame(elf: &goblin::elf::Elf, buf: &[u8]) -> Vec<String> {
+    use gimli::UnwindSection;
+    let mut problems = Vec::new();
+    let find = |name| (0..elf.section_headers.len()).find(|idx| section_name(elf, *idx) == name);
+    let idx = match find(".eh_frame") {
+        Some(idx) => idx,
+        None => {
+            println!("There is no .eh_frame section in this file.");
+            return problems;
+        }
+    };
+    let endian = if elf.little_endian {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let address = |name| find(name).map(|idx| elf.section_headers[idx].sh_addr);
+    let mut bases = gimli::BaseAddresses::default().set_eh_frame(elf.section_headers[idx].sh_addr);
+    if let Some(text) = address(".text") {
+        bases = bases.set_text(text);
+    }
+    if let Some(got) = address(".got") {
+        bases = bases.set_got(got);
+    }
+    if let Some(hdr) = address(".eh_frame_hdr") {
+        bases = bases.set_eh_frame_hdr(hdr);
+    }
+    let address_size = if elf.is_64 { 8 } else { 4 };
+    let mut eh_frame = gimli::EhFrame::new(section_contents(elf, buf, idx).unwrap_or(&[]), endian);
+    eh_frame.set_address_size(address_size);
+
+    println!("Contents of the .eh_frame section:");
+    let mut fdes = Vec::new();
+    let mut ctx = gimli::UnwindContext::new();
+    let mut entries = eh_frame.entries(&bases);
+    loop {
+        let entry = match entries.next() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(err) => {
+                problems.push(format!(
+                    "cannot parse the entry after the last one: {}",
+                    err
+                ));
+                break;
+            }
+        };
+        println!();
+        let partial = match entry {
+            gimli::CieOrFde::Cie(cie) => {
+                let mut augmentation = Vec::new();
+                if let Some(encoding) = cie.fde_address_encoding() {
+                    augmentation.push(format!("fde_encoding={:#x}", encoding.0));
+                }
+                if let Some(encoding) = cie.lsda_encoding() {
+                    augmentation.push(format!("lsda_encoding={:#x}", encoding.0));
+                }
+                if let Some(personality) = cie.personality() {
+                    augmentation.push(format!("personality={:#x}", personality.pointer()));
+                }
+                if cie.is_signal_trampoline() {
+                    augmentation.push(String::from("signal_frame"));
+                }
+                augmentation.insert(
+                    0,
+                    format!(
+                        "{:08x} CIE version {} code_align {} data_align {} ra {}",
+                        cie.offset(),
+                        cie.version(),
+                        cie.code_alignment_factor(),
+                        cie.data_alignment_factor(),
+                        register_name(elf, cie.return_address_register())
+                    ),
+                );
+                println!("{}", augmentation.join(" "));
+                continue;
+            }
+            gimli::CieOrFde::Fde(partial) => partial,
+        };
+        let offset = partial.offset();
+        let fde =
+            match partial.parse(|section, bases, offset| section.cie_from_offset(bases, offset)) {
+                Ok(fde) => fde,
+                Err(err) => {
+                    problems.push(format!("FDE at {:#x} cannot be parsed: {}", offset, err));
+                    continue;
+                }
+            };
+        let (start, end) = (
+            fde.initial_address(),
+            fde.initial_address().wrapping_add(fde.len()),
+        );
+        println!(
+            "{:08x} FDE cie={:08x} pc={:016x}..{:016x}",
+            offset,
+            fde.cie().offset(),
+            start,
+            end
+        );
+        let mut rows = match fde.rows(&eh_frame, &bases, &mut ctx) {
+            Ok(rows) => rows,
+            Err(err) => {
+                problems.push(format!(
+                    "FDE at {:#x} has invalid instructions: {}",
+                    offset, err
+                ));
+                continue;
+            }
+        };
+        loop {
+            match rows.next_row() {
+                Ok(Some(row)) => println!("  {:#x}: {}", row.start_address(), format_row(elf, row)),
+                Ok(None) => break,
+                Err(err) => {
+                    problems.push(format!(
+                        "FDE at {:#x} has invalid instructions: {}",
+                        offset, err
+                    ));
+                    break;
+                }
+            }
+        }
+        fdes.push((start, end, offset));
+    }
+
+    // Objects have no addresses yet, only linked files can be checked against sections.
+    if elf.header.e_type != goblin::elf::header::ET_REL {
+        let code = |start: u64, end: u64| {
+            elf.section_headers.iter().any(|sec| {
+                sec.is_executable() && start >= sec.sh_addr && end <= sec.sh_addr + sec.sh_size
+            })
+        };
+        for (start, end, offset) in &fdes {
+            if !code(*start, *end) {
+                problems.push(format!(
+                    "FDE at {:#x} covers {:#x}..{:#x}, which is not inside a code section",
+                    offset, start, end
+                ));
+            }
+        }
+        let mut sorted = fdes.clone();
+        sorted.sort();
+        for pair in sorted.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                problems.push(format!(
+                    "FDE at {:#x} overlaps the FDE at {:#x}",
+                    pair[1].2, pair[0].2
+                ));
+            }
+        }
+        if let Some(hdr_idx) = find(".eh_frame_hdr") {
+            let hdr =
+                gimli::EhFrameHdr::new(section_contents(elf, buf, hdr_idx).unwrap_or(&[]), endian);
+            problems.extend(check_eh_frame_hdr(
+                &hdr,
+                &eh_frame,
+                &bases,
+                address_size,
+                &fdes,
+            ));
+        }
+    }
+    problems
+}
+
+// The problems of the .eh_frame_hdr binary search table: it has to point to .eh_frame and
+// list every FDE by initial address in order.
+fn check_eh_frame_hdr(
+    hdr: &gimli::EhFrameHdr<Slice>,
+    eh_frame: &gimli::EhFrame<Slice>,
+    bases: &gimli::BaseAddresses,
+    address_size: u8,
+    fdes: &[(u64, u64, usize)],
+) -> Vec<String> {
+    use gimli::UnwindSection;
+    let mut problems = Vec::new();
+    let hdr = match hdr.parse(bases, address_size) {
+        Ok(hdr) => hdr,
+        Err(err) => return vec![format!(".eh_frame_hdr cannot be parsed: {}", err)],
+    };
+    if hdr.eh_frame_ptr().pointer() != bases.eh_frame.section.unwrap_or(0) {
+        problems.push(format!(
+            ".eh_frame_hdr points to {:#x} instead of .eh_frame",
+            hdr.eh_frame_ptr().pointer()
+        ));
+    }
+    let table = match hdr.table() {
+        Some(table) => table,
+        None => return problems,
+    };
+    let mut entries = Vec::new();
+    let mut iter = table.iter(bases);
+    loop {
+        match iter.next() {
+            Ok(Some((initial, fde))) => entries.push((initial.pointer(), fde)),
+            Ok(None) => break,
+            Err(err) => {
+                problems.push(format!(".eh_frame_hdr table cannot be parsed: {}", err));
+                return problems;
+            }
+        }
+    }
+    if entries.len() != fdes.len() {
+        problems.push(format!(
+            ".eh_frame_hdr lists {} FDEs but .eh_frame has {}",
+            entries.len(),
+            fdes.len()
+        ));
+    }
+    if entries.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+        problems.push(String::from(".eh_frame_hdr table is not sorted"));
+    }
+    for (initial, pointer) in entries {
+        let fde = table.pointer_to_offset(pointer).and_then(|offset| {
+            eh_frame.fde_from_offset(bases, offset, |section, bases, offset| {
+                section.cie_from_offset(bases, offset)
+            })
+        });
+        match fde {
+            Ok(fde) if fde.initial_address() == initial => {}
+            Ok(fde) => problems.push(format!(
+                ".eh_frame_hdr entry for {:#x} points to the FDE for {:#x}",
+                initial,
+                fde.initial_address()
+            )),
+            Err(err) => problems.push(format!(
+                ".eh_frame_hdr entry for {:#x} does not point to an FDE: {}",
+                initial, err
+            )),
+        }
+    }
+    problems
+}
+
+// A UTC date like `2021-03-04 12:34:56`, from Howard Hinnant's days_from_civil inverse.
+fn format_date(seconds: u64) -> String {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// The permissions of an octal mode like `ls -l` shows them.
+fn format_mode(mode: u32) -> String {
+    (0..9)
+        .map(|bit| {
+            if mode & (0o400 >> bit) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect()
+}
+
+fn print_archive(archive: &goblin::archive::Archive, buf: &[u8]) {
+    use goblin::archive::SIZEOF_HEADER;
+    let summary = archive.summarize();
+    println!("Archive contains {} members:", summary.len());
+    println!("  Mode      Owner/Group       Size Date                Name");
+    for (name, member, _) in &summary {
+        // goblin only parses the name and size of the header, the other fields are decimal
+        // apart from the octal mode.
+        let start = usize::try_from(member.header_offset).unwrap();
+        let header = buf.get(start..start + SIZEOF_HEADER).unwrap_or(&[]);
+        let field = |range: std::ops::Range<usize>, radix| {
+            header
+                .get(range)
+                .and_then(|field| std::str::from_utf8(field).ok())
+                .and_then(|field| u64::from_str_radix(field.trim(), radix).ok())
+                .unwrap_or(0)
+        };
+        println!(
+            "  {} {:>5}/{:<5} {:>10} {} {}",
+            format_mode(u32::try_from(field(40..48, 8)).unwrap_or(0)),
+            field(28..34, 10),
+            field(34..40, 10),
+            member.size(),
+            format_date(field(16..28, 10)),
+            name
+        );
+    }
+    let symbols: usize = summary.iter().map(|(_, _, symbols)| symbols.len()).sum();
+    println!();
+    println!("Archive index contains {} symbols:", symbols);
+    for (name, _, symbols) in summary.iter().filter(|(_, _, symbols)| !symbols.is_empty()) {
+        println!("  {}:", name);
+        for symbol in symbols {
+            println!("    {}", symbol);
+        }
+    }
+}
+
+fn main() {
     let opts = Opts::parse();
-    let buf = fs::read(opts.input)?;
-    let elf = goblin::elf::Elf::parse(&buf)?;
-    println!("{:#?}", elf);
+    // Quit quietly instead of panicking when the pager or `head` stops reading.
+    unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+    if io::stdout().is_terminal() {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        COLOR.store(!opts.no_color && !no_color, Ordering::Relaxed);
+        if !opts.no_pager {
+            start_pager();
+        }
+    }
+    if let Err(err) = run(opts) {
+        eprintln!("Error: {:?}", err);
+        exit(1);
+    }
+    exit(0);
+}
+
+fn run(mut opts: Opts) -> Result<(), error::Error> {
+    if opts.all {
+        opts.file_header = true;
+        opts.section_headers = true;
+        opts.program_headers = true;
+        opts.symbols = true;
+        opts.relocations = true;
+        opts.dynamic = true;
+        opts.notes = true;
+        opts.versions = true;
+    }
+    match &opts.command {
+        Some(Command::Diff { old, new }) => {
+            if print_diff(old, new)? {
+                exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Graph { inputs }) => return print_graph(inputs),
+        None => {}
+    }
+    let file = match &opts.input {
+        Some(input) => fs::read(input)?,
+        None => {
+            eprintln!("dump: no input file");
+            exit(1);
+        }
+    };
+    let any = opts.file_header
+        || opts.section_headers
+        || opts.program_headers
+        || opts.symbols
+        || opts.relocations
+        || opts.dynamic
+        || opts.needed
+        || opts.tree
+        || opts.versions
+        || opts.checksec
+        || opts.notes
+        || !opts.hex_dump.is_empty()
+        || !opts.string_dump.is_empty()
+        || !opts.extract.is_empty()
+        || opts.disassemble.is_some()
+        || opts.eh_frame
+        || opts.dwarf
+        || opts.addr2line.is_some()
+        || opts.size
+        || opts.verify
+        || opts.debug;
+    let buf = if file.starts_with(goblin::archive::MAGIC) {
+        let archive = goblin::archive::Archive::parse(&file)?;
+        match &opts.member {
+            Some(member) if archive.get(member).is_some() => archive.extract(member, &file)?,
+            Some(member) => {
+                eprintln!("dump: the archive has no member {}", member);
+                exit(1);
+            }
+            None if any => {
+                eprintln!("dump: the input is an archive, pick a member with --member");
+                exit(1);
+            }
+            None => {
+                print_archive(&archive, &file);
+                return Ok(());
+            }
+        }
+    } else if opts.member.is_some() {
+        eprintln!("dump: --member needs an archive");
+        exit(1);
+    } else {
+        &file
+    };
+    let elf = goblin::elf::Elf::parse(buf)?;
+    // The outputs are separated by empty lines.
+    let mut first = true;
+    let mut separate = || {
+        if !first {
+            println!();
+        }
+        first = false;
+    };
+    if opts.file_header || !any {
+        separate();
+        print_file_header(&elf);
+    }
+    if opts.section_headers {
+        separate();
+        print_section_headers(&elf);
+    }
+    if opts.program_headers {
+        separate();
+        print_program_headers(&elf);
+    }
+    if opts.symbols {
+        separate();
+        if !elf.dynsyms.is_empty() {
+            print_symbols(&opts, ".dynsym", &elf.dynsyms, &elf.dynstrtab);
+            println!();
+        }
+        print_symbols(&opts, ".symtab", &elf.syms, &elf.strtab);
+    }
+    if opts.relocations {
+        separate();
+        print_relocations(&opts, &elf);
+    }
+    if opts.dynamic {
+        separate();
+        print_dynamic(&elf);
+    }
+    if opts.notes {
+        separate();
+        print_notes(&elf, buf)?;
+    }
+    if opts.versions {
+        separate();
+        print_versions(&elf, buf);
+    }
+    if opts.needed {
+        separate();
+        for library in &elf.libraries {
+            println!("{}", library);
+        }
+    }
+    if opts.checksec {
+        separate();
+        print_checksec(&elf);
+    }
+    if opts.tree {
+        separate();
+        let path = Path::new(opts.input.as_deref().unwrap_or(""));
+        println!("{}", path.display());
+        if let Some(interpreter) = elf.interpreter {
+            println!("  interpreter {}", interpreter);
+        }
+        print_tree(&elf, path, 1, &mut Vec::new(), &mut BTreeSet::new());
+    }
+    let dumps = opts.hex_dump.iter().map(|section| (section, true));
+    for (section, hex) in dumps.chain(opts.string_dump.iter().map(|section| (section, false))) {
+        separate();
+        match find_section(&elf, section) {
+            Some(idx) if hex => print_hex_dump(&elf, buf, idx),
+            Some(idx) => print_string_dump(&elf, buf, idx),
+            None => {
+                eprintln!("dump: there is no section {}", section);
+                exit(1);
+            }
+        }
+    }
+    for extract in &opts.extract {
+        match find_section(&elf, &extract.section) {
+            // Sections without contents in the file, like .bss, give an empty file.
+            Some(idx) => fs::write(
+                &extract.path,
+                section_contents(&elf, buf, idx).unwrap_or(&[]),
+            )?,
+            None => {
+                eprintln!("dump: there is no section {}", extract.section);
+                exit(1);
+            }
+        }
+    }
+    if let Some(symbol) = &opts.disassemble {
+        separate();
+        print_disassembly(&opts, &elf, buf, symbol.as_deref());
+    }
+    if opts.eh_frame {
+        separate();
+        let problems = print_eh_frame(&elf, buf);
+        if !problems.is_empty() {
+            println!();
+            for problem in &problems {
+                println!("{}", paint(problem, Color::Red));
+            }
+            exit(1);
+        }
+    }
+    if opts.dwarf || opts.addr2line.is_some() {
+        separate();
+        print_dwarf(&opts, &elf, buf)
+            .map_err(|err| error::Error::Malformed(format!("Invalid DWARF: {}", err)))?;
+    }
+    if opts.size {
+        separate();
+        print_sizes(&opts, &elf, buf);
+    }
+    if opts.verify {
+        let problems = verify(&elf, buf);
+        if !problems.is_empty() {
+            separate();
+        }
+        for problem in &problems {
+            println!("{}", paint(problem, Color::Red));
+        }
+        if !problems.is_empty() {
+            exit(1);
+        }
+    }
+    if opts.debug {
+        separate();
+        println!("{:#?}", elf);
+    }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,231 @@
+//! The buffer the output is written to. For an output file it maps the file into memory
+//! so that the linker writes the output only once and never holds a second copy of it.
+
+use crate::error::LinkError;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+/// The contents of the output, either in memory or mapped from the file it is written
+/// to. It dereferences to the bytes either way.
+pub struct OutputBuffer(Storage);
+
+enum Storage {
+    Memory(Vec<u8>),
+    Mapped(MappedFile),
+}
+
+// A temporary file next to the output that replaces it once the link succeeded.
+struct MappedFile {
+    file: fs::File,
+    tmp_path: String,
+    committed: bool,
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is owned like the contents of a Vec.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl OutputBuffer {
+    // A zeroed buffer of `len` bytes that ends up in the file at `path`, or only in memory
+    // if there is none.
+    pub(crate) fn new(path: Option<&str>, len: usize) -> Result<Self, LinkError> {
+        match path {
+            Some(path) => Ok(OutputBuffer(Storage::Mapped(
+                MappedFile::create(path, len).map_err(|err| LinkError::io(path, err))?,
+            ))),
+            None => {
+                let mut contents = Vec::new();
+                reserve(&mut contents, len)?;
+                contents.resize(len, 0);
+                Ok(OutputBuffer::from(contents))
+            }
+        }
+    }
+    // Grow or shrink the buffer to `len` bytes, new bytes are zero.
+    pub(crate) fn resize(&mut self, len: usize) -> Result<(), LinkError> {
+        match &mut self.0 {
+            Storage::Memory(contents) => contents.resize(len, 0),
+            Storage::Mapped(file) => file
+                .resize(len)
+                .map_err(|err| LinkError::io(&file.tmp_path, err))?,
+        }
+        Ok(())
+    }
+    /// The contents as a Vec, which copies them if they are mapped from the output file.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            Storage::Memory(contents) => contents,
+            Storage::Mapped(ref file) => file.to_vec(),
+        }
+    }
+    // Make the output file at `path` contain the buffer. A mapped buffer has been written
+    // there already and only needs to replace the previous output.
+    pub(crate) fn write_to(&mut self, path: &str, executable: bool) -> io::Result<()> {
+        match &mut self.0 {
+            Storage::Memory(contents) => crate::write_file(path, contents, executable),
+            Storage::Mapped(file) => file.commit(path, executable),
+        }
+    }
+}
+
+// Make room for `additional` more bytes in `buf`. The sizes come from the inputs, which
+// can claim more than there is memory.
+pub(crate) fn reserve(buf: &mut Vec<u8>, additional: usize) -> Result<(), LinkError> {
+    buf.try_reserve(additional).map_err(|_| {
+        LinkError::overflow(format!(
+            "An output of {} bytes does not fit in memory",
+            buf.len().saturating_add(additional)
+        ))
+    })
+}
+
+impl From<Vec<u8>> for OutputBuffer {
+    fn from(contents: Vec<u8>) -> Self {
+        OutputBuffer(Storage::Memory(contents))
+    }
+}
+
+impl Deref for OutputBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Storage::Memory(contents) => contents,
+            Storage::Mapped(file) => file,
+        }
+    }
+}
+
+impl DerefMut for OutputBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.0 {
+            Storage::Memory(contents) => contents,
+            Storage::Mapped(file) => file,
+        }
+    }
+}
+
+impl fmt::Debug for OutputBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Storage::Memory(contents) => f.debug_tuple("Memory").field(contents).finish(),
+            Storage::Mapped(file) => f
+                .debug_struct("Mapped")
+                .field("path", &file.tmp_path)
+                .field("len", &file.len)
+                .finish(),
+        }
+    }
+}
+
+impl MappedFile {
+    fn create(path: &str, len: usize) -> io::Result<Self> {
+        let tmp_path = format!("{}.tmp", path);
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        let mut mapped = MappedFile {
+            file,
+            tmp_path,
+            committed: false,
+            ptr: std::ptr::null_mut(),
+            len: 0,
+        };
+        // Dropping it removes the file again if this fails.
+        mapped.resize(len)?;
+        Ok(mapped)
+    }
+    // Resize the file and map all of it again. The file reads as zeros until the linker
+    // writes to it.
+    fn resize(&mut self, len: usize) -> io::Result<()> {
+        self.unmap();
+        self.file.set_len(u64::try_from(len).unwrap())?;
+        self.map(len, libc::MAP_SHARED)
+    }
+    fn map(&mut self, len: usize, flags: libc::c_int) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        if len == 0 {
+            return Ok(());
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        self.ptr = ptr.cast();
+        self.len = len;
+        Ok(())
+    }
+    fn unmap(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr.cast(), self.len);
+            }
+        }
+        self.ptr = std::ptr::null_mut();
+        self.len = 0;
+    }
+    fn commit(&mut self, path: &str, executable: bool) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        if self.len > 0 && unsafe { libc::msync(self.ptr.cast(), self.len, libc::MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if executable {
+            let mut permissions = self.file.metadata()?.permissions();
+            permissions.set_mode(0o755);
+            self.file.set_permissions(permissions)?;
+        }
+        fs::rename(&self.tmp_path, path)?;
+        self.committed = true;
+        // A file that is open for writing cannot be executed, so only keep a copy-on-write
+        // mapping of the finished output.
+        let len = self.len;
+        self.unmap();
+        self.file = fs::File::open(path)?;
+        self.map(len, libc::MAP_PRIVATE)
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for MappedFile {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        self.unmap();
+        // A failed link leaves the previous output intact.
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
@@ -0,0 +1,2417 @@
+//! Writing the ELF headers, section headers, symbol tables, notes and the other output
+//! formats, and the link map.
+
+use crate::buffer::OutputBuffer;
+use crate::error::LinkError;
+use crate::input::{Input, InputSection, RelocationSection};
+use crate::layout::{
+    is_reserved_shndx, section_index, section_key, MergedSection, Output, OutputSection, Placement,
+    SegmentInfo,
+};
+use crate::resolve::SymbolTable;
+use crate::script::wildcard_match;
+use crate::{
+    align, BindPolicy, BuildId, InputLayout, Layout, Opts, SectionLayout, SegmentLayout,
+    SyntheticSection,
+};
+use goblin::container::Ctx;
+use goblin::elf::dynamic::Dyn;
+use goblin::elf::{Header, ProgramHeader, SectionHeader};
+use scroll::ctx::SizeWith;
+use scroll::Pwrite;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::prelude::*;
+
+// The segments with their contents at their address, without the uninitialized part.
+// Gaps between them are zero. There is nothing in front of the lowest one.
+pub(crate) fn binary_image(segments: &[(usize, &[u8])]) -> Vec<u8> {
+    let start = match segments.iter().map(|(address, _)| *address).min() {
+        Some(start) => start,
+        None => return vec![],
+    };
+    let end = segments
+        .iter()
+        .map(|(address, data)| address + data.len())
+        .max()
+        .unwrap();
+    let mut image = vec![0; end - start];
+    for (address, data) in segments {
+        image[address - start..address - start + data.len()].copy_from_slice(data);
+    }
+    image
+}
+
+// One Intel HEX record, the checksum makes the sum of all its bytes zero.
+fn ihex_record(out: &mut String, address: u16, record_type: u8, data: &[u8]) {
+    use std::fmt::Write;
+    let mut bytes = vec![u8::try_from(data.len()).unwrap()];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+    out.push(':');
+    for byte in bytes {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+// The segments as Intel HEX data records of up to 16 bytes. Extended linear address
+// records give the upper half of the 32-bit addresses whenever it changes.
+pub(crate) fn ihex_image(segments: &[(usize, &[u8])], entry: usize) -> Result<Vec<u8>, LinkError> {
+    const DATA: u8 = 0;
+    const END_OF_FILE: u8 = 1;
+    const EXTENDED_LINEAR_ADDRESS: u8 = 4;
+    const START_LINEAR_ADDRESS: u8 = 5;
+    let mut out = String::new();
+    let mut upper = None;
+    for (start, data) in segments {
+        let mut offset = 0;
+        while offset < data.len() {
+            let address = match u32::try_from(start + offset) {
+                Ok(address) => address,
+                Err(_) => {
+                    return Err(LinkError::overflow(format!(
+                        "Address {:#x} does not fit into Intel HEX",
+                        start + offset
+                    )))
+                }
+            };
+            let address_upper = u16::try_from(address >> 16).unwrap();
+            if upper != Some(address_upper) {
+                ihex_record(
+                    &mut out,
+                    0,
+                    EXTENDED_LINEAR_ADDRESS,
+                    &address_upper.to_be_bytes(),
+                );
+                upper = Some(address_upper);
+            }
+            // A record must not cross into the next 64KiB.
+            let lower = usize::try_from(address & 0xffff).unwrap();
+            let len = (data.len() - offset).min(16).min(0x10000 - lower);
+            let lower = u16::try_from(lower).unwrap();
+            ihex_record(&mut out, lower, DATA, &data[offset..offset + len]);
+            offset += len;
+        }
+    }
+    let entry = u32::try_from(entry).unwrap();
+    ihex_record(&mut out, 0, START_LINEAR_ADDRESS, &entry.to_be_bytes());
+    ihex_record(&mut out, 0, END_OF_FILE, &[]);
+    Ok(out.into_bytes())
+}
+
+// One S-record with an address of `address_len` bytes, the checksum is the complement of
+// the sum of the other bytes after the type.
+fn srec_record(out: &mut String, record_type: u8, address: u32, address_len: usize, data: &[u8]) {
+    use std::fmt::Write;
+    let mut bytes = vec![u8::try_from(address_len + data.len() + 1).unwrap()];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - address_len..]);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(!sum);
+    write!(out, "S{}", record_type).unwrap();
+    for byte in bytes {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+// The segments as S-records of up to 16 bytes. All data records use the shortest
+// address that fits every address: S1 for 16 bits, S2 for 24 bits and S3 for 32 bits,
+// the record with the entry point matches.
+pub(crate) fn srec_image(segments: &[(usize, &[u8])], entry: usize) -> Result<Vec<u8>, LinkError> {
+    let end = segments
+        .iter()
+        .map(|(address, data)| address + data.len())
+        .chain(std::iter::once(entry + 1))
+        .max()
+        .unwrap();
+    let (data_type, address_len) = match end - 1 {
+        0..=0xffff => (1, 2),
+        0x1_0000..=0xff_ffff => (2, 3),
+        0x100_0000..=0xffff_ffff => (3, 4),
+        _ => {
+            return Err(LinkError::overflow(format!(
+                "Address {:#x} does not fit into an S-record",
+                end - 1
+            )))
+        }
+    };
+    let mut out = String::new();
+    srec_record(&mut out, 0, 0, 2, &[]);
+    let mut count = 0;
+    for (start, data) in segments {
+        for (i, chunk) in data.chunks(16).enumerate() {
+            let address = u32::try_from(start + 16 * i).unwrap();
+            srec_record(&mut out, data_type, address, address_len, chunk);
+            count += 1;
+        }
+    }
+    // The count record is optional and only has room for 16 bits.
+    if let Ok(count) = u16::try_from(count) {
+        srec_record(&mut out, 5, u32::from(count), 2, &[]);
+    }
+    let entry = u32::try_from(entry).unwrap();
+    srec_record(&mut out, 10 - data_type, entry, address_len, &[]);
+    Ok(out.into_bytes())
+}
+
+/// The `.note.gnu.build-id` note identifying the output for debuggers and crash tools.
+#[derive(Debug)]
+pub(crate) struct BuildIdNote {
+    pub(crate) address: usize,
+    kind: BuildId,
+}
+
+pub(crate) const NT_GNU_BUILD_ID: u32 = 3;
+
+impl BuildIdNote {
+    pub(crate) fn new(opts: &Opts, offset: &mut usize) -> Option<Self> {
+        let kind = opts.build_id.clone()?.unwrap_or(BuildId::Sha1);
+        *offset = align(*offset, 4);
+        let note = BuildIdNote {
+            address: *offset,
+            kind,
+        };
+        *offset += note.size();
+        Some(note)
+    }
+    fn id_size(&self) -> usize {
+        match &self.kind {
+            BuildId::Sha1 => 20,
+            BuildId::Md5 | BuildId::Uuid => 16,
+            BuildId::Hex(id) => id.len(),
+        }
+    }
+    // The header, the name and the identifier padded to 4 bytes
+    pub(crate) fn size(&self) -> usize {
+        16 + align(self.id_size(), 4)
+    }
+    fn write_header(&self, buf: &mut [u8], base: usize, ctx: Ctx) -> Result<(), LinkError> {
+        let mut offset = self.address - base;
+        buf.gwrite_with(4u32, &mut offset, ctx.le)?;
+        buf.gwrite_with(u32::try_from(self.id_size()).unwrap(), &mut offset, ctx.le)?;
+        buf.gwrite_with(NT_GNU_BUILD_ID, &mut offset, ctx.le)?;
+        buf.gwrite_with(&b"GNU\0"[..], &mut offset, ())?;
+        Ok(())
+    }
+    // Hashes cover the whole file with the identifier still zero, so this comes last.
+    pub(crate) fn write_id(&self, buf: &mut [u8], base: usize) -> Result<(), LinkError> {
+        let id = match &self.kind {
+            BuildId::Sha1 => sha1(buf).to_vec(),
+            BuildId::Md5 => md5(buf).to_vec(),
+            BuildId::Uuid if std::env::var_os("SOURCE_DATE_EPOCH").is_some() => {
+                // A name based version 3 UUID with the contents as the name
+                let mut uuid = md5(buf);
+                uuid[6] = (uuid[6] & 0x0f) | 0x30;
+                uuid[8] = (uuid[8] & 0x3f) | 0x80;
+                uuid.to_vec()
+            }
+            BuildId::Uuid => {
+                let mut uuid = [0; 16];
+                fs::File::open("/dev/urandom")
+                    .and_then(|mut file| file.read_exact(&mut uuid))
+                    .map_err(|err| LinkError::io("/dev/urandom", err))?;
+                // A random version 4 UUID
+                uuid[6] = (uuid[6] & 0x0f) | 0x40;
+                uuid[8] = (uuid[8] & 0x3f) | 0x80;
+                uuid.to_vec()
+            }
+            BuildId::Hex(id) => id.clone(),
+        };
+        buf.pwrite_with(&id[..], self.address - base + 16, ())?;
+        Ok(())
+    }
+}
+
+/// The `.note.gnu.property` note with the properties of all inputs combined. Most
+/// properties are feature bits, depending on their type a bit is set in the output if it
+/// is set in all inputs (e.g. IBT and SHSTK support) or in any input (e.g. required ISA
+/// extensions).
+#[derive(Debug)]
+pub(crate) struct GnuPropertyNote {
+    pub(crate) address: usize,
+    pub(crate) ctx: Ctx,
+    // Type and value sorted by type
+    properties: Vec<(u32, u32)>,
+}
+
+pub(crate) const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+
+pub(crate) const PT_GNU_PROPERTY: u32 = 0x6474e553;
+
+// Properties in these ranges are u32 bitmasks combined with AND or OR.
+const GNU_PROPERTY_UINT32_AND: std::ops::Range<u32> = 0xb0000000..0xb0008000;
+
+const GNU_PROPERTY_UINT32_OR: std::ops::Range<u32> = 0xb0008000..0xb0010000;
+
+const GNU_PROPERTY_X86_UINT32_AND: std::ops::Range<u32> = 0xc0000002..0xc0008000;
+
+const GNU_PROPERTY_X86_UINT32_OR: std::ops::Range<u32> = 0xc0008000..0xc0010000;
+
+fn is_and_property(pr_type: u32) -> bool {
+    GNU_PROPERTY_UINT32_AND.contains(&pr_type) || GNU_PROPERTY_X86_UINT32_AND.contains(&pr_type)
+}
+
+fn is_or_property(pr_type: u32) -> bool {
+    GNU_PROPERTY_UINT32_OR.contains(&pr_type) || GNU_PROPERTY_X86_UINT32_OR.contains(&pr_type)
+}
+
+// The `size` bytes at `start` of a .note.gnu.property, which may claim more than is there.
+fn note_part(contents: &[u8], start: usize, size: usize) -> Result<&[u8], LinkError> {
+    start
+        .checked_add(size)
+        .and_then(|end| contents.get(start..end))
+        .ok_or_else(|| LinkError::malformed("", "Note in .note.gnu.property is truncated"))
+}
+
+// The u32 properties of each NT_GNU_PROPERTY_TYPE_0 note in a section.
+fn parse_gnu_properties(contents: &[u8], ctx: Ctx) -> Result<Vec<Vec<(u32, u32)>>, LinkError> {
+    use scroll::Pread;
+    let mut notes = Vec::new();
+    let mut offset = 0;
+    while offset < contents.len() {
+        let namesz = usize::try_from(contents.pread_with::<u32>(offset, ctx.le)?).unwrap();
+        let descsz = usize::try_from(contents.pread_with::<u32>(offset + 4, ctx.le)?).unwrap();
+        let n_type = contents.pread_with::<u32>(offset + 8, ctx.le)?;
+        let name = note_part(contents, offset + 12, namesz)?;
+        let desc_start = offset + 12 + align(namesz, 4);
+        let desc = note_part(contents, desc_start, descsz)?;
+        offset = desc_start + align(descsz, ctx.size());
+        if n_type != NT_GNU_PROPERTY_TYPE_0 || name != b"GNU\0" {
+            continue;
+        }
+        let mut properties = Vec::new();
+        let mut pr_offset = 0;
+        while pr_offset < desc.len() {
+            let pr_type = desc.pread_with::<u32>(pr_offset, ctx.le)?;
+            let pr_datasz =
+                usize::try_from(desc.pread_with::<u32>(pr_offset + 4, ctx.le)?).unwrap();
+            let data = note_part(desc, pr_offset + 8, pr_datasz)?;
+            if pr_datasz == 4 {
+                properties.push((pr_type, data.pread_with::<u32>(0, ctx.le)?));
+            }
+            pr_offset += 8 + align(pr_datasz, ctx.size());
+        }
+        notes.push(properties);
+    }
+    Ok(notes)
+}
+
+impl GnuPropertyNote {
+    pub(crate) fn new(
+        sections: &[InputSection],
+        file_buffers: &[&[u8]],
+        file_names: &[String],
+        offset: &mut usize,
+        ctx: Ctx,
+    ) -> Result<Option<Self>, LinkError> {
+        let mut notes = vec![Vec::new(); file_buffers.len()];
+        for sec in sections {
+            let start = usize::try_from(sec.section.sh_offset).unwrap();
+            let size = usize::try_from(sec.section.sh_size).unwrap();
+            let contents = &file_buffers[sec.file_idx][start..start + size];
+            let mut file_notes = parse_gnu_properties(contents, ctx)
+                .map_err(|err| err.in_file(&file_names[sec.file_idx]))?
+                .into_iter();
+            // The first note replaces the empty properties of the file, the notes of a
+            // relocatable link are combined as if they came from separate files.
+            if let Some(first) = file_notes.next() {
+                notes[sec.file_idx] = first;
+            }
+            notes.extend(file_notes);
+        }
+        let mut types: Vec<u32> = notes
+            .iter()
+            .flatten()
+            .map(|(pr_type, _)| *pr_type)
+            .collect();
+        types.sort_unstable();
+        types.dedup();
+        let mut properties = Vec::new();
+        for pr_type in types {
+            let values = notes.iter().map(|properties| {
+                properties
+                    .iter()
+                    .find(|(ty, _)| *ty == pr_type)
+                    .map_or(0, |(_, value)| *value)
+            });
+            // Inputs without the property count as 0.
+            let value = if is_and_property(pr_type) {
+                values.fold(!0, |acc, value| acc & value)
+            } else if is_or_property(pr_type) {
+                values.fold(0, |acc, value| acc | value)
+            } else {
+                // We don't know how to combine other properties so they are dropped.
+                continue;
+            };
+            if value != 0 {
+                properties.push((pr_type, value));
+            }
+        }
+        if properties.is_empty() {
+            return Ok(None);
+        }
+        *offset = align(*offset, ctx.size());
+        let note = GnuPropertyNote {
+            address: *offset,
+            ctx,
+            properties,
+        };
+        *offset += note.size();
+        Ok(Some(note))
+    }
+    // The header, the name and each property with its data padded to the word size
+    pub(crate) fn size(&self) -> usize {
+        16 + self.properties.len() * align(12, self.ctx.size())
+    }
+    pub(crate) fn write(&self, buf: &mut [u8], base: usize) -> Result<(), LinkError> {
+        let ctx = self.ctx;
+        let mut offset = self.address - base;
+        buf.gwrite_with(4u32, &mut offset, ctx.le)?;
+        buf.gwrite_with(
+            u32::try_from(self.size() - 16).unwrap(),
+            &mut offset,
+            ctx.le,
+        )?;
+        buf.gwrite_with(NT_GNU_PROPERTY_TYPE_0, &mut offset, ctx.le)?;
+        buf.gwrite_with(&b"GNU\0"[..], &mut offset, ())?;
+        for (pr_type, value) in &self.properties {
+            buf.pwrite_with(*pr_type, offset, ctx.le)?;
+            buf.pwrite_with(4u32, offset + 4, ctx.le)?;
+            buf.pwrite_with(*value, offset + 8, ctx.le)?;
+            offset += align(12, ctx.size());
+        }
+        Ok(())
+    }
+}
+
+// Pad a message to a multiple of 64 bytes ending in its length in bits as used by SHA-1
+// and MD5.
+fn pad_message(data: &[u8], big_endian: bool) -> Vec<u8> {
+    let bits = u64::try_from(data.len()).unwrap() * 8;
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    if big_endian {
+        message.extend_from_slice(&bits.to_be_bytes());
+    } else {
+        message.extend_from_slice(&bits.to_le_bytes());
+    }
+    message
+}
+
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    for chunk in pad_message(data, true).chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [[u32; 4]; 4] = [
+        [7, 12, 17, 22],
+        [5, 9, 14, 20],
+        [4, 11, 16, 23],
+        [6, 10, 15, 21],
+    ];
+    // The integer part of 2^32 * |sin(i + 1)|
+    let k: Vec<u32> = (0..64)
+        .map(|i| (f64::from(i + 1).sin().abs() * 4294967296.0) as u32)
+        .collect();
+    let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in pad_message(data, false).chunks(64) {
+        let m: Vec<u32> = chunk
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16][i % 4]));
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 16];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_le_bytes());
+    }
+    digest
+}
+
+/// The binary search table over the FDEs in .eh_frame used by unwinders.
+#[derive(Debug)]
+pub(crate) struct EhFrameHdr {
+    pub(crate) address: usize,
+    pub(crate) eh_frame_address: usize,
+    pub(crate) eh_frame_size: usize,
+    pub(crate) fde_count: usize,
+}
+
+// DW_EH_PE_* pointer encodings
+const DW_EH_PE_UDATA4: u8 = 0x03;
+
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+// Offsets and CIE pointers of the records in .eh_frame up to the terminator.
+pub(crate) fn eh_frame_records(contents: &[u8], ctx: Ctx) -> Result<Vec<(usize, u32)>, LinkError> {
+    use scroll::Pread;
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= contents.len() {
+        let length = contents.pread_with::<u32>(offset, ctx.le)?;
+        if length == 0 {
+            break;
+        }
+        records.push((offset, contents.pread_with::<u32>(offset + 4, ctx.le)?));
+        offset += 4 + usize::try_from(length).unwrap();
+    }
+    Ok(records)
+}
+
+// Read a pointer encoded as described by a DW_EH_PE_* byte. Addresses are file offsets so
+// pc-relative pointers are relative to the offset they are read from.
+// Read a pointer from `buf` which is mapped at `base`.
+fn read_encoded_pointer(
+    buf: &[u8],
+    offset: &mut usize,
+    base: usize,
+    encoding: u8,
+    ctx: Ctx,
+) -> Result<i64, LinkError> {
+    use scroll::Pread;
+    let address = i64::try_from(base + *offset).unwrap();
+    let value = match encoding & 0x0f {
+        0x00 if ctx.size() == 8 => buf.gread_with::<i64>(offset, ctx.le)?,
+        0x00 => i64::from(buf.gread_with::<u32>(offset, ctx.le)?),
+        0x02 => i64::from(buf.gread_with::<u16>(offset, ctx.le)?),
+        DW_EH_PE_UDATA4 => i64::from(buf.gread_with::<u32>(offset, ctx.le)?),
+        0x04 | 0x0c => buf.gread_with::<i64>(offset, ctx.le)?,
+        0x0a => i64::from(buf.gread_with::<i16>(offset, ctx.le)?),
+        DW_EH_PE_SDATA4 => i64::from(buf.gread_with::<i32>(offset, ctx.le)?),
+        _ => return Err(unsupported_pointer_encoding(encoding)),
+    };
+    match encoding & 0x70 {
+        0x00 => Ok(value),
+        DW_EH_PE_PCREL => Ok(address.wrapping_add(value)),
+        _ => Err(unsupported_pointer_encoding(encoding)),
+    }
+}
+
+fn unsupported_pointer_encoding(encoding: u8) -> LinkError {
+    LinkError::malformed(
+        "",
+        format!("Unsupported pointer encoding {:#x} in .eh_frame", encoding),
+    )
+}
+
+// The encoding of the code pointers in the FDEs using the CIE at `offset`.
+fn cie_pointer_encoding(buf: &[u8], offset: usize, ctx: Ctx) -> Result<u8, LinkError> {
+    use scroll::{Pread, Sleb128, Uleb128};
+    let mut offset = offset + 8;
+    let version = buf.gread::<u8>(&mut offset)?;
+    let augmentation = buf
+        .pread::<&str>(offset)
+        .map_err(|_| LinkError::malformed("", "Invalid CIE augmentation in .eh_frame"))?;
+    offset += augmentation.len() + 1;
+    buf.gread::<Uleb128>(&mut offset)?;
+    buf.gread::<Sleb128>(&mut offset)?;
+    if version == 1 {
+        offset += 1;
+    } else {
+        buf.gread::<Uleb128>(&mut offset)?;
+    }
+    if !augmentation.starts_with('z') {
+        return Ok(0);
+    }
+    buf.gread::<Uleb128>(&mut offset)?;
+    for c in augmentation[1..].chars() {
+        match c {
+            'R' => return Ok(buf.pread::<u8>(offset)?),
+            'P' => {
+                let encoding = buf.gread::<u8>(&mut offset)?;
+                // Only the encoding is needed, not where the personality routine is.
+                read_encoded_pointer(buf, &mut offset, 0, encoding & 0x7f, ctx)?;
+            }
+            'L' => offset += 1,
+            _ => {}
+        }
+    }
+    Ok(0)
+}
+
+impl EhFrameHdr {
+    pub(crate) fn size(&self) -> usize {
+        12 + 8 * self.fde_count
+    }
+    // Needs the relocated .eh_frame to find the code every FDE covers.
+    pub(crate) fn write(&self, buf: &mut [u8], base: usize, ctx: Ctx) -> Result<(), LinkError> {
+        let eh_frame = self.eh_frame_address - base;
+        let mut table = Vec::new();
+        let mut cie_encodings = HashMap::new();
+        let contents = &buf[eh_frame..eh_frame + self.eh_frame_size];
+        for (offset, cie_pointer) in eh_frame_records(contents, ctx)? {
+            if cie_pointer == 0 {
+                continue;
+            }
+            let fde = eh_frame + offset;
+            // The CIE pointer counts back from itself to a CIE in the same section.
+            let cie = usize::try_from(cie_pointer)
+                .ok()
+                .and_then(|cie_pointer| (fde + 4).checked_sub(cie_pointer))
+                .filter(|cie| *cie >= eh_frame)
+                .ok_or_else(|| LinkError::malformed("", "Invalid CIE pointer in .eh_frame"))?;
+            let encoding = match cie_encodings.get(&cie) {
+                Some(encoding) => *encoding,
+                None => {
+                    let encoding = cie_pointer_encoding(buf, cie, ctx)?;
+                    cie_encodings.insert(cie, encoding);
+                    encoding
+                }
+            };
+            let pc_begin = read_encoded_pointer(buf, &mut (fde + 8), base, encoding, ctx)?;
+            table.push((pc_begin, i64::try_from(base + fde).unwrap()));
+        }
+        table.sort_unstable();
+        let hdr = i64::try_from(self.address).unwrap();
+        let mut offset = self.address - base;
+        // Version and the encodings of eh_frame_ptr, fde_count and the table
+        let encodings = [
+            1,
+            DW_EH_PE_PCREL | DW_EH_PE_SDATA4,
+            DW_EH_PE_UDATA4,
+            DW_EH_PE_DATAREL | DW_EH_PE_SDATA4,
+        ];
+        buf.gwrite_with(&encodings[..], &mut offset, ())?;
+        let eh_frame_ptr =
+            i64::try_from(self.eh_frame_address).unwrap() - i64::try_from(base + offset).unwrap();
+        buf.gwrite_with(i32::try_from(eh_frame_ptr).unwrap(), &mut offset, ctx.le)?;
+        buf.gwrite_with(u32::try_from(table.len()).unwrap(), &mut offset, ctx.le)?;
+        for (pc_begin, fde) in table {
+            let pc_begin = i32::try_from(pc_begin.wrapping_sub(hdr)).map_err(|_| {
+                LinkError::overflow(format!("{:#x} is too far from .eh_frame_hdr", pc_begin))
+            })?;
+            buf.gwrite_with(pc_begin, &mut offset, ctx.le)?;
+            buf.gwrite_with(i32::try_from(fde - hdr).unwrap(), &mut offset, ctx.le)?;
+        }
+        Ok(())
+    }
+}
+
+fn elf_ident(osabi: u8, ctx: Ctx) -> [u8; 16] {
+    let mut ident = Header::new(ctx).e_ident;
+    ident[goblin::elf::header::EI_OSABI] = osabi;
+    ident
+}
+
+pub(crate) fn prog_header_offset(i: usize, ctx: Ctx) -> usize {
+    Header::size(ctx) + i * ProgramHeader::size(ctx)
+}
+
+impl<'a> Output<'a> {
+    // The part of the file with the segment is mapped at `base`, which is page aligned so
+    // file offsets and addresses are congruent modulo the page size.
+    fn prog_header(&self, info: SegmentInfo, base: usize) -> Result<ProgramHeader, LinkError> {
+        let address = u64::try_from(info.offset).unwrap();
+        let offset = match info.offset.checked_sub(base) {
+            Some(offset) => u64::try_from(offset).unwrap(),
+            None => {
+                return Err(LinkError::overflow(format!(
+                    "Segment at {:#x} lies below the image base",
+                    info.offset
+                )))
+            }
+        };
+        let size = u64::try_from(info.size).unwrap();
+        let mem_size = u64::try_from(info.mem_size).unwrap();
+        Ok(ProgramHeader {
+            p_type: goblin::elf::program_header::PT_LOAD,
+            p_flags: 0,
+            p_offset: offset,
+            p_vaddr: address,
+            p_paddr: address,
+            p_filesz: size,
+            p_memsz: mem_size,
+            p_align: u64::try_from(self.target.page_size()).unwrap(),
+        })
+    }
+}
+
+impl<'a> Output<'a> {
+    pub(crate) fn write(&self, buf: &mut [u8], ctx: Ctx) -> Result<(), LinkError> {
+        use goblin::elf::program_header::*;
+        let entry = u64::try_from(self.entry()?).unwrap();
+        let mut prog_headers: Vec<ProgramHeader> = self
+            .load_segments
+            .iter()
+            .map(|(p_flags, segment)| {
+                Ok(ProgramHeader {
+                    p_flags: *p_flags,
+                    ..self.prog_header(*segment, self.base(segment.offset))?
+                })
+            })
+            .collect::<Result<_, LinkError>>()?;
+        if !self.dynamic.is_empty() {
+            prog_headers.push(ProgramHeader {
+                p_type: PT_DYNAMIC,
+                p_flags: PF_R | PF_W,
+                p_align: u64::try_from(ctx.size()).unwrap(),
+                ..self.prog_header(
+                    SegmentInfo::new(self.dynamic.address, self.dynamic.size(ctx)),
+                    self.base(self.dynamic.address),
+                )?
+            });
+            self.dynamic
+                .write(buf, self.base(self.dynamic.address), ctx)?;
+            self.dynamic
+                .write_strtab(buf, self.base(self.dynamic.strtab_address))?;
+            self.write_dynamic_symbols(buf, ctx)?;
+        }
+        if let Some(tls) = &self.tls {
+            prog_headers.push(ProgramHeader {
+                p_type: PT_TLS,
+                p_flags: PF_R,
+                p_filesz: u64::try_from(tls.file_size).unwrap(),
+                p_memsz: u64::try_from(tls.mem_size).unwrap(),
+                p_align: u64::try_from(tls.align).unwrap(),
+                ..self.prog_header(
+                    SegmentInfo::new(tls.offset, tls.file_size),
+                    self.base(tls.offset),
+                )?
+            });
+        }
+        if let Some(hdr) = &self.eh_frame_hdr {
+            prog_headers.push(ProgramHeader {
+                p_type: PT_GNU_EH_FRAME,
+                p_flags: PF_R,
+                p_align: 4,
+                ..self.prog_header(
+                    SegmentInfo::new(hdr.address, hdr.size()),
+                    self.base(hdr.address),
+                )?
+            });
+        }
+        for (segment, align) in &self.notes {
+            prog_headers.push(ProgramHeader {
+                p_type: PT_NOTE,
+                p_flags: PF_R,
+                p_align: u64::try_from(*align).unwrap(),
+                ..self.prog_header(*segment, self.base(segment.offset))?
+            });
+        }
+        if let Some(note) = &self.build_id {
+            note.write_header(buf, self.base(note.address), ctx)?;
+        }
+        if let Some(note) = &self.gnu_property {
+            // The kernel and the dynamic loader find the properties through this header.
+            prog_headers.push(ProgramHeader {
+                p_type: PT_GNU_PROPERTY,
+                p_flags: PF_R,
+                p_align: u64::try_from(ctx.size()).unwrap(),
+                ..self.prog_header(
+                    SegmentInfo::new(note.address, note.size()),
+                    self.base(note.address),
+                )?
+            });
+            note.write(buf, self.base(note.address))?;
+        }
+        if let Some(relro) = self.relro {
+            prog_headers.push(ProgramHeader {
+                p_type: PT_GNU_RELRO,
+                p_flags: PF_R,
+                p_align: 1,
+                ..self.prog_header(relro, self.base(relro.offset))?
+            });
+        }
+        // The headers are mapped with the first segment if it is mapped at the image base,
+        // otherwise they get a segment of their own. Either way PT_PHDR comes first.
+        let shares_base = self
+            .segment_bases
+            .first()
+            .is_some_and(|(_, base)| *base == self.image_base);
+        let phnum = prog_headers.len() + if shares_base { 1 } else { 2 };
+        // A linker script can put sections anywhere, even where the headers go.
+        let headers_end = prog_header_offset(phnum, ctx);
+        if self.load_segments.iter().any(|(_, segment)| {
+            segment.mem_size > 0 && segment.offset < self.image_base + headers_end
+        }) || (!shares_base && headers_end > self.headers_size)
+        {
+            return Err(LinkError::overflow(
+                "Sections overlap the ELF and program headers",
+            ));
+        }
+        if shares_base {
+            let first = &mut prog_headers[0];
+            first.p_vaddr -= first.p_offset;
+            first.p_paddr -= first.p_offset;
+            first.p_filesz += first.p_offset;
+            first.p_memsz += first.p_offset;
+            first.p_offset = 0;
+        } else {
+            prog_headers.insert(
+                0,
+                ProgramHeader {
+                    p_flags: PF_R,
+                    ..self.prog_header(
+                        SegmentInfo::new(self.image_base, headers_end),
+                        self.image_base,
+                    )?
+                },
+            );
+        }
+        let phoff = Header::size(ctx);
+        prog_headers.insert(
+            0,
+            ProgramHeader {
+                p_type: PT_PHDR,
+                p_flags: PF_R,
+                p_align: u64::try_from(ctx.size()).unwrap(),
+                ..self.prog_header(
+                    SegmentInfo::new(self.image_base + phoff, headers_end - phoff),
+                    self.image_base,
+                )?
+            },
+        );
+
+        let elf_header = Header {
+            e_ident: elf_ident(self.osabi, ctx),
+            e_type: if self.shared {
+                goblin::elf::header::ET_DYN
+            } else {
+                goblin::elf::header::ET_EXEC
+            },
+            e_machine: self.target.machine(),
+            e_flags: self.flags,
+            e_entry: entry,
+            e_phoff: u64::try_from(phoff).unwrap(),
+            e_phnum: u16::try_from(prog_headers.len()).unwrap(),
+            ..Header::new(ctx)
+        };
+        buf.pwrite_with(elf_header, 0, ctx.le)?;
+        for (i, prog_header) in prog_headers.into_iter().enumerate() {
+            buf.pwrite_with(prog_header, prog_header_offset(i, ctx), ctx)?;
+        }
+        let mut code_sections: Vec<(usize, usize)> = self
+            .sections
+            .iter()
+            .filter(|sec| {
+                sec.input_section.section.sh_flags
+                    & u64::from(goblin::elf::section_header::SHF_EXECINSTR)
+                    != 0
+            })
+            .map(|sec| {
+                let start = self.file_offset(sec.address);
+                (
+                    start,
+                    start + usize::try_from(sec.input_section.section.sh_size).unwrap(),
+                )
+            })
+            .collect();
+        code_sections.sort_unstable();
+        for window in code_sections.windows(2) {
+            let (gap_start, gap_end) = (window[0].1, window[1].0);
+            // Sections in different segments have no padding between them in the file.
+            let in_code_segment = self.load_segments.iter().any(|(p_flags, segment)| {
+                let start = self.file_offset(segment.offset);
+                p_flags & PF_X != 0 && start <= gap_start && gap_end <= start + segment.size
+            });
+            if gap_start < gap_end && in_code_segment {
+                buf[gap_start..gap_end].fill(self.fill);
+            }
+        }
+        for sec in &self.sections {
+            let input_sec = &sec.input_section.section;
+            // Empty sections can be outside of any segment.
+            if input_sec.sh_type == goblin::elf::section_header::SHT_NOBITS
+                || input_sec.sh_size == 0
+            {
+                continue;
+            }
+            let offset = usize::try_from(input_sec.sh_offset).unwrap();
+            let size = usize::try_from(input_sec.sh_size).unwrap();
+            let file_buf = self.file_buffers[sec.input_section.file_idx];
+            let start = self.file_offset(sec.address);
+            buf[start..start + size].copy_from_slice(&file_buf[offset..offset + size]);
+        }
+        for sec in &self.merged_sections {
+            let start = self.file_offset(sec.address);
+            buf[start..start + sec.data.len()].copy_from_slice(&sec.data);
+        }
+        for (sec, start) in self
+            .debug_sections
+            .iter()
+            .flat_map(|sec| &sec.input_sections)
+        {
+            let offset = usize::try_from(sec.section.sh_offset).unwrap();
+            let size = usize::try_from(sec.section.sh_size).unwrap();
+            let file_buf = self.file_buffers[sec.file_idx];
+            buf[*start..start + size].copy_from_slice(&file_buf[offset..offset + size]);
+        }
+        if let Some(comment) = &self.comment {
+            buf[comment.offset..comment.offset + comment.data.len()].copy_from_slice(&comment.data);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Output<'a> {
+    // .dynsym and .hash of a shared library. Exported symbols are defined in the output
+    // section of their input section.
+    fn write_dynamic_symbols(&self, buf: &mut [u8], ctx: Ctx) -> Result<(), LinkError> {
+        use goblin::elf::section_header::SHN_ABS;
+        let symbols = match &self.dynamic.symbols {
+            Some(symbols) => symbols,
+            None => return Ok(()),
+        };
+        let (_, section_map) = self.section_headers(ctx);
+        let mut offset = self.file_offset(symbols.symtab_address);
+        buf.gwrite_with(goblin::elf::Sym::default(), &mut offset, ctx)?;
+        let mut names = vec![""];
+        for ((file_idx, sym_idx), name) in &symbols.exports {
+            let sym = self.symtab.get(*file_idx, *sym_idx);
+            let st_shndx = if self.symtab.is_absolute(*file_idx, &sym) {
+                usize::try_from(SHN_ABS).unwrap()
+            } else {
+                section_map[&(*file_idx, sym.st_shndx)].0
+            };
+            let dynsym = goblin::elf::Sym {
+                st_name: *name,
+                st_shndx,
+                st_value: u64::try_from(self.symbol_address(*file_idx, *sym_idx)?).unwrap(),
+                ..sym
+            };
+            buf.gwrite_with(dynsym, &mut offset, ctx)?;
+            names.push(self.symtab.name(*file_idx, &sym));
+        }
+        // Every symbol gets its own bucket, symbols that hash to the same bucket are
+        // chained.
+        let count = u32::try_from(symbols.count()).unwrap();
+        let mut buckets = vec![0; names.len()];
+        let mut chains = vec![0; names.len()];
+        for (idx, name) in names.iter().enumerate().skip(1) {
+            let bucket = usize::try_from(elf_hash(name) % count).unwrap();
+            chains[idx] = buckets[bucket];
+            buckets[bucket] = u32::try_from(idx).unwrap();
+        }
+        let mut offset = self.file_offset(symbols.hash_address);
+        for word in [count, count].iter().chain(&buckets).chain(&chains) {
+            buf.gwrite_with(*word, &mut offset, ctx.le)?;
+        }
+        Ok(())
+    }
+    // Section headers for everything in the loaded image together with a map from input
+    // sections to their output section index and offset in it.
+    pub(crate) fn section_headers(&self, ctx: Ctx) -> (SectionHeaderTable, SectionMap) {
+        use goblin::elf::dynamic::*;
+        use goblin::elf::section_header::*;
+        enum Item<'s, 'a> {
+            Input(&'s OutputSection<'a>),
+            Merged(&'s MergedSection<'a>),
+            Synthetic(&'static str, SectionHeader),
+        }
+        // The alignment has to match what the layout gives the section.
+        let synthetic =
+            |sh_type, sh_flags, address: usize, size: usize, align: usize, entsize: usize| {
+                SectionHeader {
+                    sh_type,
+                    sh_flags: u64::from(sh_flags),
+                    sh_addr: u64::try_from(address).unwrap(),
+                    sh_offset: u64::try_from(self.file_offset(address)).unwrap(),
+                    sh_size: u64::try_from(size).unwrap(),
+                    sh_addralign: u64::try_from(align).unwrap(),
+                    sh_entsize: u64::try_from(entsize).unwrap(),
+                    ..SectionHeader::default()
+                }
+            };
+        // Sections created by the linker with their address
+        let mut synthetic_sections = Vec::new();
+        if let Some(symbols) = &self.dynamic.symbols {
+            let header = synthetic(
+                SHT_HASH,
+                SHF_ALLOC,
+                symbols.hash_address,
+                symbols.hash_size(),
+                ctx.size(),
+                4,
+            );
+            synthetic_sections.push((symbols.hash_address, Item::Synthetic(".hash", header)));
+            let header = SectionHeader {
+                // Only the null symbol is local.
+                sh_info: 1,
+                ..synthetic(
+                    SHT_DYNSYM,
+                    SHF_ALLOC,
+                    symbols.symtab_address,
+                    symbols.symtab_size(ctx),
+                    ctx.size(),
+                    goblin::elf::Sym::size_with(&ctx),
+                )
+            };
+            synthetic_sections.push((symbols.symtab_address, Item::Synthetic(".dynsym", header)));
+            if symbols.relocations > 0 {
+                let header = synthetic(
+                    SHT_RELA,
+                    SHF_ALLOC,
+                    symbols.rela_address,
+                    symbols.rela_size(ctx),
+                    ctx.size(),
+                    goblin::elf::Reloc::size(true, ctx),
+                );
+                synthetic_sections
+                    .push((symbols.rela_address, Item::Synthetic(".rela.dyn", header)));
+            }
+        }
+        if self.dynamic.has_strtab() {
+            let header = synthetic(
+                SHT_STRTAB,
+                SHF_ALLOC,
+                self.dynamic.strtab_address,
+                self.dynamic.strtab.len(),
+                1,
+                0,
+            );
+            synthetic_sections.push((
+                self.dynamic.strtab_address,
+                Item::Synthetic(".dynstr", header),
+            ));
+        }
+        for sec in &self.merged_sections {
+            synthetic_sections.push((sec.address, Item::Merged(sec)));
+        }
+        if let Some(note) = &self.build_id {
+            let header = synthetic(SHT_NOTE, SHF_ALLOC, note.address, note.size(), 4, 0);
+            synthetic_sections.push((note.address, Item::Synthetic(".note.gnu.build-id", header)));
+        }
+        if let Some(note) = &self.gnu_property {
+            let header = synthetic(
+                SHT_NOTE,
+                SHF_ALLOC,
+                note.address,
+                note.size(),
+                ctx.size(),
+                0,
+            );
+            synthetic_sections.push((note.address, Item::Synthetic(".note.gnu.property", header)));
+        }
+        if let Some(hdr) = &self.eh_frame_hdr {
+            let header = synthetic(SHT_PROGBITS, SHF_ALLOC, hdr.address, hdr.size(), 4, 0);
+            synthetic_sections.push((hdr.address, Item::Synthetic(".eh_frame_hdr", header)));
+        }
+        if !self.dynamic.is_empty() {
+            let header = synthetic(
+                SHT_DYNAMIC,
+                SHF_ALLOC | SHF_WRITE,
+                self.dynamic.address,
+                self.dynamic.size(ctx),
+                ctx.size(),
+                Dyn::size_with(&ctx),
+            );
+            synthetic_sections.push((self.dynamic.address, Item::Synthetic(".dynamic", header)));
+        }
+        if !self.got.is_empty() {
+            let header = synthetic(
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_WRITE,
+                self.got.address,
+                self.got.size(ctx),
+                ctx.size(),
+                ctx.size(),
+            );
+            synthetic_sections.push((self.got.address, Item::Synthetic(".got", header)));
+        }
+        synthetic_sections.sort_by_key(|(address, _)| *address);
+        // Input sections stay in layout order because .tbss overlaps what follows it.
+        let mut items = Vec::new();
+        let mut synthetic_sections = synthetic_sections.into_iter().peekable();
+        for sec in &self.sections {
+            while let Some((_, item)) =
+                synthetic_sections.next_if(|(address, _)| *address < sec.address)
+            {
+                items.push(item);
+            }
+            items.push(Item::Input(sec));
+        }
+        items.extend(synthetic_sections.map(|(_, item)| item));
+
+        let mut table = SectionHeaderTable::new(self.total_size);
+        let mut section_map = HashMap::new();
+        let mut dynstr_idx = None;
+        let mut dynamic_idx = None;
+        let mut dynsym_idx = None;
+        let mut dynsym_users = Vec::new();
+        let mut previous_unique = false;
+        for item in items {
+            let (name, header) = match &item {
+                Item::Input(sec) => {
+                    let input_sec = &sec.input_section.section;
+                    let address = u64::try_from(sec.address).unwrap();
+                    let offset = self.file_offset(sec.address);
+                    let header = SectionHeader {
+                        sh_type: input_sec.sh_type,
+                        sh_flags: input_sec.sh_flags,
+                        sh_addr: address,
+                        // .bss lies beyond the end of the file.
+                        sh_offset: u64::try_from(std::cmp::min(offset, self.total_size)).unwrap(),
+                        sh_size: input_sec.sh_size,
+                        sh_addralign: std::cmp::max(input_sec.sh_addralign, 1),
+                        ..SectionHeader::default()
+                    };
+                    (sec.name, header)
+                }
+                Item::Merged(sec) => {
+                    let address = u64::try_from(sec.address).unwrap();
+                    let header = SectionHeader {
+                        sh_type: SHT_PROGBITS,
+                        sh_flags: sec.flags,
+                        sh_addr: address,
+                        sh_offset: u64::try_from(self.file_offset(sec.address)).unwrap(),
+                        sh_size: u64::try_from(sec.data.len()).unwrap(),
+                        sh_addralign: u64::try_from(sec.align).unwrap(),
+                        sh_entsize: u64::try_from(sec.entsize).unwrap(),
+                        ..SectionHeader::default()
+                    };
+                    (sec.name, header)
+                }
+                Item::Synthetic(name, header) => (*name, header.clone()),
+            };
+            // Input sections and merged pieces of the same output section are next to each
+            // other.
+            let unique = matches!(&item, Item::Input(sec) if sec.unique);
+            let idx = match (&item, table.names.last()) {
+                (Item::Input(_) | Item::Merged(_), Some(last))
+                    if last == name && !unique && !previous_unique =>
+                {
+                    let idx = table.headers.len() - 1;
+                    let existing = &mut table.headers[idx];
+                    existing.sh_size = header.sh_addr + header.sh_size - existing.sh_addr;
+                    existing.sh_addralign =
+                        std::cmp::max(existing.sh_addralign, header.sh_addralign);
+                    idx
+                }
+                _ => table.push(name, header),
+            };
+            previous_unique = unique;
+            let base = usize::try_from(table.headers[idx].sh_addr).unwrap();
+            match item {
+                Item::Input(sec) => {
+                    section_map.insert(
+                        (sec.input_section.file_idx, sec.input_section.shdr_idx),
+                        (idx, Placement::Offset(sec.address - base)),
+                    );
+                }
+                Item::Merged(sec) => {
+                    for (key, placement) in &self.section_offsets {
+                        if let Placement::Pieces(pieces) = placement {
+                            if pieces.first().is_some_and(|(_, output_offset)| {
+                                (sec.address..sec.address + sec.data.len()).contains(output_offset)
+                            }) {
+                                section_map.insert(*key, (idx, placement.relative_to(base)));
+                            }
+                        }
+                    }
+                }
+                Item::Synthetic(".dynstr", _) => dynstr_idx = Some(idx),
+                Item::Synthetic(".dynamic", _) => dynamic_idx = Some(idx),
+                Item::Synthetic(".dynsym", _) => dynsym_idx = Some(idx),
+                Item::Synthetic(".hash" | ".rela.dyn", _) => dynsym_users.push(idx),
+                Item::Synthetic(..) => {}
+            }
+        }
+        if let Some(dynstr_idx) = dynstr_idx {
+            for idx in dynamic_idx.iter().chain(&dynsym_idx) {
+                table.headers[*idx].sh_link = u32::try_from(dynstr_idx).unwrap();
+            }
+        }
+        if let Some(dynsym_idx) = dynsym_idx {
+            for idx in dynsym_users {
+                table.headers[idx].sh_link = u32::try_from(dynsym_idx).unwrap();
+            }
+        }
+        for sec in &self.debug_sections {
+            let idx = table.push(
+                sec.name,
+                SectionHeader {
+                    sh_type: SHT_PROGBITS,
+                    sh_flags: sec.flags,
+                    sh_offset: u64::try_from(sec.offset).unwrap(),
+                    sh_size: u64::try_from(sec.size).unwrap(),
+                    sh_addralign: sec.align,
+                    sh_entsize: sec.entsize,
+                    ..SectionHeader::default()
+                },
+            );
+            for (input, _) in &sec.input_sections {
+                let key = (input.file_idx, input.shdr_idx);
+                section_map.insert(key, (idx, self.section_offsets[&key].clone()));
+            }
+        }
+        if let Some(comment) = &self.comment {
+            table.push(
+                ".comment",
+                SectionHeader {
+                    sh_type: SHT_PROGBITS,
+                    sh_flags: u64::from(SHF_MERGE | SHF_STRINGS),
+                    sh_offset: u64::try_from(comment.offset).unwrap(),
+                    sh_size: u64::try_from(comment.data.len()).unwrap(),
+                    sh_addralign: 1,
+                    sh_entsize: 1,
+                    ..SectionHeader::default()
+                },
+            );
+        }
+        (table, section_map)
+    }
+    // Append the symbol table, the relocations with --emit-relocs and the section header
+    // table so that tools like objdump and debuggers can make sense of the output.
+    pub(crate) fn write_section_headers(
+        &self,
+        buf: &mut OutputBuffer,
+        emit_relocs: bool,
+        ctx: Ctx,
+    ) -> Result<(), LinkError> {
+        let (mut table, section_map) = self.section_headers(ctx);
+        let tls_start = self.tls.as_ref().map_or(0, |tls| tls.offset);
+        let reloc_sections: &[RelocationSection] = if emit_relocs {
+            &self.reloc_sections
+        } else {
+            &[]
+        };
+        let symbols = self.symtab.merge(
+            reloc_sections,
+            &section_map,
+            &table.headers,
+            tls_start,
+            true,
+        )?;
+        table.append_symbols(symbols, ctx)?;
+        table.finish(buf, ctx)
+    }
+}
+
+impl<'a> Output<'a> {
+    // The output sections with the input sections copied into them and the global
+    // symbols these define. Merged sections only appear as a whole.
+    fn layout(&self, ctx: Ctx) -> Vec<LayoutSection<'_>> {
+        use goblin::elf::sym::*;
+        let mut symbols: HashMap<(usize, goblin::elf::ShdrIdx), Vec<(u64, &str)>> = HashMap::new();
+        for (file_idx, (syms, strtab)) in &self.symtab.by_file {
+            for sym in syms {
+                if st_bind(sym.st_info) != STB_LOCAL && sym.st_shndx != 0 {
+                    let name = strtab.get_unsafe(sym.st_name).unwrap_or("");
+                    symbols
+                        .entry((*file_idx, sym.st_shndx))
+                        .or_default()
+                        .push((sym.st_value, name));
+                }
+            }
+        }
+        let (table, section_map) = self.section_headers(ctx);
+        let mut sections = Vec::new();
+        for (idx, (name, header)) in table.names.iter().zip(&table.headers).enumerate().skip(1) {
+            let inputs = self
+                .sections
+                .iter()
+                .filter(|sec| {
+                    let key = (sec.input_section.file_idx, sec.input_section.shdr_idx);
+                    section_map
+                        .get(&key)
+                        .is_some_and(|(output_idx, _)| *output_idx == idx)
+                })
+                .map(|sec| {
+                    let input = &sec.input_section;
+                    let address = u64::try_from(sec.address).unwrap();
+                    let mut defined: Vec<(u64, &str)> = symbols
+                        .get(&(input.file_idx, input.shdr_idx))
+                        .into_iter()
+                        .flatten()
+                        .map(|(value, name)| (address + value, *name))
+                        .collect();
+                    defined.sort_unstable();
+                    LayoutInput {
+                        file: &self.file_names[input.file_idx],
+                        name: input.name,
+                        address,
+                        size: input.section.sh_size,
+                        symbols: defined,
+                    }
+                })
+                .collect();
+            sections.push(LayoutSection {
+                name: name.clone(),
+                address: header.sh_addr,
+                size: header.sh_size,
+                inputs,
+            });
+        }
+        sections
+    }
+    // A map of the output in the format of GNU ld.
+    pub(crate) fn map(&self, ctx: Ctx) -> String {
+        use std::fmt::Write;
+        let width = 2 * ctx.size();
+        let mut out = String::new();
+        // Names that do not fit in front of the address get a line of their own.
+        let line = |out: &mut String, indent: &str, name: &str, address: u64, size: u64| {
+            let name = format!("{}{}", indent, name);
+            if name.len() >= 15 {
+                write!(out, "{}\n{:15}", name, "").unwrap();
+            } else {
+                write!(out, "{:15}", name).unwrap();
+            }
+            write!(out, " 0x{:0width$x} {:#10x}", address, size, width = width).unwrap();
+        };
+        let symbol = |out: &mut String, value: u64, name: &str| {
+            writeln!(
+                out,
+                "{:16}0x{:0width$x}{:16}{}",
+                "",
+                value,
+                "",
+                name,
+                width = width
+            )
+            .unwrap();
+        };
+        out.push_str("Discarded input sections\n\n");
+        for sec in &self.discarded_sections {
+            line(&mut out, " ", sec.name, 0, sec.section.sh_size);
+            writeln!(out, " {}", self.file_names[sec.file_idx]).unwrap();
+        }
+        out.push_str("\nMemory map\n\n");
+        for sec in self.layout(ctx) {
+            line(&mut out, "", &sec.name, sec.address, sec.size);
+            out.push('\n');
+            for input in sec.inputs {
+                line(&mut out, " ", input.name, input.address, input.size);
+                writeln!(out, " {}", input.file).unwrap();
+                for (value, name) in input.symbols {
+                    symbol(&mut out, value, name);
+                }
+            }
+        }
+        out.push_str("\nLinker symbols\n\n");
+        for (name, value) in &self.symtab.linker_symbols {
+            match self.symtab.provenance.get(name) {
+                Some(provenance) => symbol(&mut out, *value, &format!("{} ({})", name, provenance)),
+                None => symbol(&mut out, *value, name),
+            }
+        }
+        out
+    }
+    // The same as the map as JSON for tools, together with the PT_LOAD segments.
+    pub(crate) fn layout_json(&self, ctx: Ctx) -> String {
+        use goblin::elf::program_header::*;
+        let segments: Vec<String> = self
+            .load_segments
+            .iter()
+            .map(|(flags, segment)| {
+                let flags: String = [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')]
+                    .iter()
+                    .map(|(flag, c)| if flags & flag != 0 { *c } else { '-' })
+                    .collect();
+                format!(
+                    "{{\"address\": {}, \"offset\": {}, \"file_size\": {}, \"memory_size\": {}, \"flags\": \"{}\"}}",
+                    segment.offset,
+                    self.file_offset(segment.offset),
+                    segment.size,
+                    segment.mem_size,
+                    flags
+                )
+            })
+            .collect();
+        let sections: Vec<String> = self
+            .layout(ctx)
+            .iter()
+            .map(|sec| {
+                let inputs: Vec<String> = sec
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        let symbols: Vec<String> = input
+                            .symbols
+                            .iter()
+                            .map(|(value, name)| {
+                                format!("{{\"name\": {}, \"address\": {}}}", json_string(name), value)
+                            })
+                            .collect();
+                        format!(
+                            "{{\"file\": {}, \"name\": {}, \"address\": {}, \"size\": {}, \"symbols\": [{}]}}",
+                            json_string(input.file),
+                            json_string(input.name),
+                            input.address,
+                            input.size,
+                            symbols.join(", ")
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"name\": {}, \"address\": {}, \"size\": {}, \"inputs\": [{}]}}",
+                    json_string(&sec.name),
+                    sec.address,
+                    sec.size,
+                    inputs.join(", ")
+                )
+            })
+            .collect();
+        let linker_symbols: Vec<String> = self
+            .symtab
+            .linker_symbols
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{{\"name\": {}, \"address\": {}}}",
+                    json_string(name),
+                    value
+                )
+            })
+            .collect();
+        let discarded: Vec<String> = self
+            .discarded_sections
+            .iter()
+            .map(|sec| {
+                format!(
+                    "{{\"file\": {}, \"name\": {}, \"size\": {}}}",
+                    json_string(&self.file_names[sec.file_idx]),
+                    json_string(sec.name),
+                    sec.section.sh_size
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"segments\": [{}],\n  \"sections\": [{}],\n  \"linker_symbols\": [{}],\n  \"discarded\": [{}]\n}}\n",
+            segments.join(", "),
+            sections.join(", "),
+            linker_symbols.join(", "),
+            discarded.join(", ")
+        )
+    }
+    // The layout for API users. The segments are the PT_LOAD headers that `write` put into
+    // `elf`, where the first one may also map the ELF headers.
+    pub(crate) fn inspect(&self, elf: &[u8], ctx: Ctx) -> Result<Layout, LinkError> {
+        use goblin::elf::program_header::*;
+        use scroll::Pread;
+        let header: Header = elf.pread_with(0, ctx.le)?;
+        let segments = ProgramHeader::parse(
+            elf,
+            usize::try_from(header.e_phoff).unwrap(),
+            usize::from(header.e_phnum),
+            ctx,
+        )?
+        .into_iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .map(|phdr| SegmentLayout {
+            address: phdr.p_vaddr,
+            offset: phdr.p_offset,
+            file_size: phdr.p_filesz,
+            memory_size: phdr.p_memsz,
+            flags: phdr.p_flags,
+        })
+        .collect();
+        let sections = self
+            .layout(ctx)
+            .into_iter()
+            .map(|sec| SectionLayout {
+                name: sec.name,
+                address: sec.address,
+                size: sec.size,
+                inputs: sec
+                    .inputs
+                    .iter()
+                    .map(|input| InputLayout {
+                        file: String::from(input.file),
+                        name: String::from(input.name),
+                        address: input.address,
+                        size: input.size,
+                    })
+                    .collect(),
+            })
+            .collect();
+        // Symbols in sections removed by --gc-sections have no address.
+        let mut symbols: HashMap<String, u64> = self
+            .symtab
+            .globals
+            .iter()
+            .filter(|(_, (file_idx, sym_idx))| self.is_placed(*file_idx, *sym_idx))
+            .map(|(name, (file_idx, sym_idx))| {
+                let address = self.symbol_address(*file_idx, *sym_idx)?;
+                Ok((String::from(*name), u64::try_from(address).unwrap()))
+            })
+            .collect::<Result<_, LinkError>>()?;
+        for (name, value) in &self.symtab.linker_symbols {
+            symbols.insert(String::from(*name), *value);
+        }
+        Ok(Layout {
+            segments,
+            sections,
+            symbols,
+        })
+    }
+    // What takes up the space in the output: the sizes of the segments, of the output
+    // sections and of the largest functions and objects, local ones included. The
+    // segments are the ones in the written program headers, where the first one also
+    // maps the ELF headers.
+    pub(crate) fn size_report(
+        &self,
+        file_size: usize,
+        segments: &[SegmentLayout],
+        count: usize,
+        ctx: Ctx,
+    ) -> String {
+        use goblin::elf::program_header::*;
+        use goblin::elf::sym::*;
+        use std::fmt::Write;
+        let mut out = format!("File size: {} bytes\n\nSegments:\n", file_size);
+        writeln!(out, "  {:>10} {:>10}  flags  address", "file", "memory").unwrap();
+        for segment in segments {
+            let flags: String = [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')]
+                .iter()
+                .map(|(flag, c)| if segment.flags & flag != 0 { *c } else { '-' })
+                .collect();
+            writeln!(
+                out,
+                "  {:>10} {:>10}  {}    {:#x}",
+                segment.file_size, segment.memory_size, flags, segment.address
+            )
+            .unwrap();
+        }
+        let mut sections = self.layout(ctx);
+        let total: u64 = sections.iter().map(|sec| sec.size).sum();
+        sections.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        out.push_str("\nSections:\n");
+        for sec in &sections {
+            let share = 100.0 * sec.size as f64 / total.max(1) as f64;
+            writeln!(out, "  {:>10} {:>5.1}%  {}", sec.size, share, sec.name).unwrap();
+        }
+        let kept: HashSet<(usize, usize)> = self
+            .sections
+            .iter()
+            .map(|sec| (sec.input_section.file_idx, sec.input_section.shdr_idx))
+            .collect();
+        let mut symbols = Vec::new();
+        for (file_idx, (syms, strtab)) in &self.symtab.by_file {
+            for sym in syms {
+                let sized = matches!(st_type(sym.st_info), STT_FUNC | STT_OBJECT | STT_TLS);
+                if sized && sym.st_size > 0 && kept.contains(&(*file_idx, sym.st_shndx)) {
+                    let name = strtab.get_unsafe(sym.st_name).unwrap_or("");
+                    symbols.push((sym.st_size, name, &self.file_names[*file_idx]));
+                }
+            }
+        }
+        symbols.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        writeln!(out, "\nLargest symbols:").unwrap();
+        for (size, name, file) in symbols.iter().take(count) {
+            writeln!(out, "  {:>10}  {} ({})", size, name, file).unwrap();
+        }
+        out
+    }
+}
+
+/// An output section as listed in the map files.
+#[derive(Debug)]
+pub(crate) struct LayoutSection<'s> {
+    pub(crate) name: String,
+    pub(crate) address: u64,
+    pub(crate) size: u64,
+    inputs: Vec<LayoutInput<'s>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct LayoutInput<'s> {
+    pub(crate) file: &'s str,
+    pub(crate) name: &'s str,
+    pub(crate) address: u64,
+    pub(crate) size: u64,
+    // Global symbols defined in the section with their address
+    pub(crate) symbols: Vec<(u64, &'s str)>,
+}
+
+// A JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// An output section of a relocatable link, made up of all input sections with its name.
+#[derive(Debug)]
+pub(crate) struct RelocatableSection<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) header: SectionHeader,
+    // Input sections and their offset in this section
+    inputs: Vec<(&'a InputSection<'a>, usize)>,
+}
+
+impl<'a> Input<'a> {
+    // Merge all input files into a single ET_REL object for a later link.
+    pub(crate) fn link_relocatable(
+        &'a self,
+        unique: &[String],
+        ctx: Ctx,
+    ) -> Result<Vec<u8>, LinkError> {
+        use goblin::elf::header::*;
+        use goblin::elf::section_header::*;
+
+        // Concatenate input sections with the same name. The output section index is the
+        // position in `sections` plus one for the null section.
+        let mut sections: Vec<RelocatableSection> = Vec::new();
+        let mut by_name: HashMap<&str, usize> = HashMap::new();
+        let mut section_map: SectionMap = HashMap::new();
+        for sec in self
+            .code_sections
+            .iter()
+            .chain(&self.tls_data_sections)
+            .chain(&self.tls_bss_sections)
+            .chain(&self.relro_sections)
+            .chain(&self.init_array_sections)
+            .chain(&self.data_sections)
+            .chain(&self.bss_sections)
+            .chain(&self.ro_data_sections)
+            // Concatenated strings are still mergeable, the final link deduplicates them.
+            .chain(&self.merge_sections)
+            .chain(&self.eh_frame_sections)
+            .chain(&self.note_sections)
+            .chain(&self.property_sections)
+            .chain(&self.debug_sections)
+            .chain(&self.comment_sections)
+        {
+            let is_unique = unique
+                .iter()
+                .any(|pattern| wildcard_match(pattern, sec.name));
+            let idx = match by_name.get(sec.name) {
+                Some(idx) if !is_unique => *idx,
+                _ => {
+                    by_name.insert(sec.name, sections.len());
+                    sections.push(RelocatableSection {
+                        name: sec.name,
+                        header: SectionHeader {
+                            sh_type: sec.section.sh_type,
+                            sh_flags: sec.section.sh_flags,
+                            sh_entsize: sec.section.sh_entsize,
+                            sh_addralign: 1,
+                            ..SectionHeader::default()
+                        },
+                        inputs: Vec::new(),
+                    });
+                    sections.len() - 1
+                }
+            };
+            let out = &mut sections[idx];
+            let sec_align = std::cmp::max(sec.section.sh_addralign, 1);
+            out.header.sh_addralign = std::cmp::max(out.header.sh_addralign, sec_align);
+            let offset = align(
+                usize::try_from(out.header.sh_size).unwrap(),
+                usize::try_from(sec_align).unwrap(),
+            );
+            out.header.sh_size = u64::try_from(offset).unwrap() + sec.section.sh_size;
+            out.inputs.push((sec, offset));
+            section_map.insert(
+                (sec.file_idx, sec.shdr_idx),
+                (idx + 1, Placement::Offset(offset)),
+            );
+        }
+
+        let mut buf = OutputBuffer::from(vec![0; Header::size(ctx)]);
+        let elf_header = Header {
+            e_ident: elf_ident(self.osabi, ctx),
+            e_type: ET_REL,
+            e_machine: self.target().machine(),
+            e_flags: self.flags,
+            e_phentsize: 0,
+            ..Header::new(ctx)
+        };
+        buf.pwrite_with(elf_header, 0, ctx.le)?;
+        let mut table = SectionHeaderTable::new(buf.len());
+        for out in &sections {
+            let mut contents = Vec::new();
+            if out.header.sh_type != SHT_NOBITS {
+                contents.resize(usize::try_from(out.header.sh_size).unwrap(), 0);
+                for (sec, sec_offset) in &out.inputs {
+                    let input_offset = usize::try_from(sec.section.sh_offset).unwrap();
+                    let size = usize::try_from(sec.section.sh_size).unwrap();
+                    let file_buf = self.file_buffers[sec.file_idx];
+                    contents[*sec_offset..sec_offset + size]
+                        .copy_from_slice(&file_buf[input_offset..input_offset + size]);
+                }
+            }
+            table.append(out.name, out.header.clone(), &contents);
+        }
+        let symbols =
+            self.symtab
+                .merge(&self.reloc_sections, &section_map, &table.headers, 0, false)?;
+        table.append_symbols(symbols, ctx)?;
+        table.finish(&mut buf, ctx)?;
+        Ok(buf.into_vec())
+    }
+}
+
+// An object file with the sections added by `Linker::add_section`, for the same target as
+// `like`, one of the object files of the link.
+pub(crate) fn synthetic_object(
+    sections: &[SyntheticSection],
+    like: &[u8],
+) -> Result<Vec<u8>, LinkError> {
+    use goblin::elf::header::*;
+    use goblin::elf::section_header::*;
+    use goblin::elf::sym::*;
+    use goblin::elf::Sym;
+    let header = goblin::elf::Elf::parse_header(like)?;
+    let ctx = Ctx::new(header.container()?, header.endianness()?);
+    let mut buf = OutputBuffer::from(vec![0; Header::size(ctx)]);
+    let elf_header = Header {
+        e_ident: elf_ident(header.e_ident[EI_OSABI], ctx),
+        e_type: ET_REL,
+        e_machine: header.e_machine,
+        e_flags: header.e_flags,
+        e_phentsize: 0,
+        ..Header::new(ctx)
+    };
+    buf.pwrite_with(elf_header, 0, ctx.le)?;
+    let mut table = SectionHeaderTable::new(buf.len());
+    let mut strtab = StringTable::new();
+    let mut symbols = vec![Sym::default()];
+    for section in sections {
+        let mut flags = SHF_ALLOC;
+        if section.writable {
+            flags |= SHF_WRITE;
+        }
+        if section.executable {
+            flags |= SHF_EXECINSTR;
+        }
+        let idx = table.append(
+            &section.name,
+            SectionHeader {
+                sh_type: SHT_PROGBITS,
+                sh_flags: u64::from(flags),
+                sh_addralign: std::cmp::max(section.align, 1),
+                ..SectionHeader::default()
+            },
+            &section.contents,
+        );
+        let st_type = if section.executable {
+            STT_FUNC
+        } else {
+            STT_OBJECT
+        };
+        for (name, offset) in &section.symbols {
+            symbols.push(Sym {
+                st_name: strtab.add(name),
+                st_info: STB_GLOBAL << 4 | st_type,
+                st_shndx: section_key(idx),
+                st_value: *offset,
+                ..Sym::default()
+            });
+        }
+    }
+    let merged = MergedSymbols {
+        symbols,
+        strtab,
+        first_global: 1,
+        relocations: Vec::new(),
+    };
+    table.append_symbols(merged, ctx)?;
+    table.finish(&mut buf, ctx)?;
+    Ok(buf.into_vec())
+}
+
+// Map from file (idx, section idx) to the output section index and the placement in it
+pub(crate) type SectionMap = HashMap<(usize, goblin::elf::ShdrIdx), (usize, Placement)>;
+
+/// Symbols and relocations of all input files rewritten to refer to output sections.
+#[derive(Debug)]
+pub(crate) struct MergedSymbols {
+    pub(crate) symbols: Vec<goblin::elf::Sym>,
+    pub(crate) strtab: StringTable,
+    // Index of the first non-local symbol
+    first_global: usize,
+    // Relocations by the index of the output section they apply to
+    pub(crate) relocations: Vec<(usize, Vec<goblin::elf::Reloc>)>,
+}
+
+impl<'a> SymbolTable<'a> {
+    // Build a single symbol table for the output. `section_map` maps input sections to
+    // the index of their output section in `headers` and their offset in it. TLS symbols
+    // are relative to `tls_start`. References to linker-defined symbols get their values
+    // with `define_linker_symbols`.
+    fn merge(
+        &self,
+        reloc_sections: &[RelocationSection],
+        section_map: &SectionMap,
+        headers: &[SectionHeader],
+        tls_start: usize,
+        define_linker_symbols: bool,
+    ) -> Result<MergedSymbols, LinkError> {
+        use goblin::elf::section_header::*;
+        use goblin::elf::sym::*;
+        use goblin::elf::{Reloc, Sym};
+
+        // The symbol table starts with a section symbol for each output section so
+        // relocations against input sections can be rewritten to them.
+        let mut strtab = StringTable::new();
+        let mut symbols = vec![Sym::default()];
+        for (idx, header) in headers.iter().enumerate().skip(1) {
+            symbols.push(Sym {
+                st_info: STT_SECTION,
+                st_shndx: section_key(idx),
+                st_value: header.sh_addr,
+                ..Sym::default()
+            });
+        }
+        let remap = |file_idx: usize, sym: Sym| -> Result<Option<Sym>, LinkError> {
+            if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap()
+                || sym.st_shndx == usize::try_from(SHN_ABS).unwrap()
+                || sym.st_shndx == usize::try_from(SHN_COMMON).unwrap()
+            {
+                return Ok(Some(sym));
+            }
+            let (out_idx, placement) = match section_map.get(&(file_idx, sym.st_shndx)) {
+                Some(target) => target,
+                None => return Ok(None),
+            };
+            let mut value = headers[*out_idx].sh_addr
+                + u64::try_from(placement.translate(i64::try_from(sym.st_value).unwrap())).unwrap();
+            if st_type(sym.st_info) == STT_TLS {
+                value = value
+                    .checked_sub(u64::try_from(tls_start).unwrap())
+                    .ok_or_else(|| {
+                        LinkError::malformed(
+                            "",
+                            format!(
+                                "TLS symbol {} at {:#x} lies below the TLS segment",
+                                self.name(file_idx, &sym),
+                                value
+                            ),
+                        )
+                    })?;
+            }
+            Ok(Some(Sym {
+                st_shndx: section_key(*out_idx),
+                st_value: value,
+                ..sym
+            }))
+        };
+        // Map from file (idx, symbol idx) to the index in the output symbol table
+        let mut symbol_map: HashMap<(usize, usize), usize> = HashMap::new();
+        for file_idx in 0..self.by_file.len() {
+            let symtab = &self.by_file[&file_idx].0;
+            for (sym_idx, sym) in symtab.iter().copied().enumerate().skip(1) {
+                if st_bind(sym.st_info) != STB_LOCAL {
+                    continue;
+                }
+                if st_type(sym.st_info) == STT_SECTION {
+                    if let Some((out_idx, _)) = section_map.get(&(file_idx, sym.st_shndx)) {
+                        symbol_map.insert((file_idx, sym_idx), *out_idx);
+                    }
+                    continue;
+                }
+                // Locals in sections we drop are dropped as well.
+                if let Some(out_sym) = remap(file_idx, sym)? {
+                    let name = self.name(file_idx, &sym);
+                    symbol_map.insert((file_idx, sym_idx), symbols.len());
+                    symbols.push(Sym {
+                        st_name: strtab.add(name),
+                        ..out_sym
+                    });
+                }
+            }
+        }
+        // Globals come last and only once per name, definitions win over references.
+        let first_global = symbols.len();
+        let mut globals: HashMap<&str, usize> = HashMap::new();
+        for file_idx in 0..self.by_file.len() {
+            let symtab = &self.by_file[&file_idx].0;
+            for (sym_idx, sym) in symtab.iter().copied().enumerate().skip(1) {
+                if st_bind(sym.st_info) == STB_LOCAL {
+                    continue;
+                }
+                let name = self.name(file_idx, &sym);
+                let out_sym = remap(file_idx, sym)?.unwrap_or(Sym {
+                    st_shndx: usize::try_from(SHN_UNDEF).unwrap(),
+                    st_value: 0,
+                    ..sym
+                });
+                let out_sym = match self.linker_symbol(name) {
+                    Some(idx)
+                        if define_linker_symbols
+                            && out_sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() =>
+                    {
+                        Sym {
+                            st_shndx: usize::try_from(SHN_ABS).unwrap(),
+                            st_value: self.linker_symbols[idx].1,
+                            ..out_sym
+                        }
+                    }
+                    _ => out_sym,
+                };
+                let idx = match globals.get(name) {
+                    Some(idx) => {
+                        let existing = &mut symbols[*idx];
+                        let undefined = usize::try_from(SHN_UNDEF).unwrap();
+                        if existing.st_shndx == undefined && out_sym.st_shndx != undefined
+                            || st_bind(existing.st_info) == STB_WEAK
+                                && st_bind(out_sym.st_info) == STB_GLOBAL
+                                && out_sym.st_shndx != undefined
+                        {
+                            *existing = Sym {
+                                st_name: existing.st_name,
+                                ..out_sym
+                            };
+                        }
+                        *idx
+                    }
+                    None => {
+                        globals.insert(name, symbols.len());
+                        symbols.push(Sym {
+                            st_name: strtab.add(name),
+                            ..out_sym
+                        });
+                        symbols.len() - 1
+                    }
+                };
+                symbol_map.insert((file_idx, sym_idx), idx);
+            }
+        }
+
+        let mut relocations: Vec<(usize, Vec<Reloc>)> = Vec::new();
+        for reloc_sec in reloc_sections {
+            let file_idx = reloc_sec.applies_to_file;
+            let (out_idx, placement) = match section_map.get(&(file_idx, reloc_sec.applies_to_sec))
+            {
+                Some(target) => target,
+                None => continue,
+            };
+            let out_idx = *out_idx;
+            let out_relocs = match relocations.iter().position(|(idx, _)| *idx == out_idx) {
+                Some(pos) => &mut relocations[pos].1,
+                None => {
+                    relocations.push((out_idx, Vec::new()));
+                    &mut relocations.last_mut().unwrap().1
+                }
+            };
+            for reloc in reloc_sec.relocations.iter() {
+                let sym = self.get(file_idx, reloc.r_sym);
+                let a = reloc.r_addend.unwrap();
+                let (r_sym, r_addend) = if reloc.r_sym == 0 {
+                    (0, a)
+                } else if st_type(sym.st_info) == STT_SECTION {
+                    // The input section moved within the output section.
+                    let (sym_out_idx, sym_placement) = &section_map[&(file_idx, sym.st_shndx)];
+                    (*sym_out_idx, sym_placement.translate(a))
+                } else {
+                    (symbol_map[&(file_idx, reloc.r_sym)], a)
+                };
+                out_relocs.push(Reloc {
+                    r_offset: headers[out_idx].sh_addr
+                        + u64::try_from(
+                            placement.translate(i64::try_from(reloc.r_offset).unwrap()),
+                        )
+                        .unwrap(),
+                    r_addend: Some(r_addend),
+                    r_sym,
+                    r_type: reloc.r_type,
+                });
+            }
+        }
+        relocations.sort_by_key(|(idx, _)| *idx);
+        Ok(MergedSymbols {
+            symbols,
+            strtab,
+            first_global,
+            relocations,
+        })
+    }
+}
+
+/// The section header table of the output. Sections that are not part of a loaded
+/// segment, like the symbol table, are appended to the file starting at `start`.
+#[derive(Debug)]
+pub(crate) struct SectionHeaderTable {
+    pub(crate) headers: Vec<SectionHeader>,
+    names: Vec<String>,
+    shstrtab: StringTable,
+    pub(crate) start: usize,
+    pub(crate) contents: Vec<u8>,
+}
+
+impl SectionHeaderTable {
+    pub(crate) fn new(start: usize) -> Self {
+        SectionHeaderTable {
+            headers: vec![SectionHeader::default()],
+            names: vec![String::new()],
+            shstrtab: StringTable::new(),
+            start,
+            contents: Vec::new(),
+        }
+    }
+    // Add a header for a section whose contents are already in place.
+    pub(crate) fn push(&mut self, name: &str, header: SectionHeader) -> usize {
+        self.headers.push(SectionHeader {
+            sh_name: self.shstrtab.add(name),
+            ..header
+        });
+        self.names.push(String::from(name));
+        self.headers.len() - 1
+    }
+    // Add a section and place its contents after the previously appended ones.
+    fn append(&mut self, name: &str, header: SectionHeader, data: &[u8]) -> usize {
+        use goblin::elf::section_header::*;
+        let sec_align = usize::try_from(std::cmp::max(header.sh_addralign, 1)).unwrap();
+        let offset = align(self.start + self.contents.len(), sec_align);
+        self.contents.resize(offset - self.start, 0);
+        self.contents.extend_from_slice(data);
+        let sh_size = if header.sh_type == SHT_NOBITS {
+            header.sh_size
+        } else {
+            u64::try_from(data.len()).unwrap()
+        };
+        self.push(
+            name,
+            SectionHeader {
+                sh_offset: u64::try_from(offset).unwrap(),
+                sh_size,
+                ..header
+            },
+        )
+    }
+    // Append `.rela` sections for the relocations followed by `.symtab` and `.strtab`.
+    fn append_symbols(&mut self, merged: MergedSymbols, ctx: Ctx) -> Result<(), LinkError> {
+        use goblin::elf::section_header::*;
+        use goblin::elf::{Reloc, Sym};
+        let symtab_idx = self.headers.len() + merged.relocations.len();
+        let reloc_size = Reloc::size(true, ctx);
+        for (idx, relocations) in &merged.relocations {
+            let mut data = vec![0; relocations.len() * reloc_size];
+            let mut offset = 0;
+            for reloc in relocations {
+                data.gwrite_with(*reloc, &mut offset, (true, ctx))?;
+            }
+            let name = format!(".rela{}", self.names[*idx]);
+            self.append(
+                &name,
+                SectionHeader {
+                    sh_type: SHT_RELA,
+                    sh_flags: u64::from(SHF_INFO_LINK),
+                    sh_link: u32::try_from(symtab_idx).unwrap(),
+                    sh_info: u32::try_from(*idx).unwrap(),
+                    sh_addralign: u64::try_from(ctx.size()).unwrap(),
+                    sh_entsize: u64::try_from(reloc_size).unwrap(),
+                    ..SectionHeader::default()
+                },
+                &data,
+            );
+        }
+        let sym_size = Sym::size_with(&ctx);
+        let mut data = vec![0; merged.symbols.len() * sym_size];
+        let mut offset = 0;
+        // Indices that do not fit into st_shndx, empty unless there are that many sections
+        let mut shndx_table = Vec::new();
+        let xindex = usize::try_from(SHN_XINDEX).unwrap();
+        for (sym_idx, sym) in merged.symbols.into_iter().enumerate() {
+            let idx = section_index(sym.st_shndx);
+            let st_shndx = if idx >= usize::try_from(SHN_LORESERVE).unwrap()
+                && !is_reserved_shndx(sym.st_shndx)
+            {
+                shndx_table.resize(sym_idx + 1, 0);
+                shndx_table[sym_idx] = u32::try_from(idx).unwrap();
+                xindex
+            } else {
+                sym.st_shndx
+            };
+            data.gwrite_with(Sym { st_shndx, ..sym }, &mut offset, ctx)?;
+        }
+        let symbol_count = data.len() / sym_size;
+        self.append(
+            ".symtab",
+            SectionHeader {
+                sh_type: SHT_SYMTAB,
+                sh_link: u32::try_from(symtab_idx + 1).unwrap(),
+                sh_info: u32::try_from(merged.first_global).unwrap(),
+                sh_addralign: u64::try_from(ctx.size()).unwrap(),
+                sh_entsize: u64::try_from(sym_size).unwrap(),
+                ..SectionHeader::default()
+            },
+            &data,
+        );
+        self.append(
+            ".strtab",
+            SectionHeader {
+                sh_type: SHT_STRTAB,
+                sh_addralign: 1,
+                ..SectionHeader::default()
+            },
+            merged.strtab.as_bytes(),
+        );
+        if !shndx_table.is_empty() {
+            shndx_table.resize(symbol_count, 0);
+            let mut data = vec![0; shndx_table.len() * 4];
+            let mut offset = 0;
+            for idx in shndx_table {
+                data.gwrite_with(idx, &mut offset, ctx.le)?;
+            }
+            self.append(
+                ".symtab_shndx",
+                SectionHeader {
+                    sh_type: SHT_SYMTAB_SHNDX,
+                    sh_link: u32::try_from(symtab_idx).unwrap(),
+                    sh_addralign: 4,
+                    sh_entsize: 4,
+                    ..SectionHeader::default()
+                },
+                &data,
+            );
+        }
+        Ok(())
+    }
+    // Append `.shstrtab`, the contents and the header table to `buf` and point the ELF
+    // header at the table.
+    fn finish(mut self, buf: &mut OutputBuffer, ctx: Ctx) -> Result<(), LinkError> {
+        use goblin::elf::section_header::*;
+        use scroll::Pread;
+        assert_eq!(buf.len(), self.start);
+        // .shstrtab contains its own name so its contents are only final after the push.
+        let shstrndx = self.push(
+            ".shstrtab",
+            SectionHeader {
+                sh_type: SHT_STRTAB,
+                sh_offset: u64::try_from(self.start + self.contents.len()).unwrap(),
+                sh_addralign: 1,
+                ..SectionHeader::default()
+            },
+        );
+        self.headers[shstrndx].sh_size = u64::try_from(self.shstrtab.len()).unwrap();
+        self.contents.extend_from_slice(self.shstrtab.as_bytes());
+        // Counts and indices that do not fit into the ELF header go into the null section
+        // header.
+        let reserved = usize::try_from(SHN_LORESERVE).unwrap();
+        let shnum = if self.headers.len() >= reserved {
+            self.headers[0].sh_size = u64::try_from(self.headers.len()).unwrap();
+            0
+        } else {
+            u16::try_from(self.headers.len()).unwrap()
+        };
+        let shstrndx = if shstrndx >= reserved {
+            self.headers[0].sh_link = u32::try_from(shstrndx).unwrap();
+            u16::try_from(SHN_XINDEX).unwrap()
+        } else {
+            u16::try_from(shstrndx).unwrap()
+        };
+
+        let end = self.start + self.contents.len();
+        let shoff = align(end, ctx.size());
+        buf.resize(shoff + self.headers.len() * SectionHeader::size(ctx))?;
+        buf[self.start..end].copy_from_slice(&self.contents);
+        let mut offset = shoff;
+        for header in &self.headers {
+            buf.gwrite_with(header.clone(), &mut offset, ctx)?;
+        }
+        let elf_header: Header = buf.pread_with(0, ctx.le)?;
+        let elf_header = Header {
+            e_shoff: u64::try_from(shoff).unwrap(),
+            e_shnum: shnum,
+            e_shstrndx: shstrndx,
+            ..elf_header
+        };
+        buf.pwrite_with(elf_header, 0, ctx.le)?;
+        Ok(())
+    }
+}
+
+/// The linker-generated `.dynamic` section.
+#[derive(Debug)]
+pub(crate) struct DynamicSection {
+    pub(crate) address: usize,
+    pub(crate) entries: Vec<Dyn>,
+    pub(crate) strtab: StringTable,
+    pub(crate) strtab_address: usize,
+    // The exported symbols and dynamic relocations of a shared library
+    pub(crate) symbols: Option<DynamicSymbols>,
+}
+
+/// The `.hash` and `.dynsym` sections of a shared library with the symbols it exports,
+/// and its `.rela.dyn` with the relocations the dynamic loader applies for the address
+/// the library ends up at.
+#[derive(Debug)]
+pub(crate) struct DynamicSymbols {
+    // Resolved (file idx, symbol idx) of every exported symbol with its name in .dynstr,
+    // .dynsym starts with the null symbol in front of them
+    pub(crate) exports: Vec<((usize, usize), usize)>,
+    pub(crate) hash_address: usize,
+    pub(crate) symtab_address: usize,
+    // Number of R_X86_64_RELATIVE relocations in .rela.dyn
+    pub(crate) relocations: usize,
+    pub(crate) rela_address: usize,
+}
+
+impl DynamicSymbols {
+    fn count(&self) -> usize {
+        self.exports.len() + 1
+    }
+    // A bucket for each symbol, followed by the chain of each symbol.
+    pub(crate) fn hash_size(&self) -> usize {
+        (2 + 2 * self.count()) * 4
+    }
+    pub(crate) fn symtab_size(&self, ctx: Ctx) -> usize {
+        self.count() * goblin::elf::Sym::size_with(&ctx)
+    }
+    pub(crate) fn rela_size(&self, ctx: Ctx) -> usize {
+        self.relocations * goblin::elf::Reloc::size(true, ctx)
+    }
+}
+
+// The hash function of the System V ABI for .hash.
+fn elf_hash(name: &str) -> u32 {
+    let mut h: u32 = 0;
+    for c in name.bytes() {
+        h = (h << 4).wrapping_add(u32::from(c));
+        let g = h & 0xf000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+impl DynamicSection {
+    // The symbols in `exports` and `relocations` R_X86_64_RELATIVE relocations end up in
+    // the dynamic section of a shared library, executables have none.
+    pub(crate) fn new(
+        opts: &Opts,
+        exports: &[((usize, usize), &str)],
+        relocations: usize,
+        ctx: Ctx,
+    ) -> Self {
+        use goblin::elf::dynamic::*;
+        let mut entries = Vec::new();
+        let mut strtab = StringTable::new();
+        if let Some(soname) = &opts.soname {
+            entries.push(Dyn {
+                d_tag: DT_SONAME,
+                d_val: u64::try_from(strtab.add(soname)).unwrap(),
+            });
+        }
+        if !opts.rpath.is_empty() {
+            entries.push(Dyn {
+                d_tag: DT_RUNPATH,
+                d_val: u64::try_from(strtab.add(&opts.rpath.join(":"))).unwrap(),
+            });
+        }
+        if opts.bind_policy() == BindPolicy::Now {
+            entries.push(Dyn {
+                d_tag: DT_FLAGS,
+                d_val: DF_BIND_NOW,
+            });
+            entries.push(Dyn {
+                d_tag: DT_FLAGS_1,
+                d_val: DF_1_NOW,
+            });
+        }
+        let symbols = opts.shared.then(|| DynamicSymbols {
+            exports: exports
+                .iter()
+                .map(|(def, name)| (*def, strtab.add(name)))
+                .collect(),
+            hash_address: 0,
+            symtab_address: 0,
+            relocations,
+            rela_address: 0,
+        });
+        // The addresses of the tables are only known after allocation and get filled in
+        // by `write`.
+        if symbols.is_some() {
+            entries.push(Dyn {
+                d_tag: DT_HASH,
+                d_val: 0,
+            });
+            entries.push(Dyn {
+                d_tag: DT_SYMTAB,
+                d_val: 0,
+            });
+            entries.push(Dyn {
+                d_tag: DT_SYMENT,
+                d_val: u64::try_from(goblin::elf::Sym::size_with(&ctx)).unwrap(),
+            });
+        }
+        if relocations > 0 {
+            let rela_size = goblin::elf::Reloc::size(true, ctx);
+            entries.push(Dyn {
+                d_tag: DT_RELA,
+                d_val: 0,
+            });
+            entries.push(Dyn {
+                d_tag: DT_RELASZ,
+                d_val: u64::try_from(relocations * rela_size).unwrap(),
+            });
+            entries.push(Dyn {
+                d_tag: DT_RELAENT,
+                d_val: u64::try_from(rela_size).unwrap(),
+            });
+        }
+        if !strtab.is_empty() || symbols.is_some() {
+            entries.push(Dyn {
+                d_tag: DT_STRTAB,
+                d_val: 0,
+            });
+            entries.push(Dyn {
+                d_tag: DT_STRSZ,
+                d_val: u64::try_from(strtab.len()).unwrap(),
+            });
+        }
+        DynamicSection {
+            address: 0,
+            entries,
+            strtab,
+            strtab_address: 0,
+            symbols,
+        }
+    }
+    // Whether there is a .dynstr, a shared library always has one for .dynsym.
+    pub(crate) fn has_strtab(&self) -> bool {
+        !self.strtab.is_empty() || self.symbols.is_some()
+    }
+    // Place the tables that go into the read-only data at `offset`: .hash, .dynsym,
+    // .dynstr and .rela.dyn.
+    pub(crate) fn place_tables(&mut self, offset: &mut usize, ctx: Ctx) {
+        if let Some(symbols) = &mut self.symbols {
+            *offset = align(*offset, ctx.size());
+            symbols.hash_address = *offset;
+            *offset += symbols.hash_size();
+            *offset = align(*offset, ctx.size());
+            symbols.symtab_address = *offset;
+            *offset += symbols.symtab_size(ctx);
+        }
+        if self.has_strtab() {
+            self.strtab_address = *offset;
+            *offset += self.strtab.len();
+        }
+        if let Some(symbols) = &mut self.symbols {
+            *offset = align(*offset, ctx.size());
+            symbols.rela_address = *offset;
+            *offset += symbols.rela_size(ctx);
+        }
+    }
+    // We only emit a dynamic section if there is something to put in it.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub(crate) fn size(&self, ctx: Ctx) -> usize {
+        // Leave space for the terminating DT_NULL entry.
+        (self.entries.len() + 1) * Dyn::size_with(&ctx)
+    }
+    pub(crate) fn write(&self, buf: &mut [u8], base: usize, ctx: Ctx) -> Result<(), LinkError> {
+        use goblin::elf::dynamic::*;
+        let mut offset = self.address - base;
+        for entry in self.entries.iter().chain(std::iter::once(&Dyn::default())) {
+            let mut entry = entry.clone();
+            let address = match (entry.d_tag, &self.symbols) {
+                (DT_STRTAB, _) => Some(self.strtab_address),
+                (DT_HASH, Some(symbols)) => Some(symbols.hash_address),
+                (DT_SYMTAB, Some(symbols)) => Some(symbols.symtab_address),
+                (DT_RELA, Some(symbols)) => Some(symbols.rela_address),
+                _ => None,
+            };
+            if let Some(address) = address {
+                entry.d_val = u64::try_from(address).unwrap();
+            }
+            buf.gwrite_with(entry, &mut offset, ctx)?;
+        }
+        Ok(())
+    }
+    // .dynstr is in the read-only segment, `base` is where that part of the file is mapped.
+    fn write_strtab(&self, buf: &mut [u8], base: usize) -> Result<(), LinkError> {
+        if self.has_strtab() {
+            buf.pwrite_with(self.strtab.as_bytes(), self.strtab_address - base, ())?;
+        }
+        Ok(())
+    }
+}
+
+/// A string table in the format used by `.strtab` and friends.
+#[derive(Debug)]
+pub(crate) struct StringTable {
+    pub(crate) data: Vec<u8>,
+}
+
+impl StringTable {
+    pub(crate) fn new() -> Self {
+        // Index 0 is always the empty string.
+        StringTable { data: vec![0] }
+    }
+    pub(crate) fn add(&mut self, s: &str) -> usize {
+        let idx = self.data.len();
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        idx
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.len() == 1
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
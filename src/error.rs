@@ -0,0 +1,118 @@
+//! The ways a link can fail.
+
+use std::fmt;
+
+/// Why a link failed.
+#[derive(Debug)]
+pub enum LinkError {
+    /// A symbol is referenced but no input defines it.
+    UndefinedSymbol { symbol: String },
+    /// A relocation type, or a use of one, that the linker cannot apply.
+    UnsupportedRelocation { file: String, relocation: String },
+    /// An input the linker cannot read. `file` is empty if the error was not tied to a
+    /// particular input.
+    MalformedInput { file: String, reason: String },
+    /// Something does not fit where the layout puts it, e.g. a relocation value that is
+    /// too large for its field or sections that overlap.
+    LayoutOverflow { reason: String },
+    /// Reading an input or writing the output failed.
+    Io { path: String, error: std::io::Error },
+    /// Options that contradict each other or cannot be used for the inputs.
+    InvalidOptions { reason: String },
+    /// The [`CancellationToken`](crate::CancellationToken) of the link was cancelled.
+    Cancelled,
+}
+
+impl LinkError {
+    pub(crate) fn undefined(symbol: &str) -> Self {
+        LinkError::UndefinedSymbol {
+            symbol: String::from(symbol),
+        }
+    }
+    pub(crate) fn unsupported_relocation(file: &str, relocation: impl fmt::Display) -> Self {
+        LinkError::UnsupportedRelocation {
+            file: String::from(file),
+            relocation: relocation.to_string(),
+        }
+    }
+    pub(crate) fn malformed(file: &str, reason: impl fmt::Display) -> Self {
+        LinkError::MalformedInput {
+            file: String::from(file),
+            reason: reason.to_string(),
+        }
+    }
+    pub(crate) fn overflow(reason: impl fmt::Display) -> Self {
+        LinkError::LayoutOverflow {
+            reason: reason.to_string(),
+        }
+    }
+    pub(crate) fn io(path: &str, error: std::io::Error) -> Self {
+        LinkError::Io {
+            path: String::from(path),
+            error,
+        }
+    }
+    pub(crate) fn invalid_options(reason: impl fmt::Display) -> Self {
+        LinkError::InvalidOptions {
+            reason: reason.to_string(),
+        }
+    }
+    // Attribute an error that came up while reading `file` to it.
+    pub(crate) fn in_file(self, file: &str) -> Self {
+        match self {
+            LinkError::MalformedInput { file: old, reason } if old.is_empty() => {
+                LinkError::malformed(file, reason)
+            }
+            LinkError::UnsupportedRelocation {
+                file: old,
+                relocation,
+            } if old.is_empty() => LinkError::unsupported_relocation(file, relocation),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol { symbol } => write!(f, "undefined symbol: {}", symbol),
+            LinkError::UnsupportedRelocation { file, relocation } if file.is_empty() => {
+                write!(f, "unsupported relocation: {}", relocation)
+            }
+            LinkError::UnsupportedRelocation { file, relocation } => {
+                write!(f, "{}: unsupported relocation: {}", file, relocation)
+            }
+            LinkError::MalformedInput { file, reason } if file.is_empty() => {
+                write!(f, "{}", reason)
+            }
+            LinkError::MalformedInput { file, reason } => write!(f, "{}: {}", file, reason),
+            LinkError::LayoutOverflow { reason } => write!(f, "{}", reason),
+            LinkError::Io { path, error } => write!(f, "{}: {}", path, error),
+            LinkError::InvalidOptions { reason } => write!(f, "{}", reason),
+            LinkError::Cancelled => write!(f, "link cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LinkError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Errors from parsing or writing with goblin and scroll. Reading an input is the only
+// way these can come up, writing the output only uses offsets that the layout reserved.
+impl From<goblin::error::Error> for LinkError {
+    fn from(err: goblin::error::Error) -> Self {
+        LinkError::malformed("", err)
+    }
+}
+
+impl From<scroll::Error> for LinkError {
+    fn from(err: scroll::Error) -> Self {
+        LinkError::malformed("", err)
+    }
+}
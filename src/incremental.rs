@@ -0,0 +1,604 @@
+//! Incremental links. A link with `--incremental` leaves room after each code section and
+//! records where every input section went. The next one patches the output in place if
+//! only one object file changed and its sections still fit, otherwise it links everything
+//! again.
+
+use crate::emit::sha1;
+use crate::error::LinkError;
+use crate::input::parse_object;
+use crate::layout::{is_reserved_shndx, section_key, Output};
+use crate::relocate::{Fixup, RelocHandlers, RelocValue};
+use crate::target;
+use crate::{Opts, OutputFormat};
+use goblin::container::Ctx;
+use goblin::elf::{Elf, SectionHeader};
+use scroll::Pwrite;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+
+const HEADER: &str = "toy-linker incremental 1";
+
+// The room a code section of the given size gets in an incremental link.
+pub(crate) fn capacity(size: usize) -> usize {
+    size + size / 2 + 64
+}
+
+fn state_path(output: &str) -> String {
+    format!("{}.incremental", output)
+}
+
+fn hash(data: &[u8]) -> String {
+    sha1(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn options_hash(opts: &Opts) -> String {
+    hash(format!("{:?}", opts).as_bytes())
+}
+
+fn section_hash(object: &[u8], sec: &SectionHeader) -> Option<String> {
+    use goblin::elf::section_header::*;
+    if sec.sh_type == SHT_NOBITS {
+        return Some(hash(&sec.sh_size.to_le_bytes()));
+    }
+    let start = usize::try_from(sec.sh_offset).ok()?;
+    let end = start.checked_add(usize::try_from(sec.sh_size).ok()?)?;
+    Some(hash(object.get(start..end)?))
+}
+
+// Where an input section is in the output.
+#[derive(Clone, Copy, Debug)]
+struct Placement {
+    offset: usize,
+    address: usize,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct SectionState {
+    sh_type: u32,
+    sh_flags: u64,
+    hash: String,
+    // None for sections that are not copied as a whole, these cannot change
+    placement: Option<Placement>,
+}
+
+#[derive(Debug)]
+struct InputState {
+    hash: String,
+    name: String,
+    // The index in the output symbol table of the first local symbol of the file and the
+    // number of its local symbols there
+    first_local: usize,
+    locals: usize,
+    sections: Vec<SectionState>,
+}
+
+/// What a link with `--incremental` remembers about the output.
+#[derive(Debug)]
+struct State {
+    options: String,
+    output: String,
+    fill: u8,
+    inputs: Vec<InputState>,
+    // Address of each global symbol with the file defining it, None for linker symbols
+    symbols: HashMap<String, (Option<usize>, u64)>,
+}
+
+impl State {
+    fn parse(text: &str) -> Option<State> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let mut state = State {
+            options: String::new(),
+            output: String::new(),
+            fill: 0,
+            inputs: Vec::new(),
+            symbols: HashMap::new(),
+        };
+        for line in lines {
+            let (kind, rest) = line.split_once(' ')?;
+            match kind {
+                "options" => state.options = String::from(rest),
+                "output" => state.output = String::from(rest),
+                "fill" => state.fill = rest.parse().ok()?,
+                "input" => {
+                    let mut fields = rest.splitn(4, ' ');
+                    state.inputs.push(InputState {
+                        hash: String::from(fields.next()?),
+                        first_local: fields.next()?.parse().ok()?,
+                        locals: fields.next()?.parse().ok()?,
+                        name: String::from(fields.next()?),
+                        sections: Vec::new(),
+                    });
+                }
+                "section" => {
+                    let fields: Vec<&str> = rest.split(' ').collect();
+                    if fields.len() != 6 {
+                        return None;
+                    }
+                    let placement = if fields[3] == "-" {
+                        None
+                    } else {
+                        Some(Placement {
+                            offset: fields[3].parse().ok()?,
+                            address: fields[4].parse().ok()?,
+                            capacity: fields[5].parse().ok()?,
+                        })
+                    };
+                    state.inputs.last_mut()?.sections.push(SectionState {
+                        sh_type: fields[0].parse().ok()?,
+                        sh_flags: fields[1].parse().ok()?,
+                        hash: String::from(fields[2]),
+                        placement,
+                    });
+                }
+                "symbol" => {
+                    let mut fields = rest.splitn(3, ' ');
+                    let file = match fields.next()? {
+                        "-" => None,
+                        file => Some(file.parse().ok()?),
+                    };
+                    let address = fields.next()?.parse().ok()?;
+                    state
+                        .symbols
+                        .insert(String::from(fields.next()?), (file, address));
+                }
+                _ => return None,
+            }
+        }
+        Some(state)
+    }
+    fn write(&self) -> String {
+        use std::fmt::Write;
+        let mut out = format!(
+            "{}\noptions {}\noutput {}\nfill {}\n",
+            HEADER, self.options, self.output, self.fill
+        );
+        for input in &self.inputs {
+            writeln!(
+                out,
+                "input {} {} {} {}",
+                input.hash, input.first_local, input.locals, input.name
+            )
+            .unwrap();
+            for sec in &input.sections {
+                let placement = match sec.placement {
+                    Some(p) => format!("{} {} {}", p.offset, p.address, p.capacity),
+                    None => String::from("- - -"),
+                };
+                writeln!(
+                    out,
+                    "section {} {} {} {}",
+                    sec.sh_type, sec.sh_flags, sec.hash, placement
+                )
+                .unwrap();
+            }
+        }
+        let mut symbols: Vec<_> = self.symbols.iter().collect();
+        symbols.sort_unstable();
+        for (name, (file, address)) in symbols {
+            let file = file.map_or(String::from("-"), |file| file.to_string());
+            writeln!(out, "symbol {} {} {}", file, address, name).unwrap();
+        }
+        out
+    }
+}
+
+impl<'a> Output<'a> {
+    // Remember the layout of a full link for the next incremental link.
+    pub(crate) fn write_incremental_state(
+        &self,
+        opts: &Opts,
+        output: &[u8],
+        ctx: Ctx,
+    ) -> Result<(), LinkError> {
+        use goblin::elf::section_header::*;
+        use goblin::elf::sym::*;
+        let placements: HashMap<(usize, goblin::elf::ShdrIdx), Placement> = self
+            .sections
+            .iter()
+            .map(|sec| {
+                let key = (sec.input_section.file_idx, sec.input_section.shdr_idx);
+                let placement = Placement {
+                    offset: self.file_offset(sec.address),
+                    address: sec.address,
+                    capacity: sec.capacity,
+                };
+                (key, placement)
+            })
+            .collect();
+        // Local symbols come right after the section symbols in the order of the files,
+        // like `SymbolTable::merge` writes them.
+        let (table, section_map) = self.section_headers(ctx);
+        let mut first_local = table.headers.len();
+        let mut inputs = Vec::new();
+        for (file_idx, buf) in self.file_buffers.iter().enumerate() {
+            let elf = Elf::parse(buf)?;
+            let sections = elf
+                .section_headers
+                .iter()
+                .enumerate()
+                .map(|(idx, sec)| SectionState {
+                    sh_type: sec.sh_type,
+                    sh_flags: sec.sh_flags,
+                    hash: section_hash(buf, sec).unwrap_or_default(),
+                    placement: placements.get(&(file_idx, section_key(idx))).copied(),
+                })
+                .collect();
+            let locals = self.symtab.by_file[&file_idx]
+                .0
+                .iter()
+                .skip(1)
+                .filter(|sym| {
+                    st_bind(sym.st_info) == STB_LOCAL
+                        && st_type(sym.st_info) != STT_SECTION
+                        && (sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap()
+                            || sym.st_shndx == usize::try_from(SHN_ABS).unwrap()
+                            || sym.st_shndx == usize::try_from(SHN_COMMON).unwrap()
+                            || section_map.contains_key(&(file_idx, sym.st_shndx)))
+                })
+                .count();
+            inputs.push(InputState {
+                hash: hash(buf),
+                name: self.file_names[file_idx].clone(),
+                first_local,
+                locals,
+                sections,
+            });
+            first_local += locals;
+        }
+        let mut symbols: HashMap<String, (Option<usize>, u64)> = self
+            .symtab
+            .globals
+            .iter()
+            .filter(|(_, (file_idx, sym_idx))| self.is_placed(*file_idx, *sym_idx))
+            .map(|(name, (file_idx, sym_idx))| {
+                let address = u64::try_from(self.symbol_address(*file_idx, *sym_idx)?).unwrap();
+                Ok((String::from(*name), (Some(*file_idx), address)))
+            })
+            .collect::<Result<_, LinkError>>()?;
+        for (name, value) in &self.symtab.linker_symbols {
+            symbols.insert(String::from(*name), (None, *value));
+        }
+        let state = State {
+            options: options_hash(opts),
+            output: hash(output),
+            fill: self.fill,
+            inputs,
+            symbols,
+        };
+        let path = state_path(&opts.output);
+        fs::write(&path, state.write()).map_err(|err| LinkError::io(&path, err))
+    }
+}
+
+// Patch the output of the previous incremental link for the inputs. Returns None if that
+// is not possible and everything needs to be linked again.
+pub(crate) fn patch_output(
+    opts: &Opts,
+    inputs: &[(String, Vec<u8>)],
+    handlers: &RelocHandlers,
+) -> Result<Option<Vec<u8>>, LinkError> {
+    // Anything else written about the output would be out of date after a patch.
+    if opts.oformat != OutputFormat::Elf
+        || opts.relocatable
+        || opts.emit_relocs
+        || opts.build_id.is_some()
+        || opts.map.is_some()
+        || opts.layout_json.is_some()
+        || opts.print_size
+        || opts.output == "-"
+    {
+        return Ok(None);
+    }
+    let path = state_path(&opts.output);
+    let mut state = match fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| State::parse(&s))
+    {
+        Some(state) => state,
+        None => return Ok(None),
+    };
+    let mut output = match fs::read(&opts.output) {
+        Ok(output) => output,
+        Err(_) => return Ok(None),
+    };
+    if state.options != options_hash(opts)
+        || state.output != hash(&output)
+        || state.inputs.len() != inputs.len()
+        || state
+            .inputs
+            .iter()
+            .zip(inputs)
+            .any(|(input, (name, _))| input.name != *name)
+    {
+        return Ok(None);
+    }
+    let changed: Vec<usize> = inputs
+        .iter()
+        .enumerate()
+        .filter(|(idx, (_, buf))| state.inputs[*idx].hash != hash(buf))
+        .map(|(idx, _)| idx)
+        .collect();
+    let file_idx = match changed[..] {
+        [] => return Ok(Some(output)),
+        [file_idx] => file_idx,
+        _ => return Ok(None),
+    };
+    let object = &inputs[file_idx].1;
+    if patch(&state, &mut output, file_idx, object, handlers).is_none() {
+        return Ok(None);
+    }
+    let elf = parse_object(object)?;
+    let input = &mut state.inputs[file_idx];
+    input.hash = hash(object);
+    for (sec, header) in input.sections.iter_mut().zip(&elf.section_headers) {
+        sec.hash = section_hash(object, header).unwrap_or_default();
+    }
+    state.output = hash(&output);
+    fs::write(&path, state.write()).map_err(|err| LinkError::io(&path, err))?;
+    Ok(Some(output))
+}
+
+// Write the new version of one object file into the output. Returns None without
+// touching `buf` if anything besides the contents of its sections changed in a way that
+// affects other parts of the output.
+fn patch(
+    state: &State,
+    buf: &mut [u8],
+    file_idx: usize,
+    object: &[u8],
+    handlers: &RelocHandlers,
+) -> Option<()> {
+    use goblin::elf::section_header::*;
+    use goblin::elf::sym::*;
+    let input = &state.inputs[file_idx];
+    let elf = parse_object(object).ok()?;
+    let out = Elf::parse(buf).ok()?;
+    let ctx = Ctx::new(out.header.container().ok()?, out.header.endianness().ok()?);
+    let machine = out.header.e_machine;
+    // Thumb code needs the mapping symbols to find symbol addresses.
+    if elf.header.e_machine != machine
+        || target::for_object(machine, ctx).ok()?.interworking()
+        || elf.section_headers.len() != input.sections.len()
+    {
+        return None;
+    }
+    let placement = |shndx: usize| input.sections.get(shndx)?.placement;
+    for (idx, (sec, old)) in elf.section_headers.iter().zip(&input.sections).enumerate() {
+        if sec.sh_type != old.sh_type || sec.sh_flags != old.sh_flags {
+            return None;
+        }
+        // Relocations of placed sections are applied again below and the symbols are
+        // compared one by one, everything else has to stay as it is.
+        let patched = match sec.sh_type {
+            SHT_REL | SHT_RELA => placement(usize::try_from(sec.sh_info).ok()?).is_some(),
+            SHT_SYMTAB => true,
+            SHT_STRTAB => idx != usize::from(elf.header.e_shstrndx),
+            _ => old.placement.is_some(),
+        };
+        if !patched && section_hash(object, sec)? != old.hash {
+            return None;
+        }
+        if let Some(p) = old.placement {
+            let align = std::cmp::max(sec.sh_addralign, 1);
+            if usize::try_from(sec.sh_size).ok()? > p.capacity
+                || u64::try_from(p.address).ok()? % align != 0
+            {
+                return None;
+            }
+        }
+    }
+
+    // The address of a symbol defined in the object file
+    let sym_address = |sym: &Sym| -> Option<u64> {
+        if st_type(sym.st_info) == STT_TLS {
+            return None;
+        }
+        if sym.st_shndx == usize::try_from(SHN_ABS).unwrap() {
+            return Some(sym.st_value);
+        }
+        if is_reserved_shndx(sym.st_shndx) || sym.st_shndx == 0 {
+            return None;
+        }
+        let base = if st_type(sym.st_info) == STT_SECTION {
+            0
+        } else {
+            sym.st_value
+        };
+        u64::try_from(placement(sym.st_shndx)?.address)
+            .ok()?
+            .checked_add(base)
+    };
+    let recorded = |name: &str| state.symbols.get(name).copied();
+    let mut locals = Vec::new();
+    let mut globals = Vec::new();
+    for sym in elf.syms.iter().skip(1) {
+        let name = elf.strtab.get_unsafe(sym.st_name)?;
+        if st_bind(sym.st_info) == STB_LOCAL {
+            if st_type(sym.st_info) == STT_SECTION {
+                continue;
+            }
+            if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() {
+                locals.push((name, sym));
+            } else {
+                locals.push((
+                    name,
+                    Sym {
+                        st_value: sym_address(&sym)?,
+                        ..sym
+                    },
+                ));
+            }
+        } else if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() {
+            recorded(name)?;
+        } else {
+            match recorded(name)? {
+                (Some(file), address) if file == file_idx => {
+                    if sym_address(&sym)? != address {
+                        return None;
+                    }
+                    globals.push((name, sym));
+                }
+                // A weak definition that lost against one in another file
+                _ if st_bind(sym.st_info) == STB_WEAK => {}
+                _ => return None,
+            }
+        }
+    }
+    let defined_here = state
+        .symbols
+        .values()
+        .filter(|(file, _)| *file == Some(file_idx))
+        .count();
+    if locals.len() != input.locals || globals.len() != defined_here {
+        return None;
+    }
+    // Relocations of sections that are not patched only stay valid if their symbols
+    // keep their addresses.
+    for (shndx, relocs) in &elf.shdr_relocs {
+        let target = usize::try_from(elf.section_headers[*shndx].sh_info).ok()?;
+        if placement(target).is_none() {
+            for reloc in relocs.iter() {
+                let sym = elf.syms.get(reloc.r_sym)?;
+                if st_bind(sym.st_info) == STB_LOCAL && st_type(sym.st_info) != STT_SECTION {
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Match the symbols with their entries in the output symbol table.
+    let symtab = out
+        .section_headers
+        .iter()
+        .find(|sec| sec.sh_type == SHT_SYMTAB)?;
+    let symtab_offset = usize::try_from(symtab.sh_offset).ok()?;
+    let sym_size = usize::try_from(symtab.sh_entsize).ok()?;
+    let mut updates = Vec::new();
+    for (i, (name, sym)) in locals.iter().enumerate() {
+        let idx = input.first_local + i;
+        let old = out.syms.get(idx)?;
+        if out.strtab.get_unsafe(old.st_name)? != *name {
+            return None;
+        }
+        updates.push((
+            idx,
+            Sym {
+                st_name: old.st_name,
+                st_shndx: old.st_shndx,
+                ..*sym
+            },
+        ));
+    }
+    for (name, sym) in &globals {
+        let (idx, old) = out.syms.iter().enumerate().find(|(_, old)| {
+            st_bind(old.st_info) != STB_LOCAL && out.strtab.get_unsafe(old.st_name) == Some(*name)
+        })?;
+        updates.push((
+            idx,
+            Sym {
+                st_size: sym.st_size,
+                ..old
+            },
+        ));
+    }
+
+    // Apply the relocations to a copy of the new contents so that nothing is written
+    // unless all of them fit.
+    let mut patched: Vec<(Placement, Vec<u8>, bool)> = Vec::new();
+    let mut by_section = HashMap::new();
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        let p = match placement(idx) {
+            Some(p) => p,
+            None => continue,
+        };
+        let mut contents = vec![0; p.capacity];
+        if sec.sh_type != SHT_NOBITS {
+            let start = usize::try_from(sec.sh_offset).ok()?;
+            let size = usize::try_from(sec.sh_size).ok()?;
+            contents[..size].copy_from_slice(object.get(start..start + size)?);
+            if sec.sh_flags & u64::from(SHF_EXECINSTR) != 0 {
+                contents[size..].fill(state.fill);
+            }
+        }
+        by_section.insert(idx, patched.len());
+        patched.push((p, contents, sec.sh_type != SHT_NOBITS));
+    }
+    for (shndx, relocs) in &elf.shdr_relocs {
+        let target = usize::try_from(elf.section_headers[*shndx].sh_info).ok()?;
+        let (p, contents, _) = match by_section.get(&target) {
+            Some(idx) => &mut patched[*idx],
+            None => continue,
+        };
+        for reloc in relocs.iter() {
+            let (handler, howto) = handlers.howto(machine, reloc.r_type).ok()?;
+            if howto.value != RelocValue::Absolute && howto.value != RelocValue::PcRelative {
+                return None;
+            }
+            let s = match reloc.r_sym {
+                0 => 0,
+                r_sym => {
+                    let sym = elf.syms.get(r_sym)?;
+                    if st_bind(sym.st_info) == STB_LOCAL {
+                        sym_address(&sym)?
+                    } else {
+                        recorded(elf.strtab.get_unsafe(sym.st_name)?)?.1
+                    }
+                }
+            };
+            let offset = usize::try_from(reloc.r_offset).ok()?;
+            let addend = match reloc.r_addend {
+                Some(addend) => addend,
+                None => howto.field.read(contents, offset, ctx).ok()?,
+            };
+            let fixup = Fixup {
+                offset,
+                addend,
+                value: howto.value,
+                field: howto.field,
+            };
+            let s = i64::try_from(s).ok()?;
+            let relaxed = handler.relax(contents, reloc.r_type, fixup, s).ok()?;
+            if relaxed.consumes_next {
+                return None;
+            }
+            let fixup = match relaxed.fixup {
+                Some(fixup) => fixup,
+                None => continue,
+            };
+            let a = fixup.addend;
+            // Addends can be anything, the fields are checked for overflow instead. A full
+            // link reports the ones that do not fit.
+            let value = match fixup.value {
+                RelocValue::Absolute => s.wrapping_add(a),
+                RelocValue::PcRelative => {
+                    let p = i64::try_from(p.address.checked_add(fixup.offset)?).ok()?;
+                    s.wrapping_add(a).wrapping_sub(p)
+                }
+                _ => return None,
+            };
+            if !fixup.field.fits(value) {
+                return None;
+            }
+            fixup.field.write(contents, fixup.offset, value, ctx).ok()?;
+        }
+    }
+
+    drop(out);
+    for (p, contents, in_file) in patched {
+        // Empty sections may be past the end of the file.
+        if in_file && p.capacity > 0 {
+            buf.get_mut(p.offset..p.offset + p.capacity)?
+                .copy_from_slice(&contents);
+        }
+    }
+    for (idx, sym) in updates {
+        buf.pwrite_with(sym, symtab_offset + idx * sym_size, ctx)
+            .ok()?;
+    }
+    Some(())
+}
@@ -0,0 +1,715 @@
+//! Reading ELF object files into the sections, relocations and symbols of a link.
+
+use crate::error::LinkError;
+use crate::layout::{is_reserved_shndx, output_section_name, section_index, section_key};
+use crate::relocate::RelocHandlers;
+use crate::resolve::SymbolTable;
+use crate::target::{self, Target, DEFAULT_TARGET};
+use crate::{SectionRename, SHT_ARM_ATTRIBUTES};
+use goblin::container::Ctx;
+use goblin::elf::SectionHeader;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+#[derive(Debug)]
+pub(crate) struct InputSection<'a> {
+    pub(crate) file_idx: usize,
+    pub(crate) shdr_idx: goblin::elf::ShdrIdx,
+    pub(crate) section: SectionHeader,
+    // Inline name for debugging
+    #[allow(dead_code)]
+    pub(crate) name: &'a str,
+}
+
+#[derive(Debug)]
+pub(crate) struct RelocationSection {
+    pub(crate) applies_to_file: usize,
+    pub(crate) applies_to_sec: goblin::elf::ShdrIdx,
+    // File offset of the section the relocations apply to
+    pub(crate) applies_to_offset: usize,
+    // Relocations with their addends, implicit ones are read from the section contents
+    pub(crate) relocations: Vec<goblin::elf::Reloc>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Input<'a> {
+    pub(crate) ctx: Ctx,
+    // From --target or the first object file
+    pub(crate) target: Option<&'static dyn Target>,
+    pub(crate) flags: u32,
+    pub(crate) osabi: u8,
+    pub(crate) file_names: Vec<String>,
+    pub(crate) file_buffers: Vec<&'a [u8]>,
+    pub(crate) code_sections: Vec<InputSection<'a>>,
+    pub(crate) tls_data_sections: Vec<InputSection<'a>>,
+    pub(crate) tls_bss_sections: Vec<InputSection<'a>>,
+    pub(crate) relro_sections: Vec<InputSection<'a>>,
+    pub(crate) data_sections: Vec<InputSection<'a>>,
+    pub(crate) bss_sections: Vec<InputSection<'a>>,
+    pub(crate) ro_data_sections: Vec<InputSection<'a>>,
+    pub(crate) merge_sections: Vec<InputSection<'a>>,
+    pub(crate) init_array_sections: Vec<InputSection<'a>>,
+    pub(crate) eh_frame_sections: Vec<InputSection<'a>>,
+    pub(crate) debug_sections: Vec<InputSection<'a>>,
+    pub(crate) comment_sections: Vec<InputSection<'a>>,
+    pub(crate) note_sections: Vec<InputSection<'a>>,
+    pub(crate) property_sections: Vec<InputSection<'a>>,
+    pub(crate) reloc_sections: Vec<RelocationSection>,
+    pub(crate) section_names: HashMap<(usize, goblin::elf::ShdrIdx), &'a str>,
+    pub(crate) symtab: SymbolTable<'a>,
+    // Position of code sections in the --symbol-ordering-file
+    pub(crate) section_order: HashMap<(usize, goblin::elf::ShdrIdx), usize>,
+    // Names of the .gnu.linkonce sections seen so far, later copies are discarded
+    linkonce_sections: HashSet<&'a str>,
+    // Sections dropped as duplicates or by --gc-sections
+    pub(crate) discarded_sections: Vec<InputSection<'a>>,
+    // Files with SHN_COMMON symbols and the key of the section their space goes into, one
+    // past the sections of the file
+    common_sections: Vec<(usize, goblin::elf::ShdrIdx)>,
+    // The symbol the executable starts at
+    pub(crate) entry_symbol: String,
+    // Whether the output is a shared library, which can do without an entry point
+    pub(crate) shared: bool,
+    pub(crate) reloc_handlers: RelocHandlers<'a>,
+}
+
+impl<'a> Input<'a> {
+    pub(crate) fn new() -> Self {
+        Input {
+            ctx: Ctx::default(),
+            target: None,
+            flags: 0,
+            osabi: 0,
+            file_names: vec![],
+            file_buffers: vec![],
+            code_sections: vec![],
+            tls_data_sections: vec![],
+            tls_bss_sections: vec![],
+            relro_sections: vec![],
+            data_sections: vec![],
+            bss_sections: vec![],
+            ro_data_sections: vec![],
+            merge_sections: vec![],
+            init_array_sections: vec![],
+            eh_frame_sections: vec![],
+            debug_sections: vec![],
+            comment_sections: vec![],
+            note_sections: vec![],
+            property_sections: vec![],
+            reloc_sections: vec![],
+            section_names: HashMap::new(),
+            section_order: HashMap::new(),
+            linkonce_sections: HashSet::new(),
+            discarded_sections: vec![],
+            common_sections: vec![],
+            entry_symbol: String::from("_start"),
+            shared: false,
+            reloc_handlers: RelocHandlers::new(&[]),
+            symtab: SymbolTable::new(),
+        }
+    }
+
+    pub(crate) fn set_target(&mut self, target: &'static dyn Target) -> &'static dyn Target {
+        self.target = Some(target);
+        self.ctx = target.ctx();
+        target
+    }
+    pub(crate) fn target(&self) -> &'static dyn Target {
+        self.target.unwrap_or(DEFAULT_TARGET)
+    }
+
+    pub(crate) fn process_object_file(
+        &mut self,
+        name: &str,
+        file: &'a [u8],
+    ) -> Result<(), LinkError> {
+        use goblin::elf::header::machine_to_str;
+        use goblin::elf::section_header::*;
+        use scroll::Pread;
+        let elf = parse_object(file)?;
+        let file_idx = self.file_buffers.len();
+        self.file_names.push(String::from(name));
+        self.file_buffers.push(file);
+        let machine = elf.header.e_machine;
+        let ctx = Ctx::new(
+            if elf.is_64 {
+                goblin::container::Container::Big
+            } else {
+                goblin::container::Container::Little
+            },
+            scroll::Endian::from(elf.little_endian),
+        );
+        if file_idx == 0 {
+            self.flags = elf.header.e_flags;
+            self.osabi = elf.header.e_ident[goblin::elf::header::EI_OSABI];
+        }
+        let object_target = target::for_object(machine, ctx)?;
+        let target = match self.target {
+            Some(target) => target,
+            None => self.set_target(object_target),
+        };
+        if machine != target.machine() {
+            return Err(LinkError::malformed(
+                name,
+                format!(
+                    "machine {} does not match {}",
+                    machine_to_str(machine),
+                    machine_to_str(target.machine())
+                ),
+            ));
+        } else if ctx.le != self.ctx.le {
+            // Every relocation is written with the byte order of the output.
+            return Err(LinkError::malformed(
+                name,
+                "byte order does not match the other inputs",
+            ));
+        }
+        // With more than SHN_LORESERVE sections the index of .shstrtab is in the null
+        // section header.
+        let shdr_strtab = if u32::from(elf.header.e_shstrndx) == SHN_XINDEX {
+            let shdr = elf
+                .section_headers
+                .get(usize::try_from(elf.section_headers[0].sh_link).unwrap())
+                .ok_or_else(|| LinkError::malformed("", "Invalid section name table index"))?;
+            goblin::strtab::Strtab::parse(
+                file,
+                usize::try_from(shdr.sh_offset).unwrap(),
+                usize::try_from(shdr.sh_size).unwrap(),
+                0,
+            )?
+        } else {
+            elf.shdr_strtab
+        };
+        // Pre-COMDAT toolchains put inline functions and the like in .gnu.linkonce
+        // sections, only the first section of each name is kept.
+        let section_names = elf
+            .section_headers
+            .iter()
+            .map(|sec| match shdr_strtab.get(sec.sh_name) {
+                Some(Ok(name)) => Ok(name),
+                _ => Err(LinkError::malformed(
+                    "",
+                    format!("Invalid section name offset {}", sec.sh_name),
+                )),
+            })
+            .collect::<Result<Vec<&str>, LinkError>>()?;
+        let discarded: HashSet<goblin::elf::ShdrIdx> = section_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| {
+                name.starts_with(".gnu.linkonce.") && !self.linkonce_sections.insert(name)
+            })
+            .map(|(idx, _)| section_key(idx))
+            .collect();
+        let handlers = &self.reloc_handlers;
+        let sym_count = elf.syms.len();
+        let section_headers = &elf.section_headers;
+        for (i, reloc) in elf.shdr_relocs {
+            let sec = &elf.section_headers[i];
+            let applies_to_idx = usize::try_from(sec.sh_info).unwrap();
+            let applies_to_sec = section_key(applies_to_idx);
+            if discarded.contains(&applies_to_sec) {
+                continue;
+            }
+            let applies_to = elf.section_headers.get(applies_to_idx).ok_or_else(|| {
+                LinkError::malformed(
+                    "",
+                    format!("Relocations for invalid section index {}", applies_to_idx),
+                )
+            })?;
+            let applies_to_offset = usize::try_from(applies_to.sh_offset).unwrap();
+            // SHT_REL relocations have no addend field, the addend is stored in the bytes
+            // that get relocated instead.
+            let relocations = reloc
+                .iter()
+                .map(|reloc| {
+                    check_relocation(&reloc, applies_to, sym_count, handlers, machine)?;
+                    let r_addend = match reloc.r_addend {
+                        Some(a) => a,
+                        None => handlers.howto(machine, reloc.r_type)?.1.field.read(
+                            file,
+                            applies_to_offset + usize::try_from(reloc.r_offset).unwrap(),
+                            ctx,
+                        )?,
+                    };
+                    Ok(goblin::elf::Reloc {
+                        r_addend: Some(r_addend),
+                        ..reloc
+                    })
+                })
+                .collect::<Result<_, LinkError>>()?;
+            let reloc_sec = RelocationSection {
+                applies_to_file: file_idx,
+                applies_to_sec,
+                applies_to_offset,
+                relocations,
+            };
+            self.reloc_sections.push(reloc_sec);
+        }
+        // Symbols in sections from SHN_LORESERVE on have SHN_XINDEX as their section index,
+        // the real one is in the SHT_SYMTAB_SHNDX section.
+        let shndx_table = elf
+            .section_headers
+            .iter()
+            .find(|sec| sec.sh_type == SHT_SYMTAB_SHNDX)
+            .map(|sec| usize::try_from(sec.sh_offset).unwrap());
+        let syms: Vec<goblin::elf::Sym> = elf
+            .syms
+            .iter()
+            .enumerate()
+            .map(|(sym_idx, sym)| {
+                let st_shndx = match shndx_table {
+                    Some(table) if sym.st_shndx == usize::try_from(SHN_XINDEX).unwrap() => {
+                        let idx = file.pread_with::<u32>(table + 4 * sym_idx, ctx.le)?;
+                        section_key(usize::try_from(idx).unwrap())
+                    }
+                    _ => sym.st_shndx,
+                };
+                if !is_reserved_shndx(st_shndx) {
+                    // Symbols can be at the end of their section but not past it.
+                    let sec = section_headers.get(section_index(st_shndx));
+                    if sec.is_none_or(|sec| st_shndx != 0 && sym.st_value > sec.sh_size) {
+                        return Err(LinkError::malformed(
+                            "",
+                            format!("Symbol {} is outside of its section", sym_idx),
+                        ));
+                    }
+                }
+                // Global definitions in discarded sections refer to the kept copy.
+                let st_shndx = if discarded.contains(&st_shndx)
+                    && goblin::elf::sym::st_bind(sym.st_info) != goblin::elf::sym::STB_LOCAL
+                {
+                    usize::try_from(SHN_UNDEF).unwrap()
+                } else {
+                    st_shndx
+                };
+                Ok(goblin::elf::Sym { st_shndx, ..sym })
+            })
+            .collect::<Result<_, LinkError>>()?;
+        if syms
+            .iter()
+            .any(|sym| sym.st_shndx == usize::try_from(SHN_COMMON).unwrap())
+        {
+            self.common_sections
+                .push((file_idx, section_key(section_headers.len())));
+        }
+        self.symtab.insert(file_idx, syms, elf.strtab);
+        for ((idx, sec), name) in elf
+            .section_headers
+            .into_iter()
+            .enumerate()
+            .zip(section_names)
+        {
+            let idx = section_key(idx);
+            self.section_names.insert((file_idx, idx), name);
+            if discarded.contains(&idx) {
+                self.discarded_sections.push(InputSection {
+                    file_idx,
+                    shdr_idx: idx,
+                    section: sec,
+                    name,
+                });
+                continue;
+            }
+            match sec.sh_type {
+                SHT_PROGBITS => {
+                    let input_sec = InputSection {
+                        file_idx,
+                        shdr_idx: idx,
+                        section: sec,
+                        name,
+                    };
+                    if name == ".eh_frame" {
+                        self.eh_frame_sections.push(input_sec);
+                    } else if name.starts_with(".debug")
+                        && input_sec.section.sh_flags & u64::from(SHF_ALLOC) == 0
+                    {
+                        if input_sec.section.sh_flags & u64::from(SHF_COMPRESSED) != 0 {
+                            return Err(LinkError::malformed(
+                                "",
+                                format!("Compressed debug sections are not supported: {}", name),
+                            ));
+                        }
+                        self.debug_sections.push(input_sec);
+                    } else if name == ".comment" {
+                        self.comment_sections.push(input_sec);
+                    } else if input_sec.section.sh_flags == u64::from(SHF_ALLOC | SHF_EXECINSTR) {
+                        self.code_sections.push(input_sec);
+                    } else if input_sec.section.sh_flags
+                        == u64::from(SHF_ALLOC | SHF_WRITE | SHF_TLS)
+                    {
+                        self.tls_data_sections.push(input_sec);
+                    } else if input_sec.section.sh_flags == u64::from(SHF_ALLOC | SHF_WRITE) {
+                        if output_section_name(name) == ".data.rel.ro" {
+                            self.relro_sections.push(input_sec);
+                        } else {
+                            self.data_sections.push(input_sec);
+                        }
+                    } else if input_sec.section.sh_flags & !u64::from(SHF_MERGE | SHF_STRINGS)
+                        == u64::from(SHF_ALLOC)
+                    {
+                        if input_sec.section.sh_flags & u64::from(SHF_MERGE) != 0
+                            && input_sec.section.sh_entsize != 0
+                        {
+                            self.merge_sections.push(input_sec);
+                        } else {
+                            self.ro_data_sections.push(input_sec);
+                        }
+                    } else if input_sec.section.sh_flags & u64::from(SHF_ALLOC)
+                        == u64::from(SHF_ALLOC)
+                    {
+                        // Reject unknown alloc flags, we ignore non-alloc sections.
+                        return Err(LinkError::malformed(
+                            "",
+                            format!("Unknown flags {} in {}", input_sec.section.sh_flags, name),
+                        ));
+                    }
+                }
+                SHT_NOBITS if sec.sh_flags & u64::from(SHF_ALLOC) != 0 => {
+                    let input_sec = InputSection {
+                        file_idx,
+                        shdr_idx: idx,
+                        section: sec,
+                        name,
+                    };
+                    if input_sec.section.sh_flags & u64::from(SHF_TLS) != 0 {
+                        self.tls_bss_sections.push(input_sec);
+                    } else {
+                        self.bss_sections.push(input_sec);
+                    }
+                }
+                SHT_PREINIT_ARRAY | SHT_INIT_ARRAY | SHT_FINI_ARRAY => {
+                    self.init_array_sections.push(InputSection {
+                        file_idx,
+                        shdr_idx: idx,
+                        section: sec,
+                        name,
+                    });
+                }
+                SHT_NOTE if sec.sh_flags & u64::from(SHF_ALLOC) != 0 => {
+                    let input_sec = InputSection {
+                        file_idx,
+                        shdr_idx: idx,
+                        section: sec,
+                        name,
+                    };
+                    if name == ".note.gnu.property" {
+                        self.property_sections.push(input_sec);
+                    } else {
+                        self.note_sections.push(input_sec);
+                    }
+                }
+                SHT_NULL | SHT_NOBITS | SHT_NOTE | SHT_REL | SHT_RELA | SHT_SYMTAB | SHT_STRTAB
+                | SHT_SYMTAB_SHNDX | SHT_ARM_ATTRIBUTES => {}
+                unknown => {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!(
+                            "Unknown section type: {} ({})",
+                            goblin::elf::section_header::sht_to_str(unknown),
+                            unknown
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+    // Rank each code section by the first symbol in `symbols` that it defines, local
+    // symbols count too as profiles also list static functions.
+    pub(crate) fn set_symbol_order(&mut self, symbols: &[&str]) {
+        use goblin::elf::section_header::*;
+        let ranks: HashMap<&str, usize> = symbols
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(rank, name)| (*name, rank))
+            .collect();
+        let mut found = HashSet::new();
+        for (file_idx, (syms, strtab)) in &self.symtab.by_file {
+            for sym in syms.iter() {
+                let name = strtab.get_unsafe(sym.st_name).unwrap_or("");
+                let rank = match ranks.get(name) {
+                    Some(rank) => *rank,
+                    None => continue,
+                };
+                if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap()
+                    || is_reserved_shndx(sym.st_shndx)
+                {
+                    continue;
+                }
+                found.insert(name);
+                let order = self
+                    .section_order
+                    .entry((*file_idx, sym.st_shndx))
+                    .or_insert(rank);
+                *order = std::cmp::min(*order, rank);
+            }
+        }
+        for name in symbols {
+            if !found.contains(name) {
+                log::warn!("symbol ordering file: no such symbol: {}", name);
+            }
+        }
+    }
+    // Tentative definitions get space in a .bss section of their file, aligned to their
+    // value. Those that lost symbol resolution to another definition refer to it instead.
+    pub(crate) fn allocate_common_symbols(&mut self) {
+        use goblin::elf::section_header::*;
+        let common = usize::try_from(SHN_COMMON).unwrap();
+        for (file_idx, shdr_idx) in std::mem::take(&mut self.common_sections) {
+            let globals = &self.symtab.globals;
+            let (syms, strtab) = self.symtab.by_file.get_mut(&file_idx).unwrap();
+            let mut size = 0u64;
+            let mut alignment = 1;
+            for (sym_idx, sym) in syms.iter_mut().enumerate() {
+                if sym.st_shndx != common {
+                    continue;
+                }
+                let name = strtab.get_unsafe(sym.st_name).unwrap_or("");
+                if globals
+                    .get(name)
+                    .is_none_or(|def| *def == (file_idx, sym_idx))
+                {
+                    let sym_align = max(sym.st_value, 1);
+                    alignment = max(alignment, sym_align);
+                    let offset = size.next_multiple_of(sym_align);
+                    size = offset + sym.st_size;
+                    sym.st_shndx = shdr_idx;
+                    sym.st_value = offset;
+                } else {
+                    sym.st_shndx = usize::try_from(SHN_UNDEF).unwrap();
+                    sym.st_value = 0;
+                }
+            }
+            self.section_names.insert((file_idx, shdr_idx), "COMMON");
+            self.bss_sections.push(InputSection {
+                file_idx,
+                shdr_idx,
+                section: SectionHeader {
+                    sh_type: SHT_NOBITS,
+                    sh_flags: u64::from(SHF_ALLOC | SHF_WRITE),
+                    sh_size: size,
+                    sh_addralign: alignment,
+                    ..SectionHeader::default()
+                },
+                name: ".bss",
+            });
+        }
+    }
+    // Input sections get their new names from --rename-section, the original names are
+    // only kept for diagnostics.
+    pub(crate) fn rename_sections(&mut self, renames: &'a [SectionRename]) {
+        if renames.is_empty() {
+            return;
+        }
+        for sections in [
+            &mut self.code_sections,
+            &mut self.tls_data_sections,
+            &mut self.tls_bss_sections,
+            &mut self.relro_sections,
+            &mut self.data_sections,
+            &mut self.bss_sections,
+            &mut self.ro_data_sections,
+            &mut self.merge_sections,
+            &mut self.init_array_sections,
+            &mut self.eh_frame_sections,
+            &mut self.debug_sections,
+            &mut self.comment_sections,
+            &mut self.note_sections,
+            &mut self.property_sections,
+        ] {
+            for sec in sections.iter_mut() {
+                if let Some(rename) = renames.iter().find(|rename| rename.from == sec.name) {
+                    sec.name = &rename.to;
+                }
+            }
+        }
+    }
+}
+
+// Replace the archives among `inputs` by the members that define a symbol that is still
+// undefined. Members are searched until nothing changes, so their order in the archives
+// and on the command line does not matter.
+pub(crate) fn load_archive_members(
+    inputs: Vec<(String, Vec<u8>)>,
+) -> Result<Vec<(String, Vec<u8>)>, LinkError> {
+    use goblin::archive::{Archive, MAGIC};
+    let (archives, mut objects): (Vec<_>, Vec<_>) = inputs
+        .into_iter()
+        .partition(|(_, buf)| buf.starts_with(MAGIC));
+    let mut defined = HashSet::new();
+    let mut undefined = Vec::new();
+    for (name, buf) in &objects {
+        add_global_symbols(buf, &mut defined, &mut undefined).map_err(|err| err.in_file(name))?;
+    }
+    let mut parsed = Vec::new();
+    for (name, buf) in &archives {
+        let archive = Archive::parse(buf).map_err(|err| LinkError::from(err).in_file(name))?;
+        parsed.push((name, buf, archive));
+    }
+    let mut loaded = HashSet::new();
+    let mut next = 0;
+    while next < undefined.len() {
+        let symbol = undefined[next].clone();
+        next += 1;
+        if defined.contains(&symbol) {
+            continue;
+        }
+        let found = parsed
+            .iter()
+            .enumerate()
+            .find_map(|(i, (name, buf, archive))| {
+                archive
+                    .member_of_symbol(&symbol)
+                    .map(|member| (i, *name, *buf, archive, member))
+            });
+        if let Some((i, name, buf, archive, member)) = found {
+            if loaded.insert((i, member)) {
+                let member_name = format!("{}({})", name, member);
+                let contents = archive
+                    .extract(member, buf)
+                    .map(<[u8]>::to_vec)
+                    .map_err(|err| LinkError::from(err).in_file(&member_name))?;
+                add_global_symbols(&contents, &mut defined, &mut undefined)
+                    .map_err(|err| err.in_file(&member_name))?;
+                objects.push((member_name, contents));
+            }
+        }
+    }
+    Ok(objects)
+}
+
+// Sections without contents can be larger than the file, but not larger than the user
+// address space of x86-64.
+pub(crate) const MAX_SECTION_SIZE: u64 = 1 << 47;
+
+// Parse an ELF object once its section headers say nothing absurd. goblin reserves room
+// for as many symbols as the size of the symbol table claims, and everything after this
+// slices the file with the offsets and sizes of the sections and looks up symbol names
+// without checking them again.
+pub(crate) fn parse_object(file: &[u8]) -> Result<goblin::elf::Elf<'_>, LinkError> {
+    use goblin::elf::section_header::{SHT_NOBITS, SHT_NULL};
+    let header = goblin::elf::Elf::parse_header(file)?;
+    let ctx = Ctx::new(header.container()?, header.endianness()?);
+    let section_headers = SectionHeader::parse(
+        file,
+        usize::try_from(header.e_shoff).unwrap(),
+        usize::from(header.e_shnum),
+        ctx,
+    )?;
+    for (idx, sec) in section_headers.iter().enumerate() {
+        if sec.sh_type == SHT_NOBITS {
+            if sec.sh_size > MAX_SECTION_SIZE {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Section {} has invalid size {:#x}", idx, sec.sh_size),
+                ));
+            }
+        } else if sec.sh_type != SHT_NULL {
+            let end = sec.sh_offset.checked_add(sec.sh_size);
+            if end.is_none_or(|end| end > u64::try_from(file.len()).unwrap()) {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Section {} extends past the end of the file", idx),
+                ));
+            }
+        }
+        if sec.sh_addralign > u64::from(u32::MAX)
+            || sec.sh_addralign > 1 && !sec.sh_addralign.is_power_of_two()
+        {
+            return Err(LinkError::malformed(
+                "",
+                format!("Section {} has invalid alignment {}", idx, sec.sh_addralign),
+            ));
+        }
+    }
+    let elf = goblin::elf::Elf::parse(file)?;
+    for (sym_idx, sym) in elf.syms.iter().enumerate() {
+        match elf.strtab.get(sym.st_name) {
+            Some(Ok(_)) => {}
+            _ => {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Symbol {} has an invalid name", sym_idx),
+                ))
+            }
+        }
+    }
+    Ok(elf)
+}
+
+// Reject a relocation outside of the section it applies to or for a symbol that does not
+// exist. The field and the instruction bytes around it that relaxations look at have to
+// be inside the section as well.
+fn check_relocation(
+    reloc: &goblin::elf::Reloc,
+    applies_to: &SectionHeader,
+    syms: usize,
+    handlers: &RelocHandlers,
+    machine: u16,
+) -> Result<(), LinkError> {
+    let (before, after) = match handlers.howto(machine, reloc.r_type) {
+        Ok((handler, howto)) => {
+            let (before, after) = handler.code_around(reloc.r_type);
+            (before, max(after, howto.field.size()))
+        }
+        // Unsupported relocation types are reported when they get applied.
+        Err(_) => (0, 1),
+    };
+    let (before, after) = (
+        u64::try_from(before).unwrap(),
+        u64::try_from(after).unwrap(),
+    );
+    if reloc.r_offset < before
+        || reloc
+            .r_offset
+            .checked_add(after)
+            .is_none_or(|end| end > applies_to.sh_size)
+    {
+        return Err(LinkError::malformed(
+            "",
+            format!(
+                "Relocation at offset {:#x} is outside of its section",
+                reloc.r_offset
+            ),
+        ));
+    }
+    if reloc.r_sym >= syms {
+        return Err(LinkError::malformed(
+            "",
+            format!("Relocation for invalid symbol index {}", reloc.r_sym),
+        ));
+    }
+    Ok(())
+}
+
+// Record the global symbols an ELF object defines and the ones it needs.
+fn add_global_symbols(
+    buf: &[u8],
+    defined: &mut HashSet<String>,
+    undefined: &mut Vec<String>,
+) -> Result<(), LinkError> {
+    use goblin::elf::section_header::SHN_UNDEF;
+    use goblin::elf::sym::{STB_GLOBAL, STB_LOCAL};
+    if !buf.starts_with(goblin::elf::header::ELFMAG) {
+        return Ok(());
+    }
+    let elf = parse_object(buf)?;
+    for sym in elf.syms.iter() {
+        let name = elf.strtab.get_unsafe(sym.st_name).unwrap_or("");
+        if name.is_empty() || sym.st_bind() == STB_LOCAL {
+            continue;
+        }
+        if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() {
+            // Weak references do not pull in archive members.
+            if sym.st_bind() == STB_GLOBAL {
+                undefined.push(String::from(name));
+            }
+        } else {
+            defined.insert(String::from(name));
+        }
+    }
+    Ok(())
+}
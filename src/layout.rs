@@ -0,0 +1,1530 @@
+//! Placing the input sections in output sections and segments.
+
+use crate::emit::{
+    eh_frame_records, prog_header_offset, BuildIdNote, DynamicSection, EhFrameHdr, GnuPropertyNote,
+};
+use crate::error::LinkError;
+use crate::incremental;
+use crate::input::{Input, InputSection, RelocationSection};
+use crate::relocate::{GotSection, RelocHandlers};
+use crate::resolve::SymbolTable;
+use crate::script::{wildcard_match, LinkerScript, ScriptCommand, SectionCommand};
+use crate::target::Target;
+use crate::{align, Opts};
+use goblin::container::Ctx;
+use scroll::Pwrite;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+#[derive(Debug)]
+pub(crate) struct OutputSection<'a> {
+    pub(crate) address: usize,
+    // Name of the output section this is part of
+    pub(crate) name: &'a str,
+    pub(crate) input_section: InputSection<'a>,
+    // The only input section of its output section, from --unique
+    pub(crate) unique: bool,
+    // The room the section has in the output, more than its size in an incremental link
+    pub(crate) capacity: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Output<'a> {
+    pub(crate) target: &'static dyn Target,
+    pub(crate) flags: u32,
+    pub(crate) osabi: u8,
+    pub(crate) file_names: Vec<String>,
+    pub(crate) file_buffers: Vec<&'a [u8]>,
+    // Input sections in the order they were laid out
+    pub(crate) sections: Vec<OutputSection<'a>>,
+    pub(crate) merged_sections: Vec<MergedSection<'a>>,
+    pub(crate) debug_sections: Vec<DebugSection<'a>>,
+    pub(crate) comment: Option<CommentSection>,
+    pub(crate) dynamic: DynamicSection,
+    pub(crate) got: GotSection,
+    // PT_LOAD segments with their p_flags
+    pub(crate) load_segments: Vec<(u32, SegmentInfo)>,
+    pub(crate) tls: Option<TlsSegment>,
+    pub(crate) eh_frame_hdr: Option<EhFrameHdr>,
+    pub(crate) build_id: Option<BuildIdNote>,
+    pub(crate) gnu_property: Option<GnuPropertyNote>,
+    // PT_NOTE segments with their alignment
+    pub(crate) notes: Vec<(SegmentInfo, usize)>,
+    pub(crate) relro: Option<SegmentInfo>,
+    // Map from file (idx, section idx) to the placement in the output file
+    pub(crate) section_offsets: HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+    pub(crate) section_names: HashMap<(usize, goblin::elf::ShdrIdx), &'a str>,
+    pub(crate) discarded_sections: Vec<InputSection<'a>>,
+    pub(crate) reloc_sections: Vec<RelocationSection>,
+    pub(crate) symtab: SymbolTable<'a>,
+    pub(crate) entry_symbol: String,
+    pub(crate) shared: bool,
+    pub(crate) reloc_handlers: RelocHandlers<'a>,
+    // Address of the start of the file
+    pub(crate) image_base: usize,
+    // Room for the ELF and program headers before the first segment in the file
+    pub(crate) headers_size: usize,
+    // Start address of each PT_LOAD segment with the address its part of the file is
+    // mapped at, sorted by address
+    pub(crate) segment_bases: Vec<(usize, usize)>,
+    // Byte written to the padding between code sections
+    pub(crate) fill: u8,
+    // Size of the file without the section header table
+    pub(crate) total_size: usize,
+}
+
+impl<'a> Input<'a> {
+    // How many program headers the output needs at most, known before anything is laid
+    // out: PT_PHDR, the PT_LOADs of the headers, code, read-only data, relro region, data
+    // and .bss and of each --section-start, and those of the dynamic section, TLS,
+    // .eh_frame_hdr, the notes, the properties and the relro region.
+    fn max_program_headers(&self, opts: &Opts, dynamic: &DynamicSection) -> usize {
+        let page_size = self.target().page_size();
+        let tls = !self.tls_data_sections.is_empty() || !self.tls_bss_sections.is_empty();
+        let properties = !self.property_sections.is_empty();
+        let notes = self.note_sections.len()
+            + usize::from(opts.build_id.is_some())
+            + usize::from(properties);
+        7 + opts.section_start.len()
+            + usize::from(!dynamic.is_empty())
+            + usize::from(tls)
+            + usize::from(!self.eh_frame_sections.is_empty())
+            + notes
+            + usize::from(properties)
+            + usize::from(opts.relro(page_size))
+    }
+    // The dynamic section, with the symbols and relocations of a shared library.
+    fn dynamic_section(
+        &self,
+        opts: &Opts,
+        got: &GotSection,
+        ctx: Ctx,
+    ) -> Result<DynamicSection, LinkError> {
+        if !opts.shared {
+            return Ok(DynamicSection::new(opts, &[], 0, ctx));
+        }
+        let relocations = self.dynamic_relocations(got)?;
+        Ok(DynamicSection::new(
+            opts,
+            &self.exported_symbols(),
+            relocations,
+            ctx,
+        ))
+    }
+    pub(crate) fn allocate(mut self, opts: &Opts, ctx: Ctx) -> Result<Output<'a>, LinkError> {
+        use goblin::elf::program_header::*;
+        let mut section_offsets = HashMap::new();
+        let target = self.target();
+        let page_size = target.page_size();
+        let mut got = self.collect_got_entries(opts, false)?;
+        let mut dynamic = self.dynamic_section(opts, &got, ctx)?;
+        let headers_size = prog_header_offset(self.max_program_headers(opts, &dynamic), ctx);
+        // Sections with an address from --section-start are laid out last, each in a segment
+        // of its own.
+        let mut pinned = Vec::new();
+        for start in &opts.section_start {
+            let mut group = Vec::new();
+            for sections in [
+                &mut self.code_sections,
+                &mut self.ro_data_sections,
+                &mut self.data_sections,
+                &mut self.bss_sections,
+            ] {
+                let (matching, rest): (Vec<_>, Vec<_>) = std::mem::take(sections)
+                    .into_iter()
+                    .partition(|sec| output_section_name(sec.name) == start.name);
+                *sections = rest;
+                group.extend(matching);
+            }
+            pinned.push((start.address, group));
+        }
+        if let Some(sort) = opts.sort_section {
+            for sections in [
+                &mut self.code_sections,
+                &mut self.ro_data_sections,
+                &mut self.tls_data_sections,
+                &mut self.tls_bss_sections,
+                &mut self.relro_sections,
+                &mut self.data_sections,
+                &mut self.bss_sections,
+            ] {
+                sort.sort(sections);
+            }
+            for (_, group) in &mut pinned {
+                sort.sort(group);
+            }
+        }
+        order_sections(&mut self.code_sections, &self.section_order);
+        // The code directly follows the headers and its segment maps them as well.
+        let mut offset = opts.text_address.unwrap_or(opts.image_base + headers_size);
+        let code_start = offset;
+        let code_sections = place_sections_reserving(
+            self.code_sections,
+            &mut offset,
+            &mut section_offsets,
+            opts.incremental,
+        );
+        let code_segment = SegmentInfo::new(code_start, offset - code_start);
+        if !opts.no_rosegment {
+            offset = next_segment_address(offset, opts.segment_align(page_size));
+        }
+        let ro_data_start = offset;
+        let build_id = BuildIdNote::new(opts, &mut offset);
+        let gnu_property = GnuPropertyNote::new(
+            &self.property_sections,
+            &self.file_buffers,
+            &self.file_names,
+            &mut offset,
+            ctx,
+        )?;
+        let note_sections = place_sections(self.note_sections, &mut offset, &mut section_offsets);
+        let notes = note_segments(build_id.as_ref(), gnu_property.as_ref(), &note_sections);
+        dynamic.place_tables(&mut offset, ctx);
+        let ro_data_sections =
+            place_sections(self.ro_data_sections, &mut offset, &mut section_offsets);
+        let mut merged_sections = merge_sections(
+            self.merge_sections,
+            &self.file_buffers,
+            &mut offset,
+            &mut section_offsets,
+        )?;
+        let eh_frame = merge_eh_frames(
+            &self.eh_frame_sections,
+            &self.file_buffers,
+            &self.symtab,
+            &mut self.reloc_sections,
+            ctx,
+            &mut offset,
+            &mut section_offsets,
+        )?;
+        let eh_frame_hdr = eh_frame.as_ref().map(|eh_frame| {
+            offset = align(offset, 4);
+            let hdr = EhFrameHdr {
+                address: offset,
+                eh_frame_address: eh_frame.address,
+                eh_frame_size: eh_frame.data.len(),
+                fde_count: eh_frame_records(&eh_frame.data, ctx)
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, cie_pointer)| *cie_pointer != 0)
+                    .count(),
+            };
+            offset += hdr.size();
+            hdr
+        });
+        merged_sections.extend(eh_frame);
+        let ro_data_segment = SegmentInfo::new(ro_data_start, offset - ro_data_start);
+        // The data segment comes last so that .bss at its end needs no space in the file.
+        offset = segment_start(
+            offset,
+            opts.data_address,
+            "-Tdata",
+            opts.segment_align(page_size),
+        )?;
+        let data_start = offset;
+        // The TLS initialization image is only read when creating threads so it is part of
+        // the relro region that follows.
+        let tls_data_sections =
+            place_sections(self.tls_data_sections, &mut offset, &mut section_offsets);
+        // .tbss takes up space in the TLS block of each thread but not in the data segment.
+        let mut tls_end = offset;
+        let tls_bss_sections =
+            place_sections(self.tls_bss_sections, &mut tls_end, &mut section_offsets);
+        let tls = tls_segment(tls_data_sections.iter().chain(&tls_bss_sections));
+        // Sections that are only written during relocation come first so that a single
+        // PT_GNU_RELRO header can cover them.
+        let relro_sections = place_sections(self.relro_sections, &mut offset, &mut section_offsets);
+        sort_init_arrays(&mut self.init_array_sections);
+        let init_array_sections =
+            place_sections(self.init_array_sections, &mut offset, &mut section_offsets);
+        set_init_array_symbols(&mut self.symtab, &init_array_sections, offset, &[]);
+        if !dynamic.is_empty() {
+            offset = align(offset, ctx.size());
+            dynamic.address = offset;
+            offset += dynamic.size(ctx);
+        }
+        if !got.is_empty() {
+            offset = align(offset, ctx.size());
+            got.address = offset;
+            offset += got.size(ctx);
+        }
+        let relro = if opts.relro(page_size) && offset > data_start {
+            // The loader only protects whole pages. The relro region gets a segment of its
+            // own that fills its last page in memory but not in the file, and the writable
+            // data continues on the next page.
+            let relro = SegmentInfo {
+                offset: data_start,
+                size: offset - data_start,
+                mem_size: align(offset, page_size) - data_start,
+            };
+            let rw_follows = self
+                .data_sections
+                .iter()
+                .chain(&self.bss_sections)
+                .any(|sec| sec.section.sh_size > 0);
+            if rw_follows {
+                offset = next_segment_address(offset, page_size);
+            }
+            Some(relro)
+        } else {
+            None
+        };
+        let rw_start = if relro.is_some() { offset } else { data_start };
+        let data_sections = place_sections(self.data_sections, &mut offset, &mut section_offsets);
+        let file_end = offset;
+        let code_flags = if opts.omagic {
+            PF_R | PF_W | PF_X
+        } else {
+            PF_R | PF_X
+        };
+        let mut load_segments = if opts.no_rosegment {
+            let size = ro_data_segment.offset + ro_data_segment.size - code_start;
+            vec![(code_flags, SegmentInfo::new(code_start, size))]
+        } else {
+            vec![(code_flags, code_segment), (PF_R, ro_data_segment)]
+        };
+        // .bss at a given address goes into a segment of its own, otherwise it ends the data
+        // segment.
+        load_segments.extend(relro.map(|relro| (PF_R | PF_W, relro)));
+        let bss_start = match opts.bss_address {
+            Some(_) => {
+                load_segments.push((PF_R | PF_W, SegmentInfo::new(rw_start, offset - rw_start)));
+                offset = segment_start(
+                    offset,
+                    opts.bss_address,
+                    "-Tbss",
+                    opts.segment_align(page_size),
+                )?;
+                offset
+            }
+            None => rw_start,
+        };
+        let bss_sections = place_sections(self.bss_sections, &mut offset, &mut section_offsets);
+        load_segments.push((
+            PF_R | PF_W,
+            SegmentInfo {
+                offset: bss_start,
+                size: file_end.saturating_sub(bss_start),
+                mem_size: offset - bss_start,
+            },
+        ));
+        let mut sections: Vec<OutputSection> = vec![
+            code_sections,
+            note_sections,
+            ro_data_sections,
+            tls_data_sections,
+            tls_bss_sections,
+            relro_sections,
+            init_array_sections,
+            data_sections,
+            bss_sections,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        pinned.sort_by_key(|(address, _)| *address);
+        for (address, group) in pinned {
+            let mut location = address;
+            let mut placed = Vec::new();
+            for sec in group {
+                let name = output_section_name(sec.name);
+                place_section(
+                    sec,
+                    name,
+                    &mut location,
+                    &mut section_offsets,
+                    &mut placed,
+                    false,
+                );
+            }
+            let (flags, segment) = match load_segment(&placed) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let end = segment.offset + segment.mem_size;
+            if let Some((_, other)) = load_segments.iter().find(|(_, other)| {
+                other.mem_size > 0
+                    && segment.offset < other.offset + other.mem_size
+                    && other.offset < end
+            }) {
+                return Err(LinkError::overflow(format!(
+                    "Section {} at {:#x} overlaps the segment at {:#x}",
+                    placed[0].name, address, other.offset
+                )));
+            }
+            let index = sections
+                .iter()
+                .position(|sec| sec.address > address)
+                .unwrap_or(sections.len());
+            sections.splice(index..index, placed);
+            load_segments.push((flags, segment));
+        }
+        make_unique(&mut sections, &opts.unique);
+        // Without sections of a kind there is no segment for them.
+        load_segments.retain(|(_, segment)| segment.mem_size > 0);
+        let load_segments = merge_load_segments(load_segments, page_size);
+        let (segment_bases, mut total_size) =
+            segment_bases(&load_segments, opts.image_base, headers_size, page_size);
+        let debug_sections =
+            place_debug_sections(self.debug_sections, &mut total_size, &mut section_offsets);
+        let comment =
+            CommentSection::new(&self.comment_sections, &self.file_buffers, &mut total_size)?;
+        Ok(Output {
+            target,
+            flags: self.flags,
+            osabi: self.osabi,
+            file_names: self.file_names,
+            file_buffers: self.file_buffers,
+            entry_symbol: self.entry_symbol,
+            shared: self.shared,
+            reloc_handlers: self.reloc_handlers,
+            reloc_sections: self.reloc_sections,
+            sections,
+            merged_sections,
+            debug_sections,
+            comment,
+            dynamic,
+            got,
+            load_segments,
+            tls,
+            eh_frame_hdr,
+            build_id,
+            gnu_property,
+            notes,
+            relro,
+            section_offsets,
+            section_names: self.section_names,
+            discarded_sections: self.discarded_sections,
+            image_base: opts.image_base,
+            headers_size,
+            segment_bases,
+            fill: opts.fill.unwrap_or(target.fill()),
+            total_size,
+            symtab: self.symtab,
+        })
+    }
+    // Lay out the sections as described by a linker script. Input sections the script does
+    // not mention follow the last output section of the script, then come the dynamic
+    // section and the GOT. Mergeable sections and .eh_frame are copied as they are and
+    // there is no relro region.
+    pub(crate) fn allocate_with_script(
+        mut self,
+        script: &LinkerScript<'a>,
+        opts: &Opts,
+        ctx: Ctx,
+    ) -> Result<Output<'a>, LinkError> {
+        use goblin::elf::program_header::*;
+        use goblin::elf::section_header::*;
+        let mut section_offsets = HashMap::new();
+        let mut got = self.collect_got_entries(opts, true)?;
+        let mut dynamic = self.dynamic_section(opts, &got, ctx)?;
+        let target = self.target();
+        let page_size = target.page_size();
+        sort_init_arrays(&mut self.init_array_sections);
+        let mut unplaced: Vec<Option<InputSection>> = vec![
+            self.code_sections,
+            self.note_sections,
+            self.ro_data_sections,
+            self.merge_sections,
+            self.eh_frame_sections,
+            self.tls_data_sections,
+            self.tls_bss_sections,
+            self.relro_sections,
+            self.init_array_sections,
+            self.data_sections,
+            self.bss_sections,
+        ]
+        .into_iter()
+        .flatten()
+        .map(Some)
+        .collect();
+        let mut location = opts.image_base + page_size;
+        let mut sections = Vec::new();
+        let mut load_segments = Vec::new();
+        for command in &script.commands {
+            let (name, address, commands) = match command {
+                ScriptCommand::Assign(symbol, value) => {
+                    let value = value.eval(location, &self.symtab)?;
+                    assign_script_symbol(&mut self.symtab, symbol, value, &mut location)?;
+                    continue;
+                }
+                ScriptCommand::OutputSection {
+                    name,
+                    address,
+                    commands,
+                } => (name, address, commands),
+            };
+            if let Some(address) = address {
+                location = address.eval(location, &self.symtab)?;
+            }
+            let first = sections.len();
+            for command in commands {
+                let spec = match command {
+                    SectionCommand::Assign(symbol, value) => {
+                        let value = value.eval(location, &self.symtab)?;
+                        assign_script_symbol(&mut self.symtab, symbol, value, &mut location)?;
+                        continue;
+                    }
+                    SectionCommand::Input(spec) => spec,
+                };
+                let file_names = &self.file_names;
+                let mut matching: Vec<InputSection> = unplaced
+                    .iter_mut()
+                    .filter(|slot| {
+                        slot.as_ref()
+                            .is_some_and(|sec| spec.matches(&file_names[sec.file_idx], sec.name))
+                    })
+                    .map(|slot| slot.take().unwrap())
+                    .collect();
+                if let Some(sort) = spec.sort.or(opts.sort_section) {
+                    sort.sort(&mut matching);
+                }
+                order_sections(&mut matching, &self.section_order);
+                for sec in matching {
+                    // .tbss only takes up space in the TLS block of each thread.
+                    let is_tls_bss = sec.section.sh_type == SHT_NOBITS
+                        && sec.section.sh_flags & u64::from(SHF_TLS) != 0;
+                    let mut offset = location;
+                    place_section(
+                        sec,
+                        name,
+                        &mut offset,
+                        &mut section_offsets,
+                        &mut sections,
+                        false,
+                    );
+                    if !is_tls_bss {
+                        location = offset;
+                    }
+                }
+            }
+            load_segments.extend(load_segment(&sections[first..]));
+        }
+        // Everything else ends up in the output section it would get without a script.
+        let mut orphans: Vec<InputSection> = unplaced.into_iter().flatten().collect();
+        if let Some(sort) = opts.sort_section {
+            sort.sort(&mut orphans);
+        }
+        order_sections(&mut orphans, &self.section_order);
+        let mut orphans = place_sections(orphans, &mut location, &mut section_offsets);
+        make_unique(&mut orphans, &opts.unique);
+        let mut first = 0;
+        for (i, sec) in orphans.iter().enumerate() {
+            if orphans.get(i + 1).is_none_or(|next| next.name != sec.name) {
+                load_segments.extend(load_segment(&orphans[first..=i]));
+                first = i + 1;
+            }
+        }
+        sections.extend(orphans);
+        let synthetic_start = align(location, ctx.size());
+        location = synthetic_start;
+        let build_id = BuildIdNote::new(opts, &mut location);
+        let gnu_property = GnuPropertyNote::new(
+            &self.property_sections,
+            &self.file_buffers,
+            &self.file_names,
+            &mut location,
+            ctx,
+        )?;
+        let notes = note_segments(
+            build_id.as_ref(),
+            gnu_property.as_ref(),
+            sections
+                .iter()
+                .filter(|sec| sec.input_section.section.sh_type == SHT_NOTE),
+        );
+        dynamic.place_tables(&mut location, ctx);
+        if !dynamic.is_empty() {
+            location = align(location, ctx.size());
+            dynamic.address = location;
+            location += dynamic.size(ctx);
+        }
+        if !got.is_empty() {
+            location = align(location, ctx.size());
+            got.address = location;
+            location += got.size(ctx);
+        }
+        if location > synthetic_start {
+            load_segments.push((
+                PF_R | PF_W,
+                SegmentInfo::new(synthetic_start, location - synthetic_start),
+            ));
+        }
+        let load_segments = merge_load_segments(load_segments, page_size);
+        set_init_array_symbols(&mut self.symtab, &sections, location, &script.symbols());
+        let tls = tls_segment(
+            sections
+                .iter()
+                .filter(|sec| sec.input_section.section.sh_flags & u64::from(SHF_TLS) != 0),
+        );
+        // Only PT_LOADs and PT_NOTEs can be more than one.
+        let headers_size = prog_header_offset(load_segments.len() + notes.len() + 7, ctx);
+        let (segment_bases, mut total_size) =
+            segment_bases(&load_segments, opts.image_base, headers_size, page_size);
+        let debug_sections =
+            place_debug_sections(self.debug_sections, &mut total_size, &mut section_offsets);
+        let comment =
+            CommentSection::new(&self.comment_sections, &self.file_buffers, &mut total_size)?;
+        Ok(Output {
+            target,
+            flags: self.flags,
+            osabi: self.osabi,
+            file_names: self.file_names,
+            file_buffers: self.file_buffers,
+            entry_symbol: self.entry_symbol,
+            shared: self.shared,
+            reloc_handlers: self.reloc_handlers,
+            reloc_sections: self.reloc_sections,
+            sections,
+            merged_sections: vec![],
+            debug_sections,
+            comment,
+            dynamic,
+            got,
+            load_segments,
+            tls,
+            eh_frame_hdr: None,
+            build_id,
+            gnu_property,
+            notes,
+            relro: None,
+            section_offsets,
+            section_names: self.section_names,
+            discarded_sections: self.discarded_sections,
+            image_base: opts.image_base,
+            headers_size,
+            segment_bases,
+            fill: opts.fill.unwrap_or(target.fill()),
+            total_size,
+            symtab: self.symtab,
+        })
+    }
+}
+
+fn assign_script_symbol(
+    symtab: &mut SymbolTable,
+    symbol: &str,
+    value: usize,
+    location: &mut usize,
+) -> Result<(), LinkError> {
+    if symbol == "." {
+        if value < *location {
+            return Err(LinkError::overflow(
+                "Cannot move the location counter backwards in linker script",
+            ));
+        }
+        *location = value;
+    } else {
+        symtab.set_linker_symbol(symbol, value);
+    }
+    Ok(())
+}
+
+// The loader maps whole pages so segments sharing a page are merged into one with the
+// permissions of both.
+fn merge_load_segments(
+    mut segments: Vec<(u32, SegmentInfo)>,
+    page_size: usize,
+) -> Vec<(u32, SegmentInfo)> {
+    segments.sort_by_key(|(_, segment)| segment.offset);
+    let mut merged: Vec<(u32, SegmentInfo)> = Vec::new();
+    for (flags, segment) in segments {
+        match merged.last_mut() {
+            Some((last_flags, last))
+                if align(last.offset + last.mem_size, page_size) > segment.offset =>
+            {
+                *last_flags |= flags;
+                let end = last.offset + last.mem_size;
+                if segment.size > 0 {
+                    last.size = segment.offset + segment.size - last.offset;
+                }
+                last.mem_size = end.max(segment.offset + segment.mem_size) - last.offset;
+            }
+            _ => merged.push((flags, segment)),
+        }
+    }
+    merged
+}
+
+// The PT_LOAD segment covering the sections of one output section, with the permissions
+// any of them needs.
+fn load_segment(sections: &[OutputSection]) -> Option<(u32, SegmentInfo)> {
+    use goblin::elf::program_header::*;
+    use goblin::elf::section_header::*;
+    let sections: Vec<&OutputSection> = sections
+        .iter()
+        .filter(|sec| {
+            let header = &sec.input_section.section;
+            header.sh_type != SHT_NOBITS || header.sh_flags & u64::from(SHF_TLS) == 0
+        })
+        .collect();
+    let start = sections.iter().map(|sec| sec.address).min()?;
+    let end = |sec: &&OutputSection| {
+        sec.address + usize::try_from(sec.input_section.section.sh_size).unwrap()
+    };
+    let file_end = sections
+        .iter()
+        .filter(|sec| sec.input_section.section.sh_type != SHT_NOBITS)
+        .map(end)
+        .max()
+        .unwrap_or(start);
+    let mem_end = sections.iter().map(end).max().unwrap();
+    if mem_end == start {
+        return None;
+    }
+    let mut flags = PF_R;
+    for sec in &sections {
+        if sec.input_section.section.sh_flags & u64::from(SHF_EXECINSTR) != 0 {
+            flags |= PF_X;
+        }
+        if sec.input_section.section.sh_flags & u64::from(SHF_WRITE) != 0 {
+            flags |= PF_W;
+        }
+    }
+    Some((
+        flags,
+        SegmentInfo {
+            offset: start,
+            size: file_end - start,
+            mem_size: mem_end - start,
+        },
+    ))
+}
+
+// Name of the output section an input section ends up in, like the default GNU ld
+// linker script.
+pub(crate) fn output_section_name(name: &str) -> &str {
+    for (prefix, output) in [
+        (".gnu.linkonce.t", ".text"),
+        (".gnu.linkonce.r", ".rodata"),
+        (".gnu.linkonce.d.rel.ro", ".data.rel.ro"),
+        (".gnu.linkonce.d", ".data"),
+        (".gnu.linkonce.td", ".tdata"),
+        (".gnu.linkonce.tb", ".tbss"),
+        (".gnu.linkonce.b", ".bss"),
+    ] {
+        if name.starts_with(prefix) && name[prefix.len()..].starts_with('.') {
+            return output;
+        }
+    }
+    for prefix in [
+        ".text",
+        ".rodata",
+        ".data.rel.ro",
+        ".data",
+        ".preinit_array",
+        ".init_array",
+        ".fini_array",
+        ".tdata",
+        ".tbss",
+        ".bss",
+    ] {
+        if name == prefix || name.starts_with(prefix) && name[prefix.len()..].starts_with('.') {
+            return prefix;
+        }
+    }
+    name
+}
+
+/// A non-alloc `.debug_*` section of the output. It is stored after the loaded part of
+/// the file and only the section header table refers to it.
+#[derive(Debug)]
+pub(crate) struct DebugSection<'a> {
+    pub(crate) name: &'a str,
+    // Offset in the file
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+    pub(crate) flags: u64,
+    pub(crate) entsize: u64,
+    pub(crate) align: u64,
+    // Input sections with their offset in the file
+    pub(crate) input_sections: Vec<(InputSection<'a>, usize)>,
+}
+
+/// The `.comment` section with the compiler version strings of all inputs, each of them
+/// only once.
+#[derive(Debug)]
+pub(crate) struct CommentSection {
+    // Offset in the file
+    pub(crate) offset: usize,
+    pub(crate) data: Vec<u8>,
+}
+
+impl CommentSection {
+    pub(crate) fn new(
+        sections: &[InputSection],
+        file_buffers: &[&[u8]],
+        file_end: &mut usize,
+    ) -> Result<Option<Self>, LinkError> {
+        let mut data = Vec::new();
+        let mut seen = HashSet::new();
+        for sec in sections {
+            let offset = usize::try_from(sec.section.sh_offset).unwrap();
+            let size = usize::try_from(sec.section.sh_size).unwrap();
+            let contents = &file_buffers[sec.file_idx][offset..offset + size];
+            for (_, string) in split_strings(contents, 1)? {
+                if seen.insert(string) {
+                    data.extend_from_slice(string);
+                }
+            }
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let comment = CommentSection {
+            offset: *file_end,
+            data,
+        };
+        *file_end += comment.data.len();
+        Ok(Some(comment))
+    }
+}
+
+// Concatenate the debug sections with the same name at `file_end`. Symbols in them are
+// offsets in their output section as debug sections have no address.
+fn place_debug_sections<'a>(
+    sections: Vec<InputSection<'a>>,
+    file_end: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+) -> Vec<DebugSection<'a>> {
+    let mut output_sections: Vec<DebugSection> = Vec::new();
+    for sec in sections {
+        let idx = match output_sections.iter().position(|out| out.name == sec.name) {
+            Some(idx) => idx,
+            None => {
+                output_sections.push(DebugSection {
+                    name: sec.name,
+                    offset: 0,
+                    size: 0,
+                    flags: sec.section.sh_flags,
+                    entsize: sec.section.sh_entsize,
+                    align: 1,
+                    input_sections: Vec::new(),
+                });
+                output_sections.len() - 1
+            }
+        };
+        let out = &mut output_sections[idx];
+        let sec_align = std::cmp::max(sec.section.sh_addralign, 1);
+        out.align = std::cmp::max(out.align, sec_align);
+        let offset = align(out.size, usize::try_from(sec_align).unwrap());
+        out.size = offset + usize::try_from(sec.section.sh_size).unwrap();
+        section_offsets.insert((sec.file_idx, sec.shdr_idx), Placement::Offset(offset));
+        out.input_sections.push((sec, offset));
+    }
+    for out in &mut output_sections {
+        out.offset = align(*file_end, usize::try_from(out.align).unwrap());
+        for (_, offset) in &mut out.input_sections {
+            *offset += out.offset;
+        }
+        *file_end = out.offset + out.size;
+    }
+    output_sections
+}
+
+// Start the next segment on a fresh page or at the address given with `option`.
+fn segment_start(
+    offset: usize,
+    address: Option<usize>,
+    option: &str,
+    segment_align: usize,
+) -> Result<usize, LinkError> {
+    match address {
+        Some(address) if address < offset => Err(LinkError::overflow(format!(
+            "{} address {:#x} overlaps the preceding segment",
+            option, address
+        ))),
+        Some(address) => Ok(address),
+        None => Ok(next_segment_address(offset, segment_align)),
+    }
+}
+
+// A new segment starts on the next page at the same offset within the page, like with
+// GNU ld. It then directly follows the previous segment in the file. With an alignment
+// of 1 it directly follows in memory as well.
+fn next_segment_address(offset: usize, segment_align: usize) -> usize {
+    align(offset, segment_align) + offset % segment_align
+}
+
+// Lay out the segments one after the other in the file after the `headers_size` bytes of
+// headers. Their file offsets only have to be congruent to their addresses modulo the page
+// size so there is less than a page of padding when a segment is at a fixed address. A
+// segment in the page with the headers shares it with them. Returns the start address of
+// each segment with the address its part of the file is mapped at, and the end of the
+// segments in the file.
+pub(crate) fn segment_bases(
+    load_segments: &[(u32, SegmentInfo)],
+    image_base: usize,
+    headers_size: usize,
+    page_size: usize,
+) -> (Vec<(usize, usize)>, usize) {
+    let mut segments: Vec<&SegmentInfo> =
+        load_segments.iter().map(|(_, segment)| segment).collect();
+    segments.sort_by_key(|segment| segment.offset);
+    let mut file_end = match segments.first() {
+        Some(first) if (image_base..image_base + page_size).contains(&first.offset) => {
+            first.offset - image_base
+        }
+        _ => headers_size,
+    };
+    let mut bases = Vec::new();
+    for segment in segments {
+        let padding = (segment.offset % page_size + page_size - file_end % page_size) % page_size;
+        let file_offset = file_end + padding;
+        bases.push((segment.offset, segment.offset - file_offset));
+        file_end = file_offset + segment.size;
+    }
+    (bases, file_end)
+}
+
+fn place_sections<'a>(
+    sections: Vec<InputSection<'a>>,
+    offset: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+) -> Vec<OutputSection<'a>> {
+    place_sections_reserving(sections, offset, section_offsets, false)
+}
+
+// With `reserve` each section is followed by room to grow for an incremental link.
+fn place_sections_reserving<'a>(
+    mut sections: Vec<InputSection<'a>>,
+    offset: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+    reserve: bool,
+) -> Vec<OutputSection<'a>> {
+    // Input sections of the same output section have to be next to each other.
+    let mut names: Vec<&str> = Vec::new();
+    for sec in &sections {
+        let name = output_section_name(sec.name);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    sections.sort_by_key(|sec| {
+        names
+            .iter()
+            .position(|name| *name == output_section_name(sec.name))
+            .unwrap()
+    });
+    let mut output_sections = Vec::new();
+    for sec in sections {
+        let name = output_section_name(sec.name);
+        place_section(
+            sec,
+            name,
+            offset,
+            section_offsets,
+            &mut output_sections,
+            reserve,
+        );
+    }
+    output_sections
+}
+
+fn place_section<'a>(
+    sec: InputSection<'a>,
+    name: &'a str,
+    offset: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+    output_sections: &mut Vec<OutputSection<'a>>,
+    reserve: bool,
+) {
+    *offset = align(*offset, usize::try_from(sec.section.sh_addralign).unwrap());
+    section_offsets.insert((sec.file_idx, sec.shdr_idx), Placement::Offset(*offset));
+    let size = usize::try_from(sec.section.sh_size).unwrap();
+    let capacity = if reserve {
+        incremental::capacity(size)
+    } else {
+        size
+    };
+    output_sections.push(OutputSection {
+        address: *offset,
+        name,
+        input_section: sec,
+        unique: false,
+        capacity,
+    });
+    *offset += capacity;
+}
+
+// Sections matching a --unique pattern keep their name and are not merged with their
+// neighbours.
+fn make_unique(sections: &mut [OutputSection], patterns: &[String]) {
+    for sec in sections {
+        if patterns
+            .iter()
+            .any(|pattern| wildcard_match(pattern, sec.input_section.name))
+        {
+            sec.name = sec.input_section.name;
+            sec.unique = true;
+        }
+    }
+}
+
+// Move the sections listed in the symbol ordering file to the front, the sort is stable
+// so the others keep their order.
+fn order_sections(
+    sections: &mut [InputSection],
+    order: &HashMap<(usize, goblin::elf::ShdrIdx), usize>,
+) {
+    if !order.is_empty() {
+        sections.sort_by_key(|sec| {
+            order
+                .get(&(sec.file_idx, sec.shdr_idx))
+                .copied()
+                .unwrap_or(usize::MAX)
+        });
+    }
+}
+
+// Section indices from SHN_LORESERVE on are moved past the reserved range so that they
+// cannot be mistaken for SHN_ABS and friends in st_shndx. Sections are identified by these
+// keys, also in the output symbol table until it gets written.
+pub(crate) fn section_key(idx: usize) -> usize {
+    let reserved = usize::try_from(goblin::elf::section_header::SHN_LORESERVE).unwrap();
+    if idx >= reserved {
+        idx + 0x100
+    } else {
+        idx
+    }
+}
+
+pub(crate) fn section_index(key: usize) -> usize {
+    if key > usize::try_from(goblin::elf::section_header::SHN_HIRESERVE).unwrap() {
+        key - 0x100
+    } else {
+        key
+    }
+}
+
+pub(crate) fn is_reserved_shndx(shndx: usize) -> bool {
+    use goblin::elf::section_header::*;
+    (usize::try_from(SHN_LORESERVE).unwrap()..=usize::try_from(SHN_HIRESERVE).unwrap())
+        .contains(&shndx)
+}
+
+// Lower priorities run first, constructors without a priority run last.
+fn sort_init_arrays(sections: &mut [InputSection]) {
+    sections.sort_by_key(|sec| {
+        let name = output_section_name(sec.name);
+        let kind = [".preinit_array", ".init_array", ".fini_array"]
+            .iter()
+            .position(|n| *n == name);
+        let priority = sec.name[name.len()..]
+            .strip_prefix('.')
+            .and_then(|priority| priority.parse::<u32>().ok());
+        (kind, priority.is_none(), priority)
+    });
+}
+
+// Define the bounds of the constructor and destructor arrays, empty ones are at `offset`.
+// Symbols in `defined` are left alone.
+fn set_init_array_symbols(
+    symtab: &mut SymbolTable,
+    sections: &[OutputSection],
+    offset: usize,
+    defined: &[&str],
+) {
+    for name in ["preinit_array", "init_array", "fini_array"] {
+        let output_name = format!(".{}", name);
+        let sections: Vec<&OutputSection> = sections
+            .iter()
+            .filter(|sec| output_section_name(sec.input_section.name) == output_name)
+            .collect();
+        let (start, end) = match (sections.first(), sections.last()) {
+            (Some(first), Some(last)) => (
+                first.address,
+                last.address + usize::try_from(last.input_section.section.sh_size).unwrap(),
+            ),
+            _ => (offset, offset),
+        };
+        for (symbol, value) in [
+            (format!("__{}_start", name), start),
+            (format!("__{}_end", name), end),
+        ] {
+            if !defined.contains(&symbol.as_str()) {
+                symtab.set_linker_symbol(&symbol, value);
+            }
+        }
+    }
+}
+
+// The TLS segment covering the given thread-local sections, if there is anything in it.
+fn tls_segment<'s, 'a: 's>(
+    sections: impl Iterator<Item = &'s OutputSection<'a>> + Clone,
+) -> Option<TlsSegment> {
+    use goblin::elf::section_header::*;
+    let start = sections.clone().map(|sec| sec.address).min()?;
+    let end = |sec: &OutputSection| {
+        sec.address + usize::try_from(sec.input_section.section.sh_size).unwrap()
+    };
+    let file_end = sections
+        .clone()
+        .filter(|sec| sec.input_section.section.sh_type != SHT_NOBITS)
+        .map(end)
+        .max()
+        .unwrap_or(start);
+    let mem_end = sections.clone().map(end).max().unwrap();
+    if mem_end == start {
+        return None;
+    }
+    Some(TlsSegment {
+        offset: start,
+        file_size: file_end - start,
+        mem_size: mem_end - start,
+        align: sections
+            .map(|sec| usize::try_from(sec.input_section.section.sh_addralign).unwrap())
+            .max()
+            .unwrap(),
+    })
+}
+
+/// Where the contents of an input section ended up.
+#[derive(Clone, Debug)]
+pub(crate) enum Placement {
+    // Copied as a whole to this offset
+    Offset(usize),
+    // Split into pieces that got deduplicated, (input offset, output offset) of each
+    // piece sorted by input offset
+    Pieces(Vec<(u64, usize)>),
+}
+
+impl Placement {
+    // Translate an offset in the input section to an offset in the output. Addends can
+    // take the offset anywhere, the result wraps around like relocation arithmetic does.
+    pub(crate) fn translate(&self, offset: i64) -> i64 {
+        match self {
+            Placement::Offset(start) => i64::try_from(*start).unwrap().wrapping_add(offset),
+            Placement::Pieces(pieces) => {
+                let idx = pieces.partition_point(|(input_offset, _)| {
+                    i64::try_from(*input_offset).unwrap() <= offset
+                });
+                let (input_offset, output_offset) = pieces[idx.saturating_sub(1)];
+                i64::try_from(output_offset)
+                    .unwrap()
+                    .wrapping_add(offset)
+                    .wrapping_sub(i64::try_from(input_offset).unwrap())
+            }
+        }
+    }
+    pub(crate) fn relative_to(&self, base: usize) -> Placement {
+        match self {
+            Placement::Offset(start) => Placement::Offset(start - base),
+            Placement::Pieces(pieces) => Placement::Pieces(
+                pieces
+                    .iter()
+                    .map(|(input_offset, output_offset)| (*input_offset, output_offset - base))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// A linker-generated section holding the deduplicated contents of mergeable sections.
+#[derive(Debug)]
+pub(crate) struct MergedSection<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) flags: u64,
+    pub(crate) entsize: usize,
+    pub(crate) align: usize,
+    pub(crate) address: usize,
+    pub(crate) data: Vec<u8>,
+}
+
+// Split the contents of a SHF_STRINGS section into strings including their terminator,
+// together with their offsets.
+fn split_strings(contents: &[u8], entsize: usize) -> Result<Vec<(u64, &[u8])>, LinkError> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+    while start < contents.len() {
+        let mut end = start;
+        loop {
+            let c = match contents.get(end..end.saturating_add(entsize)) {
+                Some(c) => c,
+                None => {
+                    return Err(LinkError::malformed(
+                        "",
+                        "Unterminated string in mergeable section",
+                    ))
+                }
+            };
+            if c.iter().all(|b| *b == 0) {
+                break;
+            }
+            end += entsize;
+        }
+        strings.push((
+            u64::try_from(start).unwrap(),
+            &contents[start..end + entsize],
+        ));
+        start = end + entsize;
+    }
+    Ok(strings)
+}
+
+// Split the contents of a SHF_MERGE section without SHF_STRINGS into its fixed size
+// entries together with their offsets.
+fn split_entries(contents: &[u8], entsize: usize) -> Result<Vec<(u64, &[u8])>, LinkError> {
+    if !contents.len().is_multiple_of(entsize) {
+        return Err(LinkError::malformed(
+            "",
+            "Mergeable section size is not a multiple of its entry size",
+        ));
+    }
+    Ok(contents
+        .chunks(entsize)
+        .enumerate()
+        .map(|(i, entry)| (u64::try_from(i * entsize).unwrap(), entry))
+        .collect())
+}
+
+// Deduplicate the strings or constants of SHF_MERGE sections that end up in the same
+// output section with the same flags and entry size and place the merged sections at
+// `offset`.
+pub(crate) fn merge_sections<'a>(
+    sections: Vec<InputSection<'a>>,
+    file_buffers: &[&'a [u8]],
+    offset: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+) -> Result<Vec<MergedSection<'a>>, LinkError> {
+    use goblin::elf::section_header::*;
+    let mut merged: Vec<MergedSection> = Vec::new();
+    // Output offset of every distinct piece of each merged section
+    let mut seen: Vec<HashMap<&[u8], usize>> = Vec::new();
+    let mut pieces = Vec::new();
+    for sec in &sections {
+        let name = output_section_name(sec.name);
+        let entsize = usize::try_from(std::cmp::max(sec.section.sh_entsize, 1)).unwrap();
+        let idx = match merged.iter().position(|out| {
+            out.name == name && out.flags == sec.section.sh_flags && out.entsize == entsize
+        }) {
+            Some(idx) => idx,
+            None => {
+                merged.push(MergedSection {
+                    name,
+                    flags: sec.section.sh_flags,
+                    entsize,
+                    align: 1,
+                    address: 0,
+                    data: Vec::new(),
+                });
+                seen.push(HashMap::new());
+                merged.len() - 1
+            }
+        };
+        let out = &mut merged[idx];
+        // Each piece keeps the alignment of its input section.
+        let sec_align = usize::try_from(std::cmp::max(sec.section.sh_addralign, 1)).unwrap();
+        out.align = std::cmp::max(out.align, sec_align);
+        let start = usize::try_from(sec.section.sh_offset).unwrap();
+        let size = usize::try_from(sec.section.sh_size).unwrap();
+        let contents = &file_buffers[sec.file_idx][start..start + size];
+        let mut sec_pieces = Vec::new();
+        let split = if sec.section.sh_flags & u64::from(SHF_STRINGS) != 0 {
+            split_strings(contents, entsize)
+        } else {
+            split_entries(contents, entsize)
+        }?;
+        for (input_offset, piece) in split {
+            let output_offset = match seen[idx].get(piece) {
+                Some(output_offset) if output_offset % sec_align == 0 => *output_offset,
+                _ => {
+                    let output_offset = align(out.data.len(), sec_align);
+                    out.data.resize(output_offset, 0);
+                    out.data.extend_from_slice(piece);
+                    seen[idx].insert(piece, output_offset);
+                    output_offset
+                }
+            };
+            sec_pieces.push((input_offset, output_offset));
+        }
+        // Symbols in an empty section are at the end of what was merged before it.
+        if sec_pieces.is_empty() {
+            sec_pieces.push((0, out.data.len()));
+        }
+        pieces.push(((sec.file_idx, sec.shdr_idx), idx, sec_pieces));
+    }
+    for out in &mut merged {
+        *offset = align(*offset, out.align);
+        out.address = *offset;
+        *offset += out.data.len();
+    }
+    for (key, idx, sec_pieces) in pieces {
+        let address = merged[idx].address;
+        section_offsets.insert(
+            key,
+            Placement::Pieces(
+                sec_pieces
+                    .into_iter()
+                    .map(|(input_offset, output_offset)| (input_offset, address + output_offset))
+                    .collect(),
+            ),
+        );
+    }
+    Ok(merged)
+}
+
+// Concatenate the .eh_frame sections into one, sharing identical CIEs and dropping the
+// FDEs of code that is not in the output, and terminate it.
+fn merge_eh_frames<'a>(
+    sections: &[InputSection<'a>],
+    file_buffers: &[&'a [u8]],
+    symtab: &SymbolTable,
+    reloc_sections: &mut [RelocationSection],
+    ctx: Ctx,
+    offset: &mut usize,
+    section_offsets: &mut HashMap<(usize, goblin::elf::ShdrIdx), Placement>,
+) -> Result<Option<MergedSection<'a>>, LinkError> {
+    use scroll::Pread;
+    let first = match sections.first() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    let flags = first.section.sh_flags;
+    let le = ctx.le;
+    let mut data = Vec::new();
+    // Output offset of each CIE by its contents and relocations
+    #[allow(clippy::type_complexity)]
+    let mut cies: HashMap<(&[u8], Vec<(u64, u32, (usize, usize), i64)>), usize> = HashMap::new();
+    let mut pieces = Vec::new();
+    // Input ranges of records that are not copied whose relocations must not be applied
+    let mut dropped = Vec::new();
+    for sec in sections {
+        let key = (sec.file_idx, sec.shdr_idx);
+        let start = usize::try_from(sec.section.sh_offset).unwrap();
+        let size = usize::try_from(sec.section.sh_size).unwrap();
+        let contents = &file_buffers[sec.file_idx][start..start + size];
+        let relocations: Vec<&goblin::elf::Reloc> = reloc_sections
+            .iter()
+            .filter(|reloc_sec| (reloc_sec.applies_to_file, reloc_sec.applies_to_sec) == key)
+            .flat_map(|reloc_sec| reloc_sec.relocations.iter())
+            .collect();
+        // Output offset of the CIEs of this section by their input offset
+        let mut cie_offsets = HashMap::new();
+        let mut sec_pieces = Vec::new();
+        let mut record_start = 0;
+        while record_start + 4 <= contents.len() {
+            let length = contents.pread_with::<u32>(record_start, le)?;
+            if length == 0 {
+                break;
+            }
+            if length == 0xffff_ffff {
+                return Err(LinkError::malformed(
+                    "",
+                    "64-bit .eh_frame records are not supported",
+                ));
+            }
+            let record_end = record_start + 4 + usize::try_from(length).unwrap();
+            let record = contents
+                .get(record_start..record_end)
+                .ok_or_else(|| LinkError::malformed("", "Truncated record in .eh_frame"))?;
+            let record_relocs: Vec<&&goblin::elf::Reloc> = relocations
+                .iter()
+                .filter(|reloc| {
+                    (record_start..record_end).contains(&usize::try_from(reloc.r_offset).unwrap())
+                })
+                .collect();
+            let cie_pointer = record.pread_with::<u32>(4, le)?;
+            let output_offset = if cie_pointer == 0 {
+                let cie = (
+                    record,
+                    record_relocs
+                        .iter()
+                        .map(|reloc| {
+                            Ok((
+                                reloc.r_offset - u64::try_from(record_start).unwrap(),
+                                reloc.r_type,
+                                symtab.resolve(sec.file_idx, reloc.r_sym)?,
+                                reloc.r_addend.unwrap(),
+                            ))
+                        })
+                        .collect::<Result<_, LinkError>>()?,
+                );
+                let output_offset = match cies.get(&cie) {
+                    Some(output_offset) => {
+                        dropped.push((key, record_start..record_end));
+                        *output_offset
+                    }
+                    None => {
+                        cies.insert(cie, data.len());
+                        data.extend_from_slice(record);
+                        data.len() - record.len()
+                    }
+                };
+                cie_offsets.insert(record_start, output_offset);
+                Some(output_offset)
+            } else {
+                // The first relocation of an FDE is the start of the code it describes.
+                let live = match record_relocs.first() {
+                    Some(reloc) => {
+                        let (file_idx, sym_idx) = symtab.resolve(sec.file_idx, reloc.r_sym)?;
+                        let sym = symtab.get(file_idx, sym_idx);
+                        is_reserved_shndx(sym.st_shndx)
+                            || section_offsets.contains_key(&(file_idx, sym.st_shndx))
+                    }
+                    None => true,
+                };
+                if live {
+                    let cie = (record_start + 4)
+                        .checked_sub(usize::try_from(cie_pointer).unwrap())
+                        .and_then(|cie| cie_offsets.get(&cie))
+                        .ok_or_else(|| {
+                            LinkError::malformed("", "FDE without a CIE in .eh_frame")
+                        })?;
+                    let output_offset = data.len();
+                    data.extend_from_slice(record);
+                    // The CIE pointer is relative to its own position.
+                    let cie_pointer = output_offset + 4 - cie;
+                    data.pwrite_with(u32::try_from(cie_pointer).unwrap(), output_offset + 4, le)
+                        .unwrap();
+                    Some(output_offset)
+                } else {
+                    dropped.push((key, record_start..record_end));
+                    None
+                }
+            };
+            if let Some(output_offset) = output_offset {
+                sec_pieces.push((u64::try_from(record_start).unwrap(), output_offset));
+            }
+            record_start = record_end;
+        }
+        if sec_pieces.is_empty() {
+            sec_pieces.push((0, data.len()));
+        }
+        pieces.push((key, sec_pieces));
+    }
+    data.extend_from_slice(&[0; 4]);
+    let max_align = sections
+        .iter()
+        .map(|sec| usize::try_from(std::cmp::max(sec.section.sh_addralign, 1)).unwrap())
+        .max()
+        .unwrap();
+    *offset = align(*offset, max_align);
+    let address = *offset;
+    *offset += data.len();
+    for (key, sec_pieces) in pieces {
+        section_offsets.insert(
+            key,
+            Placement::Pieces(
+                sec_pieces
+                    .into_iter()
+                    .map(|(input_offset, output_offset)| (input_offset, address + output_offset))
+                    .collect(),
+            ),
+        );
+    }
+    for reloc_sec in reloc_sections.iter_mut() {
+        let key = (reloc_sec.applies_to_file, reloc_sec.applies_to_sec);
+        reloc_sec.relocations.retain(|reloc| {
+            let r_offset = usize::try_from(reloc.r_offset).unwrap();
+            !dropped
+                .iter()
+                .any(|(sec, range)| *sec == key && range.contains(&r_offset))
+        });
+    }
+    Ok(Some(MergedSection {
+        name: ".eh_frame",
+        flags,
+        entsize: 0,
+        align: max_align,
+        address,
+        data,
+    }))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SegmentInfo {
+    pub(crate) size: usize,
+    pub(crate) offset: usize,
+    // Larger than `size` if the end is not backed by the file
+    pub(crate) mem_size: usize,
+}
+
+impl SegmentInfo {
+    pub(crate) fn new(offset: usize, size: usize) -> Self {
+        SegmentInfo {
+            size,
+            offset,
+            mem_size: size,
+        }
+    }
+}
+
+// Adjacent notes with the same alignment share a PT_NOTE segment, readers expect the
+// notes in a segment to be aligned the same way.
+fn note_segments<'s, 'a: 's>(
+    build_id: Option<&BuildIdNote>,
+    gnu_property: Option<&GnuPropertyNote>,
+    sections: impl IntoIterator<Item = &'s OutputSection<'a>>,
+) -> Vec<(SegmentInfo, usize)> {
+    let mut notes: Vec<(usize, usize, usize)> = build_id
+        .map(|note| (note.address, note.size(), 4))
+        .into_iter()
+        .chain(gnu_property.map(|note| (note.address, note.size(), note.ctx.size())))
+        .chain(sections.into_iter().map(|sec| {
+            let header = &sec.input_section.section;
+            (
+                sec.address,
+                usize::try_from(header.sh_size).unwrap(),
+                usize::try_from(std::cmp::max(header.sh_addralign, 4)).unwrap(),
+            )
+        }))
+        .collect();
+    notes.sort_by_key(|(address, _, _)| *address);
+    let mut segments: Vec<(SegmentInfo, usize)> = Vec::new();
+    for (address, size, note_align) in notes {
+        match segments.last_mut() {
+            Some((segment, last_align))
+                if *last_align == note_align
+                    && align(segment.offset + segment.size, note_align) == address =>
+            {
+                segment.size = address + size - segment.offset;
+                segment.mem_size = segment.size;
+            }
+            _ => segments.push((SegmentInfo::new(address, size), note_align)),
+        }
+    }
+    segments
+}
+
+#[derive(Debug)]
+pub(crate) struct TlsSegment {
+    pub(crate) offset: usize,
+    pub(crate) file_size: usize,
+    pub(crate) mem_size: usize,
+    pub(crate) align: usize,
+}
+
+impl<'a> Output<'a> {
+    // The address the part of the file containing `address` is mapped at. Everything
+    // before the first segment is mapped at the image base.
+    pub(crate) fn base(&self, address: usize) -> usize {
+        self.segment_bases
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= address)
+            .map_or(self.image_base, |(_, base)| *base)
+    }
+    pub(crate) fn file_offset(&self, address: usize) -> usize {
+        address - self.base(address)
+    }
+    // A shared library without the entry symbol has 0 as its entry point.
+    pub(crate) fn entry(&self) -> Result<usize, LinkError> {
+        match self.symtab.globals.get(self.entry_symbol.as_str()) {
+            Some((file_idx, sym_idx)) => self.symbol_address(*file_idx, *sym_idx),
+            None if self.shared => Ok(0),
+            None => Err(LinkError::undefined(&self.entry_symbol)),
+        }
+    }
+    // The address and the part in the written file `buf` of each segment with contents.
+    pub(crate) fn segment_contents<'b>(&self, buf: &'b [u8]) -> Vec<(usize, &'b [u8])> {
+        self.load_segments
+            .iter()
+            .filter(|(_, segment)| segment.size > 0)
+            .map(|(_, segment)| {
+                let start = self.file_offset(segment.offset);
+                (segment.offset, &buf[start..start + segment.size])
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,1149 @@
+//! A linker for x86-64, i386 and ARM ELF objects, with backends for COFF, Mach-O and
+//! wasm objects. [`Linker`] links files like the `toy-linker` binary does.
+//!
+//! The ELF path goes through the modules in order: `input` reads the object files,
+//! `resolve` builds the symbol table, `layout` places the sections and segments,
+//! `relocate` applies the relocations and `emit` writes the headers and tables.
+
+mod buffer;
+mod emit;
+mod error;
+mod incremental;
+mod input;
+mod layout;
+mod macho;
+mod pe;
+mod progress;
+mod relocate;
+mod resolve;
+mod script;
+mod stats;
+mod target;
+#[cfg(test)]
+mod tests;
+mod trace;
+mod wasm;
+
+pub use crate::buffer::OutputBuffer;
+pub use crate::error::LinkError;
+pub use crate::progress::{CancellationToken, Phase};
+pub use crate::relocate::{Field, Fixup, Relaxed, RelocHandler, RelocValue, RelocationHowTo};
+
+use crate::emit::{binary_image, ihex_image, srec_image, synthetic_object};
+use crate::input::{load_archive_members, Input, InputSection};
+use crate::macho::{is_macho_object, link_macho};
+use crate::pe::{is_coff_object, link_pe, PeImport};
+use crate::progress::Progress;
+use crate::relocate::RelocHandlers;
+use crate::script::LinkerScript;
+use crate::target::Target;
+use crate::wasm::{is_wasm_object, link_wasm};
+use clap::Clap;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::prelude::*;
+use tracing::level_filters::LevelFilter;
+
+/// The options of a link, parsed from the command line.
+#[derive(Clap, Debug)]
+pub struct Opts {
+    #[clap(short)]
+    input: Vec<String>,
+    /// The output file, `-` writes it to stdout.
+    #[clap(short)]
+    output: String,
+    /// The symbol the executable starts at.
+    #[clap(short = 'e', long = "entry", default_value = "_start")]
+    entry: String,
+    /// Linker keywords, e.g. `-z now` or `-z lazy`.
+    #[clap(short = 'z', number_of_values = 1)]
+    keywords: Vec<Keyword>,
+    /// Add a directory to the runtime library search path (DT_RUNPATH).
+    #[clap(long = "rpath", number_of_values = 1)]
+    rpath: Vec<String>,
+    /// Add a directory to the link time search path for dependencies of shared libraries.
+    /// Rejected since shared libraries are never read.
+    #[clap(long = "rpath-link", number_of_values = 1, parse(try_from_str = parse_directory))]
+    rpath_link: Vec<String>,
+    /// Produce a relocatable object instead of an executable.
+    #[clap(short = 'r', long = "relocatable")]
+    relocatable: bool,
+    /// Produce a shared library that exports the global symbols of the inputs. Only
+    /// x86-64 position independent code is supported and nothing is imported from other
+    /// libraries.
+    #[clap(long = "shared")]
+    shared: bool,
+    /// Set the name recorded as DT_SONAME of a shared library, which programs linked
+    /// against it record as the library they need.
+    #[clap(long = "soname")]
+    soname: Option<String>,
+    /// Keep the relocations of the input files as `.rela` sections in the executable
+    /// for post-link tools.
+    #[clap(long = "emit-relocs")]
+    emit_relocs: bool,
+    /// Remove input sections that cannot be reached from the entry point.
+    #[clap(long = "gc-sections")]
+    gc_sections: bool,
+    /// List the sections removed by --gc-sections on stderr, as info messages.
+    #[clap(long = "print-gc-sections")]
+    print_gc_sections: bool,
+    /// Never remove input sections matching the pattern with --gc-sections, like KEEP
+    /// in a linker script.
+    #[clap(long = "keep-section", number_of_values = 1)]
+    keep_section: Vec<String>,
+    /// Lay out the output as described by the SECTIONS command of a linker script.
+    #[clap(short = 'T', long = "script")]
+    script: Option<String>,
+    /// Start address of the code segment, in hexadecimal.
+    #[clap(long = "Ttext", parse(try_from_str = parse_address))]
+    text_address: Option<usize>,
+    /// Start address of the data segment, in hexadecimal.
+    #[clap(long = "Tdata", parse(try_from_str = parse_address))]
+    data_address: Option<usize>,
+    /// Start address of .bss, in hexadecimal. It then gets a segment of its own.
+    #[clap(long = "Tbss", parse(try_from_str = parse_address))]
+    bss_address: Option<usize>,
+    /// Address the file is mapped at, in hexadecimal. The ELF headers come first and the
+    /// code segment directly follows them.
+    #[clap(long = "image-base", parse(try_from_str = parse_address), default_value = "400000")]
+    image_base: usize,
+    /// Do not start segments on a fresh page, each one directly follows the previous one.
+    /// Segments sharing a page get merged. There is no relro region.
+    #[clap(short = 'n', long = "nmagic")]
+    nmagic: bool,
+    /// Like --nmagic and also make the code writable.
+    #[clap(short = 'N', long = "omagic")]
+    omagic: bool,
+    /// Put the read-only data into the code segment instead of a segment of its own.
+    #[clap(long = "no-rosegment")]
+    no_rosegment: bool,
+    /// Identify the output with a .note.gnu.build-id computed with sha1 (the default), md5,
+    /// uuid or given in hexadecimal as 0xHEX. The uuid is random unless SOURCE_DATE_EPOCH
+    /// is set for a reproducible build, then it is derived from the contents.
+    #[clap(long = "build-id", require_equals = true)]
+    build_id: Option<Option<BuildId>>,
+    /// Place an output section at a fixed address, e.g. `--section-start=.boot=0x100000`.
+    #[clap(long = "section-start", number_of_values = 1)]
+    section_start: Vec<SectionStart>,
+    /// Rename input sections before they are laid out, e.g.
+    /// `--rename-section .text.startup=.init.text`. The new name decides the output
+    /// section, also for --section-start and linker scripts.
+    #[clap(long = "rename-section", number_of_values = 1)]
+    rename_section: Vec<SectionRename>,
+    /// Byte in hexadecimal that fills the alignment padding between code sections.
+    /// Defaults to int3 (0xcc) on x86-64 so that stray jumps into the padding trap.
+    #[clap(long = "fill", parse(try_from_str = parse_fill))]
+    fill: Option<u8>,
+    /// Sort the input sections of each output section by `name` or by `alignment`,
+    /// largest first. Sections of a linker script that use SORT keep their own order.
+    #[clap(long = "sort-section")]
+    sort_section: Option<SortSection>,
+    /// Keep each input section matching the pattern as an output section of its own
+    /// instead of concatenating it with the other sections of the same name. With a
+    /// linker script this only applies to sections the script does not place.
+    #[clap(long = "unique", require_equals = true, number_of_values = 1)]
+    unique: Vec<String>,
+    /// File with one symbol per line, hot functions first. The code sections defining
+    /// them are laid out first and in this order, the others follow. Works best with
+    /// -ffunction-sections.
+    #[clap(long = "symbol-ordering-file")]
+    symbol_ordering_file: Option<String>,
+    /// Format of the output file: `elf`, `binary` for just the contents of the segments
+    /// as they are laid out in memory, starting at the lowest address, `ihex` for Intel
+    /// HEX, `srec` for Motorola S-records, `pei-x86-64` for a PE executable based at
+    /// 0x140000000, which is the default for COFF input files, or `mach-o` for a macOS
+    /// executable starting at `_main`, which is the default for Mach-O input files. It is
+    /// neither position independent nor code signed. `wasm` links wasm32 objects into a
+    /// module exporting its memory and `_start`, undefined functions become imports.
+    #[clap(long = "oformat", default_value = "elf")]
+    oformat: OutputFormat,
+    /// The architecture of an ELF output: `x86_64`, `i386`, `arm` or `armeb`. By default
+    /// it is the one of the first object file, the others have to match it either way.
+    #[clap(long = "target", parse(try_from_str = target::by_name))]
+    target: Option<&'static dyn Target>,
+    /// Print the log of the link to stderr, up to `error`, `warn` (the default), `info`,
+    /// `debug` for the phases of the link with their times or `trace` for every input file.
+    /// A program using the library gets the messages and spans with its own subscriber.
+    #[clap(long = "log-level", parse(try_from_str = trace::parse_level))]
+    log_level: Option<LevelFilter>,
+    /// Write a map of the output sections with their input sections and symbols, and of
+    /// the discarded input sections, to the file.
+    #[clap(long = "Map")]
+    map: Option<String>,
+    /// Write the segments, the sections with their input sections, the symbols and the
+    /// discarded sections as JSON to the file.
+    #[clap(long = "layout-json")]
+    layout_json: Option<String>,
+    /// Print the file size, the sizes of the segments and output sections and the
+    /// largest symbols once the output is written. To stderr with `-o -`.
+    #[clap(long = "print-size")]
+    print_size: bool,
+    /// How many symbols --print-size lists.
+    #[clap(long = "print-size-symbols", default_value = "10")]
+    print_size_symbols: usize,
+    /// Leave room after each code section and remember the layout in
+    /// `<output>.incremental`. The next link with the option patches the output in place
+    /// if only one object file changed and its sections still fit, otherwise it links
+    /// everything again.
+    #[clap(long = "incremental")]
+    incremental: bool,
+    /// Print the wall-clock and CPU time of each phase of the link and counts of what it
+    /// processed once the output is written. To stderr with `-o -`.
+    #[clap(long = "time-report")]
+    time_report: bool,
+    /// Write the times of the phases to the file in the Chrome trace event format, for
+    /// viewing in chrome://tracing or Perfetto.
+    #[clap(long = "time-trace")]
+    time_trace: Option<String>,
+    /// Print how many bytes the input buffers, relocations, symbol tables, merged strings,
+    /// GOT, layout and output image of an ELF link hold, and the peak memory of the
+    /// process. To stderr with `-o -`.
+    #[clap(long = "stats")]
+    stats: bool,
+    /// OS/ABI of the output: `sysv`, `gnu` (or `linux`), `freebsd` or `standalone`.
+    /// Defaults to the one of the first input file.
+    #[clap(long = "osabi")]
+    osabi: Option<OsAbi>,
+    /// Processor specific flags of the output in hexadecimal, e.g. the float ABI on ARM.
+    /// Defaults to the flags of the first input file.
+    #[clap(long = "e-flags", parse(try_from_str = parse_e_flags))]
+    e_flags: Option<u32>,
+    /// Import a function from a DLL into a PE executable, e.g.
+    /// `--import kernel32.dll:ExitProcess`. Like with an import library `__imp_ExitProcess`
+    /// is its entry in the import address table and `ExitProcess` jumps through it.
+    #[clap(long = "import", number_of_values = 1)]
+    import: Vec<PeImport>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum BuildId {
+    Sha1,
+    Md5,
+    // Random instead of derived from the contents
+    Uuid,
+    Hex(Vec<u8>),
+}
+
+impl std::str::FromStr for BuildId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha1" | "tree" => Ok(BuildId::Sha1),
+            "md5" => Ok(BuildId::Md5),
+            "uuid" => Ok(BuildId::Uuid),
+            _ => match s.strip_prefix("0x") {
+                Some(hex) if !hex.is_empty() && hex.len() % 2 == 0 => (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<Result<_, _>>()
+                    .map(BuildId::Hex)
+                    .map_err(|_| format!("invalid --build-id: {}", s)),
+                _ => Err(format!("invalid --build-id: {}", s)),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortSection {
+    Name,
+    Alignment,
+}
+
+impl std::str::FromStr for SortSection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortSection::Name),
+            "alignment" => Ok(SortSection::Alignment),
+            _ => Err(format!("invalid --sort-section: {}", s)),
+        }
+    }
+}
+
+impl SortSection {
+    // The sort is stable so equal sections stay in input order.
+    fn sort(self, sections: &mut [InputSection]) {
+        match self {
+            SortSection::Name => sections.sort_by_key(|sec| sec.name),
+            SortSection::Alignment => {
+                sections.sort_by_key(|sec| std::cmp::Reverse(sec.section.sh_addralign))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OsAbi {
+    SysV,
+    Gnu,
+    FreeBsd,
+    Standalone,
+}
+
+impl std::str::FromStr for OsAbi {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sysv" | "none" => Ok(OsAbi::SysV),
+            "gnu" | "linux" => Ok(OsAbi::Gnu),
+            "freebsd" => Ok(OsAbi::FreeBsd),
+            "standalone" => Ok(OsAbi::Standalone),
+            _ => Err(format!("invalid --osabi: {}", s)),
+        }
+    }
+}
+
+impl OsAbi {
+    // The value of EI_OSABI
+    fn value(self) -> u8 {
+        match self {
+            OsAbi::SysV => 0,
+            OsAbi::Gnu => 3,
+            OsAbi::FreeBsd => 9,
+            OsAbi::Standalone => 255,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Elf,
+    Binary,
+    Ihex,
+    Srec,
+    Pe,
+    MachO,
+    Wasm,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "elf" | "elf64-x86-64" => Ok(OutputFormat::Elf),
+            "binary" => Ok(OutputFormat::Binary),
+            "ihex" => Ok(OutputFormat::Ihex),
+            "srec" => Ok(OutputFormat::Srec),
+            "pei-x86-64" => Ok(OutputFormat::Pe),
+            "mach-o" => Ok(OutputFormat::MachO),
+            "wasm" => Ok(OutputFormat::Wasm),
+            _ => Err(format!("invalid --oformat: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SectionStart {
+    name: String,
+    address: usize,
+}
+
+impl std::str::FromStr for SectionStart {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, address)) => Ok(SectionStart {
+                name: name.to_string(),
+                address: parse_address(address)?,
+            }),
+            None => Err(format!("expected SECTION=ADDRESS: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SectionRename {
+    from: String,
+    to: String,
+}
+
+impl std::str::FromStr for SectionRename {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(SectionRename {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(format!("expected OLD=NEW: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Keyword {
+    Now,
+    Lazy,
+    Relro,
+    NoRelro,
+}
+
+impl std::str::FromStr for Keyword {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "now" => Ok(Keyword::Now),
+            "lazy" => Ok(Keyword::Lazy),
+            "relro" => Ok(Keyword::Relro),
+            "norelro" => Ok(Keyword::NoRelro),
+            _ => Err(format!("unknown -z keyword: {}", s)),
+        }
+    }
+}
+
+// Addresses on the command line are hexadecimal like in GNU ld, with or without 0x.
+fn parse_address(s: &str) -> Result<usize, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("invalid address: {}", s))
+}
+
+fn parse_directory(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        Err(String::from("expected a directory"))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+fn parse_e_flags(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid e_flags: {}", s))
+}
+
+fn parse_fill(s: &str) -> Result<u8, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("invalid fill byte: {}", s))
+}
+
+// GNU ld spells the segment address options, the library search paths and the shared
+// library options with a single dash, which clap would take for -T or -r with a value or
+// for a group of short options.
+pub fn normalize_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    args.map(|arg| {
+        for option in [
+            "-Ttext",
+            "-Tdata",
+            "-Tbss",
+            "-Map",
+            "-rpath",
+            "-rpath-link",
+            "-shared",
+            "-soname",
+        ] {
+            if arg == option || arg.starts_with(&format!("{}=", option)) {
+                return format!("-{}", arg);
+            }
+        }
+        arg
+    })
+    .collect()
+}
+
+/// When the dynamic loader should resolve symbols.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BindPolicy {
+    Lazy,
+    Now,
+}
+
+impl Opts {
+    /// The level the `toy-linker` binary logs to stderr at. --print-gc-sections raises
+    /// it to `info` for its messages.
+    pub fn log_level(&self) -> LevelFilter {
+        let level = self.log_level.unwrap_or(LevelFilter::WARN);
+        if self.print_gc_sections {
+            level.max(LevelFilter::INFO)
+        } else {
+            level
+        }
+    }
+    /// The output file, `-` for stdout.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+    fn bind_policy(&self) -> BindPolicy {
+        // Later keywords override earlier ones like in GNU ld.
+        let mut policy = BindPolicy::Lazy;
+        for keyword in &self.keywords {
+            match keyword {
+                Keyword::Now => policy = BindPolicy::Now,
+                Keyword::Lazy => policy = BindPolicy::Lazy,
+                _ => {}
+            }
+        }
+        policy
+    }
+    // Segments start on a fresh page unless disabled by --nmagic or --omagic.
+    fn segment_align(&self, page_size: usize) -> usize {
+        if self.nmagic || self.omagic {
+            1
+        } else {
+            page_size
+        }
+    }
+    fn relro(&self, page_size: usize) -> bool {
+        if self.segment_align(page_size) < page_size {
+            return false;
+        }
+        let mut relro = true;
+        for keyword in &self.keywords {
+            match keyword {
+                Keyword::Relro => relro = true,
+                Keyword::NoRelro => relro = false,
+                _ => {}
+            }
+        }
+        relro
+    }
+}
+
+const SHT_ARM_ATTRIBUTES: u32 = 0x7000_0003;
+
+const PAGE_SIZE: usize = 4096;
+
+// Sign extend the lowest `bits` bits of `value`.
+fn sign_extend(value: i64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (value << shift) >> shift
+}
+
+// An alignment of 0 means none, as in ELF section headers.
+fn align(offset: usize, align: usize) -> usize {
+    let r = offset % align.max(1);
+    if r == 0 {
+        offset
+    } else {
+        offset - r + align
+    }
+}
+
+/// What a link produces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputKind {
+    Executable,
+    /// An object file that can be linked again, like `-r`.
+    Relocatable,
+}
+
+/// What a link produced.
+#[derive(Debug)]
+pub struct LinkResult {
+    /// The contents of the output file.
+    pub output: OutputBuffer,
+    /// The address execution starts at, for ELF executables.
+    pub entry: Option<u64>,
+    /// Where everything ended up, for ELF executables that were not patched by an
+    /// incremental link.
+    pub layout: Option<Layout>,
+    /// The report of --print-size for ELF executables, which the library leaves to the
+    /// caller to print.
+    pub size_report: Option<String>,
+    /// The report of --stats, for ELF executables.
+    pub stats: Option<String>,
+    /// The table of --time-report.
+    pub time_report: Option<String>,
+}
+
+/// The addresses an ELF link assigned, without having to parse the output.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    /// The PT_LOAD segments by address.
+    pub segments: Vec<SegmentLayout>,
+    /// The output sections with the input sections copied into them.
+    pub sections: Vec<SectionLayout>,
+    /// The address of each global symbol in the output, including the ones defined by the
+    /// linker.
+    pub symbols: HashMap<String, u64>,
+}
+
+impl Layout {
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+    /// Where the section `name` of the input `file` was copied to.
+    pub fn input_section(&self, file: &str, name: &str) -> Option<&InputLayout> {
+        self.sections
+            .iter()
+            .flat_map(|sec| &sec.inputs)
+            .find(|input| input.file == file && input.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentLayout {
+    pub address: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    /// The `p_flags`, e.g. `PF_R | PF_X`.
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionLayout {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// Empty for merged sections, which only exist as a whole.
+    pub inputs: Vec<InputLayout>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputLayout {
+    pub file: String,
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
+/// A definition for a symbol that no input defines, see [`Linker::resolve_undefined`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolDefinition {
+    /// The absolute address of the symbol.
+    pub address: u64,
+    /// Where the definition comes from, e.g. the host library of a JIT. The map shows it
+    /// next to the symbol.
+    pub provenance: String,
+}
+
+/// A section that the embedder generates instead of an object file, e.g. a resource blob
+/// or a version struct. It is laid out like any input section with its name and flags
+/// and goes away with `--gc-sections` if nothing references it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyntheticSection {
+    pub name: String,
+    pub contents: Vec<u8>,
+    pub align: u64,
+    pub writable: bool,
+    pub executable: bool,
+    /// Global symbols with their offset in the section.
+    pub symbols: Vec<(String, u64)>,
+}
+
+type Resolver = dyn Fn(&str) -> Option<SymbolDefinition>;
+
+// What the builder methods add to a link besides options and inputs.
+#[derive(Default)]
+struct Hooks {
+    resolver: Option<Box<Resolver>>,
+    reloc_handlers: Vec<Box<dyn RelocHandler>>,
+    sections: Vec<SyntheticSection>,
+    progress: Progress,
+}
+
+/// Links object files into an executable. The options come either from the command line
+/// or from the builder methods.
+///
+/// ```no_run
+/// use toy_linker::{Linker, OutputKind};
+///
+/// let result = Linker::new()
+///     .add_object(std::fs::read("main.o")?)
+///     .add_archive("libfoo.a")
+///     .entry("main")
+///     .output_kind(OutputKind::Executable)
+///     .output("main")
+///     .link()?;
+/// println!("entry point at {:#x}", result.entry.unwrap());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Linker {
+    opts: Opts,
+    // Object files added with `add_object`, linked after the input files
+    objects: Vec<Vec<u8>>,
+    hooks: Hooks,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Linker::new()
+    }
+}
+
+impl Linker {
+    /// A link without any inputs that writes an executable to `a.out`.
+    pub fn new() -> Self {
+        Linker::from_opts(Opts::parse_from(["toy-linker", "-o", "a.out"]))
+    }
+    /// A link described by the command line options.
+    pub fn from_opts(opts: Opts) -> Self {
+        Linker {
+            opts,
+            objects: Vec::new(),
+            hooks: Hooks::default(),
+        }
+    }
+    /// Add an object file that is already in memory.
+    pub fn add_object(mut self, object: impl Into<Vec<u8>>) -> Self {
+        self.objects.push(object.into());
+        self
+    }
+    /// Add an archive. Only the members that define a symbol the link needs are used.
+    pub fn add_archive(mut self, path: impl Into<String>) -> Self {
+        self.opts.input.push(path.into());
+        self
+    }
+    /// Set the symbol the executable starts at instead of `_start`.
+    pub fn entry(mut self, symbol: impl Into<String>) -> Self {
+        self.opts.entry = symbol.into();
+        self
+    }
+    pub fn output_kind(mut self, kind: OutputKind) -> Self {
+        self.opts.relocatable = kind == OutputKind::Relocatable;
+        self
+    }
+    /// Call `resolver` for each symbol that is referenced but not defined by any input.
+    /// The symbols it returns a definition for become absolute symbols, the others are
+    /// still an error. Only ELF links support this.
+    pub fn resolve_undefined(
+        mut self,
+        resolver: impl Fn(&str) -> Option<SymbolDefinition> + 'static,
+    ) -> Self {
+        self.hooks.resolver = Some(Box::new(resolver));
+        self
+    }
+    /// Add a generated section with symbols that the inputs can reference. Only ELF links
+    /// support this, the section is for the target of the object files.
+    pub fn add_section(mut self, section: SyntheticSection) -> Self {
+        self.hooks.sections.push(section);
+        self
+    }
+    /// Register a handler for relocation types the linker does not know or should apply
+    /// differently. Handlers registered later are asked after the earlier ones.
+    pub fn reloc_handler(mut self, handler: impl RelocHandler + 'static) -> Self {
+        self.hooks.reloc_handlers.push(Box::new(handler));
+        self
+    }
+    /// Call `callback` with the phase the link is in and how much of it is done in percent,
+    /// at the start of each phase and between input files and relocation sections.
+    pub fn on_progress(mut self, callback: impl Fn(Phase, u8) + 'static) -> Self {
+        self.hooks.progress.callback = Some(Box::new(callback));
+        self
+    }
+    /// Stop the link with [`LinkError::Cancelled`] once `token` is cancelled, e.g. when
+    /// the inputs changed and a new link makes this one stale.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.hooks.progress.token = Some(token);
+        self
+    }
+    /// Set the output file, `-` writes it to stdout.
+    pub fn output(mut self, path: impl Into<String>) -> Self {
+        self.opts.output = path.into();
+        self
+    }
+    /// Read the input files, link them and write the output file.
+    pub fn link(&self) -> Result<LinkResult, LinkError> {
+        let opts = &self.opts;
+        if opts.time_report || opts.time_trace.is_some() {
+            trace::start_recording();
+        }
+        let progress = &self.hooks.progress;
+        let mut inputs = Vec::new();
+        {
+            let _span = trace::span!("read");
+            progress.report(Phase::Read, 0, opts.input.len())?;
+            for (i, file) in opts.input.iter().enumerate() {
+                let buf = fs::read(file).map_err(|err| LinkError::io(file, err))?;
+                inputs.push((file.clone(), buf));
+                progress.report(Phase::Read, i + 1, opts.input.len())?;
+            }
+        }
+        inputs.extend(object_inputs(&self.objects));
+        let script_text = match &opts.script {
+            Some(file) => Some(fs::read_to_string(file).map_err(|err| LinkError::io(file, err))?),
+            None => None,
+        };
+        let script = match (&opts.script, &script_text) {
+            (Some(file), Some(text)) => {
+                Some(LinkerScript::parse(text).map_err(|err| err.in_file(file))?)
+            }
+            _ => None,
+        };
+        let symbol_order = match &opts.symbol_ordering_file {
+            Some(file) => Some(fs::read_to_string(file).map_err(|err| LinkError::io(file, err))?),
+            None => None,
+        };
+        // ELF executables are written straight into the output file.
+        let map_to = Some(opts.output.as_str()).filter(|path| *path != "-");
+        let mut result = link_inputs(
+            opts,
+            inputs,
+            script.as_ref(),
+            symbol_order.as_deref(),
+            map_to,
+            &self.hooks,
+        )?;
+        let path = &opts.output;
+        {
+            let _span = trace::span!("write");
+            progress.report(Phase::Write, 0, 1)?;
+            result
+                .output
+                .write_to(path, !opts.relocatable)
+                .map_err(|err| LinkError::io(path, err))?;
+            progress.finish();
+        }
+        result.time_report = report_times(opts)?;
+        Ok(result)
+    }
+}
+
+// The `<object i>` names that errors use for objects that are not read from a file.
+fn object_inputs<T: AsRef<[u8]>>(objects: &[T]) -> Vec<(String, Vec<u8>)> {
+    objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (format!("<object {}>", i), object.as_ref().to_vec()))
+        .collect()
+}
+
+/// Link object files that are already in memory and return the contents of the output
+/// file. Unlike [`Linker::link`] this never touches the filesystem, so it fails if the
+/// options name any input, script, symbol ordering file, map or time trace or ask for an
+/// incremental link, and the output path is ignored. The reports of --print-size, --stats
+/// and --time-report are not made, [`Linker::link`] returns them.
+pub fn link_in_memory(objects: &[&[u8]], opts: &Opts) -> Result<Vec<u8>, LinkError> {
+    if !opts.input.is_empty()
+        || opts.script.is_some()
+        || opts.symbol_ordering_file.is_some()
+        || opts.map.is_some()
+        || opts.layout_json.is_some()
+        || opts.incremental
+        || opts.time_trace.is_some()
+    {
+        return Err(LinkError::invalid_options(
+            "An in-memory link cannot read or write files besides the objects and the output",
+        ));
+    }
+    let hooks = Hooks::default();
+    let result = link_inputs(opts, object_inputs(objects), None, None, None, &hooks)?;
+    Ok(result.output.into_vec())
+}
+
+// Write the times recorded for --time-trace and return the table for --time-report.
+fn report_times(opts: &Opts) -> Result<Option<String>, LinkError> {
+    let report = match trace::finish_recording() {
+        Some(report) => report,
+        None => return Ok(None),
+    };
+    if let Some(file) = &opts.time_trace {
+        fs::write(file, report.chrome_trace()).map_err(|err| LinkError::io(file, err))?;
+    }
+    Ok(opts.time_report.then(|| report.table()))
+}
+
+// Everything about a link except reading the input files and writing the output file.
+// An ELF executable is written into a mapping of a temporary file for `map_to` if given.
+fn link_inputs(
+    opts: &Opts,
+    inputs: Vec<(String, Vec<u8>)>,
+    script: Option<&LinkerScript>,
+    symbol_order: Option<&str>,
+    map_to: Option<&str>,
+    hooks: &Hooks,
+) -> Result<LinkResult, LinkError> {
+    let _span = trace::span!("link");
+    if !opts.rpath_link.is_empty() {
+        return Err(LinkError::invalid_options(
+            "--rpath-link is not supported, shared libraries are never read",
+        ));
+    }
+    if opts.soname.is_some() && !opts.shared {
+        return Err(LinkError::invalid_options(
+            "--soname is only supported with --shared",
+        ));
+    }
+    if opts.shared
+        && (opts.relocatable
+            || opts.incremental
+            || opts.gc_sections
+            || script.is_some()
+            || opts.oformat != OutputFormat::Elf)
+    {
+        return Err(LinkError::invalid_options(
+            "--shared cannot be combined with -r, --incremental, --gc-sections, a linker \
+             script or an --oformat other than elf",
+        ));
+    }
+    let progress = &hooks.progress;
+    progress.report(Phase::Parse, 0, inputs.len())?;
+    let mut inputs = load_archive_members(inputs)?;
+    let any_input = |is_format: fn(&[u8]) -> bool| inputs.iter().any(|(_, buf)| is_format(buf));
+    if opts.oformat == OutputFormat::Pe || any_input(is_coff_object) {
+        return Ok(LinkResult {
+            output: OutputBuffer::from(link_pe(opts, &inputs)?),
+            entry: None,
+            layout: None,
+            size_report: None,
+            stats: None,
+            time_report: None,
+        });
+    }
+    if opts.oformat == OutputFormat::MachO || any_input(is_macho_object) {
+        return Ok(LinkResult {
+            output: OutputBuffer::from(link_macho(opts, &inputs)?),
+            entry: None,
+            layout: None,
+            size_report: None,
+            stats: None,
+            time_report: None,
+        });
+    }
+    if opts.oformat == OutputFormat::Wasm || any_input(is_wasm_object) {
+        return Ok(LinkResult {
+            output: OutputBuffer::from(link_wasm(opts, &inputs)?),
+            entry: None,
+            layout: None,
+            size_report: None,
+            stats: None,
+            time_report: None,
+        });
+    }
+    if !hooks.sections.is_empty() {
+        let object = match inputs.first() {
+            Some((_, buf)) => synthetic_object(&hooks.sections, buf)?,
+            None => {
+                return Err(LinkError::invalid_options(
+                    "Synthetic sections need an object file to take the target from",
+                ))
+            }
+        };
+        inputs.push((String::from("<synthetic>"), object));
+    }
+    let reloc_handlers = RelocHandlers::new(&hooks.reloc_handlers);
+    // A resolver may answer differently this time, so its symbols cannot be reused.
+    if opts.incremental && hooks.resolver.is_none() {
+        if let Some(output) = incremental::patch_output(opts, &inputs, &reloc_handlers)? {
+            let entry = goblin::elf::Elf::parse(&output)?.header.e_entry;
+            return Ok(LinkResult {
+                output: OutputBuffer::from(output),
+                entry: Some(entry),
+                layout: None,
+                size_report: None,
+                stats: None,
+                time_report: None,
+            });
+        }
+    }
+    let mut input = Input::new();
+    input.reloc_handlers = reloc_handlers;
+    if let Some(target) = opts.target {
+        input.set_target(target);
+    }
+    {
+        let _span = trace::span!("parse");
+        for (i, (name, buffer)) in inputs.iter().enumerate() {
+            log::trace!("{} ({} bytes)", name, buffer.len());
+            input
+                .process_object_file(name, buffer)
+                .map_err(|err| err.in_file(name))?;
+            progress.report(Phase::Parse, i + 1, inputs.len())?;
+        }
+        let relocations = input
+            .reloc_sections
+            .iter()
+            .map(|sec| sec.relocations.len())
+            .sum();
+        log::debug!("{} input files, {} relocations", inputs.len(), relocations);
+        trace::count("input files", inputs.len());
+        trace::count("relocations", relocations);
+    }
+    input.entry_symbol = opts.entry.clone();
+    input.shared = opts.shared;
+    if opts.shared && input.target().machine() != goblin::elf::header::EM_X86_64 {
+        return Err(LinkError::invalid_options(
+            "--shared is only supported for x86-64",
+        ));
+    }
+    if !opts.image_base.is_multiple_of(input.target().page_size()) {
+        return Err(LinkError::invalid_options(
+            "--image-base must be a multiple of the page size",
+        ));
+    }
+
+    let ctx = input.ctx;
+    if let Some(osabi) = opts.osabi {
+        input.osabi = osabi.value();
+    }
+    if let Some(flags) = opts.e_flags {
+        input.flags = flags;
+    }
+    input.rename_sections(&opts.rename_section);
+    if let Some(text) = symbol_order {
+        let symbols: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        input.set_symbol_order(&symbols);
+    }
+
+    if opts.relocatable {
+        if script.is_some() {
+            return Err(LinkError::invalid_options(
+                "Linker scripts are not supported with -r",
+            ));
+        }
+        if opts.oformat != OutputFormat::Elf {
+            return Err(LinkError::invalid_options(
+                "Only ELF output is supported with -r",
+            ));
+        }
+        let output_vec = input.link_relocatable(&opts.unique, ctx)?;
+        return Ok(LinkResult {
+            output: OutputBuffer::from(output_vec),
+            entry: None,
+            layout: None,
+            size_report: None,
+            stats: None,
+            time_report: None,
+        });
+    }
+
+    {
+        let _span = trace::span!("resolve");
+        progress.report(Phase::Resolve, 0, 1)?;
+        input.allocate_common_symbols();
+        // Symbols assigned by the script can be referenced like any other symbol.
+        for name in script.iter().flat_map(|script| script.symbols()) {
+            input.symtab.add_linker_symbol(name);
+        }
+        if opts.gc_sections {
+            let removed = input.gc_sections(script, &opts.keep_section);
+            log::debug!("removed {} unused sections", removed.len());
+            if opts.print_gc_sections {
+                for sec in removed {
+                    log::info!(
+                        "removing unused section '{}' in file '{}'",
+                        input.section_names[&sec],
+                        input.file_names[sec.0]
+                    );
+                }
+            }
+        }
+
+        if let Some(resolver) = &hooks.resolver {
+            input.resolve_undefined(resolver);
+        }
+        input.check_undefined()?;
+        log::debug!("{} global symbols", input.symtab.globals.len());
+        trace::count("global symbols", input.symtab.globals.len());
+    }
+
+    let output = {
+        let _span = trace::span!("layout");
+        progress.report(Phase::Layout, 0, 1)?;
+        match script {
+            Some(script) => input.allocate_with_script(script, opts, ctx)?,
+            None => input.allocate(opts, ctx)?,
+        }
+    };
+    log::debug!(
+        "{} output sections in {} segments, {} bytes",
+        output.sections.len(),
+        output.load_segments.len(),
+        output.total_size
+    );
+    trace::count("output sections", output.sections.len());
+
+    let map_to = map_to.filter(|_| opts.oformat == OutputFormat::Elf);
+    let mut output_vec = OutputBuffer::new(map_to, output.total_size)?;
+
+    {
+        let _span = trace::span!("copy");
+        progress.report(Phase::Copy, 0, 1)?;
+        output.write(&mut output_vec, ctx)?;
+    }
+    {
+        let _span = trace::span!("relocate");
+        output.relocate(&mut output_vec, ctx, progress)?;
+    }
+    {
+        let _span = trace::span!("headers");
+        progress.report(Phase::Headers, 0, 1)?;
+        if let Some(hdr) = &output.eh_frame_hdr {
+            hdr.write(&mut output_vec, output.base(hdr.address), ctx)?;
+        }
+        output.write_section_headers(&mut output_vec, opts.emit_relocs, ctx)?;
+        if let Some(note) = &output.build_id {
+            note.write_id(&mut output_vec, output.base(note.address))?;
+        }
+    }
+    if let Some(map) = &opts.map {
+        fs::write(map, output.map(ctx)).map_err(|err| LinkError::io(map, err))?;
+    }
+    if let Some(file) = &opts.layout_json {
+        fs::write(file, output.layout_json(ctx)).map_err(|err| LinkError::io(file, err))?;
+    }
+    let layout = output.inspect(&output_vec, ctx)?;
+    if opts.incremental && opts.oformat == OutputFormat::Elf && opts.output != "-" {
+        output.write_incremental_state(opts, &output_vec, ctx)?;
+    }
+    let output_vec = match opts.oformat {
+        OutputFormat::Elf => output_vec,
+        OutputFormat::Binary => {
+            OutputBuffer::from(binary_image(&output.segment_contents(&output_vec)))
+        }
+        OutputFormat::Ihex => OutputBuffer::from(ihex_image(
+            &output.segment_contents(&output_vec),
+            output.entry()?,
+        )?),
+        OutputFormat::Srec => OutputBuffer::from(srec_image(
+            &output.segment_contents(&output_vec),
+            output.entry()?,
+        )?),
+        OutputFormat::Pe | OutputFormat::MachO | OutputFormat::Wasm => unreachable!(),
+    };
+
+    trace::count("output bytes", output_vec.len());
+    let entry = output.entry()?;
+    let entry = u64::try_from(entry)
+        .map_err(|_| LinkError::overflow(format!("Entry {:#x} does not fit in 64 bits", entry)))?;
+    let size_report = opts.print_size.then(|| {
+        output.size_report(
+            output_vec.len(),
+            &layout.segments,
+            opts.print_size_symbols,
+            ctx,
+        )
+    });
+    let stats = opts
+        .stats
+        .then(|| stats::report(&output.memory_usage(&output_vec, ctx)));
+    Ok(LinkResult {
+        output: output_vec,
+        entry: Some(entry),
+        layout: Some(layout),
+        size_report,
+        stats,
+        time_report: None,
+    })
+}
+
+// Write to a temporary file that replaces the output only once it is complete, so a
+// failed link leaves the previous output intact.
+pub(crate) fn write_file(path: &str, contents: &[u8], executable: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if path == "-" {
+        // Whatever reads it decides about the permissions.
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(contents)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let file = fs::File::create(&tmp_path)?;
+    let mut buffer = std::io::BufWriter::new(&file);
+    buffer.write_all(contents)?;
+    buffer.flush()?;
+    if executable {
+        let mut permissions = file.metadata()?.permissions();
+        permissions.set_mode(0o755);
+        file.set_permissions(permissions)?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,938 @@
+//! Linking x86-64 and ARM64 Mach-O objects into macOS executables.
+
+use crate::buffer::reserve;
+use crate::emit::StringTable;
+use crate::error::LinkError;
+use crate::input::MAX_SECTION_SIZE;
+use crate::resolve::{
+    ObjectInput, ObjectRelocation, ObjectSection, ObjectSymbol, RelocationTarget, SymbolDef,
+};
+use crate::{align, sign_extend, Opts, PAGE_SIZE};
+use goblin::container::Ctx;
+use scroll::{Pread, Pwrite};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// The first 4 GiB are __PAGEZERO so that truncated pointers fault.
+pub(crate) const MACHO_TEXT_BASE: u64 = 0x1_0000_0000;
+
+const MACHO_DYLINKER: &str = "/usr/lib/dyld";
+
+const MACHO_LIBSYSTEM: &str = "/usr/lib/libSystem.B.dylib";
+
+const LC_BUILD_VERSION_SIZE: usize = 24;
+
+// goblin's SIZEOF_DYLIB_COMMAND is 4 bytes short.
+const DYLIB_COMMAND_SIZE: usize = 8 + goblin::mach::load_command::SIZEOF_DYLIB;
+
+const PLATFORM_MACOS: u32 = 1;
+
+// 11.0.0
+const MACOS_VERSION: u32 = 0x000b_0000;
+
+pub(crate) fn is_macho_object(buf: &[u8]) -> bool {
+    use goblin::mach::header::*;
+    use scroll::Pread;
+    buf.len() >= SIZEOF_HEADER_64
+        && buf.pread_with::<u32>(0, scroll::LE).unwrap() == MH_MAGIC_64
+        && buf.pread_with::<u32>(12, scroll::LE).unwrap() == MH_OBJECT
+}
+
+/// A section of the Mach-O executable.
+pub(crate) struct MachOSection {
+    pub(crate) segment: &'static str,
+    pub(crate) name: String,
+    pub(crate) flags: u32,
+    pub(crate) align: usize,
+    pub(crate) address: u64,
+    pub(crate) size: usize,
+    // Shorter than `size` for zerofill sections
+    pub(crate) data: Vec<u8>,
+}
+
+// The size of a load command with a string, padded to 8 bytes as for all 64 bit commands.
+fn macho_string_command_size(header: usize, s: &str) -> usize {
+    align(header + s.len() + 1, 8)
+}
+
+// Encode the 26 bit word offset of a `b` or `bl`.
+fn arm64_branch26(insn: u32, delta: i64) -> Option<u32> {
+    if delta % 4 != 0 || sign_extend(delta, 28) != delta {
+        return None;
+    }
+    let imm = u32::try_from((delta >> 2) & 0x03ff_ffff).unwrap();
+    Some(insn & 0xfc00_0000 | imm)
+}
+
+// Encode the 4 KiB page offset of an `adrp`.
+fn arm64_page21(insn: u32, pages: i64) -> Option<u32> {
+    if sign_extend(pages, 21) != pages {
+        return None;
+    }
+    let imm = u32::try_from(pages & 0x1f_ffff).unwrap();
+    Some(insn & 0x9f00_001f | (imm & 3) << 29 | (imm >> 2) << 5)
+}
+
+// Encode the low 12 bits of an address in an `add` or a load or store, which scales
+// them by the size of the access.
+fn arm64_pageoff12(insn: u32, value: u64) -> Option<u32> {
+    let mut offset = u32::try_from(value & 0xfff).unwrap();
+    if insn & 0x3b00_0000 == 0x3900_0000 {
+        let scale = if insn & 0x0480_0000 == 0x0480_0000 {
+            // 128 bit vector register
+            4
+        } else {
+            insn >> 30
+        };
+        if offset & ((1 << scale) - 1) != 0 {
+            return None;
+        }
+        offset >>= scale;
+    }
+    Some(insn & !(0xfff << 10) | offset << 10)
+}
+
+impl<'a> ObjectInput<'a> {
+    // Returns the cputype of the file.
+    fn add_macho_file(&mut self, name: &'a str, buf: &'a [u8]) -> Result<u32, LinkError> {
+        use goblin::mach::constants::*;
+        use goblin::mach::cputype::*;
+        use goblin::mach::relocation::*;
+        use goblin::mach::symbols::*;
+        // goblin reserves room for every load command the header claims before reading
+        // them, a command takes at least 8 bytes.
+        let ncmds = buf.pread_with::<u32>(16, scroll::LE)?;
+        if usize::try_from(ncmds).unwrap() > buf.len() / 8 {
+            return Err(LinkError::malformed(
+                "",
+                format!("{} load commands do not fit in the file", ncmds),
+            ));
+        }
+        // It also expects the string table to follow the symbols.
+        let mut offset = goblin::mach::header::SIZEOF_HEADER_64;
+        for _ in 0..ncmds {
+            let cmd = buf.pread_with::<u32>(offset, scroll::LE)?;
+            let cmdsize = buf.pread_with::<u32>(offset + 4, scroll::LE)?;
+            if cmd == goblin::mach::load_command::LC_SYMTAB
+                && buf.pread_with::<u32>(offset + 16, scroll::LE)?
+                    < buf.pread_with::<u32>(offset + 8, scroll::LE)?
+            {
+                return Err(LinkError::malformed(
+                    "",
+                    "String table before the symbol table",
+                ));
+            }
+            // goblin rejects the commands that are too small, but slices the thread state
+            // without checking it against the command or the file.
+            let cmdsize = usize::try_from(cmdsize).unwrap();
+            if cmdsize < 8 {
+                break;
+            }
+            let thread_state_end = match cmd {
+                goblin::mach::load_command::LC_THREAD
+                | goblin::mach::load_command::LC_UNIXTHREAD => {
+                    let count = buf.pread_with::<u32>(offset + 12, scroll::LE)?;
+                    16 + 4 * usize::try_from(count).unwrap()
+                }
+                _ => 0,
+            };
+            if offset + cmdsize.max(thread_state_end) > buf.len() {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Load command at {:#x} does not fit in the file", offset),
+                ));
+            }
+            offset += cmdsize;
+        }
+        let macho = goblin::mach::MachO::parse(buf, 0)?;
+        let ctx = Ctx::new(goblin::container::Container::Big, scroll::LE);
+        let cputype = macho.header.cputype;
+        let file = self.file_names.len();
+        self.file_names.push(name);
+        let first_section = self.sections.len();
+        let mut relocations = Vec::new();
+        for segment in macho.segments.iter() {
+            for section in segment {
+                let (section, data) = section?;
+                let segment = section.segname()?.to_string();
+                let zerofill = section.flags & SECTION_TYPE == S_ZEROFILL;
+                if section.align >= 32 {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!("Alignment 2^{} is too large", section.align),
+                    ));
+                }
+                if section.size > MAX_SECTION_SIZE
+                    || section
+                        .addr
+                        .checked_add(section.size)
+                        .is_none_or(|end| end > MAX_SECTION_SIZE)
+                {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!("Section {} has an invalid address or size", section.name()?),
+                    ));
+                }
+                let loaded =
+                    section.flags & S_ATTR_DEBUG == 0 && segment != "__LD" && segment != "__DWARF";
+                relocations.push(
+                    section
+                        .iter_relocations(buf, ctx)
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
+                self.sections.push(ObjectSection {
+                    file,
+                    segment,
+                    name: section.name()?.to_string(),
+                    flags: section.flags,
+                    address: section.addr,
+                    align: 1 << section.align,
+                    contents: if zerofill { None } else { Some(data) },
+                    size: usize::try_from(section.size).unwrap(),
+                    loaded,
+                    relocations: Vec::new(),
+                });
+            }
+        }
+        let (addend_type, subtractor_type) = match cputype {
+            CPU_TYPE_X86_64 => (None, X86_64_RELOC_SUBTRACTOR),
+            CPU_TYPE_ARM64 => (Some(ARM64_RELOC_ADDEND), ARM64_RELOC_SUBTRACTOR),
+            _ => {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Unsupported Mach-O cputype: {:#x}", cputype),
+                ))
+            }
+        };
+        // Section numbers start at 1.
+        let section_index = |number: usize| {
+            number
+                .checked_sub(1)
+                .map(|number| first_section + number)
+                .ok_or_else(|| LinkError::malformed("", "Reference to section 0"))
+        };
+        for (i, relocs) in relocations.into_iter().enumerate() {
+            // ARM64_RELOC_ADDEND and SUBTRACTOR go with the relocation that follows them.
+            let mut addend = 0;
+            let mut minus = None;
+            for reloc in relocs {
+                let target = if reloc.is_extern() {
+                    RelocationTarget::Symbol(reloc.r_symbolnum())
+                } else {
+                    RelocationTarget::Section(section_index(reloc.r_symbolnum())?)
+                };
+                if Some(reloc.r_type()) == addend_type {
+                    addend = sign_extend(i64::try_from(reloc.r_symbolnum()).unwrap(), 24);
+                    continue;
+                }
+                if reloc.r_type() == subtractor_type {
+                    minus = Some(target);
+                    continue;
+                }
+                self.sections[first_section + i]
+                    .relocations
+                    .push(ObjectRelocation {
+                        // Scattered relocations have the highest bit set.
+                        offset: usize::try_from(reloc.r_address).map_err(|_| {
+                            LinkError::malformed("", "Scattered relocations are not supported")
+                        })?,
+                        target,
+                        r_type: u32::from(reloc.r_type()),
+                        length: reloc.r_length(),
+                        addend,
+                        minus,
+                    });
+                addend = 0;
+                minus = None;
+            }
+        }
+
+        let mut symbols = Vec::new();
+        for sym in macho.symbols() {
+            let (name, nlist) = sym?;
+            if nlist.is_stab() {
+                symbols.push(None);
+                continue;
+            }
+            let def = match nlist.get_type() {
+                N_UNDF if nlist.n_value > MAX_SECTION_SIZE => {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!("Common symbol {} is too large", name),
+                    ))
+                }
+                N_UNDF if nlist.n_value > 0 => SymbolDef::Common(nlist.n_value),
+                N_ABS => SymbolDef::Absolute(nlist.n_value),
+                N_SECT => {
+                    let section = section_index(nlist.n_sect)?;
+                    let value = self
+                        .sections
+                        .get(section)
+                        .and_then(|section| nlist.n_value.checked_sub(section.address))
+                        .ok_or_else(|| {
+                            LinkError::malformed(
+                                "",
+                                format!("Symbol {} is outside of its section", name),
+                            )
+                        })?;
+                    SymbolDef::Section(section, value)
+                }
+                _ => SymbolDef::Undefined(None),
+            };
+            symbols.push(Some(ObjectSymbol {
+                name,
+                external: nlist.is_global(),
+                weak: nlist.n_desc & N_WEAK_DEF != 0,
+                def,
+            }));
+        }
+        // Weak definitions that lost stay in the output, other symbols in their section may
+        // still be used.
+        self.add_symbols(symbols);
+        self.check_file(first_section)?;
+        Ok(cputype)
+    }
+    fn link_macho(&self, cputype: u32) -> Result<Vec<u8>, LinkError> {
+        use goblin::mach::constants::*;
+        use goblin::mach::cputype::*;
+        use goblin::mach::header::*;
+        use goblin::mach::load_command::*;
+        use goblin::mach::relocation::*;
+        use scroll::Pread;
+        let arm64 = cputype == CPU_TYPE_ARM64;
+        let page_size = if arm64 { 0x4000 } else { PAGE_SIZE };
+
+        // Code and read-only data go to __TEXT, everything else to __DATA. __text comes
+        // first and zerofill sections last.
+        let mut keys: Vec<(&'static str, &str, bool)> = Vec::new();
+        for section in self.sections.iter().filter(|section| section.loaded) {
+            let segment = if section.segment == "__TEXT" {
+                "__TEXT"
+            } else {
+                "__DATA"
+            };
+            let key = (segment, section.name.as_str(), section.contents.is_none());
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys.sort_by_key(|(segment, name, zerofill)| {
+            (*segment == "__DATA", *name != "__text", *zerofill)
+        });
+        let groups = self.groups(keys.len(), |section| {
+            keys.iter()
+                .position(|(segment, name, zerofill)| {
+                    (section.segment == "__TEXT") == (*segment == "__TEXT")
+                        && section.name == *name
+                        && section.contents.is_none() == *zerofill
+                })
+                .unwrap()
+        });
+        let commons = self.commons();
+
+        // Symbols accessed through the GOT
+        let mut got_entries: Vec<(usize, usize)> = Vec::new();
+        let got_types: &[u8] = if arm64 {
+            &[
+                ARM64_RELOC_GOT_LOAD_PAGE21,
+                ARM64_RELOC_GOT_LOAD_PAGEOFF12,
+                ARM64_RELOC_POINTER_TO_GOT,
+            ]
+        } else {
+            &[X86_64_RELOC_GOT_LOAD, X86_64_RELOC_GOT]
+        };
+        for idx in groups.iter().flatten() {
+            let section = &self.sections[*idx];
+            for reloc in &section.relocations {
+                if let RelocationTarget::Symbol(sym) = reloc.target {
+                    let r_type = u8::try_from(reloc.r_type).unwrap();
+                    if got_types.contains(&r_type) && !got_entries.contains(&(section.file, sym)) {
+                        got_entries.push((section.file, sym));
+                    }
+                }
+            }
+        }
+
+        let mut sections = Vec::new();
+        let mut placements = HashMap::new();
+        for (i, (segment, name, _)) in keys.iter().enumerate() {
+            let first = &self.sections[groups[i][0]];
+            // int3 between x86-64 functions
+            let fill = if *segment == "__TEXT" && !arm64 {
+                0xcc
+            } else {
+                0
+            };
+            let (data, size) =
+                self.concatenate(&groups[i], fill, sections.len(), &mut placements)?;
+            sections.push(MachOSection {
+                segment,
+                name: name.to_string(),
+                flags: first.flags,
+                align: groups[i]
+                    .iter()
+                    .map(|idx| self.sections[*idx].align)
+                    .max()
+                    .unwrap(),
+                address: 0,
+                size,
+                data,
+            });
+        }
+        if !got_entries.is_empty() {
+            let position = sections
+                .iter()
+                .position(|section| {
+                    section.segment == "__DATA" && section.data.len() < section.size
+                })
+                .unwrap_or(sections.len());
+            sections.insert(
+                position,
+                MachOSection {
+                    segment: "__DATA",
+                    name: "__got".to_string(),
+                    flags: S_REGULAR,
+                    align: 8,
+                    address: 0,
+                    size: got_entries.len() * 8,
+                    data: vec![0; got_entries.len() * 8],
+                },
+            );
+            for placement in placements.values_mut() {
+                if placement.0 >= position {
+                    placement.0 += 1;
+                }
+            }
+        }
+        let mut common_offsets = HashMap::new();
+        if !commons.is_empty() {
+            let mut size = 0;
+            for (name, common_size) in &commons {
+                size = align(size, 16);
+                common_offsets.insert(*name, size);
+                size += usize::try_from(*common_size).unwrap();
+            }
+            sections.push(MachOSection {
+                segment: "__DATA",
+                name: "__common".to_string(),
+                flags: S_ZEROFILL,
+                align: 16,
+                address: 0,
+                size,
+                data: Vec::new(),
+            });
+        }
+        let text_count = sections.iter().filter(|s| s.segment == "__TEXT").count();
+        let data_count = sections.len() - text_count;
+
+        // The header and load commands are at the start of __TEXT.
+        let mut commands_size = SIZEOF_SEGMENT_COMMAND_64 * 3
+            + SIZEOF_SECTION_64 * sections.len()
+            + SIZEOF_SYMTAB_COMMAND
+            + SIZEOF_DYSYMTAB_COMMAND
+            + macho_string_command_size(SIZEOF_DYLINKER_COMMAND, MACHO_DYLINKER)
+            + SIZEOF_ENTRY_POINT_COMMAND
+            + macho_string_command_size(DYLIB_COMMAND_SIZE, MACHO_LIBSYSTEM)
+            + LC_BUILD_VERSION_SIZE;
+        let mut ncmds = 9;
+        if data_count > 0 {
+            commands_size += SIZEOF_SEGMENT_COMMAND_64;
+            ncmds += 1;
+        }
+        let mut address =
+            MACHO_TEXT_BASE + u64::try_from(SIZEOF_HEADER_64 + commands_size).unwrap();
+        let data_start = sections.iter().position(|s| s.segment == "__DATA");
+        let mut text_size = 0;
+        for (i, section) in sections.iter_mut().enumerate() {
+            if Some(i) == data_start {
+                text_size = usize::try_from(address - MACHO_TEXT_BASE).unwrap();
+                text_size = align(text_size, page_size);
+                address = MACHO_TEXT_BASE + u64::try_from(text_size).unwrap();
+            }
+            address =
+                u64::try_from(align(usize::try_from(address).unwrap(), section.align)).unwrap();
+            section.address = address;
+            address += u64::try_from(section.size).unwrap();
+        }
+        let end = usize::try_from(address - MACHO_TEXT_BASE).unwrap();
+        if data_start.is_none() {
+            text_size = align(end, page_size);
+        }
+        // The file contains the data up to the last initialized section.
+        let file_end = sections
+            .iter()
+            .filter(|s| !s.data.is_empty() || s.size == 0)
+            .map(|s| usize::try_from(s.address - MACHO_TEXT_BASE).unwrap() + s.data.len())
+            .max()
+            .unwrap_or(0)
+            .max(SIZEOF_HEADER_64 + commands_size);
+        let data_file_size = align(file_end, page_size) - text_size.min(align(file_end, page_size));
+        let linkedit_offset = text_size + data_file_size;
+        let linkedit_address = MACHO_TEXT_BASE + u64::try_from(align(end, page_size)).unwrap();
+
+        let addresses: Vec<u64> = sections.iter().map(|section| section.address).collect();
+        let common_address = sections.last().map_or(0, |section| section.address);
+        let symbol_address = |file: usize, idx: usize| -> Result<u64, LinkError> {
+            let sym = self.resolve(file, idx);
+            match sym.def {
+                SymbolDef::Section(section, value) => {
+                    let (out, offset) = self.placement(&placements, section)?;
+                    Ok(addresses[out] + u64::try_from(offset).unwrap() + value)
+                }
+                SymbolDef::Absolute(value) => Ok(value),
+                // Only external common symbols get allocated.
+                SymbolDef::Common(_) => common_offsets
+                    .get(sym.name)
+                    .map(|offset| common_address + u64::try_from(*offset).unwrap())
+                    .ok_or_else(|| {
+                        LinkError::malformed(
+                            self.file_names[file],
+                            format!("Common symbol {} is not external", sym.name),
+                        )
+                    }),
+                SymbolDef::Undefined(_) if sym.name == "__mh_execute_header" => Ok(MACHO_TEXT_BASE),
+                SymbolDef::Undefined(_) => Err(LinkError::undefined(sym.name)),
+            }
+        };
+        // How far a section moved from its address in the object file
+        let slide = |section: usize| -> Result<i64, LinkError> {
+            let (out, offset) = self.placement(&placements, section)?;
+            let address = addresses[out] + u64::try_from(offset).unwrap();
+            Ok(i64::try_from(address).unwrap()
+                - i64::try_from(self.sections[section].address).unwrap())
+        };
+        // Absolute symbols can have any value, the fields are checked for overflow instead.
+        let signed = |value: u64| i64::from_le_bytes(value.to_le_bytes());
+        let got = sections.iter().position(|s| s.name == "__got");
+        let got_address = |file: usize, idx: usize| -> i64 {
+            let entry = got_entries
+                .iter()
+                .position(|entry| *entry == (file, idx))
+                .unwrap();
+            i64::try_from(addresses[got.unwrap()]).unwrap() + i64::try_from(entry * 8).unwrap()
+        };
+        let got_values: Vec<u64> = got_entries
+            .iter()
+            .map(|(file, idx)| symbol_address(*file, *idx))
+            .collect::<Result<_, _>>()?;
+
+        let mut writes = Vec::new();
+        for (idx, (out, offset)) in &placements {
+            let section = &self.sections[*idx];
+            for reloc in &section.relocations {
+                let file = section.file;
+                let p = i64::try_from(addresses[*out]).unwrap()
+                    + i64::try_from(offset + reloc.offset).unwrap();
+                let (s, g) = match reloc.target {
+                    RelocationTarget::Symbol(sym) => {
+                        let s = signed(symbol_address(file, sym)?);
+                        let g = got_entries
+                            .contains(&(file, sym))
+                            .then(|| got_address(file, sym));
+                        (s, g)
+                    }
+                    RelocationTarget::Section(target) => (slide(target)?, None),
+                    RelocationTarget::Type(_) => unreachable!(),
+                };
+                let m = match reloc.minus {
+                    Some(RelocationTarget::Symbol(sym)) => signed(symbol_address(file, sym)?),
+                    Some(RelocationTarget::Section(target)) => slide(target)?,
+                    Some(RelocationTarget::Type(_)) => unreachable!(),
+                    None => 0,
+                };
+                let extern_target = matches!(reloc.target, RelocationTarget::Symbol(_));
+                writes.push((
+                    *out,
+                    offset + reloc.offset,
+                    reloc,
+                    p,
+                    s.wrapping_sub(m),
+                    g,
+                    extern_target,
+                    slide(*idx)?,
+                ));
+            }
+        }
+        for (i, value) in got_values.iter().enumerate() {
+            sections[got.unwrap()]
+                .data
+                .pwrite_with(*value, i * 8, scroll::LE)?;
+        }
+        for (out, offset, reloc, p, s, g, extern_target, p_slide) in writes {
+            let data = &mut sections[out].data;
+            let r_type = u8::try_from(reloc.r_type).unwrap();
+            let overflow = || {
+                LinkError::overflow(format!(
+                    "relocation truncated to fit: {} at {:#x}",
+                    reloc_to_str(r_type, cputype),
+                    p
+                ))
+            };
+            // Only relocations against a symbol can go through the GOT.
+            let g = || {
+                g.ok_or_else(|| {
+                    LinkError::unsupported_relocation(
+                        "",
+                        format!("{} against a section", reloc_to_str(r_type, cputype)),
+                    )
+                })
+            };
+            if arm64 && r_type != ARM64_RELOC_UNSIGNED && r_type != ARM64_RELOC_POINTER_TO_GOT {
+                let insn: u32 = data.pread_with(offset, scroll::LE)?;
+                let s = s.wrapping_add(reloc.addend);
+                let insn = match r_type {
+                    ARM64_RELOC_BRANCH26 => arm64_branch26(insn, s.wrapping_sub(p)),
+                    ARM64_RELOC_PAGE21 => arm64_page21(insn, (s >> 12) - (p >> 12)),
+                    ARM64_RELOC_PAGEOFF12 => {
+                        arm64_pageoff12(insn, u64::try_from(s & 0xfff).unwrap())
+                    }
+                    ARM64_RELOC_GOT_LOAD_PAGE21 => arm64_page21(insn, (g()? >> 12) - (p >> 12)),
+                    ARM64_RELOC_GOT_LOAD_PAGEOFF12 => {
+                        arm64_pageoff12(insn, u64::try_from(g()?).unwrap())
+                    }
+                    _ => {
+                        return Err(LinkError::unsupported_relocation(
+                            "",
+                            reloc_to_str(r_type, cputype),
+                        ))
+                    }
+                };
+                data.pwrite_with(insn.ok_or_else(overflow)?, offset, scroll::LE)?;
+                continue;
+            }
+            // The addend is stored in the field.
+            let a = match reloc.length {
+                3 => data.pread_with::<i64>(offset, scroll::LE)?,
+                2 => i64::from(data.pread_with::<i32>(offset, scroll::LE)?),
+                _ => {
+                    return Err(LinkError::unsupported_relocation(
+                        "",
+                        format!(
+                            "{} of length {}",
+                            reloc_to_str(r_type, cputype),
+                            reloc.length
+                        ),
+                    ))
+                }
+            };
+            let unsigned = if arm64 {
+                r_type == ARM64_RELOC_UNSIGNED
+            } else {
+                r_type == X86_64_RELOC_UNSIGNED
+            };
+            let value = if unsigned {
+                s.wrapping_add(a)
+            } else if r_type == ARM64_RELOC_POINTER_TO_GOT && arm64 {
+                g()?.wrapping_add(a).wrapping_sub(p)
+            } else {
+                // The field is followed by 1, 2 or 4 bytes for SIGNED_1, SIGNED_2 and
+                // SIGNED_4, which the addend of relocations against a symbol
+                // already accounts for.
+                let base = match r_type {
+                    X86_64_RELOC_GOT_LOAD | X86_64_RELOC_GOT => g()?,
+                    X86_64_RELOC_SIGNED
+                    | X86_64_RELOC_BRANCH
+                    | X86_64_RELOC_SIGNED_1
+                    | X86_64_RELOC_SIGNED_2
+                    | X86_64_RELOC_SIGNED_4 => s,
+                    _ => {
+                        return Err(LinkError::unsupported_relocation(
+                            "",
+                            reloc_to_str(r_type, cputype),
+                        ))
+                    }
+                };
+                if extern_target {
+                    base.wrapping_add(a).wrapping_sub(p + 4)
+                } else {
+                    // The field holds the distance in the object file.
+                    a.wrapping_add(base).wrapping_sub(p_slide)
+                }
+            };
+            match reloc.length {
+                3 => data.pwrite_with(value, offset, scroll::LE)?,
+                _ => data.pwrite_with(
+                    i32::try_from(value).map_err(|_| overflow())?,
+                    offset,
+                    scroll::LE,
+                )?,
+            };
+        }
+
+        // Only the external symbols go into the symbol table.
+        let mut exports: Vec<(&str, u64, usize)> =
+            vec![("__mh_execute_header", MACHO_TEXT_BASE, 0)];
+        for (name, (file, idx)) in &self.globals {
+            let out = match self.symbols[*file][*idx].as_ref().unwrap().def {
+                SymbolDef::Section(section, _) => self.placement(&placements, section)?.0,
+                SymbolDef::Common(_) => sections.len() - 1,
+                _ => continue,
+            };
+            let address = symbol_address(*file, *idx)?;
+            exports.push((name, address, out));
+        }
+        exports.sort_unstable();
+        let mut strtab = StringTable::new();
+        let mut symtab = Vec::new();
+        for (name, address, out) in &exports {
+            let n_strx = u32::try_from(strtab.add(name)).unwrap();
+            symtab.extend_from_slice(&n_strx.to_le_bytes());
+            symtab.push(goblin::mach::symbols::N_SECT | goblin::mach::symbols::N_EXT);
+            symtab.push(u8::try_from(out + 1).unwrap());
+            let n_desc = if *name == "__mh_execute_header" {
+                goblin::mach::symbols::REFERENCED_DYNAMICALLY
+            } else {
+                0
+            };
+            symtab.extend_from_slice(&n_desc.to_le_bytes());
+            symtab.extend_from_slice(&address.to_le_bytes());
+        }
+        let strtab_offset = linkedit_offset + symtab.len();
+        let linkedit_size = symtab.len() + align(strtab.len(), 8);
+        // The file offsets of sections are 32 bits.
+        if u32::try_from(linkedit_offset).is_err() {
+            return Err(LinkError::overflow(format!(
+                "{:#x} bytes of sections do not fit in a Mach-O file",
+                linkedit_offset
+            )));
+        }
+        let mut buf = Vec::new();
+        reserve(&mut buf, linkedit_offset + linkedit_size)?;
+        buf.resize(linkedit_offset + linkedit_size, 0);
+
+        let (entry_file, entry_idx) = match self.globals.get("_main") {
+            Some(global) => *global,
+            None => return Err(LinkError::undefined("_main")),
+        };
+        let entry = symbol_address(entry_file, entry_idx)?;
+        let text = MACHO_TEXT_BASE..MACHO_TEXT_BASE + u64::try_from(text_size).unwrap();
+        if !text.contains(&entry) {
+            return Err(LinkError::malformed(
+                self.file_names[entry_file],
+                format!("The entry point _main at {:#x} is not in __TEXT", entry),
+            ));
+        }
+        let header = Header64 {
+            magic: MH_MAGIC_64,
+            cputype,
+            cpusubtype: if arm64 {
+                CPU_SUBTYPE_ARM64_ALL
+            } else {
+                CPU_SUBTYPE_X86_64_ALL
+            },
+            filetype: MH_EXECUTE,
+            ncmds,
+            sizeofcmds: u32::try_from(commands_size).unwrap(),
+            // Not position independent as there is no rebase information.
+            flags: MH_NOUNDEFS | MH_DYLDLINK | MH_TWOLEVEL,
+            reserved: 0,
+        };
+        let mut offset = 0;
+        buf.gwrite_with(header, &mut offset, scroll::LE)?;
+        let name16 = |name: &str| {
+            let mut bytes = [0; 16];
+            bytes[..name.len()].copy_from_slice(name.as_bytes());
+            bytes
+        };
+        let segment = |name: &str,
+                       vmaddr: u64,
+                       vmsize: usize,
+                       fileoff: usize,
+                       filesize: usize,
+                       prot: u32,
+                       nsects: usize| SegmentCommand64 {
+            cmd: LC_SEGMENT_64,
+            cmdsize: u32::try_from(SIZEOF_SEGMENT_COMMAND_64 + nsects * SIZEOF_SECTION_64).unwrap(),
+            segname: name16(name),
+            vmaddr,
+            vmsize: u64::try_from(vmsize).unwrap(),
+            fileoff: u64::try_from(fileoff).unwrap(),
+            filesize: u64::try_from(filesize).unwrap(),
+            maxprot: prot,
+            initprot: prot,
+            nsects: u32::try_from(nsects).unwrap(),
+            flags: 0,
+        };
+        buf.gwrite_with(
+            segment(
+                "__PAGEZERO",
+                0,
+                usize::try_from(MACHO_TEXT_BASE).unwrap(),
+                0,
+                0,
+                0,
+                0,
+            ),
+            &mut offset,
+            scroll::LE,
+        )?;
+        // VM_PROT_READ = 1, VM_PROT_WRITE = 2, VM_PROT_EXECUTE = 4
+        buf.gwrite_with(
+            segment(
+                "__TEXT",
+                MACHO_TEXT_BASE,
+                text_size,
+                0,
+                text_size,
+                5,
+                text_count,
+            ),
+            &mut offset,
+            scroll::LE,
+        )?;
+        for (i, section) in sections.iter().enumerate() {
+            if i == text_count {
+                let data_size = align(end, page_size) - text_size;
+                buf.gwrite_with(
+                    segment(
+                        "__DATA",
+                        MACHO_TEXT_BASE + u64::try_from(text_size).unwrap(),
+                        data_size,
+                        text_size,
+                        data_file_size,
+                        3,
+                        data_count,
+                    ),
+                    &mut offset,
+                    scroll::LE,
+                )?;
+            }
+            let file_offset = usize::try_from(section.address - MACHO_TEXT_BASE).unwrap();
+            let section_header = Section64 {
+                sectname: name16(&section.name),
+                segname: name16(section.segment),
+                addr: section.address,
+                size: u64::try_from(section.size).unwrap(),
+                offset: if section.data.len() < section.size {
+                    0
+                } else {
+                    u32::try_from(file_offset).unwrap()
+                },
+                align: section.align.trailing_zeros(),
+                reloff: 0,
+                nreloc: 0,
+                flags: section.flags,
+                reserved1: 0,
+                reserved2: 0,
+                reserved3: 0,
+            };
+            buf.gwrite_with(section_header, &mut offset, scroll::LE)?;
+            // Zero-filled sections have no place in the file.
+            if !section.data.is_empty() {
+                buf[file_offset..file_offset + section.data.len()].copy_from_slice(&section.data);
+            }
+        }
+        buf.gwrite_with(
+            segment(
+                "__LINKEDIT",
+                linkedit_address,
+                align(linkedit_size, page_size),
+                linkedit_offset,
+                linkedit_size,
+                1,
+                0,
+            ),
+            &mut offset,
+            scroll::LE,
+        )?;
+        let symbol_count = u32::try_from(exports.len()).unwrap();
+        let symtab_command = SymtabCommand {
+            cmd: LC_SYMTAB,
+            cmdsize: u32::try_from(SIZEOF_SYMTAB_COMMAND).unwrap(),
+            symoff: u32::try_from(linkedit_offset).unwrap(),
+            nsyms: symbol_count,
+            stroff: u32::try_from(strtab_offset).unwrap(),
+            strsize: u32::try_from(strtab.len()).unwrap(),
+        };
+        buf.gwrite_with(symtab_command, &mut offset, scroll::LE)?;
+        let mut dysymtab = [0u32; SIZEOF_DYSYMTAB_COMMAND / 4];
+        dysymtab[0] = LC_DYSYMTAB;
+        dysymtab[1] = u32::try_from(SIZEOF_DYSYMTAB_COMMAND).unwrap();
+        // All symbols are external definitions: iextdefsym, nextdefsym and iundefsym
+        dysymtab[4] = 0;
+        dysymtab[5] = symbol_count;
+        dysymtab[6] = symbol_count;
+        for field in &dysymtab {
+            buf.gwrite_with(*field, &mut offset, scroll::LE)?;
+        }
+        let dylinker_size = macho_string_command_size(SIZEOF_DYLINKER_COMMAND, MACHO_DYLINKER);
+        buf.pwrite_with(LC_LOAD_DYLINKER, offset, scroll::LE)?;
+        buf.pwrite_with(
+            u32::try_from(dylinker_size).unwrap(),
+            offset + 4,
+            scroll::LE,
+        )?;
+        buf.pwrite_with(
+            u32::try_from(SIZEOF_DYLINKER_COMMAND).unwrap(),
+            offset + 8,
+            scroll::LE,
+        )?;
+        buf.pwrite_with(
+            MACHO_DYLINKER.as_bytes(),
+            offset + SIZEOF_DYLINKER_COMMAND,
+            (),
+        )?;
+        offset += dylinker_size;
+        let entry_command = EntryPointCommand {
+            cmd: LC_MAIN,
+            cmdsize: u32::try_from(SIZEOF_ENTRY_POINT_COMMAND).unwrap(),
+            entryoff: entry - MACHO_TEXT_BASE,
+            stacksize: 0,
+        };
+        buf.gwrite_with(entry_command, &mut offset, scroll::LE)?;
+        let dylib_size = macho_string_command_size(DYLIB_COMMAND_SIZE, MACHO_LIBSYSTEM);
+        let dylib_command = DylibCommand {
+            cmd: LC_LOAD_DYLIB,
+            cmdsize: u32::try_from(dylib_size).unwrap(),
+            dylib: Dylib {
+                name: u32::try_from(DYLIB_COMMAND_SIZE).unwrap(),
+                timestamp: 2,
+                current_version: 0x0001_0000,
+                compatibility_version: 0x0001_0000,
+            },
+        };
+        buf.pwrite_with(dylib_command, offset, scroll::LE)?;
+        buf.pwrite_with(MACHO_LIBSYSTEM.as_bytes(), offset + DYLIB_COMMAND_SIZE, ())?;
+        offset += dylib_size;
+        // LC_BUILD_VERSION without tools
+        for field in &[
+            LC_BUILD_VERSION,
+            u32::try_from(LC_BUILD_VERSION_SIZE).unwrap(),
+            PLATFORM_MACOS,
+            MACOS_VERSION,
+            MACOS_VERSION,
+            0,
+        ] {
+            buf.gwrite_with(*field, &mut offset, scroll::LE)?;
+        }
+        assert_eq!(offset, SIZEOF_HEADER_64 + commands_size);
+        buf.pwrite_with(&symtab[..], linkedit_offset, ())?;
+        buf.pwrite_with(strtab.as_bytes(), strtab_offset, ())?;
+        Ok(buf)
+    }
+}
+
+pub(crate) fn link_macho(opts: &Opts, inputs: &[(String, Vec<u8>)]) -> Result<Vec<u8>, LinkError> {
+    if opts.relocatable {
+        return Err(LinkError::invalid_options(
+            "-r is not supported for Mach-O output",
+        ));
+    }
+    let mut input = ObjectInput::new();
+    let mut cputype = None;
+    for (name, buffer) in inputs {
+        if !is_macho_object(buffer) {
+            return Err(LinkError::invalid_options(format!(
+                "{}: Mach-O output needs 64 bit Mach-O input files",
+                name
+            )));
+        }
+        let file_cputype = input
+            .add_macho_file(name, buffer)
+            .map_err(|err| err.in_file(name))?;
+        if *cputype.get_or_insert(file_cputype) != file_cputype {
+            return Err(LinkError::malformed(name, "Mixed Mach-O cputypes"));
+        }
+    }
+    input.link_macho(cputype.unwrap())
+}
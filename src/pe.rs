@@ -0,0 +1,727 @@
+//! Linking x86-64 COFF objects into PE executables.
+
+use crate::error::LinkError;
+use crate::resolve::{
+    ObjectInput, ObjectRelocation, ObjectSection, ObjectSymbol, RelocationTarget, SymbolDef,
+};
+use crate::{align, Opts};
+use scroll::Pwrite;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// Build attributes of ARM objects, not defined by goblin
+/// A function imported into a PE executable, given as `dll:symbol`.
+#[derive(Clone, Debug)]
+pub(crate) struct PeImport {
+    pub(crate) dll: String,
+    symbol: String,
+}
+
+impl std::str::FromStr for PeImport {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((dll, symbol)) if !dll.is_empty() && !symbol.is_empty() => Ok(PeImport {
+                dll: dll.to_string(),
+                symbol: symbol.to_string(),
+            }),
+            _ => Err(format!("invalid --import, expected DLL:SYMBOL: {}", s)),
+        }
+    }
+}
+
+pub(crate) const PE_IMAGE_BASE: u64 = 0x1_4000_0000;
+
+const PE_SECTION_ALIGN: usize = 0x1000;
+
+const PE_FILE_ALIGN: usize = 0x200;
+
+// The DOS header is only the magic and the offset of the PE signature, which follows it.
+const PE_SIGNATURE_OFFSET: usize = 0x40;
+
+const PE_NUM_DATA_DIRECTORIES: usize = 16;
+
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+
+pub(crate) const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+pub(crate) fn is_coff_object(buf: &[u8]) -> bool {
+    use scroll::Pread;
+    buf.len() >= goblin::pe::header::SIZEOF_COFF_HEADER
+        && buf.pread_with::<u16>(0, scroll::LE).unwrap() == goblin::pe::header::COFF_MACHINE_X86_64
+}
+
+/// A section of the PE executable.
+pub(crate) struct PeSection {
+    pub(crate) name: &'static str,
+    characteristics: u32,
+    rva: usize,
+    // The size in memory, `data` is shorter for uninitialized data.
+    pub(crate) size: usize,
+    pub(crate) data: Vec<u8>,
+}
+
+// The output sections in the order they are laid out, the import and base relocation
+// tables follow.
+const PE_OUTPUT_SECTIONS: [(&str, u32); 4] = {
+    use goblin::pe::section_table::*;
+    [
+        (
+            ".text",
+            IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+        ),
+        (
+            ".rdata",
+            IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+        ),
+        (
+            ".data",
+            IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+        ),
+        (
+            ".bss",
+            IMAGE_SCN_CNT_UNINITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+        ),
+    ]
+};
+
+// The index in PE_OUTPUT_SECTIONS of the output section for a COFF section.
+fn pe_output_section(section: &ObjectSection) -> usize {
+    use goblin::pe::section_table::*;
+    let characteristics = section.flags;
+    if characteristics & IMAGE_SCN_CNT_CODE != 0 {
+        0
+    } else if characteristics & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0 {
+        3
+    } else if characteristics & IMAGE_SCN_MEM_WRITE != 0 {
+        2
+    } else {
+        1
+    }
+}
+
+// Grouped sections like `.text$mn` are sorted by the part after the `$`.
+fn grouped_section_suffix(name: &str) -> &str {
+    name.split_once('$').map_or("", |(_, suffix)| suffix)
+}
+
+// The import directory table, lookup tables, address tables, names and the position of
+// the address table in the data.
+fn import_tables(imports: &[PeImport], rva: usize) -> (Vec<u8>, HashMap<&str, usize>) {
+    let mut dlls: Vec<(&str, Vec<&str>)> = Vec::new();
+    for import in imports {
+        match dlls.iter_mut().find(|(dll, _)| *dll == import.dll) {
+            Some((_, symbols)) => symbols.push(&import.symbol),
+            None => dlls.push((&import.dll, vec![&import.symbol])),
+        }
+    }
+    let directory_size = (dlls.len() + 1) * 20;
+    let table_size: usize = dlls
+        .iter()
+        .map(|(_, symbols)| (symbols.len() + 1) * 8)
+        .sum();
+    let lookup_start = directory_size;
+    let address_start = lookup_start + table_size;
+    let mut names = address_start + table_size;
+    let mut data = vec![0; names];
+    let mut table_offset = 0;
+    let mut address_table = HashMap::new();
+    for (i, (dll, symbols)) in dlls.iter().enumerate() {
+        let directory = i * 20;
+        let lookup = lookup_start + table_offset;
+        let address = address_start + table_offset;
+        data.pwrite_with(u32::try_from(rva + lookup).unwrap(), directory, scroll::LE)
+            .unwrap();
+        data.pwrite_with(
+            u32::try_from(rva + address).unwrap(),
+            directory + 16,
+            scroll::LE,
+        )
+        .unwrap();
+        for (j, symbol) in symbols.iter().enumerate() {
+            // A hint of 0 followed by the name, padded to an even size.
+            let hint_name = u64::try_from(rva + names).unwrap();
+            data.pwrite_with(hint_name, lookup + j * 8, scroll::LE)
+                .unwrap();
+            data.pwrite_with(hint_name, address + j * 8, scroll::LE)
+                .unwrap();
+            address_table.insert(*symbol, address + j * 8);
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(symbol.as_bytes());
+            data.push(0);
+            data.resize(align(data.len(), 2), 0);
+            names = data.len();
+        }
+        data.pwrite_with(
+            u32::try_from(rva + names).unwrap(),
+            directory + 12,
+            scroll::LE,
+        )
+        .unwrap();
+        data.extend_from_slice(dll.as_bytes());
+        data.push(0);
+        data.resize(align(data.len(), 2), 0);
+        names = data.len();
+        table_offset += (symbols.len() + 1) * 8;
+    }
+    (data, address_table)
+}
+
+// Blocks of base relocations for the fields at the given addresses, one per page.
+fn base_relocations(mut fixups: Vec<(usize, u16)>) -> Vec<u8> {
+    fixups.sort_unstable();
+    let mut data = Vec::new();
+    let mut i = 0;
+    while i < fixups.len() {
+        let page = fixups[i].0 & !(PE_SECTION_ALIGN - 1);
+        let start = data.len();
+        data.extend_from_slice(&u32::try_from(page).unwrap().to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        while i < fixups.len() && fixups[i].0 & !(PE_SECTION_ALIGN - 1) == page {
+            let (rva, kind) = fixups[i];
+            let entry = kind << 12 | u16::try_from(rva - page).unwrap();
+            data.extend_from_slice(&entry.to_le_bytes());
+            i += 1;
+        }
+        // Blocks start on a 32 bit boundary, the padding entry is ignored.
+        data.resize(align(data.len(), 4), 0);
+        let size = u32::try_from(data.len() - start).unwrap();
+        data.pwrite_with(size, start + 4, scroll::LE).unwrap();
+    }
+    data
+}
+
+impl<'a> ObjectInput<'a> {
+    fn add_coff_file(&mut self, name: &'a str, buf: &'a [u8]) -> Result<(), LinkError> {
+        use goblin::pe::section_table::*;
+        use goblin::pe::symbol::*;
+        let file = self.file_names.len();
+        self.file_names.push(name);
+        let mut offset = 0;
+        let header = goblin::pe::header::CoffHeader::parse(buf, &mut offset)?;
+        offset += usize::from(header.size_of_optional_header);
+        let first_section = self.sections.len();
+        for section in header.sections(buf, &mut offset)? {
+            let flags = section.characteristics;
+            let name = section.name()?.to_string();
+            let size = usize::try_from(section.size_of_raw_data).unwrap();
+            let contents = if flags & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0 {
+                None
+            } else {
+                let start = usize::try_from(section.pointer_to_raw_data).unwrap();
+                Some(buf.get(start..start + size).ok_or_else(|| {
+                    LinkError::malformed(
+                        "",
+                        format!("Section {} extends past the end of the file", name),
+                    )
+                })?)
+            };
+            let align = match (flags & IMAGE_SCN_ALIGN_MASK) >> 20 {
+                // The default for object files
+                0 => 16,
+                n => 1 << (n - 1),
+            };
+            let kinds = IMAGE_SCN_CNT_CODE
+                | IMAGE_SCN_CNT_INITIALIZED_DATA
+                | IMAGE_SCN_CNT_UNINITIALIZED_DATA;
+            let loaded = flags & kinds != 0
+                && flags & (IMAGE_SCN_LNK_REMOVE | IMAGE_SCN_LNK_INFO) == 0
+                && !name.starts_with(".debug");
+            let relocations = section
+                .relocations(buf)?
+                .map(|reloc| ObjectRelocation {
+                    offset: usize::try_from(reloc.virtual_address).unwrap(),
+                    target: RelocationTarget::Symbol(
+                        usize::try_from(reloc.symbol_table_index).unwrap(),
+                    ),
+                    r_type: u32::from(reloc.typ),
+                    length: 0,
+                    addend: 0,
+                    minus: None,
+                })
+                .collect();
+            self.sections.push(ObjectSection {
+                file,
+                segment: String::new(),
+                name,
+                flags,
+                address: 0,
+                align,
+                contents,
+                size,
+                loaded,
+                relocations,
+            });
+        }
+
+        let symtab = header.symbols(buf)?;
+        let strtab = header.strings(buf)?;
+        let count = usize::try_from(header.number_of_symbol_table).unwrap();
+        let mut symbols = Vec::with_capacity(count);
+        // The COMDAT selection of each section, from the record of its section symbol
+        let mut selections = HashMap::new();
+        while symbols.len() < count {
+            let idx = symbols.len();
+            let invalid = || LinkError::malformed("", format!("Invalid symbol {}", idx));
+            let (inline_name, sym) = symtab.get(idx).ok_or_else(invalid)?;
+            let name = match inline_name {
+                Some(name) => name,
+                None => {
+                    let offset = usize::try_from(sym.name_offset().ok_or_else(invalid)?).unwrap();
+                    strtab.get(offset).ok_or_else(invalid)??
+                }
+            };
+            let def = match sym.section_number {
+                IMAGE_SYM_UNDEFINED if sym.storage_class == IMAGE_SYM_CLASS_WEAK_EXTERNAL => {
+                    let aux = symtab.aux_weak_external(idx + 1).ok_or_else(invalid)?;
+                    SymbolDef::Undefined(Some(usize::try_from(aux.tag_index).unwrap()))
+                }
+                IMAGE_SYM_UNDEFINED if sym.value > 0 => SymbolDef::Common(u64::from(sym.value)),
+                IMAGE_SYM_UNDEFINED => SymbolDef::Undefined(None),
+                IMAGE_SYM_ABSOLUTE | IMAGE_SYM_DEBUG => SymbolDef::Absolute(u64::from(sym.value)),
+                number => {
+                    // Section numbers start at 1.
+                    let section = usize::try_from(number)
+                        .ok()
+                        .and_then(|number| number.checked_sub(1))
+                        .map(|number| first_section + number)
+                        .filter(|section| *section < self.sections.len())
+                        .ok_or_else(invalid)?;
+                    if sym.storage_class == IMAGE_SYM_CLASS_STATIC
+                        && sym.number_of_aux_symbols > 0
+                        && name == self.sections[section].name
+                    {
+                        let aux = symtab.aux_section_definition(idx + 1).ok_or_else(invalid)?;
+                        selections.insert(section, aux);
+                    }
+                    SymbolDef::Section(section, u64::from(sym.value))
+                }
+            };
+            let weak = match def {
+                SymbolDef::Section(section, _) => {
+                    self.sections[section].flags & IMAGE_SCN_LNK_COMDAT != 0
+                }
+                _ => false,
+            };
+            let external = sym.storage_class == IMAGE_SYM_CLASS_EXTERNAL
+                || sym.storage_class == IMAGE_SYM_CLASS_WEAK_EXTERNAL;
+            symbols.push(Some(ObjectSymbol {
+                name,
+                external,
+                weak,
+                def,
+            }));
+            for _ in 0..sym.number_of_aux_symbols {
+                symbols.push(None);
+            }
+        }
+        let duplicates = self.add_symbols(symbols);
+        self.check_file(first_section)?;
+        for idx in duplicates {
+            if let SymbolDef::Section(section, _) = self.symbols[file][idx].as_ref().unwrap().def {
+                self.discarded.insert(section);
+            }
+        }
+        // Associative COMDAT sections, e.g. the unwind info of a function, go with the
+        // section they belong to.
+        for (section, aux) in selections {
+            if aux.selection == IMAGE_COMDAT_SELECT_ASSOCIATIVE {
+                let associated = (first_section + usize::from(aux.number)).checked_sub(1);
+                if associated.is_some_and(|associated| self.discarded.contains(&associated)) {
+                    self.discarded.insert(section);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn link_pe(&self, imports: &[PeImport]) -> Result<Vec<u8>, LinkError> {
+        use goblin::pe::data_directories::DataDirectory;
+        use goblin::pe::optional_header::*;
+        use goblin::pe::relocation::*;
+        use goblin::pe::section_table::*;
+        use scroll::Pread;
+
+        let mut groups = self.groups(PE_OUTPUT_SECTIONS.len(), pe_output_section);
+        for group in &mut groups {
+            group.sort_by_key(|idx| grouped_section_suffix(&self.sections[*idx].name));
+        }
+        let commons = self.commons();
+
+        // Lay out the sections after the page with the headers.
+        let mut sections = Vec::new();
+        // The output section and offset in it of each COFF section
+        let mut placements = HashMap::new();
+        let mut common_rvas = HashMap::new();
+        let mut thunks = 0;
+        let mut rva = PE_SECTION_ALIGN;
+        for (i, (name, characteristics)) in PE_OUTPUT_SECTIONS.iter().enumerate() {
+            // int3 between code sections
+            let fill = if i == 0 { 0xcc } else { 0 };
+            let (mut data, mut size) =
+                self.concatenate(&groups[i], fill, sections.len(), &mut placements)?;
+            if *name == ".text" && !imports.is_empty() {
+                // A `jmp *__imp_symbol(%rip)` for each import, written once the address
+                // table is placed.
+                size = align(size, 8);
+                thunks = size;
+                size += imports.len() * 8;
+                data.resize(size, fill);
+            }
+            if *name == ".bss" {
+                for (name, common_size) in &commons {
+                    size = align(size, 16);
+                    common_rvas.insert(*name, rva + size);
+                    size += usize::try_from(*common_size).unwrap();
+                }
+            }
+            if size > 0 {
+                sections.push(PeSection {
+                    name,
+                    characteristics: *characteristics,
+                    rva,
+                    size,
+                    data,
+                });
+                rva = align(rva + size, PE_SECTION_ALIGN);
+            }
+        }
+        // RIP relative addressing reaches +-2GiB, which bounds the image as well.
+        if i32::try_from(rva).is_err() {
+            return Err(LinkError::overflow(format!(
+                "PE image of {:#x} bytes is too large",
+                rva
+            )));
+        }
+        let mut import_directory = None;
+        let mut import_rvas = HashMap::new();
+        if !imports.is_empty() {
+            let (data, address_table) = import_tables(imports, rva);
+            import_directory = Some(DataDirectory {
+                virtual_address: u32::try_from(rva).unwrap(),
+                size: u32::try_from(data.len()).unwrap(),
+            });
+            for (symbol, offset) in address_table {
+                import_rvas.insert(symbol, rva + offset);
+            }
+            sections.push(PeSection {
+                name: ".idata",
+                characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA
+                    | IMAGE_SCN_MEM_READ
+                    | IMAGE_SCN_MEM_WRITE,
+                rva,
+                size: data.len(),
+                data,
+            });
+            rva = align(rva + sections.last().unwrap().size, PE_SECTION_ALIGN);
+        }
+        let text_rva = sections.first().map_or(rva, |section| section.rva);
+        let mut thunk_rvas = HashMap::new();
+        for (i, import) in imports.iter().enumerate() {
+            let offset = thunks + i * 8;
+            let target = import_rvas[import.symbol.as_str()];
+            let displacement =
+                i32::try_from(target).unwrap() - i32::try_from(text_rva + offset + 6).unwrap();
+            let text = &mut sections[0].data;
+            text.pwrite_with(&[0xff, 0x25][..], offset, ())?;
+            text.pwrite_with(displacement, offset + 2, scroll::LE)?;
+            thunk_rvas.insert(import.symbol.as_str(), text_rva + offset);
+        }
+
+        let symbol_rva = |file: usize, idx: usize| -> Result<usize, LinkError> {
+            let sym = self.resolve(file, idx);
+            match sym.def {
+                SymbolDef::Section(section, value) => {
+                    let (out, offset) = self.placement(&placements, section)?;
+                    // Empty sections are left out, their symbols are at the start of the
+                    // next one.
+                    let start = sections.get(out).map_or(rva, |section| section.rva);
+                    Ok(start + offset + usize::try_from(value).unwrap())
+                }
+                SymbolDef::Absolute(value) => {
+                    Ok(usize::try_from(value.wrapping_sub(PE_IMAGE_BASE)).unwrap())
+                }
+                // Only external common symbols get allocated.
+                SymbolDef::Common(_) => common_rvas.get(sym.name).copied().ok_or_else(|| {
+                    LinkError::malformed(
+                        self.file_names[file],
+                        format!("Common symbol {} is not external", sym.name),
+                    )
+                }),
+                SymbolDef::Undefined(_) => {
+                    let name = sym.name;
+                    if name == "__ImageBase" {
+                        return Ok(0);
+                    }
+                    if let Some(rva) = name
+                        .strip_prefix("__imp_")
+                        .and_then(|symbol| import_rvas.get(symbol))
+                    {
+                        return Ok(*rva);
+                    }
+                    match thunk_rvas.get(name) {
+                        Some(rva) => Ok(*rva),
+                        None => Err(LinkError::undefined(name)),
+                    }
+                }
+            }
+        };
+        let entry = match self.globals.get("_start") {
+            Some((file, idx)) => symbol_rva(*file, *idx)?,
+            None => return Err(LinkError::undefined("_start")),
+        };
+
+        let mut fixups = Vec::new();
+        let mut values = Vec::new();
+        for idx in groups.iter().flatten() {
+            let section = &self.sections[*idx];
+            let (out, offset) = placements[idx];
+            for reloc in &section.relocations {
+                let p = offset + reloc.offset;
+                let malformed =
+                    |reason: String| LinkError::malformed(self.file_names[section.file], reason);
+                let s = match reloc.target {
+                    RelocationTarget::Symbol(sym) => symbol_rva(section.file, sym)?,
+                    RelocationTarget::Section(_) | RelocationTarget::Type(_) => {
+                        return Err(malformed(format!(
+                            "Relocation at {:#x} in {} is not against a symbol",
+                            reloc.offset, section.name
+                        )))
+                    }
+                };
+                let typ = u16::try_from(reloc.r_type).map_err(|_| {
+                    malformed(format!("Invalid COFF relocation type {:#x}", reloc.r_type))
+                })?;
+                let s = i64::try_from(s).map_err(|_| {
+                    malformed(format!(
+                        "Relocation at {:#x} in {} against a symbol at {:#x} outside of the image",
+                        reloc.offset, section.name, s
+                    ))
+                })?;
+                values.push((out, p, typ, s, *idx));
+            }
+        }
+        for (out, p, typ, s, idx) in values {
+            let section = &mut sections[out];
+            let p_rva = section.rva + p;
+            let data = &mut section.data;
+            let base = i64::try_from(PE_IMAGE_BASE).unwrap();
+            // The addend is stored in the field.
+            let value = match typ {
+                IMAGE_REL_AMD64_ABSOLUTE => continue,
+                IMAGE_REL_AMD64_ADDR64 => {
+                    let a: i64 = data.pread_with(p, scroll::LE)?;
+                    data.pwrite_with((base + s).wrapping_add(a), p, scroll::LE)?;
+                    fixups.push((p_rva, IMAGE_REL_BASED_DIR64));
+                    continue;
+                }
+                IMAGE_REL_AMD64_ADDR32 => {
+                    fixups.push((p_rva, IMAGE_REL_BASED_HIGHLOW));
+                    base + s
+                }
+                IMAGE_REL_AMD64_ADDR32NB => s,
+                IMAGE_REL_AMD64_REL32..=IMAGE_REL_AMD64_REL32_5 => {
+                    let distance = i64::from(typ - IMAGE_REL_AMD64_REL32);
+                    s - i64::try_from(p_rva).unwrap() - 4 - distance
+                }
+                _ => {
+                    return Err(LinkError::unsupported_relocation(
+                        self.file_names[self.sections[idx].file],
+                        format!("COFF relocation type {:#x}", typ),
+                    ))
+                }
+            };
+            let a: i32 = data.pread_with(p, scroll::LE)?;
+            let value = value + i64::from(a);
+            let written = match typ {
+                IMAGE_REL_AMD64_ADDR32 | IMAGE_REL_AMD64_ADDR32NB => u32::try_from(value)
+                    .ok()
+                    .map(|value| data.pwrite_with(value, p, scroll::LE)),
+                _ => i32::try_from(value)
+                    .ok()
+                    .map(|value| data.pwrite_with(value, p, scroll::LE)),
+            };
+            if written.transpose()?.is_none() {
+                let section = &self.sections[idx];
+                return Err(LinkError::overflow(format!(
+                    "{}:({}+{:#x}): relocation truncated to fit: {} ({:#x})",
+                    self.file_names[section.file],
+                    section.name,
+                    p - placements[&idx].1,
+                    typ,
+                    value
+                )));
+            }
+        }
+
+        let mut reloc_directory = None;
+        if !fixups.is_empty() {
+            let data = base_relocations(fixups);
+            reloc_directory = Some(DataDirectory {
+                virtual_address: u32::try_from(rva).unwrap(),
+                size: u32::try_from(data.len()).unwrap(),
+            });
+            sections.push(PeSection {
+                name: ".reloc",
+                characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA
+                    | IMAGE_SCN_MEM_READ
+                    | IMAGE_SCN_MEM_DISCARDABLE,
+                rva,
+                size: data.len(),
+                data,
+            });
+            rva = align(rva + sections.last().unwrap().size, PE_SECTION_ALIGN);
+        }
+
+        let optional_header_size =
+            SIZEOF_STANDARD_FIELDS_64 + SIZEOF_WINDOWS_FIELDS_64 + PE_NUM_DATA_DIRECTORIES * 8;
+        let section_table = PE_SIGNATURE_OFFSET
+            + goblin::pe::header::SIZEOF_PE_MAGIC
+            + goblin::pe::header::SIZEOF_COFF_HEADER
+            + optional_header_size;
+        let headers_size = align(
+            section_table + sections.len() * SIZEOF_SECTION_TABLE,
+            PE_FILE_ALIGN,
+        );
+        let mut file_offset = headers_size;
+        let mut file_offsets = Vec::new();
+        for section in &sections {
+            file_offsets.push(file_offset);
+            file_offset += align(section.data.len(), PE_FILE_ALIGN);
+        }
+        let mut buf = vec![0; file_offset];
+        let mut offset = 0;
+        buf.gwrite_with(goblin::pe::header::DOS_MAGIC, &mut offset, scroll::LE)?;
+        buf.pwrite_with(
+            u32::try_from(PE_SIGNATURE_OFFSET).unwrap(),
+            usize::try_from(goblin::pe::header::PE_POINTER_OFFSET).unwrap(),
+            scroll::LE,
+        )?;
+        let mut offset = PE_SIGNATURE_OFFSET;
+        buf.gwrite_with(goblin::pe::header::PE_MAGIC, &mut offset, scroll::LE)?;
+        let coff_header = goblin::pe::header::CoffHeader {
+            machine: goblin::pe::header::COFF_MACHINE_X86_64,
+            number_of_sections: u16::try_from(sections.len()).unwrap(),
+            // Left at 0 for reproducible output.
+            time_date_stamp: 0,
+            pointer_to_symbol_table: 0,
+            number_of_symbol_table: 0,
+            size_of_optional_header: u16::try_from(optional_header_size).unwrap(),
+            characteristics: goblin::pe::characteristic::IMAGE_FILE_EXECUTABLE_IMAGE
+                | goblin::pe::characteristic::IMAGE_FILE_LARGE_ADDRESS_AWARE,
+        };
+        buf.gwrite_with(coff_header, &mut offset, scroll::LE)?;
+        let size_of = |flag: u32| -> u32 {
+            let size: usize = sections
+                .iter()
+                .filter(|section| section.characteristics & flag != 0)
+                .map(|section| align(section.data.len(), PE_FILE_ALIGN))
+                .sum();
+            u32::try_from(size).unwrap()
+        };
+        let standard_fields = StandardFields64 {
+            magic: MAGIC_64,
+            major_linker_version: 0,
+            minor_linker_version: 0,
+            size_of_code: size_of(IMAGE_SCN_CNT_CODE),
+            size_of_initialized_data: size_of(IMAGE_SCN_CNT_INITIALIZED_DATA),
+            size_of_uninitialized_data: u32::try_from(
+                sections
+                    .iter()
+                    .filter(|s| s.characteristics & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0)
+                    .map(|s| s.size)
+                    .sum::<usize>(),
+            )
+            .unwrap(),
+            address_of_entry_point: u32::try_from(entry).unwrap(),
+            base_of_code: u32::try_from(text_rva).unwrap(),
+        };
+        buf.gwrite_with(standard_fields, &mut offset, scroll::LE)?;
+        // IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA, DYNAMIC_BASE, NX_COMPAT and
+        // TERMINAL_SERVER_AWARE
+        let dll_characteristics = 0x0020 | 0x0040 | 0x0100 | 0x8000;
+        let windows_fields = WindowsFields64 {
+            image_base: PE_IMAGE_BASE,
+            section_alignment: u32::try_from(PE_SECTION_ALIGN).unwrap(),
+            file_alignment: u32::try_from(PE_FILE_ALIGN).unwrap(),
+            major_operating_system_version: 6,
+            minor_operating_system_version: 0,
+            major_image_version: 0,
+            minor_image_version: 0,
+            major_subsystem_version: 6,
+            minor_subsystem_version: 0,
+            win32_version_value: 0,
+            size_of_image: u32::try_from(rva).unwrap(),
+            size_of_headers: u32::try_from(headers_size).unwrap(),
+            check_sum: 0,
+            // IMAGE_SUBSYSTEM_WINDOWS_CUI
+            subsystem: 3,
+            dll_characteristics,
+            size_of_stack_reserve: 0x10_0000,
+            size_of_stack_commit: 0x1000,
+            size_of_heap_reserve: 0x10_0000,
+            size_of_heap_commit: 0x1000,
+            loader_flags: 0,
+            number_of_rva_and_sizes: u32::try_from(PE_NUM_DATA_DIRECTORIES).unwrap(),
+        };
+        buf.gwrite_with(windows_fields, &mut offset, scroll::LE)?;
+        let mut directories = [DataDirectory::default(); PE_NUM_DATA_DIRECTORIES];
+        if let Some(directory) = import_directory {
+            directories[1] = directory;
+            // The address tables of all DLLs follow each other.
+            let first = import_rvas.values().min().unwrap();
+            let last = import_rvas.values().max().unwrap();
+            directories[12] = DataDirectory {
+                virtual_address: u32::try_from(*first).unwrap(),
+                size: u32::try_from(last + 8 - first).unwrap(),
+            };
+        }
+        if let Some(directory) = reloc_directory {
+            directories[5] = directory;
+        }
+        for directory in &directories {
+            buf.gwrite_with(*directory, &mut offset, scroll::LE)?;
+        }
+
+        for (section, file_offset) in sections.iter().zip(file_offsets) {
+            let mut name = [0; 8];
+            name[..section.name.len()].copy_from_slice(section.name.as_bytes());
+            buf.gwrite_with(&name[..], &mut offset, ())?;
+            buf.gwrite_with(
+                u32::try_from(section.size).unwrap(),
+                &mut offset,
+                scroll::LE,
+            )?;
+            buf.gwrite_with(u32::try_from(section.rva).unwrap(), &mut offset, scroll::LE)?;
+            let raw_size = align(section.data.len(), PE_FILE_ALIGN);
+            buf.gwrite_with(u32::try_from(raw_size).unwrap(), &mut offset, scroll::LE)?;
+            let pointer = if raw_size > 0 { file_offset } else { 0 };
+            buf.gwrite_with(u32::try_from(pointer).unwrap(), &mut offset, scroll::LE)?;
+            // No relocations or line numbers
+            buf.gwrite_with(&[0; 12][..], &mut offset, ())?;
+            buf.gwrite_with(section.characteristics, &mut offset, scroll::LE)?;
+            buf[file_offset..file_offset + section.data.len()].copy_from_slice(&section.data);
+        }
+        Ok(buf)
+    }
+}
+
+pub(crate) fn link_pe(opts: &Opts, inputs: &[(String, Vec<u8>)]) -> Result<Vec<u8>, LinkError> {
+    if opts.relocatable {
+        return Err(LinkError::invalid_options(
+            "-r is not supported for PE output",
+        ));
+    }
+    let mut input = ObjectInput::new();
+    for (name, buffer) in inputs {
+        if !is_coff_object(buffer) {
+            return Err(LinkError::invalid_options(format!(
+                "{}: PE output needs x86-64 COFF input files",
+                name
+            )));
+        }
+        input
+            .add_coff_file(name, buffer)
+            .map_err(|err| err.in_file(name))?;
+    }
+    input.link_pe(&opts.import)
+}
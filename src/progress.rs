@@ -0,0 +1,78 @@
+//! Progress reports and cancellation for embedders like IDEs, which start a new link
+//! whenever the inputs change and abort the one that is still running.
+
+use crate::error::LinkError;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The phases of a link in the order they run. PE, Mach-O and wasm links do all their
+/// work in `Parse`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Read,
+    Parse,
+    Resolve,
+    Layout,
+    Copy,
+    Relocate,
+    Headers,
+    Write,
+}
+
+/// Aborts a link from another thread. Clones share the same flag, so the embedder keeps
+/// one and passes another to [`Linker::cancellation`](crate::Linker::cancellation). The
+/// link checks it between input files, relocation sections and phases and fails with
+/// [`LinkError::Cancelled`] once it is set, before the output file is replaced.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// A token that is not cancelled yet.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+    /// Cancel the links that have this token or a clone of it.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    /// Whether `cancel` was called on this token or a clone of it.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) type ProgressCallback = dyn Fn(Phase, u8);
+
+// The callback and token of a link, both optional.
+#[derive(Default)]
+pub(crate) struct Progress {
+    pub(crate) callback: Option<Box<ProgressCallback>>,
+    pub(crate) token: Option<CancellationToken>,
+}
+
+impl Progress {
+    // Report that `done` out of `total` units of `phase` are done, unless the link was
+    // cancelled.
+    pub(crate) fn report(&self, phase: Phase, done: usize, total: usize) -> Result<(), LinkError> {
+        if self
+            .token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(LinkError::Cancelled);
+        }
+        if let Some(callback) = &self.callback {
+            // A phase without units of work is done as soon as it starts.
+            let percent = (done * 100).checked_div(total).unwrap_or(100);
+            callback(phase, u8::try_from(percent.min(100)).unwrap());
+        }
+        Ok(())
+    }
+    // The output is written, so it is too late to cancel.
+    pub(crate) fn finish(&self) {
+        if let Some(callback) = &self.callback {
+            callback(Phase::Write, 100);
+        }
+    }
+}
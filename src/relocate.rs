@@ -0,0 +1,1177 @@
+//! Applying relocations, with the relocation types of each target.
+
+use crate::error::LinkError;
+use crate::input::Input;
+use crate::layout::{section_index, Output, Placement};
+use crate::progress::{Phase, Progress};
+use crate::target::TARGETS;
+use crate::{align, sign_extend, Opts};
+use goblin::container::Ctx;
+use scroll::Pwrite;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+impl<'a> Input<'a> {
+    // GOT accesses need a GOT entry unless they can be relaxed to direct accesses. Those
+    // only reach 2 GiB, so an entry is kept as well for symbols that might be further
+    // away, and `relocate` relaxes the access only if it turns out to be in range. In a
+    // shared library the direct access also has to be relocated like the symbol.
+    pub(crate) fn collect_got_entries(
+        &self,
+        opts: &Opts,
+        script: bool,
+    ) -> Result<GotSection, LinkError> {
+        let may_be_far = script || self.may_span_2gb(opts);
+        let mut got = GotSection::new();
+        for reloc_sec in &self.reloc_sections {
+            let file_buf = self.file_buffers[reloc_sec.applies_to_file];
+            for reloc in &reloc_sec.relocations {
+                let p = reloc_sec.applies_to_offset + usize::try_from(reloc.r_offset).unwrap();
+                let (handler, howto) = self
+                    .reloc_handlers
+                    .howto(self.target().machine(), reloc.r_type)
+                    .map_err(|err| err.in_file(&self.file_names[reloc_sec.applies_to_file]))?;
+                if howto.value != RelocValue::GotPcRelative {
+                    continue;
+                }
+                let def = self
+                    .symtab
+                    .resolve(reloc_sec.applies_to_file, reloc.r_sym)?;
+                let sym = self.symtab.get(def.0, def.1);
+                let needs_entry = if !handler.relaxes_got_access(reloc.r_type, file_buf, p) {
+                    true
+                } else {
+                    let absolute = self.symtab.is_absolute(def.0, &sym);
+                    let fixup = Fixup {
+                        offset: p,
+                        addend: reloc.r_addend.unwrap(),
+                        value: howto.value,
+                        field: howto.field,
+                    };
+                    let s = if absolute {
+                        sym.st_value.cast_signed()
+                    } else {
+                        0
+                    };
+                    // Where the code ends up is not known yet, only an absolute value can
+                    // be checked.
+                    match relaxed_fixup(handler, reloc.r_type, file_buf, fixup, s)? {
+                        Some(direct) if self.shared && !relocated_alike(&direct, absolute) => true,
+                        Some(direct) if absolute => {
+                            direct.value != RelocValue::Absolute
+                                || !direct.field.fits(s.wrapping_add(direct.addend))
+                        }
+                        Some(_) => may_be_far,
+                        None => false,
+                    }
+                };
+                if needs_entry {
+                    got.add(def);
+                }
+            }
+        }
+        Ok(got)
+    }
+    // The number of R_X86_64_RELATIVE relocations a shared library needs, for its GOT
+    // entries and for 64 bit absolute addresses in the loaded sections. Other absolute
+    // relocations against symbols of the library, PC-relative ones against absolute
+    // symbols and thread-local storage cannot be relocated for where it is loaded.
+    pub(crate) fn dynamic_relocations(&self, got: &GotSection) -> Result<usize, LinkError> {
+        if !self.tls_data_sections.is_empty() || !self.tls_bss_sections.is_empty() {
+            return Err(LinkError::invalid_options(
+                "Thread-local storage is not supported with --shared",
+            ));
+        }
+        let debug_sections: HashSet<(usize, goblin::elf::ShdrIdx)> = self
+            .debug_sections
+            .iter()
+            .map(|sec| (sec.file_idx, sec.shdr_idx))
+            .collect();
+        let absolute = |def: (usize, usize)| {
+            self.symtab
+                .is_absolute(def.0, &self.symtab.get(def.0, def.1))
+        };
+        let mut count = got.entries.iter().filter(|def| !absolute(**def)).count();
+        for reloc_sec in &self.reloc_sections {
+            let file_idx = reloc_sec.applies_to_file;
+            if debug_sections.contains(&(file_idx, reloc_sec.applies_to_sec)) {
+                continue;
+            }
+            for reloc in reloc_sec
+                .relocations
+                .iter()
+                .filter(|reloc| reloc.r_sym != 0)
+            {
+                let (_, howto) = self
+                    .reloc_handlers
+                    .howto(self.target().machine(), reloc.r_type)
+                    .map_err(|err| err.in_file(&self.file_names[file_idx]))?;
+                let def = self.symtab.resolve(file_idx, reloc.r_sym)?;
+                let relocatable = match (howto.value, absolute(def)) {
+                    (RelocValue::Absolute, false) if howto.field == Field::Word64 => {
+                        count += 1;
+                        true
+                    }
+                    (RelocValue::Absolute, absolute) => absolute,
+                    (RelocValue::PcRelative, absolute) => !absolute,
+                    (RelocValue::GotPcRelative, _) => true,
+                    (RelocValue::TpOffset, _) => false,
+                };
+                if !relocatable {
+                    let sym = self.symtab.get(def.0, def.1);
+                    return Err(LinkError::unsupported_relocation(
+                        &self.file_names[file_idx],
+                        format!(
+                            "{} against `{}' in a shared library, recompile with -fPIC",
+                            goblin::elf::reloc::r_to_str(reloc.r_type, self.target().machine()),
+                            self.symtab.name(def.0, &sym)
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(count)
+    }
+    // Whether the sections might end up 2 GiB apart or above 2 GiB, where 32 bit
+    // immediates no longer reach them. Without explicit addresses they are laid out one
+    // after the other, each one at most its size and alignment after the previous one.
+    fn may_span_2gb(&self, opts: &Opts) -> bool {
+        if !opts.section_start.is_empty()
+            || opts.text_address.is_some()
+            || opts.data_address.is_some()
+            || opts.bss_address.is_some()
+        {
+            return true;
+        }
+        let sections = [
+            &self.code_sections,
+            &self.tls_data_sections,
+            &self.tls_bss_sections,
+            &self.relro_sections,
+            &self.data_sections,
+            &self.bss_sections,
+            &self.ro_data_sections,
+            &self.merge_sections,
+            &self.init_array_sections,
+            &self.eh_frame_sections,
+            &self.note_sections,
+        ];
+        // Segment boundaries, headers and the sections the linker adds
+        let slack = 16 * self.target().page_size() + (1 << 20);
+        let end = sections
+            .iter()
+            .flat_map(|sections| sections.iter())
+            .map(|sec| sec.section.sh_size.saturating_add(sec.section.sh_addralign))
+            .fold(
+                u64::try_from(opts.image_base + slack).unwrap(),
+                u64::saturating_add,
+            );
+        end >= 1 << 31
+    }
+}
+
+// The fixup that relaxing the code around a relocation would leave, found by relaxing a
+// copy of the code. Its offset is relative to `fixup.offset` like in the original.
+fn relaxed_fixup(
+    handler: &dyn RelocHandler,
+    r_type: u32,
+    code: &[u8],
+    fixup: Fixup,
+    s: i64,
+) -> Result<Option<Fixup>, LinkError> {
+    let (before, after) = handler.code_around(r_type);
+    let size = max(after, fixup.field.size());
+    let mut copy = code[fixup.offset - before..fixup.offset + size].to_vec();
+    let relaxed = handler.relax(
+        &mut copy,
+        r_type,
+        Fixup {
+            offset: before,
+            ..fixup
+        },
+        s,
+    )?;
+    Ok(relaxed.fixup.map(|relaxed| Fixup {
+        offset: fixup.offset + relaxed.offset - before,
+        ..relaxed
+    }))
+}
+
+// Whether the value of a fixup left by relaxing the code around a relocation against a
+// symbol at `s` fits, with the field mapped at `base`.
+fn relaxation_fits(direct: &Fixup, s: i64, base: usize) -> bool {
+    let value = match direct.value {
+        RelocValue::Absolute => s.wrapping_add(direct.addend),
+        RelocValue::PcRelative => s
+            .wrapping_add(direct.addend)
+            .wrapping_sub(i64::try_from(base + direct.offset).unwrap()),
+        _ => return true,
+    };
+    direct.field.fits(value)
+}
+
+// A shared library can be loaded anywhere, so a direct access in it only refers to the
+// symbol if it is absolute for absolute symbols and PC-relative for the others.
+fn relocated_alike(direct: &Fixup, absolute: bool) -> bool {
+    (direct.value == RelocValue::Absolute) == absolute
+}
+
+impl<'a> Output<'a> {
+    // Resolve a symbol referenced from the given file to its address in the output.
+    pub(crate) fn symbol_address(
+        &self,
+        file_idx: usize,
+        sym_idx: usize,
+    ) -> Result<usize, LinkError> {
+        use goblin::elf::section_header::*;
+        let (file_idx, sym_idx) = self.symtab.resolve(file_idx, sym_idx)?;
+        let sym = self.symtab.get(file_idx, sym_idx);
+        if sym.st_shndx == usize::try_from(SHN_ABS).unwrap() {
+            return Ok(usize::try_from(sym.st_value).unwrap());
+        }
+        let address = self.input_address((file_idx, sym.st_shndx), sym.st_value)?;
+        // On ARM the lowest bit of a code address selects Thumb state. Functions have it
+        // set in their value, for untyped labels the mapping symbols tell.
+        if self.target.interworking()
+            && goblin::elf::sym::st_type(sym.st_info) == goblin::elf::sym::STT_NOTYPE
+            && self
+                .symtab
+                .mapping_symbol(file_idx, sym.st_shndx, sym.st_value)
+                == Some('t')
+        {
+            return Ok(address | 1);
+        }
+        Ok(address)
+    }
+    // The address of `offset` in an input section, which inputs can refer to even if it
+    // is not part of the output.
+    fn input_address(
+        &self,
+        sec: (usize, goblin::elf::ShdrIdx),
+        offset: u64,
+    ) -> Result<usize, LinkError> {
+        let placement = self.section_offsets.get(&sec).ok_or_else(|| {
+            LinkError::malformed(
+                &self.file_names[sec.0],
+                format!(
+                    "Reference to section {} that is not part of the output",
+                    section_index(sec.1)
+                ),
+            )
+        })?;
+        // Symbol values and relocation offsets were checked to be in their section.
+        Ok(usize::try_from(placement.translate(i64::try_from(offset).unwrap())).unwrap())
+    }
+    // Whether a symbol is absolute or in a section that is part of the output.
+    pub(crate) fn is_placed(&self, file_idx: usize, sym_idx: usize) -> bool {
+        use goblin::elf::section_header::*;
+        let (file_idx, sym_idx) = match self.symtab.lookup(file_idx, sym_idx) {
+            Some(def) => def,
+            None => return false,
+        };
+        let sym = self.symtab.get(file_idx, sym_idx);
+        sym.st_shndx == usize::try_from(SHN_ABS).unwrap()
+            || self.section_offsets.contains_key(&(file_idx, sym.st_shndx))
+    }
+    // The value to use for S when relocating against a symbol with addend `a`. Section
+    // symbols of merged sections use the addend to select the piece they refer to.
+    fn relocation_symbol_address(
+        &self,
+        file_idx: usize,
+        sym_idx: usize,
+        a: i64,
+    ) -> Result<i64, LinkError> {
+        use goblin::elf::sym::*;
+        let sym = self.symtab.get(file_idx, sym_idx);
+        match self.section_offsets.get(&(file_idx, sym.st_shndx)) {
+            Some(placement @ Placement::Pieces(_)) if st_type(sym.st_info) == STT_SECTION => {
+                Ok(placement.translate(a).wrapping_sub(a))
+            }
+            // Absolute symbols below zero wrap around like addresses do.
+            _ => Ok(u64::try_from(self.symbol_address(file_idx, sym_idx)?)
+                .unwrap()
+                .cast_signed()),
+        }
+    }
+    // Offset of a thread-local symbol from the thread pointer. On x86-64 the TLS block
+    // ends at the thread pointer.
+    fn tp_offset(&self, address: i64) -> Result<i64, LinkError> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => {
+                return Err(LinkError::malformed(
+                    "",
+                    "TLS relocation without TLS sections",
+                ))
+            }
+        };
+        Ok(address - i64::try_from(tls.offset + align(tls.mem_size, tls.align)).unwrap())
+    }
+    pub(crate) fn relocate(
+        &self,
+        buf: &mut [u8],
+        ctx: Ctx,
+        progress: &Progress,
+    ) -> Result<(), LinkError> {
+        use goblin::elf::reloc::*;
+        let got_field = self.target.got_entry();
+        // Address and value of every field the dynamic loader of a shared library adds the
+        // load address to
+        let mut dynamic_relocations = Vec::new();
+        for (i, (file_idx, sym_idx)) in self.got.entries.iter().enumerate() {
+            let s = i64::try_from(self.symbol_address(*file_idx, *sym_idx)?).unwrap();
+            let address = self.got.address + i * ctx.size();
+            got_field.write(buf, self.file_offset(address), s, ctx)?;
+            if self.shared
+                && !self
+                    .symtab
+                    .is_absolute(*file_idx, &self.symtab.get(*file_idx, *sym_idx))
+            {
+                dynamic_relocations.push((address, s));
+            }
+        }
+        // Debug sections are not loaded so they are found by their offset in the file.
+        let debug_offsets: HashMap<(usize, goblin::elf::ShdrIdx), usize> = self
+            .debug_sections
+            .iter()
+            .flat_map(|sec| &sec.input_sections)
+            .map(|(sec, offset)| ((sec.file_idx, sec.shdr_idx), *offset))
+            .collect();
+        let mut overflows = Vec::new();
+        for (i, reloc_sec) in self.reloc_sections.iter().enumerate() {
+            progress.report(Phase::Relocate, i, self.reloc_sections.len())?;
+            let file_idx = reloc_sec.applies_to_file;
+            let target = (file_idx, reloc_sec.applies_to_sec);
+            let debug_offset = debug_offsets.get(&target);
+            let mut relocations = reloc_sec.relocations.iter();
+            while let Some(reloc) = relocations.next() {
+                let (handler, howto) = self
+                    .reloc_handlers
+                    .howto(self.target.machine(), reloc.r_type)
+                    .map_err(|err| err.in_file(&self.file_names[file_idx]))?;
+                // The address the part of the file with the relocated field is mapped at
+                let (base, offset) = match debug_offset {
+                    Some(offset) => (
+                        self.image_base,
+                        offset + usize::try_from(reloc.r_offset).unwrap(),
+                    ),
+                    None => {
+                        let address = self.input_address(target, reloc.r_offset)?;
+                        (self.base(address), self.file_offset(address))
+                    }
+                };
+                let fixup = Fixup {
+                    offset,
+                    addend: reloc.r_addend.unwrap(),
+                    value: howto.value,
+                    field: howto.field,
+                };
+                let s = if debug_offset.is_some() && !self.is_placed(file_idx, reloc.r_sym) {
+                    // Debug information for code removed by --gc-sections
+                    0
+                } else {
+                    self.relocation_symbol_address(file_idx, reloc.r_sym, fixup.addend)?
+                };
+                // Accesses to symbols with a GOT entry stay GOT accesses if the direct access
+                // would not reach the symbol, or in a shared library not be relocated like it.
+                let keep_got = if fixup.value == RelocValue::GotPcRelative {
+                    let def = self.symtab.resolve(file_idx, reloc.r_sym)?;
+                    let absolute = self
+                        .symtab
+                        .is_absolute(def.0, &self.symtab.get(def.0, def.1));
+                    self.got.contains(def)
+                        && relaxed_fixup(handler, reloc.r_type, buf, fixup, s)
+                            .map_err(|err| err.in_file(&self.file_names[file_idx]))?
+                            .is_some_and(|direct| {
+                                !relaxation_fits(&direct, s, base)
+                                    || self.shared && !relocated_alike(&direct, absolute)
+                            })
+                } else {
+                    false
+                };
+                let relaxed = if keep_got {
+                    Relaxed::unchanged(fixup)
+                } else {
+                    handler
+                        .relax(buf, reloc.r_type, fixup, s)
+                        .map_err(|err| err.in_file(&self.file_names[file_idx]))?
+                };
+                if relaxed.consumes_next {
+                    relocations.next();
+                }
+                let fixup = match relaxed.fixup {
+                    Some(fixup) => fixup,
+                    None => continue,
+                };
+                let p = i64::try_from(base + fixup.offset).unwrap();
+                let a = fixup.addend;
+                if self.shared
+                    && debug_offset.is_none()
+                    && reloc.r_sym != 0
+                    && fixup.value == RelocValue::Absolute
+                    && fixup.field == Field::Word64
+                {
+                    let def = self.symtab.resolve(file_idx, reloc.r_sym)?;
+                    if !self
+                        .symtab
+                        .is_absolute(def.0, &self.symtab.get(def.0, def.1))
+                    {
+                        dynamic_relocations.push((base + fixup.offset, s.wrapping_add(a)));
+                    }
+                }
+                // Addends can be anything, the fields are checked for overflow instead.
+                let value = match fixup.value {
+                    RelocValue::Absolute => s.wrapping_add(a),
+                    RelocValue::PcRelative => s.wrapping_add(a).wrapping_sub(p),
+                    RelocValue::GotPcRelative => {
+                        let g = self
+                            .got
+                            .entry_address(self.symtab.resolve(file_idx, reloc.r_sym)?, ctx);
+                        i64::try_from(g).unwrap().wrapping_add(a).wrapping_sub(p)
+                    }
+                    RelocValue::TpOffset => self
+                        .tp_offset(s)
+                        .map_err(|err| err.in_file(&self.file_names[file_idx]))?
+                        .wrapping_add(a),
+                };
+                if fixup.field.fits(value) {
+                    fixup.field.write(buf, fixup.offset, value, ctx)?;
+                } else {
+                    // Keep going so that all overflows get reported at once.
+                    overflows.push(format!(
+                        "{}:({}+{:#x}): relocation truncated to fit: {} against `{}' ({:#x})",
+                        self.file_names[file_idx],
+                        self.section_names[&(file_idx, reloc_sec.applies_to_sec)],
+                        reloc.r_offset,
+                        r_to_str(reloc.r_type, self.target.machine()),
+                        self.symbol_name(file_idx, reloc.r_sym),
+                        value
+                    ));
+                }
+            }
+        }
+        if !overflows.is_empty() {
+            return Err(LinkError::overflow(overflows.join("\n")));
+        }
+        if let Some(symbols) = &self.dynamic.symbols {
+            assert_eq!(dynamic_relocations.len(), symbols.relocations);
+            dynamic_relocations.sort_unstable();
+            let mut offset = self.file_offset(symbols.rela_address);
+            for (address, value) in dynamic_relocations {
+                let reloc = goblin::elf::Reloc {
+                    r_offset: u64::try_from(address).unwrap(),
+                    r_addend: Some(value),
+                    r_sym: 0,
+                    r_type: R_X86_64_RELATIVE,
+                };
+                buf.gwrite_with(reloc, &mut offset, (true, ctx))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Output<'a> {
+    // Name of a symbol for diagnostics, section symbols are named after their section.
+    fn symbol_name(&self, file_idx: usize, sym_idx: usize) -> &str {
+        use goblin::elf::sym::*;
+        let sym = self.symtab.get(file_idx, sym_idx);
+        if st_type(sym.st_info) == STT_SECTION {
+            self.section_names[&(file_idx, sym.st_shndx)]
+        } else {
+            self.symtab.name(file_idx, &sym)
+        }
+    }
+}
+
+/// The width and signedness of a field patched by a relocation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    I8,
+    I16,
+    I32,
+    U32,
+    /// Full width fields cannot overflow.
+    Word32,
+    Word64,
+    /// imm24 of an ARM B/BL, in units of 4 bytes.
+    ArmBranch,
+    /// An ARM BL turned into BLX to switch to Thumb, bit 1 of the offset goes into H.
+    ArmBlx,
+    /// The split immediate of a Thumb-2 BL, in units of 2 bytes.
+    ThumbBranch,
+    /// A Thumb-2 BL turned into BLX to switch to ARM.
+    ThumbBlx,
+}
+
+impl Field {
+    // The number of bytes the field occupies.
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Field::I8 => 1,
+            Field::I16 => 2,
+            Field::Word64 => 8,
+            _ => 4,
+        }
+    }
+    pub(crate) fn fits(self, value: i64) -> bool {
+        match self {
+            Field::I8 => i8::try_from(value).is_ok(),
+            Field::I16 => i16::try_from(value).is_ok(),
+            Field::I32 => i32::try_from(value).is_ok(),
+            Field::U32 => u32::try_from(value).is_ok(),
+            Field::Word32 | Field::Word64 => true,
+            Field::ArmBranch | Field::ArmBlx => (-(1 << 25)..1 << 25).contains(&value),
+            Field::ThumbBranch | Field::ThumbBlx => (-(1 << 24)..1 << 24).contains(&value),
+        }
+    }
+    pub(crate) fn read(self, buf: &[u8], offset: usize, ctx: Ctx) -> Result<i64, LinkError> {
+        use scroll::Pread;
+        let value = match self {
+            Field::I8 => i64::from(buf.pread_with::<i8>(offset, ctx.le)?),
+            Field::I16 => i64::from(buf.pread_with::<i16>(offset, ctx.le)?),
+            Field::I32 => i64::from(buf.pread_with::<i32>(offset, ctx.le)?),
+            Field::U32 => i64::from(buf.pread_with::<u32>(offset, ctx.le)?),
+            Field::Word32 => i64::from(buf.pread_with::<i32>(offset, ctx.le)?),
+            Field::Word64 => buf.pread_with::<i64>(offset, ctx.le)?,
+            Field::ArmBranch | Field::ArmBlx => {
+                let insn = buf.pread_with::<u32>(offset, ctx.le)?;
+                sign_extend(i64::from(insn & 0xff_ffff) << 2, 26)
+            }
+            Field::ThumbBranch | Field::ThumbBlx => {
+                let hi = i64::from(buf.pread_with::<u16>(offset, ctx.le)?);
+                let lo = i64::from(buf.pread_with::<u16>(offset + 2, ctx.le)?);
+                let s = (hi >> 10) & 1;
+                let i1 = !((lo >> 13) ^ s) & 1;
+                let i2 = !((lo >> 11) ^ s) & 1;
+                sign_extend(
+                    s << 24 | i1 << 23 | i2 << 22 | (hi & 0x3ff) << 12 | (lo & 0x7ff) << 1,
+                    25,
+                )
+            }
+        };
+        Ok(value)
+    }
+    pub(crate) fn write(
+        self,
+        buf: &mut [u8],
+        offset: usize,
+        value: i64,
+        ctx: Ctx,
+    ) -> Result<(), LinkError> {
+        use scroll::Pread;
+        match self {
+            Field::I8 => buf.pwrite_with(i8::try_from(value).unwrap(), offset, ctx.le)?,
+            Field::I16 => buf.pwrite_with(i16::try_from(value).unwrap(), offset, ctx.le)?,
+            Field::I32 => buf.pwrite_with(i32::try_from(value).unwrap(), offset, ctx.le)?,
+            Field::U32 => buf.pwrite_with(u32::try_from(value).unwrap(), offset, ctx.le)?,
+            Field::Word32 => {
+                buf.pwrite_with(u32::try_from(value & 0xffff_ffff).unwrap(), offset, ctx.le)?
+            }
+            Field::Word64 => buf.pwrite_with(value, offset, ctx.le)?,
+            Field::ArmBranch | Field::ArmBlx => {
+                let insn = buf.pread_with::<u32>(offset, ctx.le)?;
+                let imm = u32::try_from((value >> 2) & 0xff_ffff).unwrap();
+                let insn = if let Field::ArmBlx = self {
+                    let h = u32::try_from((value >> 1) & 1).unwrap();
+                    0xfa00_0000 | h << 24 | imm
+                } else {
+                    (insn & 0xff00_0000) | imm
+                };
+                buf.pwrite_with(insn, offset, ctx.le)?
+            }
+            Field::ThumbBranch | Field::ThumbBlx => {
+                let hi = buf.pread_with::<u16>(offset, ctx.le)?;
+                let lo = buf.pread_with::<u16>(offset + 2, ctx.le)?;
+                let bits = |shift: u32, mask: i64| u16::try_from((value >> shift) & mask).unwrap();
+                let s = bits(24, 1);
+                let j1 = !(bits(23, 1) ^ s) & 1;
+                let j2 = !(bits(22, 1) ^ s) & 1;
+                let hi = (hi & 0xf800) | s << 10 | bits(12, 0x3ff);
+                let lo = (lo & 0xd000) | j1 << 13 | j2 << 11 | bits(1, 0x7ff);
+                // BLX always targets a 4 byte aligned ARM instruction.
+                let lo = if let Field::ThumbBlx = self {
+                    lo & !0x1001
+                } else {
+                    lo
+                };
+                buf.pwrite_with(hi, offset, ctx.le)?;
+                buf.pwrite_with(lo, offset + 2, ctx.le)?
+            }
+        };
+        Ok(())
+    }
+}
+
+/// How the value written by a relocation is computed from the symbol address S, the
+/// addend A and the address P of the relocated field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelocValue {
+    /// S + A
+    Absolute,
+    /// S + A - P
+    PcRelative,
+    /// G + A - P where G is the address of the GOT entry for S
+    GotPcRelative,
+    /// Offset of S + A from the thread pointer
+    TpOffset,
+}
+
+/// Table entry describing a relocation type of a target.
+#[derive(Clone, Copy, Debug)]
+pub struct RelocationHowTo {
+    pub r_type: u32,
+    pub value: RelocValue,
+    pub field: Field,
+}
+
+pub(crate) const fn howto(r_type: u32, value: RelocValue, field: Field) -> RelocationHowTo {
+    RelocationHowTo {
+        r_type,
+        value,
+        field,
+    }
+}
+
+/// A relocation that is about to be applied at `offset` in the output.
+#[derive(Clone, Copy, Debug)]
+pub struct Fixup {
+    pub offset: usize,
+    pub addend: i64,
+    pub value: RelocValue,
+    pub field: Field,
+}
+
+/// The result of letting the target rewrite the code around a relocation.
+#[derive(Debug)]
+pub struct Relaxed {
+    /// The relocation to apply instead, if anything remains to be written.
+    pub fixup: Option<Fixup>,
+    /// The following relocation belongs to the rewritten code and is dropped.
+    pub consumes_next: bool,
+}
+
+impl Relaxed {
+    /// Apply the relocation as it is.
+    pub fn unchanged(fixup: Fixup) -> Self {
+        Relaxed {
+            fixup: Some(fixup),
+            consumes_next: false,
+        }
+    }
+}
+
+/// The target specific part of applying relocations. Most relocation types are fully
+/// described by `howtos`, the hooks are only needed for relaxations that rewrite code.
+///
+/// Handlers registered with [`Linker::reloc_handler`](crate::Linker::reloc_handler) are
+/// asked before the built-in ones, so they can add relocation types to a target or
+/// replace how the linker applies one.
+pub trait RelocHandler {
+    /// The `e_machine` of the object files whose relocations this handles.
+    fn machine(&self) -> u16;
+    /// The relocation types this handles.
+    fn howtos(&self) -> &[RelocationHowTo];
+    /// Whether an access through the GOT at `offset` in `code` can be turned into a direct
+    /// access so that no GOT entry is needed.
+    fn relaxes_got_access(&self, _r_type: u32, _code: &[u8], _offset: usize) -> bool {
+        false
+    }
+    /// How many bytes before and after the start of the relocated field `relax` reads or
+    /// rewrites. Relocations too close to the edges of their section are rejected.
+    fn code_around(&self, _r_type: u32) -> (usize, usize) {
+        (0, 0)
+    }
+    /// Rewrite the code around a relocation against a symbol at address `s` before its
+    /// value gets written.
+    fn relax(
+        &self,
+        _buf: &mut [u8],
+        _r_type: u32,
+        fixup: Fixup,
+        _s: i64,
+    ) -> Result<Relaxed, LinkError> {
+        Ok(Relaxed::unchanged(fixup))
+    }
+}
+
+/// The relocation handlers of a link, the registered ones followed by the built-in ones.
+#[derive(Clone)]
+pub(crate) struct RelocHandlers<'h> {
+    handlers: Vec<&'h dyn RelocHandler>,
+}
+
+impl<'h> RelocHandlers<'h> {
+    pub(crate) fn new(registered: &'h [Box<dyn RelocHandler>]) -> Self {
+        let mut handlers: Vec<&dyn RelocHandler> =
+            registered.iter().map(|handler| handler.as_ref()).collect();
+        // Targets that only differ in byte order share a handler.
+        let mut built_in: Vec<&dyn RelocHandler> = TARGETS
+            .iter()
+            .map(|target| target.reloc_handler())
+            .collect();
+        built_in.dedup_by_key(|handler| handler.machine());
+        handlers.extend(built_in);
+        RelocHandlers { handlers }
+    }
+    pub(crate) fn check_machine(&self, machine: u16) -> Result<(), LinkError> {
+        if self
+            .handlers
+            .iter()
+            .any(|handler| handler.machine() == machine)
+        {
+            Ok(())
+        } else {
+            Err(LinkError::malformed(
+                "",
+                format!(
+                    "Unsupported machine: {}",
+                    goblin::elf::header::machine_to_str(machine)
+                ),
+            ))
+        }
+    }
+    // The first handler for the relocation type with its description.
+    pub(crate) fn howto(
+        &self,
+        machine: u16,
+        r_type: u32,
+    ) -> Result<(&'h dyn RelocHandler, &'h RelocationHowTo), LinkError> {
+        self.check_machine(machine)?;
+        self.handlers
+            .iter()
+            .filter(|handler| handler.machine() == machine)
+            .find_map(|handler| {
+                let howto = handler
+                    .howtos()
+                    .iter()
+                    .find(|howto| howto.r_type == r_type)?;
+                Some((*handler, howto))
+            })
+            .ok_or_else(|| {
+                LinkError::unsupported_relocation(
+                    "",
+                    format!(
+                        "{} ({})",
+                        goblin::elf::reloc::r_to_str(r_type, machine),
+                        r_type
+                    ),
+                )
+            })
+    }
+}
+
+impl<'h> std::fmt::Debug for RelocHandlers<'h> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.handlers.iter().map(|handler| handler.machine()))
+            .finish()
+    }
+}
+
+pub(crate) struct X86_64;
+
+const X86_64_HOWTOS: &[RelocationHowTo] = {
+    use goblin::elf::reloc::*;
+    use RelocValue::*;
+    &[
+        howto(R_X86_64_64, Absolute, Field::Word64),
+        howto(R_X86_64_PC32, PcRelative, Field::I32),
+        // We never create a PLT so the call goes directly to the symbol.
+        howto(R_X86_64_PLT32, PcRelative, Field::I32),
+        // The value gets zero extended when it is used.
+        howto(R_X86_64_32, Absolute, Field::U32),
+        // The value gets sign extended when it is used.
+        howto(R_X86_64_32S, Absolute, Field::I32),
+        howto(R_X86_64_PC16, PcRelative, Field::I16),
+        howto(R_X86_64_PC8, PcRelative, Field::I8),
+        howto(R_X86_64_PC64, PcRelative, Field::Word64),
+        howto(R_X86_64_GOTPCREL, GotPcRelative, Field::I32),
+        howto(R_X86_64_GOTPCRELX, GotPcRelative, Field::I32),
+        howto(R_X86_64_REX_GOTPCRELX, GotPcRelative, Field::I32),
+        // Local-exec, or local-dynamic after relaxing to local-exec, where the offset
+        // within the module is the offset to the thread pointer.
+        howto(R_X86_64_TPOFF32, TpOffset, Field::I32),
+        howto(R_X86_64_DTPOFF32, TpOffset, Field::I32),
+        // The remaining TLS models are always relaxed to local-exec.
+        howto(R_X86_64_GOTTPOFF, TpOffset, Field::I32),
+        howto(R_X86_64_TLSGD, TpOffset, Field::I32),
+        howto(R_X86_64_TLSLD, TpOffset, Field::I32),
+    ]
+};
+
+impl RelocHandler for X86_64 {
+    fn machine(&self) -> u16 {
+        goblin::elf::header::EM_X86_64
+    }
+    fn howtos(&self) -> &[RelocationHowTo] {
+        X86_64_HOWTOS
+    }
+    fn relaxes_got_access(&self, r_type: u32, code: &[u8], offset: usize) -> bool {
+        use goblin::elf::reloc::*;
+        match r_type {
+            R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                gotpcrel_relaxation(r_type, code, offset).is_some()
+            }
+            _ => false,
+        }
+    }
+    fn code_around(&self, r_type: u32) -> (usize, usize) {
+        use goblin::elf::reloc::*;
+        match r_type {
+            // The opcode and modrm, with a REX prefix in front for the REX_ variant
+            R_X86_64_GOTPCRELX => (2, 4),
+            R_X86_64_REX_GOTPCRELX | R_X86_64_GOTTPOFF => (3, 4),
+            // The lea and the call after it are rewritten as a whole.
+            R_X86_64_TLSGD => (4, 12),
+            R_X86_64_TLSLD => (3, 9),
+            _ => (0, 0),
+        }
+    }
+    fn relax(
+        &self,
+        buf: &mut [u8],
+        r_type: u32,
+        fixup: Fixup,
+        _s: i64,
+    ) -> Result<Relaxed, LinkError> {
+        use goblin::elf::reloc::*;
+        let p = fixup.offset;
+        let relaxed = match r_type {
+            R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                match gotpcrel_relaxation(r_type, buf, p) {
+                    Some(relaxation) => Relaxed::unchanged(relax_gotpcrel(buf, relaxation, fixup)),
+                    None => Relaxed::unchanged(fixup),
+                }
+            }
+            R_X86_64_GOTTPOFF => {
+                // Initial-exec to local-exec relaxation, we always know the offset to the
+                // thread pointer at link time.
+                relax_gottpoff(buf, p)?;
+                Relaxed::unchanged(Fixup { addend: 0, ..fixup })
+            }
+            R_X86_64_TLSGD => {
+                // General-dynamic to local-exec relaxation:
+                //   66 48 8d 3d xx xx xx xx    leaq x@tlsgd(%rip), %rdi
+                //   66 66 48 e8 xx xx xx xx    call __tls_get_addr@plt
+                // becomes
+                //   64 48 8b 04 25 00 00 00 00 mov %fs:0, %rax
+                //   48 8d 80 xx xx xx xx       lea x@tpoff(%rax), %rax
+                let start = p - 4;
+                if buf[start..start + 4] != [0x66, 0x48, 0x8d, 0x3d]
+                    || buf[start + 8..start + 12] != [0x66, 0x66, 0x48, 0xe8]
+                {
+                    return Err(LinkError::malformed(
+                        "",
+                        "Unexpected instruction sequence for R_X86_64_TLSGD",
+                    ));
+                }
+                buf.pwrite_with(
+                    &[
+                        0x64, 0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x80,
+                    ][..],
+                    start,
+                    (),
+                )?;
+                // The relocation for the call to __tls_get_addr is gone.
+                Relaxed {
+                    fixup: Some(Fixup {
+                        offset: start + 12,
+                        addend: 0,
+                        ..fixup
+                    }),
+                    consumes_next: true,
+                }
+            }
+            R_X86_64_TLSLD => {
+                // Local-dynamic to local-exec relaxation:
+                //   48 8d 3d xx xx xx xx       leaq x@tlsld(%rip), %rdi
+                //   e8 xx xx xx xx             call __tls_get_addr@plt
+                // becomes
+                //   66 66 66 64 48 8b 04 25 00 00 00 00
+                //                              mov %fs:0, %rax
+                let start = p - 3;
+                if buf[start..start + 3] != [0x48, 0x8d, 0x3d] || buf[start + 7] != 0xe8 {
+                    return Err(LinkError::malformed(
+                        "",
+                        "Unexpected instruction sequence for R_X86_64_TLSLD",
+                    ));
+                }
+                buf.pwrite_with(
+                    &[
+                        0x66, 0x66, 0x66, 0x64, 0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00,
+                    ][..],
+                    start,
+                    (),
+                )?;
+                Relaxed {
+                    fixup: None,
+                    consumes_next: true,
+                }
+            }
+            _ => Relaxed::unchanged(fixup),
+        };
+        Ok(relaxed)
+    }
+}
+
+pub(crate) struct I386;
+
+const I386_HOWTOS: &[RelocationHowTo] = {
+    use goblin::elf::reloc::*;
+    use RelocValue::*;
+    &[
+        howto(R_386_32, Absolute, Field::Word32),
+        howto(R_386_PC32, PcRelative, Field::Word32),
+        // We never create a PLT so the call goes directly to the symbol.
+        howto(R_386_PLT32, PcRelative, Field::Word32),
+    ]
+};
+
+impl RelocHandler for I386 {
+    fn machine(&self) -> u16 {
+        goblin::elf::header::EM_386
+    }
+    fn howtos(&self) -> &[RelocationHowTo] {
+        I386_HOWTOS
+    }
+}
+
+pub(crate) struct Arm;
+
+const ARM_HOWTOS: &[RelocationHowTo] = {
+    use goblin::elf::reloc::*;
+    use RelocValue::*;
+    &[
+        howto(R_ARM_ABS32, Absolute, Field::Word32),
+        howto(R_ARM_REL32, PcRelative, Field::Word32),
+        howto(R_ARM_GOT_PREL, GotPcRelative, Field::Word32),
+        howto(R_ARM_CALL, PcRelative, Field::ArmBranch),
+        howto(R_ARM_JUMP24, PcRelative, Field::ArmBranch),
+        // R_ARM_THM_CALL, goblin still uses the old name
+        howto(R_ARM_THM_PC22, PcRelative, Field::ThumbBranch),
+        howto(R_ARM_THM_JUMP24, PcRelative, Field::ThumbBranch),
+    ]
+};
+
+impl RelocHandler for Arm {
+    fn machine(&self) -> u16 {
+        goblin::elf::header::EM_ARM
+    }
+    fn howtos(&self) -> &[RelocationHowTo] {
+        ARM_HOWTOS
+    }
+    // Calls between ARM and Thumb code have to switch the instruction set. The lowest
+    // bit of `s` is set for Thumb code.
+    fn relax(
+        &self,
+        _buf: &mut [u8],
+        r_type: u32,
+        fixup: Fixup,
+        s: i64,
+    ) -> Result<Relaxed, LinkError> {
+        use goblin::elf::reloc::*;
+        let thumb = s & 1 != 0;
+        let fixup = match r_type {
+            R_ARM_CALL if thumb => Fixup {
+                field: Field::ArmBlx,
+                ..fixup
+            },
+            // BLX computes the target from the PC aligned down to 4 bytes.
+            R_ARM_THM_PC22 if !thumb => Fixup {
+                field: Field::ThumbBlx,
+                addend: fixup.addend + i64::try_from(fixup.offset & 3).unwrap(),
+                ..fixup
+            },
+            R_ARM_JUMP24 if thumb => {
+                return Err(LinkError::unsupported_relocation(
+                    "",
+                    "R_ARM_JUMP24 to Thumb code needs a veneer",
+                ))
+            }
+            R_ARM_THM_JUMP24 if !thumb => {
+                return Err(LinkError::unsupported_relocation(
+                    "",
+                    "R_ARM_THM_JUMP24 to ARM code needs a veneer",
+                ))
+            }
+            _ => fixup,
+        };
+        Ok(Relaxed::unchanged(fixup))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum GotRelaxation {
+    // mov foo@GOTPCREL(%rip), %reg -> lea foo(%rip), %reg
+    Lea,
+    // call *foo@GOTPCREL(%rip) -> addr32 call foo
+    Call,
+    // jmp *foo@GOTPCREL(%rip) -> jmp foo; nop
+    Jmp,
+    // test %reg, foo@GOTPCREL(%rip) -> test $foo, %reg
+    Test,
+    // op foo@GOTPCREL(%rip), %reg -> op $foo, %reg
+    Binop,
+}
+
+// Decide how a GOT access can be relaxed given the instruction before the displacement at
+// `offset`, which is the opcode and modrm preceded by a REX prefix for
+// R_X86_64_REX_GOTPCRELX. Everything is defined in the executable we produce so only the
+// instruction and the distance to the symbol matter, see `collect_got_entries`. Without
+// room for the instruction the GOT entry stays.
+fn gotpcrel_relaxation(r_type: u32, code: &[u8], offset: usize) -> Option<GotRelaxation> {
+    use goblin::elf::reloc::*;
+    let prefix = if r_type == R_X86_64_REX_GOTPCRELX {
+        3
+    } else {
+        2
+    };
+    let insn = code.get(offset.checked_sub(prefix)?..offset)?;
+    let opcode = insn[prefix - 2];
+    let modrm = insn[prefix - 1];
+    match (opcode, modrm) {
+        (0x8b, _) => Some(GotRelaxation::Lea),
+        // A REX prefix would end up in front of the rewritten call or jmp.
+        (0xff, 0x15) if r_type == R_X86_64_GOTPCRELX => Some(GotRelaxation::Call),
+        (0xff, 0x25) if r_type == R_X86_64_GOTPCRELX => Some(GotRelaxation::Jmp),
+        // The immediate forms are only valid with the REX prefix we know how to rewrite
+        // and a register that is encoded in modrm.reg.
+        (0x85, _) if r_type == R_X86_64_REX_GOTPCRELX && modrm & 0xc7 == 0x05 => {
+            Some(GotRelaxation::Test)
+        }
+        (0x03, _)
+        | (0x0b, _)
+        | (0x13, _)
+        | (0x1b, _)
+        | (0x23, _)
+        | (0x2b, _)
+        | (0x33, _)
+        | (0x3b, _)
+            if r_type == R_X86_64_REX_GOTPCRELX && modrm & 0xc7 == 0x05 =>
+        {
+            Some(GotRelaxation::Binop)
+        }
+        _ => None,
+    }
+}
+
+// Rewrite the instruction before the displacement to access the symbol directly.
+fn relax_gotpcrel(buf: &mut [u8], relaxation: GotRelaxation, fixup: Fixup) -> Fixup {
+    let offset = fixup.offset;
+    let pc_relative = Fixup {
+        value: RelocValue::PcRelative,
+        ..fixup
+    };
+    match relaxation {
+        GotRelaxation::Lea => {
+            buf[offset - 2] = 0x8d;
+            pc_relative
+        }
+        GotRelaxation::Call => {
+            buf[offset - 2] = 0x67;
+            buf[offset - 1] = 0xe8;
+            pc_relative
+        }
+        GotRelaxation::Jmp => {
+            // The jump is one byte shorter so the displacement moves forward.
+            buf[offset - 2] = 0xe9;
+            buf[offset + 3] = 0x90;
+            Fixup {
+                offset: offset - 1,
+                ..pc_relative
+            }
+        }
+        GotRelaxation::Test | GotRelaxation::Binop => {
+            let rex = buf[offset - 3];
+            let opcode = buf[offset - 2];
+            let reg = (buf[offset - 1] >> 3) & 7;
+            // The register moves from modrm.reg to modrm.rm so REX.R becomes REX.B.
+            buf[offset - 3] = (rex & !0x4) | ((rex & 0x4) >> 2);
+            if let GotRelaxation::Test = relaxation {
+                buf[offset - 2] = 0xf7;
+                buf[offset - 1] = 0xc0 | reg;
+            } else {
+                buf[offset - 2] = 0x81;
+                buf[offset - 1] = 0xc0 | ((opcode >> 3) << 3) | reg;
+            }
+            // The immediate is sign extended to 64 bit.
+            Fixup {
+                addend: 0,
+                value: RelocValue::Absolute,
+                ..fixup
+            }
+        }
+    }
+}
+
+// Rewrite `movq x@gottpoff(%rip), %reg` to `movq $x@tpoff, %reg` and
+// `addq x@gottpoff(%rip), %reg` to `leaq x@tpoff(%reg), %reg` like GNU ld.
+// `offset` points to the 32 bit displacement after the REX prefix, opcode and modrm.
+fn relax_gottpoff(buf: &mut [u8], offset: usize) -> Result<(), LinkError> {
+    let rex = buf[offset - 3];
+    let opcode = buf[offset - 2];
+    let modrm = buf[offset - 1];
+    let reg = (modrm >> 3) & 7;
+    let (rex, opcode, modrm) = match opcode {
+        // mov
+        0x8b => (if rex == 0x4c { 0x49 } else { rex }, 0xc7, 0xc0 | reg),
+        // add, %rsp cannot be used as a base without a SIB byte, so turn it into an
+        // add with an immediate.
+        0x03 if reg == 4 => (if rex == 0x4c { 0x49 } else { rex }, 0x81, 0xc0 | reg),
+        0x03 => (
+            if rex == 0x4c { 0x4d } else { rex },
+            0x8d,
+            0x80 | reg | (reg << 3),
+        ),
+        _ => {
+            return Err(LinkError::malformed(
+                "",
+                format!(
+                    "Unexpected instruction for R_X86_64_GOTTPOFF: {:#x}",
+                    opcode
+                ),
+            ))
+        }
+    };
+    buf.pwrite_with(&[rex, opcode, modrm][..], offset - 3, ())?;
+    Ok(())
+}
+
+/// The linker-generated `.got` for GOT accesses that could not be relaxed.
+#[derive(Debug)]
+pub(crate) struct GotSection {
+    pub(crate) address: usize,
+    // Resolved (file idx, symbol idx) of every entry
+    pub(crate) entries: Vec<(usize, usize)>,
+    indices: HashMap<(usize, usize), usize>,
+}
+
+impl GotSection {
+    pub(crate) fn new() -> Self {
+        GotSection {
+            address: 0,
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+    pub(crate) fn add(&mut self, sym: (usize, usize)) {
+        if !self.indices.contains_key(&sym) {
+            self.indices.insert(sym, self.entries.len());
+            self.entries.push(sym);
+        }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub(crate) fn contains(&self, sym: (usize, usize)) -> bool {
+        self.indices.contains_key(&sym)
+    }
+    pub(crate) fn size(&self, ctx: Ctx) -> usize {
+        self.entries.len() * ctx.size()
+    }
+    fn entry_address(&self, sym: (usize, usize), ctx: Ctx) -> usize {
+        self.address + self.indices[&sym] * ctx.size()
+    }
+}
@@ -0,0 +1,599 @@
+//! Symbol resolution for ELF objects, garbage collection of unused sections and the
+//! shared symbol table of the COFF, Mach-O and wasm paths.
+
+use crate::align;
+use crate::buffer::reserve;
+use crate::error::LinkError;
+use crate::input::{Input, RelocationSection};
+use crate::layout::output_section_name;
+use crate::script::{wildcard_match, LinkerScript};
+use crate::Resolver;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+// Symbols the linker defines if the inputs reference them without defining them
+const LINKER_SYMBOLS: &[&str] = &[
+    "__preinit_array_start",
+    "__preinit_array_end",
+    "__init_array_start",
+    "__init_array_end",
+    "__fini_array_start",
+    "__fini_array_end",
+];
+
+// File index of the symbols defined by the linker, their symbol index is the index in
+// SymbolTable::linker_symbols.
+const LINKER_FILE: usize = usize::MAX;
+
+#[derive(Debug)]
+pub(crate) struct SymbolTable<'a> {
+    // Symbols of each file with section indices turned into section keys
+    pub(crate) by_file: HashMap<usize, (Vec<goblin::elf::Sym>, goblin::strtab::Strtab<'a>)>,
+    pub(crate) globals: HashMap<&'a str, (usize, usize)>,
+    // LINKER_SYMBOLS and symbols assigned by a linker script with their values, known
+    // once the output is laid out
+    pub(crate) linker_symbols: Vec<(&'a str, u64)>,
+    // Where the linker symbols that a resolver callback defined come from
+    pub(crate) provenance: HashMap<&'a str, String>,
+}
+
+impl<'a> SymbolTable<'a> {
+    pub(crate) fn new() -> Self {
+        SymbolTable {
+            by_file: HashMap::new(),
+            globals: HashMap::new(),
+            linker_symbols: LINKER_SYMBOLS.iter().map(|name| (*name, 0)).collect(),
+            provenance: HashMap::new(),
+        }
+    }
+    pub(crate) fn insert(
+        &mut self,
+        file_idx: usize,
+        symtab: Vec<goblin::elf::Sym>,
+        strtab: goblin::strtab::Strtab<'a>,
+    ) {
+        use goblin::elf::section_header::*;
+        use goblin::elf::sym::*;
+        for (sym_idx, sym) in symtab.iter().enumerate() {
+            if st_bind(sym.st_info) == STB_GLOBAL
+                && sym.st_shndx != usize::try_from(SHN_UNDEF).unwrap()
+            {
+                let name = strtab.get_unsafe(sym.st_name).unwrap();
+                // A tentative definition only wins over a smaller one.
+                let common = usize::try_from(SHN_COMMON).unwrap();
+                if sym.st_shndx == common {
+                    if let Some(&(def_file, def_idx)) = self.globals.get(name) {
+                        let def = self.get(def_file, def_idx);
+                        if def.st_shndx != common || def.st_size >= sym.st_size {
+                            continue;
+                        }
+                    }
+                }
+                self.globals.insert(name, (file_idx, sym_idx));
+            }
+        }
+        self.by_file.insert(file_idx, (symtab, strtab));
+    }
+    pub(crate) fn get(&self, file_idx: usize, sym_idx: usize) -> goblin::elf::Sym {
+        use goblin::elf::section_header::*;
+        use goblin::elf::sym::*;
+        if file_idx == LINKER_FILE {
+            return goblin::elf::Sym {
+                st_name: sym_idx,
+                st_info: STB_GLOBAL << 4 | STT_NOTYPE,
+                st_shndx: usize::try_from(SHN_ABS).unwrap(),
+                st_value: self.linker_symbols[sym_idx].1,
+                ..goblin::elf::Sym::default()
+            };
+        }
+        let symtab = &self.by_file.get(&file_idx).unwrap().0;
+        symtab[sym_idx]
+    }
+    pub(crate) fn add_linker_symbol(&mut self, name: &'a str) {
+        if self.linker_symbol(name).is_none() {
+            self.linker_symbols.push((name, 0));
+        }
+    }
+    pub(crate) fn linker_symbol(&self, name: &str) -> Option<usize> {
+        self.linker_symbols.iter().position(|(n, _)| *n == name)
+    }
+    pub(crate) fn set_linker_symbol(&mut self, name: &str, value: usize) {
+        let idx = self.linker_symbol(name).unwrap();
+        self.linker_symbols[idx].1 = u64::try_from(value).unwrap();
+    }
+    // Whether a symbol already has its final value before the output is laid out, which
+    // is the case for absolute symbols of the inputs and the ones a resolver defined.
+    pub(crate) fn is_absolute(&self, file_idx: usize, sym: &goblin::elf::Sym) -> bool {
+        use goblin::elf::section_header::*;
+        if file_idx == LINKER_FILE {
+            return self.provenance.contains_key(self.name(file_idx, sym));
+        }
+        sym.st_shndx == usize::try_from(SHN_ABS).unwrap()
+    }
+    pub(crate) fn name(&self, file_idx: usize, sym: &goblin::elf::Sym) -> &'a str {
+        if file_idx == LINKER_FILE {
+            return self.linker_symbols[sym.st_name].0;
+        }
+        let strtab = &self.by_file.get(&file_idx).unwrap().1;
+        strtab.get_unsafe(sym.st_name).unwrap()
+    }
+    // The ARM mapping symbol ($a, $t or $d) in effect at `value` in a section, which says
+    // whether the bytes there are ARM code, Thumb code or data.
+    pub(crate) fn mapping_symbol(&self, file_idx: usize, shndx: usize, value: u64) -> Option<char> {
+        let (symtab, strtab) = &self.by_file[&file_idx];
+        symtab
+            .iter()
+            .filter(|sym| sym.st_shndx == shndx && sym.st_value <= value)
+            .filter_map(|sym| {
+                let name = strtab.get_unsafe(sym.st_name)?;
+                let kind = name.strip_prefix('$')?.chars().next()?;
+                let rest = &name[1 + kind.len_utf8()..];
+                if "atd".contains(kind) && (rest.is_empty() || rest.starts_with('.')) {
+                    Some((sym.st_value, kind))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(value, _)| *value)
+            .map(|(_, kind)| kind)
+    }
+    // Find the definition of a symbol referenced from the given file, if there is one.
+    pub(crate) fn lookup(&self, file_idx: usize, sym_idx: usize) -> Option<(usize, usize)> {
+        use goblin::elf::section_header::*;
+        let sym = self.get(file_idx, sym_idx);
+        if sym.st_shndx == usize::try_from(SHN_UNDEF).unwrap() {
+            let name = self.name(file_idx, &sym);
+            match self.globals.get(name) {
+                Some(def) => Some(*def),
+                None => self.linker_symbol(name).map(|idx| (LINKER_FILE, idx)),
+            }
+        } else {
+            Some((file_idx, sym_idx))
+        }
+    }
+    // Find the definition of a symbol referenced from the given file, which
+    // `Input::check_undefined` made sure exists for all but the relocations it skips.
+    pub(crate) fn resolve(
+        &self,
+        file_idx: usize,
+        sym_idx: usize,
+    ) -> Result<(usize, usize), LinkError> {
+        self.lookup(file_idx, sym_idx).ok_or_else(|| {
+            let sym = self.get(file_idx, sym_idx);
+            LinkError::undefined(self.name(file_idx, &sym))
+        })
+    }
+}
+
+impl<'a> Input<'a> {
+    // Remove all sections that are not reachable through relocations from the section
+    // defining the entry point or from sections that must be kept regardless. Returns the
+    // removed sections.
+    pub(crate) fn gc_sections(
+        &mut self,
+        script: Option<&LinkerScript>,
+        keep: &[String],
+    ) -> Vec<(usize, goblin::elf::ShdrIdx)> {
+        use goblin::elf::section_header::*;
+        let mut relocs_by_sec: HashMap<(usize, goblin::elf::ShdrIdx), Vec<&RelocationSection>> =
+            HashMap::new();
+        for reloc_sec in &self.reloc_sections {
+            relocs_by_sec
+                .entry((reloc_sec.applies_to_file, reloc_sec.applies_to_sec))
+                .or_default()
+                .push(reloc_sec);
+        }
+        let mut worklist = Vec::new();
+        if let Some((file_idx, sym_idx)) = self.symtab.globals.get(self.entry_symbol.as_str()) {
+            let sym = self.symtab.get(*file_idx, *sym_idx);
+            worklist.push((*file_idx, sym.st_shndx));
+        }
+        // Constructors and destructors are only referenced by the runtime.
+        for (sec, name) in &self.section_names {
+            if [
+                ".init_array",
+                ".fini_array",
+                ".preinit_array",
+                ".ctors",
+                ".dtors",
+            ]
+            .iter()
+            .any(|prefix| output_section_name(name) == *prefix || name.starts_with(prefix))
+                || script.is_some_and(|script| script.keeps(&self.file_names[sec.0], name))
+                || keep.iter().any(|pattern| wildcard_match(pattern, name))
+            {
+                worklist.push(*sec);
+            }
+        }
+        let mut live = HashSet::new();
+        let mut removed = Vec::new();
+        while let Some(sec) = worklist.pop() {
+            if sec.1 == usize::try_from(SHN_UNDEF).unwrap()
+                || sec.1 >= usize::try_from(SHN_LORESERVE).unwrap()
+                || !live.insert(sec)
+            {
+                continue;
+            }
+            for reloc_sec in relocs_by_sec.get(&sec).into_iter().flatten() {
+                for reloc in reloc_sec
+                    .relocations
+                    .iter()
+                    .filter(|reloc| reloc.r_sym != 0)
+                {
+                    // Undefined symbols get reported once the unused sections are gone.
+                    if let Some((file_idx, sym_idx)) = self.symtab.lookup(sec.0, reloc.r_sym) {
+                        let sym = self.symtab.get(file_idx, sym_idx);
+                        worklist.push((file_idx, sym.st_shndx));
+                    }
+                }
+            }
+        }
+        for sections in [
+            &mut self.code_sections,
+            &mut self.tls_data_sections,
+            &mut self.tls_bss_sections,
+            &mut self.relro_sections,
+            &mut self.init_array_sections,
+            &mut self.data_sections,
+            &mut self.bss_sections,
+            &mut self.ro_data_sections,
+            &mut self.merge_sections,
+        ] {
+            let (live_sections, dead): (Vec<_>, Vec<_>) = std::mem::take(sections)
+                .into_iter()
+                .partition(|sec| live.contains(&(sec.file_idx, sec.shdr_idx)));
+            *sections = live_sections;
+            removed.extend(dead.iter().map(|sec| (sec.file_idx, sec.shdr_idx)));
+            self.discarded_sections.extend(dead);
+        }
+        // .eh_frame is kept, the FDEs of removed code get dropped when it is merged. Debug
+        // information is kept as well, references to removed code resolve to zero.
+        let eh_frames: HashSet<_> = self
+            .eh_frame_sections
+            .iter()
+            .chain(&self.debug_sections)
+            .map(|sec| (sec.file_idx, sec.shdr_idx))
+            .collect();
+        self.reloc_sections.retain(|reloc_sec| {
+            let key = (reloc_sec.applies_to_file, reloc_sec.applies_to_sec);
+            live.contains(&key) || eh_frames.contains(&key)
+        });
+        removed.sort_unstable();
+        removed
+    }
+    // Ask the resolver for definitions of the symbols that relocations reference but no
+    // input defines, each symbol once.
+    pub(crate) fn resolve_undefined(&mut self, resolver: &Resolver) {
+        let mut undefined = Vec::new();
+        for reloc_sec in &self.reloc_sections {
+            let file_idx = reloc_sec.applies_to_file;
+            for reloc in reloc_sec
+                .relocations
+                .iter()
+                .filter(|reloc| reloc.r_sym != 0)
+            {
+                if self.symtab.lookup(file_idx, reloc.r_sym).is_none() {
+                    let sym = self.symtab.get(file_idx, reloc.r_sym);
+                    let name = self.symtab.name(file_idx, &sym);
+                    if !undefined.contains(&name) {
+                        undefined.push(name);
+                    }
+                }
+            }
+        }
+        for name in undefined {
+            if let Some(def) = resolver(name) {
+                self.symtab.linker_symbols.push((name, def.address));
+                self.symtab.provenance.insert(name, def.provenance);
+            }
+        }
+    }
+    // The global symbols a shared library exports with their names, sorted by name.
+    // Hidden symbols and those in discarded sections stay inside.
+    pub(crate) fn exported_symbols(&self) -> Vec<((usize, usize), &'a str)> {
+        use goblin::elf::sym::*;
+        let mut exports: Vec<_> = self
+            .symtab
+            .globals
+            .iter()
+            .filter(|(_, (file_idx, sym_idx))| {
+                let sym = self.symtab.get(*file_idx, *sym_idx);
+                let visibility = st_visibility(sym.st_other);
+                (visibility == STV_DEFAULT || visibility == STV_PROTECTED)
+                    && !self
+                        .discarded_sections
+                        .iter()
+                        .any(|sec| (sec.file_idx, sec.shdr_idx) == (*file_idx, sym.st_shndx))
+            })
+            .map(|(name, def)| (*def, *name))
+            .collect();
+        exports.sort_unstable_by_key(|(_, name)| *name);
+        exports
+    }
+    // Every symbol that a relocation refers to has to be defined, and so does the entry
+    // point of an executable. Calls to __tls_get_addr are always relaxed away.
+    pub(crate) fn check_undefined(&self) -> Result<(), LinkError> {
+        for reloc_sec in &self.reloc_sections {
+            let file_idx = reloc_sec.applies_to_file;
+            for reloc in reloc_sec
+                .relocations
+                .iter()
+                .filter(|reloc| reloc.r_sym != 0)
+            {
+                if self.symtab.lookup(file_idx, reloc.r_sym).is_none() {
+                    let sym = self.symtab.get(file_idx, reloc.r_sym);
+                    let name = self.symtab.name(file_idx, &sym);
+                    if name != "__tls_get_addr" {
+                        return Err(LinkError::undefined(name));
+                    }
+                }
+            }
+        }
+        if !self.shared && !self.symtab.globals.contains_key(self.entry_symbol.as_str()) {
+            return Err(LinkError::undefined(&self.entry_symbol));
+        }
+        Ok(())
+    }
+}
+
+/// A section of a COFF, Mach-O or wasm object file.
+#[derive(Debug)]
+pub(crate) struct ObjectSection<'a> {
+    pub(crate) file: usize,
+    // The segment of a Mach-O section, the kind of a wasm section, empty for COFF
+    pub(crate) segment: String,
+    pub(crate) name: String,
+    pub(crate) flags: u32,
+    // The address in a Mach-O object file, which relocations that are not against a
+    // symbol refer to
+    pub(crate) address: u64,
+    pub(crate) align: usize,
+    // None for uninitialized data
+    pub(crate) contents: Option<&'a [u8]>,
+    pub(crate) size: usize,
+    // Sections with directives for the linker or debug info do not end up in the output.
+    pub(crate) loaded: bool,
+    pub(crate) relocations: Vec<ObjectRelocation>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum RelocationTarget {
+    // An index into the symbol table of the file
+    Symbol(usize),
+    // An index into `ObjectInput::sections`, for Mach-O relocations that are not against
+    // a symbol
+    Section(usize),
+    // A type index of a wasm file
+    Type(usize),
+}
+
+/// A relocation of a COFF, Mach-O or wasm section. The addend is stored in the relocated
+/// field except for Mach-O ARM64 instructions and wasm.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ObjectRelocation {
+    pub(crate) offset: usize,
+    pub(crate) target: RelocationTarget,
+    pub(crate) r_type: u32,
+    // log2 of the size of the field, only for Mach-O
+    pub(crate) length: u8,
+    pub(crate) addend: i64,
+    // What a Mach-O SUBTRACTOR pair subtracts from the target
+    pub(crate) minus: Option<RelocationTarget>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SymbolDef {
+    // At an offset in a section, given by its index in `ObjectInput::sections`
+    Section(usize, u64),
+    Absolute(u64),
+    // A common symbol of the given size
+    Common(u64),
+    // With the index of the fallback symbol for a COFF weak external
+    Undefined(Option<usize>),
+}
+
+#[derive(Debug)]
+pub(crate) struct ObjectSymbol<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) external: bool,
+    // Defined in a COMDAT section or a weak Mach-O definition, which may appear in
+    // several files. The first definition is used.
+    pub(crate) weak: bool,
+    pub(crate) def: SymbolDef,
+}
+
+/// The COFF, Mach-O or wasm object files of a link with their symbols resolved by name.
+pub(crate) struct ObjectInput<'a> {
+    pub(crate) file_names: Vec<&'a str>,
+    pub(crate) sections: Vec<ObjectSection<'a>>,
+    // The symbol table of each file, auxiliary COFF records are None
+    pub(crate) symbols: Vec<Vec<Option<ObjectSymbol<'a>>>>,
+    pub(crate) globals: HashMap<&'a str, (usize, usize)>,
+    // COFF sections whose symbol is already defined by the same COMDAT section in an
+    // earlier file
+    pub(crate) discarded: HashSet<usize>,
+}
+
+impl<'a> ObjectInput<'a> {
+    pub(crate) fn new() -> Self {
+        ObjectInput {
+            file_names: Vec::new(),
+            sections: Vec::new(),
+            symbols: Vec::new(),
+            globals: HashMap::new(),
+            discarded: HashSet::new(),
+        }
+    }
+    // Add the symbol table of the file added last and enter its external definitions
+    // into `globals`. Returns the weak definitions that lost against an earlier one.
+    pub(crate) fn add_symbols(&mut self, symbols: Vec<Option<ObjectSymbol<'a>>>) -> Vec<usize> {
+        let file = self.symbols.len();
+        let mut duplicates = Vec::new();
+        for (idx, sym) in symbols.iter().enumerate() {
+            let sym = match sym {
+                Some(sym) if sym.external => sym,
+                _ => continue,
+            };
+            // The current file is not in `self.symbols` yet.
+            let existing = self.globals.get(sym.name).map(|(file, idx)| {
+                let symbols = self.symbols.get(*file).unwrap_or(&symbols);
+                symbols[*idx].as_ref().unwrap()
+            });
+            match (sym.def, existing) {
+                (SymbolDef::Undefined(_), _) => continue,
+                (SymbolDef::Common(_), Some(_)) => continue,
+                (SymbolDef::Section(..), Some(existing)) if sym.weak && existing.weak => {
+                    duplicates.push(idx);
+                    continue;
+                }
+                _ => {}
+            }
+            self.globals.insert(sym.name, (file, idx));
+        }
+        self.symbols.push(symbols);
+        duplicates
+    }
+    // Reject symbols and relocations of the file added last that refer to sections or
+    // symbols it does not have, so that they can be followed without checking them
+    // again. Its sections start at `first_section`.
+    pub(crate) fn check_file(&self, first_section: usize) -> Result<(), LinkError> {
+        let symbols = self.symbols.last().map_or(&[][..], Vec::as_slice);
+        let sections = first_section..self.sections.len();
+        let symbol = |idx: usize| symbols.get(idx).and_then(Option::as_ref);
+        for (idx, sym) in symbols.iter().enumerate() {
+            let valid = match sym.as_ref().map(|sym| sym.def) {
+                Some(SymbolDef::Section(section, value)) => {
+                    sections.contains(&section)
+                        && value <= u64::try_from(self.sections[section].size).unwrap()
+                }
+                // A fallback is a definition, so that there are no chains of them.
+                Some(SymbolDef::Undefined(Some(fallback))) => symbol(fallback)
+                    .is_some_and(|sym| !matches!(sym.def, SymbolDef::Undefined(Some(_)))),
+                _ => true,
+            };
+            if !valid {
+                return Err(LinkError::malformed(
+                    "",
+                    format!(
+                        "Symbol {} refers to a section or symbol that does not exist",
+                        idx
+                    ),
+                ));
+            }
+        }
+        let target = |target| match target {
+            RelocationTarget::Symbol(idx) => symbol(idx).is_some(),
+            RelocationTarget::Section(idx) => sections.contains(&idx),
+            RelocationTarget::Type(_) => true,
+        };
+        for section in &self.sections[first_section..] {
+            for reloc in &section.relocations {
+                let valid = reloc.offset < section.size
+                    && target(reloc.target)
+                    && reloc.minus.is_none_or(target);
+                if !valid {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!(
+                            "Invalid relocation at offset {:#x} in {}",
+                            reloc.offset, section.name
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+    // Where `concatenate` put a section that a symbol or relocation refers to, which may
+    // be one that is not loaded.
+    pub(crate) fn placement(
+        &self,
+        placements: &HashMap<usize, (usize, usize)>,
+        section: usize,
+    ) -> Result<(usize, usize), LinkError> {
+        placements.get(&section).copied().ok_or_else(|| {
+            let section = &self.sections[section];
+            LinkError::malformed(
+                self.file_names[section.file],
+                format!(
+                    "Reference to {} that is not part of the output",
+                    section.name
+                ),
+            )
+        })
+    }
+    // Find the definition of a symbol referenced from the given file, following weak
+    // externals to their fallback. Undefined symbols resolve to themselves.
+    pub(crate) fn resolve(&self, file: usize, idx: usize) -> &ObjectSymbol<'a> {
+        let mut sym = self.symbols[file][idx].as_ref().unwrap();
+        loop {
+            if sym.external {
+                if let Some((file, idx)) = self.globals.get(sym.name) {
+                    sym = self.symbols[*file][*idx].as_ref().unwrap();
+                }
+            }
+            match sym.def {
+                SymbolDef::Undefined(Some(fallback)) => {
+                    sym = self.symbols[file][fallback].as_ref().unwrap();
+                }
+                _ => return sym,
+            }
+        }
+    }
+    // The common symbols by name, they are allocated after the uninitialized data.
+    pub(crate) fn commons(&self) -> Vec<(&'a str, u64)> {
+        let mut commons: Vec<(&str, u64)> = self
+            .globals
+            .iter()
+            .filter_map(|(name, (file, idx))| {
+                match self.symbols[*file][*idx].as_ref().unwrap().def {
+                    SymbolDef::Common(size) => Some((*name, size)),
+                    _ => None,
+                }
+            })
+            .collect();
+        commons.sort_unstable();
+        commons
+    }
+    // The sections that are linked, grouped by the index of their output section.
+    pub(crate) fn groups(
+        &self,
+        count: usize,
+        output_section: impl Fn(&ObjectSection) -> usize,
+    ) -> Vec<Vec<usize>> {
+        let mut groups = vec![Vec::new(); count];
+        for (idx, section) in self.sections.iter().enumerate() {
+            if section.loaded && !self.discarded.contains(&idx) {
+                groups[output_section(section)].push(idx);
+            }
+        }
+        groups
+    }
+    // Concatenate the sections of a group as the output section `out`, recording where
+    // each one is placed. Returns the initialized part and the size.
+    pub(crate) fn concatenate(
+        &self,
+        group: &[usize],
+        fill: u8,
+        out: usize,
+        placements: &mut HashMap<usize, (usize, usize)>,
+    ) -> Result<(Vec<u8>, usize), LinkError> {
+        let mut data = Vec::new();
+        let mut size: usize = 0;
+        for idx in group {
+            let section = &self.sections[*idx];
+            size = align(size, section.align);
+            placements.insert(*idx, (out, size));
+            if let Some(contents) = section.contents {
+                let additional = size - data.len() + contents.len();
+                reserve(&mut data, additional)?;
+                data.resize(size, fill);
+                data.extend_from_slice(contents);
+            }
+            size = size
+                .checked_add(section.size)
+                .ok_or_else(|| LinkError::overflow("Sections are larger than the address space"))?;
+        }
+        Ok((data, size))
+    }
+}
@@ -0,0 +1,399 @@
+//! Parsing and evaluating linker scripts.
+
+use crate::error::LinkError;
+use crate::resolve::SymbolTable;
+use crate::{align, SortSection};
+use std::convert::TryFrom;
+
+/// An expression in a linker script.
+#[derive(Debug)]
+pub(crate) enum ScriptExpr<'s> {
+    Number(usize),
+    // The location counter `.`
+    Location,
+    Symbol(&'s str),
+    Align(Box<ScriptExpr<'s>>),
+    Binary(&'s str, Box<ScriptExpr<'s>>, Box<ScriptExpr<'s>>),
+}
+
+impl<'s> ScriptExpr<'s> {
+    fn operand(token: &'s str) -> Result<Self, LinkError> {
+        Ok(if token == "." {
+            ScriptExpr::Location
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            ScriptExpr::Number(parse_script_number(token)?)
+        } else {
+            ScriptExpr::Symbol(token)
+        })
+    }
+    pub(crate) fn eval(&self, location: usize, symtab: &SymbolTable) -> Result<usize, LinkError> {
+        match self {
+            ScriptExpr::Number(n) => Ok(*n),
+            ScriptExpr::Location => Ok(location),
+            ScriptExpr::Symbol(name) => match symtab.linker_symbol(name) {
+                Some(idx) => Ok(usize::try_from(symtab.linker_symbols[idx].1).unwrap()),
+                None => Err(LinkError::undefined(name)),
+            },
+            ScriptExpr::Align(alignment) => Ok(align(location, alignment.eval(location, symtab)?)),
+            ScriptExpr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(location, symtab)?;
+                let rhs = rhs.eval(location, symtab)?;
+                let value = match *op {
+                    "+" => lhs.checked_add(rhs),
+                    "-" => lhs.checked_sub(rhs),
+                    "*" => lhs.checked_mul(rhs),
+                    _ => lhs.checked_div(rhs),
+                };
+                match value {
+                    Some(value) => Ok(value),
+                    None => Err(LinkError::overflow(format!(
+                        "Overflow in linker script: {} {} {}",
+                        lhs, op, rhs
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+// Numbers in linker scripts are decimal or hexadecimal with an optional K or M suffix.
+fn parse_script_number(token: &str) -> Result<usize, LinkError> {
+    let (digits, factor) = match token.strip_suffix(&['K', 'k'][..]) {
+        Some(digits) => (digits, 1 << 10),
+        None => match token.strip_suffix(&['M', 'm'][..]) {
+            Some(digits) => (digits, 1 << 20),
+            None => (token, 1),
+        },
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    match value {
+        Ok(value) => Ok(value * factor),
+        Err(_) => Err(LinkError::malformed(
+            "",
+            format!("Invalid number in linker script: {}", token),
+        )),
+    }
+}
+
+// Match a linker script pattern where `*` matches any sequence of characters and `?` any
+// single character.
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.chars().next() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len())
+            .filter(|i| text.is_char_boundary(*i))
+            .any(|i| wildcard_match(&pattern[1..], &text[i..])),
+        Some(c) => match text.chars().next() {
+            Some(t) if c == '?' || c == t => {
+                wildcard_match(&pattern[c.len_utf8()..], &text[t.len_utf8()..])
+            }
+            _ => false,
+        },
+    }
+}
+
+/// An input section description like `*(.text .text.*)`.
+#[derive(Debug)]
+pub(crate) struct InputSpec<'s> {
+    pub(crate) file: &'s str,
+    pub(crate) sections: Vec<&'s str>,
+    // Wrapped in KEEP so --gc-sections never removes the sections
+    keep: bool,
+    // The section patterns are wrapped in SORT, SORT_BY_NAME or SORT_BY_ALIGNMENT
+    pub(crate) sort: Option<SortSection>,
+}
+
+impl<'s> InputSpec<'s> {
+    pub(crate) fn matches(&self, file_name: &str, section_name: &str) -> bool {
+        let base_name = std::path::Path::new(file_name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(file_name);
+        (wildcard_match(self.file, file_name) || wildcard_match(self.file, base_name))
+            && self
+                .sections
+                .iter()
+                .any(|pattern| wildcard_match(pattern, section_name))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum SectionCommand<'s> {
+    // Assignment to a symbol or to `.`
+    Assign(&'s str, ScriptExpr<'s>),
+    Input(InputSpec<'s>),
+}
+
+#[derive(Debug)]
+pub(crate) enum ScriptCommand<'s> {
+    // Assignment to a symbol or to `.`
+    Assign(&'s str, ScriptExpr<'s>),
+    OutputSection {
+        name: &'s str,
+        address: Option<ScriptExpr<'s>>,
+        commands: Vec<SectionCommand<'s>>,
+    },
+}
+
+/// A linker script, only the SECTIONS command is supported. The location counter is always
+/// an absolute address, also within an output section.
+#[derive(Debug)]
+pub(crate) struct LinkerScript<'s> {
+    pub(crate) commands: Vec<ScriptCommand<'s>>,
+}
+
+pub(crate) struct ScriptParser<'s> {
+    tokens: Vec<&'s str>,
+    pub(crate) pos: usize,
+}
+
+impl<'s> ScriptParser<'s> {
+    pub(crate) fn new(script: &'s str) -> Result<Self, LinkError> {
+        let mut tokens = Vec::new();
+        let mut rest = script;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(comment) = rest.strip_prefix("/*") {
+                match comment.find("*/") {
+                    Some(end) => rest = &comment[end + 2..],
+                    None => {
+                        return Err(LinkError::malformed(
+                            "",
+                            "Unterminated comment in linker script",
+                        ))
+                    }
+                }
+                continue;
+            }
+            let is_name = |c: char| c.is_ascii_alphanumeric() || "_.$*?".contains(c);
+            let len = if rest.starts_with("+=") {
+                2
+            } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                // Numbers end at operators like `*` that can be part of names.
+                rest.find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len())
+            } else if rest.starts_with(is_name) {
+                rest.find(|c: char| !is_name(c)).unwrap_or(rest.len())
+            } else {
+                rest.chars().next().unwrap().len_utf8()
+            };
+            tokens.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+        Ok(ScriptParser { tokens, pos: 0 })
+    }
+    fn peek(&self) -> Option<&'s str> {
+        self.tokens.get(self.pos).copied()
+    }
+    pub(crate) fn next(&mut self) -> Result<&'s str, LinkError> {
+        match self.peek() {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+            None => Err(LinkError::malformed("", "Unexpected end of linker script")),
+        }
+    }
+    pub(crate) fn expect(&mut self, expected: &str) -> Result<(), LinkError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(LinkError::malformed(
+                "",
+                format!(
+                    "Expected `{}` in linker script but found `{}`",
+                    expected, token
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn is_assignment(&self) -> bool {
+        matches!(self.tokens.get(self.pos + 1), Some(&"=") | Some(&"+="))
+    }
+    fn assignment(&mut self) -> Result<(&'s str, ScriptExpr<'s>), LinkError> {
+        let name = self.next()?;
+        let op = self.next()?;
+        let value = self.expr()?;
+        self.expect(";")?;
+        if op == "+=" {
+            let lhs = ScriptExpr::operand(name)?;
+            Ok((
+                name,
+                ScriptExpr::Binary("+", Box::new(lhs), Box::new(value)),
+            ))
+        } else {
+            Ok((name, value))
+        }
+    }
+    pub(crate) fn command(&mut self) -> Result<ScriptCommand<'s>, LinkError> {
+        if self.is_assignment() {
+            let (name, value) = self.assignment()?;
+            return Ok(ScriptCommand::Assign(name, value));
+        }
+        let name = self.next()?;
+        let address = if self.peek() == Some(":") {
+            None
+        } else {
+            Some(self.expr()?)
+        };
+        self.expect(":")?;
+        self.expect("{")?;
+        let mut commands = Vec::new();
+        while self.peek() != Some("}") {
+            commands.push(self.section_command()?);
+        }
+        self.expect("}")?;
+        Ok(ScriptCommand::OutputSection {
+            name,
+            address,
+            commands,
+        })
+    }
+    fn section_command(&mut self) -> Result<SectionCommand<'s>, LinkError> {
+        if self.is_assignment() {
+            let (name, value) = self.assignment()?;
+            return Ok(SectionCommand::Assign(name, value));
+        }
+        let keep = self.peek() == Some("KEEP");
+        if keep {
+            self.next()?;
+            self.expect("(")?;
+        }
+        let file = self.next()?;
+        self.expect("(")?;
+        let sort = match self.peek() {
+            Some("SORT") | Some("SORT_BY_NAME") => Some(SortSection::Name),
+            Some("SORT_BY_ALIGNMENT") => Some(SortSection::Alignment),
+            _ => None,
+        };
+        if sort.is_some() {
+            self.next()?;
+            self.expect("(")?;
+        }
+        let mut sections = Vec::new();
+        while self.peek() != Some(")") {
+            sections.push(self.next()?);
+        }
+        if sort.is_some() {
+            self.expect(")")?;
+            if self.peek() != Some(")") {
+                return Err(LinkError::malformed(
+                    "",
+                    "SORT has to enclose all section patterns of an input description",
+                ));
+            }
+        }
+        self.expect(")")?;
+        if keep {
+            self.expect(")")?;
+        }
+        Ok(SectionCommand::Input(InputSpec {
+            file,
+            sections,
+            keep,
+            sort,
+        }))
+    }
+    fn expr(&mut self) -> Result<ScriptExpr<'s>, LinkError> {
+        let mut lhs = self.term()?;
+        while let Some(op @ ("+" | "-")) = self.peek() {
+            self.next()?;
+            lhs = ScriptExpr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+    fn term(&mut self) -> Result<ScriptExpr<'s>, LinkError> {
+        let mut lhs = self.factor()?;
+        while let Some(op @ ("*" | "/")) = self.peek() {
+            self.next()?;
+            lhs = ScriptExpr::Binary(op, Box::new(lhs), Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+    fn factor(&mut self) -> Result<ScriptExpr<'s>, LinkError> {
+        match self.next()? {
+            "(" => {
+                let value = self.expr()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "ALIGN" => {
+                self.expect("(")?;
+                let alignment = self.expr()?;
+                self.expect(")")?;
+                Ok(ScriptExpr::Align(Box::new(alignment)))
+            }
+            token => ScriptExpr::operand(token),
+        }
+    }
+}
+
+impl<'s> LinkerScript<'s> {
+    pub(crate) fn parse(script: &'s str) -> Result<Self, LinkError> {
+        let mut parser = ScriptParser::new(script)?;
+        let mut commands = Vec::new();
+        while let Some(token) = parser.peek() {
+            match token {
+                "SECTIONS" => {
+                    parser.next()?;
+                    parser.expect("{")?;
+                    while parser.peek() != Some("}") {
+                        commands.push(parser.command()?);
+                    }
+                    parser.expect("}")?;
+                }
+                _ => {
+                    return Err(LinkError::malformed(
+                        "",
+                        format!("Unsupported linker script command: {}", token),
+                    ))
+                }
+            }
+        }
+        Ok(LinkerScript { commands })
+    }
+    fn input_specs(&self) -> impl Iterator<Item = &InputSpec<'s>> {
+        self.commands
+            .iter()
+            .filter_map(|command| match command {
+                ScriptCommand::OutputSection { commands, .. } => Some(commands),
+                ScriptCommand::Assign(..) => None,
+            })
+            .flatten()
+            .filter_map(|command| match command {
+                SectionCommand::Input(spec) => Some(spec),
+                SectionCommand::Assign(..) => None,
+            })
+    }
+    // Symbols assigned by the script
+    pub(crate) fn symbols(&self) -> Vec<&'s str> {
+        let mut symbols = Vec::new();
+        for command in &self.commands {
+            match command {
+                ScriptCommand::Assign(name, _) => symbols.push(*name),
+                ScriptCommand::OutputSection { commands, .. } => {
+                    for command in commands {
+                        if let SectionCommand::Assign(name, _) = command {
+                            symbols.push(*name);
+                        }
+                    }
+                }
+            }
+        }
+        symbols.retain(|name| *name != ".");
+        symbols
+    }
+    pub(crate) fn keeps(&self, file_name: &str, section_name: &str) -> bool {
+        self.input_specs()
+            .any(|spec| spec.keep && spec.matches(file_name, section_name))
+    }
+}
@@ -0,0 +1,81 @@
+//! Memory accounting for `--stats`. The sizes are what the data structures of each part
+//! of the link hold, not counting allocator overhead, so they are a guide for where a
+//! large link spends its memory rather than an exact sum.
+
+use crate::buffer::OutputBuffer;
+use crate::layout::{Output, OutputSection, Placement};
+use goblin::container::Ctx;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::mem::size_of;
+
+// The bytes held by a map with `len` entries of key K and value V
+fn map_size<K, V>(len: usize) -> usize {
+    len * (size_of::<K>() + size_of::<V>())
+}
+
+impl<'a> Output<'a> {
+    // The bytes held by each part of the link once the output is written to `image`.
+    pub(crate) fn memory_usage(
+        &self,
+        image: &OutputBuffer,
+        ctx: Ctx,
+    ) -> Vec<(&'static str, usize)> {
+        let input_buffers = self.file_buffers.iter().map(|buf| buf.len()).sum();
+        let relocations = self
+            .reloc_sections
+            .iter()
+            .map(|sec| sec.relocations.capacity() * size_of::<goblin::elf::Reloc>())
+            .sum();
+        let symbols = self
+            .symtab
+            .by_file
+            .values()
+            .map(|(syms, _)| syms.capacity() * size_of::<goblin::elf::Sym>())
+            .sum::<usize>()
+            + map_size::<&str, (usize, usize)>(self.symtab.globals.len());
+        let merged_strings = self
+            .merged_sections
+            .iter()
+            .map(|sec| sec.data.capacity())
+            .sum();
+        // The linker never creates a PLT, calls go directly to their symbol.
+        let got = self.got.entries.len() * ctx.size();
+        let layout = self.sections.capacity() * size_of::<OutputSection>()
+            + map_size::<(usize, goblin::elf::ShdrIdx), Placement>(self.section_offsets.len())
+            + map_size::<(usize, goblin::elf::ShdrIdx), &str>(self.section_names.len());
+        vec![
+            ("input buffers", input_buffers),
+            ("relocations", relocations),
+            ("symbol tables", symbols),
+            ("merged strings", merged_strings),
+            ("GOT", got),
+            ("layout", layout),
+            ("output image", image.len()),
+        ]
+    }
+}
+
+// The largest resident set size of the process so far, Linux reports it in KiB.
+fn peak_memory() -> Option<usize> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let usage = unsafe { usage.assume_init() };
+    usize::try_from(usage.ru_maxrss).ok().map(|kib| kib * 1024)
+}
+
+// The report --stats prints.
+pub(crate) fn report(usage: &[(&str, usize)]) -> String {
+    let mut out = String::from("memory:\n");
+    for (name, bytes) in usage {
+        writeln!(out, "  {:<16} {:>12}", name, bytes).unwrap();
+    }
+    let total: usize = usage.iter().map(|(_, bytes)| bytes).sum();
+    writeln!(out, "  {:<16} {:>12}", "total", total).unwrap();
+    if let Some(peak) = peak_memory() {
+        writeln!(out, "peak resident set {:>12}", peak).unwrap();
+    }
+    out
+}
@@ -0,0 +1,149 @@
+//! The architectures the linker produces ELF executables for.
+
+use crate::error::LinkError;
+use crate::relocate::{Arm, Field, RelocHandler, I386, X86_64};
+use crate::PAGE_SIZE;
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::header::{machine_to_str, EM_386, EM_ARM, EM_X86_64};
+use std::fmt;
+
+/// What the linker needs to know about an architecture. The linker never creates a PLT,
+/// calls go directly to their symbol, so the GOT is the only table a target describes.
+pub(crate) trait Target: fmt::Debug + Sync {
+    // The name `--target` selects it by
+    fn name(&self) -> &'static str;
+    fn machine(&self) -> u16;
+    // The class and byte order of its object files
+    fn ctx(&self) -> Ctx;
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+    // The byte that pads code, an instruction that traps if there is one
+    fn fill(&self) -> u8 {
+        0
+    }
+    // Applies the relocation types of the target unless a registered handler does
+    fn reloc_handler(&self) -> &'static dyn RelocHandler;
+    // How the address of a symbol is written to its GOT entry
+    fn got_entry(&self) -> Field {
+        if self.ctx().size() == 8 {
+            Field::Word64
+        } else {
+            Field::Word32
+        }
+    }
+    // Whether the lowest bit of a code address selects another instruction set, which the
+    // mapping symbols tell for untyped labels
+    fn interworking(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct X86_64Target;
+
+impl Target for X86_64Target {
+    fn name(&self) -> &'static str {
+        "x86_64"
+    }
+    fn machine(&self) -> u16 {
+        EM_X86_64
+    }
+    fn ctx(&self) -> Ctx {
+        Ctx::new(Container::Big, Endian::Little)
+    }
+    fn fill(&self) -> u8 {
+        // int3
+        0xcc
+    }
+    fn reloc_handler(&self) -> &'static dyn RelocHandler {
+        &X86_64
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct I386Target;
+
+impl Target for I386Target {
+    fn name(&self) -> &'static str {
+        "i386"
+    }
+    fn machine(&self) -> u16 {
+        EM_386
+    }
+    fn ctx(&self) -> Ctx {
+        Ctx::new(Container::Little, Endian::Little)
+    }
+    fn reloc_handler(&self) -> &'static dyn RelocHandler {
+        &I386
+    }
+}
+
+// ARM in either byte order, with Thumb interworking.
+#[derive(Debug)]
+pub(crate) struct ArmTarget(Endian);
+
+impl Target for ArmTarget {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            Endian::Little => "arm",
+            Endian::Big => "armeb",
+        }
+    }
+    fn machine(&self) -> u16 {
+        EM_ARM
+    }
+    fn ctx(&self) -> Ctx {
+        Ctx::new(Container::Little, self.0)
+    }
+    fn reloc_handler(&self) -> &'static dyn RelocHandler {
+        &Arm
+    }
+    fn interworking(&self) -> bool {
+        true
+    }
+}
+
+pub(crate) const TARGETS: &[&dyn Target] = &[
+    &X86_64Target,
+    &I386Target,
+    &ArmTarget(Endian::Little),
+    &ArmTarget(Endian::Big),
+];
+
+// The target to link for without --target or any object file
+pub(crate) const DEFAULT_TARGET: &dyn Target = &X86_64Target;
+
+pub(crate) fn by_name(name: &str) -> Result<&'static dyn Target, String> {
+    TARGETS
+        .iter()
+        .copied()
+        .find(|target| target.name() == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = TARGETS.iter().map(|target| target.name()).collect();
+            format!(
+                "invalid --target: {}, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+// The target of object files for `machine` with the class and byte order of `ctx`.
+pub(crate) fn for_object(machine: u16, ctx: Ctx) -> Result<&'static dyn Target, LinkError> {
+    TARGETS
+        .iter()
+        .copied()
+        .find(|target| {
+            let target_ctx = target.ctx();
+            target.machine() == machine
+                && target_ctx.container == ctx.container
+                && target_ctx.le == ctx.le
+        })
+        .ok_or_else(|| {
+            LinkError::malformed(
+                "",
+                format!("Unsupported machine: {}", machine_to_str(machine)),
+            )
+        })
+}
@@ -0,0 +1,3782 @@
+use crate::emit::{
+    ihex_image, srec_image, NT_GNU_BUILD_ID, NT_GNU_PROPERTY_TYPE_0, PT_GNU_PROPERTY,
+};
+use crate::input::Input;
+use crate::macho::MACHO_TEXT_BASE;
+use crate::pe::{IMAGE_REL_BASED_DIR64, PE_IMAGE_BASE};
+use crate::relocate::Field;
+use crate::{
+    align, normalize_args, LinkError, Linker, Opts, OutputKind, RelocHandler, RelocValue,
+    RelocationHowTo, SymbolDefinition, SyntheticSection, PAGE_SIZE,
+};
+use clap::Clap;
+use goblin::container::Ctx;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+
+fn gcc(
+    out_dir: &std::path::Path,
+    file: &std::path::Path,
+    flags: &[&str],
+) -> Result<std::path::PathBuf, Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    let out = out_dir.join(file.with_extension("o"));
+    let output = Command::new("gcc")
+        .args(["-nostdlib", "-Wno-main", "-Wall", "-Werror"])
+        .args(flags)
+        .args([
+            "-o",
+            out.to_str().unwrap(),
+            "-c",
+            Path::new("examples").join(file).to_str().unwrap(),
+        ])
+        .output()?;
+    assert!(output.status.success());
+    Ok(out)
+}
+
+fn llvm_mc(
+    out_dir: &std::path::Path,
+    file: &std::path::Path,
+    triple: &str,
+) -> Result<std::path::PathBuf, Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    let out = out_dir.join(file.with_extension("o"));
+    let output = Command::new("llvm-mc")
+        .args([
+            "-triple",
+            triple,
+            "-filetype=obj",
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .arg(Path::new("examples").join(file))
+        .output()?;
+    assert!(output.status.success());
+    Ok(out)
+}
+
+// There is no compiler for Windows targets, objcopy turns the ELF objects into COFF.
+fn coff(
+    out_dir: &std::path::Path,
+    file: &std::path::Path,
+    flags: &[&str],
+) -> Result<std::path::PathBuf, Box<dyn Error>> {
+    use std::process::Command;
+    let flags = [&["-fno-pic", "-fno-asynchronous-unwind-tables"], flags].concat();
+    let elf = gcc(out_dir, file, &flags)?;
+    let obj = elf.with_extension("obj");
+    let status = Command::new("objcopy")
+        .args([
+            "-O",
+            "pe-x86-64",
+            elf.to_str().unwrap(),
+            obj.to_str().unwrap(),
+        ])
+        .status()?;
+    assert!(status.success());
+    Ok(obj)
+}
+
+// Link `objects` into `out` with the extra command line arguments `args`.
+fn link_objects(
+    objects: &[&std::path::Path],
+    out: &std::path::Path,
+    args: &[&str],
+) -> Result<crate::LinkResult, LinkError> {
+    let mut cmdline = vec!["toy-linker"];
+    for object in objects {
+        cmdline.extend(["-i", object.to_str().unwrap()]);
+    }
+    cmdline.extend(args);
+    cmdline.extend(["-o", out.to_str().unwrap()]);
+    Linker::from_opts(Opts::parse_from(cmdline)).link()
+}
+
+// The header of the section called `name` in an ELF file.
+fn section_header<'e>(elf: &'e goblin::elf::Elf, name: &str) -> &'e goblin::elf::SectionHeader {
+    elf.section_headers
+        .iter()
+        .find(|sec| &elf.shdr_strtab[sec.sh_name] == name)
+        .unwrap_or_else(|| panic!("no section {}", name))
+}
+
+// The entries of the dynamic section of an ELF file as (tag, value) pairs.
+fn dynamic_entries(elf: &goblin::elf::Elf) -> Vec<(u64, u64)> {
+    elf.dynamic.as_ref().map_or(Vec::new(), |dynamic| {
+        dynamic
+            .dyns
+            .iter()
+            .map(|entry| (entry.d_tag, entry.d_val))
+            .collect()
+    })
+}
+
+#[test]
+fn link_example() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let out = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(out, "Hello world\nwuhu\n");
+    Ok(())
+}
+
+#[test]
+fn link_with_builder() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    // gc.o defines _start as well so linking it would fail.
+    let gc_o = gcc(tmp_dir.path(), Path::new("gc.c"), &[])?;
+    let archive = tmp_dir.path().join("libexample.a");
+    let status = Command::new("ar")
+        .arg("rcs")
+        .args([&archive, &gc_o, &lib_o])
+        .status()?;
+    assert!(status.success());
+    let exe = tmp_dir.path().join("main");
+    let result = Linker::new()
+        .add_object(fs::read(main_o)?)
+        .add_archive(archive.to_str().unwrap())
+        .entry("_start")
+        .output_kind(OutputKind::Executable)
+        .output(exe.to_str().unwrap())
+        .link()?;
+    assert_eq!(*result.output, *fs::read(&exe)?);
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    assert_eq!(result.entry, Some(elf.entry));
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let out = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(out, "Hello world\nwuhu\n");
+    Ok(())
+}
+
+#[test]
+fn link_in_memory() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = fs::read(gcc(tmp_dir.path(), Path::new("main.c"), &[])?)?;
+    let lib_o = fs::read(gcc(tmp_dir.path(), Path::new("lib.c"), &[])?)?;
+    let exe = tmp_dir.path().join("main");
+    let opts = Opts::parse_from(["toy-linker", "-o", exe.to_str().unwrap()]);
+    let output = crate::link_in_memory(&[&main_o, &lib_o], &opts)?;
+    assert!(!exe.exists());
+    let result = Linker::from_opts(opts)
+        .add_object(main_o.clone())
+        .add_object(lib_o.clone())
+        .link()?;
+    assert_eq!(*output, *result.output);
+
+    // Options that name files are rejected instead of being read.
+    let opts = Opts::parse_from(["toy-linker", "-o", "a.out", "--Map", "out.map"]);
+    assert!(matches!(
+        crate::link_in_memory(&[&main_o, &lib_o], &opts),
+        Err(LinkError::InvalidOptions { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn resolve_undefined_symbols() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // lib.o is missing so only the resolver can define extern_call.
+    let main_o = fs::read(gcc(tmp_dir.path(), Path::new("main.c"), &[])?)?;
+    let exe = tmp_dir.path().join("main");
+    let asked = Rc::new(RefCell::new(Vec::new()));
+    let names = asked.clone();
+    let result = Linker::new()
+        .add_object(main_o.clone())
+        .output(exe.to_str().unwrap())
+        .resolve_undefined(move |name| {
+            names.borrow_mut().push(String::from(name));
+            None
+        })
+        .link();
+    assert!(matches!(result, Err(LinkError::UndefinedSymbol { .. })));
+    assert_eq!(*asked.borrow(), ["extern_call"]);
+
+    let address = 0x500000;
+    let map = tmp_dir.path().join("main.map");
+    let result = Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-o",
+        exe.to_str().unwrap(),
+        "--Map",
+        map.to_str().unwrap(),
+    ]))
+    .add_object(main_o.clone())
+    .resolve_undefined(move |name| {
+        assert_eq!(name, "extern_call");
+        Some(SymbolDefinition {
+            address,
+            provenance: String::from("host"),
+        })
+    })
+    .link()?;
+    assert!(fs::read_to_string(&map)?.contains("extern_call (host)"));
+
+    // The call in main.o is a PLT32 relocation, S + A - P with A = -4.
+    let object = goblin::elf::Elf::parse(&main_o)?;
+    let reloc = object
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .find(|reloc| object.syms.get(reloc.r_sym).unwrap().st_name != 0)
+        .unwrap();
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    let text = elf
+        .section_headers
+        .iter()
+        .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+        .unwrap();
+    let offset = usize::try_from(text.sh_offset + reloc.r_offset).unwrap();
+    let disp: i32 = result.output.pread_with(offset, scroll::LE)?;
+    let p = text.sh_addr + reloc.r_offset;
+    assert_eq!(
+        i64::try_from(p)? + i64::from(disp) + 4,
+        i64::try_from(address)?
+    );
+    Ok(())
+}
+
+#[test]
+fn resolve_undefined_far() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Resolved symbols can be anywhere, so a call through the GOT to one that is out of
+    // reach keeps its GOT entry instead of being relaxed.
+    let main_o = fs::read(gcc(
+        tmp_dir.path(),
+        Path::new("main.c"),
+        &["-fPIC", "-fno-plt"],
+    )?)?;
+    let address = 0x7fff_0000_0000;
+    let result = Linker::new()
+        .add_object(main_o)
+        .output(tmp_dir.path().join("main").to_str().unwrap())
+        .resolve_undefined(move |_| {
+            Some(SymbolDefinition {
+                address,
+                provenance: String::from("host"),
+            })
+        })
+        .link()?;
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    let got = section_header(&elf, ".got");
+    assert_eq!(got.sh_size, 8);
+    let entry: u64 = result
+        .output
+        .pread_with(usize::try_from(got.sh_offset).unwrap(), scroll::LE)?;
+    assert_eq!(entry, address);
+    // `call *extern_call@GOTPCREL(%rip)` loads the address from the GOT entry.
+    let text = section_header(&elf, ".text");
+    let start = usize::try_from(text.sh_offset).unwrap();
+    let code = &result.output[start..start + usize::try_from(text.sh_size).unwrap()];
+    let got_call = (0..code.len() - 6).any(|i| {
+        let next = text.sh_addr + u64::try_from(i).unwrap() + 6;
+        code[i..i + 2] == [0xff, 0x15]
+            && code[i + 2..i + 6] == (got.sh_addr.wrapping_sub(next) as u32).to_le_bytes()
+    });
+    assert!(got_call);
+    Ok(())
+}
+
+#[test]
+fn custom_reloc_handler() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::header::EM_X86_64;
+    use goblin::elf::reloc::R_X86_64_16;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    struct Abs16;
+    impl RelocHandler for Abs16 {
+        fn machine(&self) -> u16 {
+            EM_X86_64
+        }
+        fn howtos(&self) -> &[RelocationHowTo] {
+            &[RelocationHowTo {
+                r_type: R_X86_64_16,
+                value: RelocValue::Absolute,
+                field: Field::I16,
+            }]
+        }
+    }
+    let answer = |_: &str| {
+        Some(SymbolDefinition {
+            address: 42,
+            provenance: String::from("test"),
+        })
+    };
+    let object = fs::read(gcc(tmp_dir.path(), Path::new("reloc16.s"), &[])?)?;
+    let exe = tmp_dir.path().join("reloc16");
+    let result = Linker::new()
+        .add_object(object.clone())
+        .output(exe.to_str().unwrap())
+        .resolve_undefined(answer)
+        .link();
+    assert!(matches!(
+        result,
+        Err(LinkError::UnsupportedRelocation { .. })
+    ));
+    Linker::new()
+        .add_object(object)
+        .output(exe.to_str().unwrap())
+        .resolve_undefined(answer)
+        .reloc_handler(Abs16)
+        .link()?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn synthetic_sections() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = fs::read(gcc(tmp_dir.path(), Path::new("synthetic.c"), &[])?)?;
+    let version = b"toy-linker 1.0\n";
+    let exe = tmp_dir.path().join("synthetic");
+    let result = Linker::new()
+        .add_object(object)
+        .add_section(SyntheticSection {
+            name: String::from(".rodata.version"),
+            contents: version.to_vec(),
+            symbols: vec![
+                (String::from("version"), 0),
+                (String::from("version_end"), u64::try_from(version.len())?),
+            ],
+            ..SyntheticSection::default()
+        })
+        .output(exe.to_str().unwrap())
+        .link()?;
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    let rodata = elf
+        .section_headers
+        .iter()
+        .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".rodata"))
+        .unwrap();
+    assert!(rodata.sh_size >= u64::try_from(version.len())?);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, version);
+    Ok(())
+}
+
+#[test]
+fn inspect_layout() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::PF_X;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let result = Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let layout = result.layout.unwrap();
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    assert_eq!(layout.symbol("_start"), Some(elf.entry));
+    for sym in elf.syms.iter().filter(|sym| sym.is_function()) {
+        let name = elf.strtab.get_unsafe(sym.st_name).unwrap();
+        if sym.st_bind() == goblin::elf::sym::STB_GLOBAL {
+            assert_eq!(layout.symbol(name), Some(sym.st_value));
+        }
+    }
+    assert!(layout.symbol("__init_array_start").is_some());
+
+    let text = layout
+        .input_section(lib_o.to_str().unwrap(), ".text")
+        .unwrap();
+    let extern_call = layout.symbol("extern_call").unwrap();
+    assert!((text.address..text.address + text.size).contains(&extern_call));
+    let code = layout
+        .segments
+        .iter()
+        .find(|segment| segment.flags & PF_X != 0)
+        .unwrap();
+    assert!((code.address..code.address + code.memory_size).contains(&extern_call));
+    let header = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_vaddr == code.address)
+        .unwrap();
+    assert_eq!(header.p_offset, code.offset);
+    assert_eq!(header.p_filesz, code.file_size);
+    Ok(())
+}
+
+#[test]
+fn incremental_link() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let flags = ["-fno-asynchronous-unwind-tables"];
+    let main_o = gcc(tmp_dir.path(), Path::new("incremental.c"), &flags)?;
+    let exe = tmp_dir.path().join("incremental");
+    let link = |answer: &str, large: bool| -> Result<bool, Box<dyn Error>> {
+        let mut flags = vec!["-fno-asynchronous-unwind-tables", answer];
+        if large {
+            flags.push("-DLARGE");
+        }
+        let lib_o = gcc(tmp_dir.path(), Path::new("incremental_lib.c"), &flags)?;
+        let result = Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "--incremental",
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        // Only full links know the layout.
+        Ok(result.layout.is_none())
+    };
+    assert!(!link("-DANSWER=1", false)?);
+    assert!(tmp_dir.path().join("incremental.incremental").exists());
+    assert_eq!(Command::new(&exe).output()?.status.code(), Some(1));
+    assert!(link("-DANSWER=2", false)?);
+    assert_eq!(Command::new(&exe).output()?.status.code(), Some(2));
+    assert!(!link("-DANSWER=3", true)?);
+    assert_eq!(Command::new(&exe).output()?.status.code(), Some(3));
+    assert!(link("-DANSWER=4", true)?);
+    assert_eq!(Command::new(&exe).output()?.status.code(), Some(4));
+    Ok(())
+}
+
+#[test]
+fn streamed_output() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = fs::read(gcc(tmp_dir.path(), Path::new("main.c"), &[])?)?;
+    let lib_o = fs::read(gcc(tmp_dir.path(), Path::new("lib.c"), &[])?)?;
+    let exe = tmp_dir.path().join("main");
+    fs::write(&exe, b"previous")?;
+    // The call to extern_call cannot reach this far, which only comes up once the output
+    // is mapped.
+    let result = Linker::new()
+        .add_object(main_o.clone())
+        .output(exe.to_str().unwrap())
+        .resolve_undefined(|_| {
+            Some(SymbolDefinition {
+                address: 0x7000_0000_0000,
+                provenance: String::from("far away"),
+            })
+        })
+        .link();
+    assert!(matches!(result, Err(LinkError::LayoutOverflow { .. })));
+    assert_eq!(fs::read(&exe)?, b"previous");
+    assert!(!tmp_dir.path().join("main.tmp").exists());
+
+    let result = Linker::new()
+        .add_object(main_o)
+        .add_object(lib_o)
+        .output(exe.to_str().unwrap())
+        .link()?;
+    let written = fs::read(&exe)?;
+    assert_eq!(*result.output, *written);
+    assert_eq!(result.output.into_vec(), written);
+    Ok(())
+}
+
+#[test]
+fn log_phases() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use tempdir::TempDir;
+    use tracing_subscriber::fmt::format::FmtSpan;
+    // The spans and messages a subscriber writes, like the binary does to stderr.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let capture = Capture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .with_target(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || link_objects(&[&main_o, &lib_o], &exe, &[]))?;
+    let log = String::from_utf8(capture.0.lock().unwrap().clone())?;
+    for phase in ["parse", "resolve", "layout", "copy", "relocate", "write"] {
+        assert!(log.contains(&format!("{}: new", phase)));
+        assert!(log.contains(&format!("{}: close time.busy=", phase)));
+    }
+    // The spans nest.
+    assert!(log.contains("link:parse: new"));
+    Ok(())
+}
+
+#[test]
+fn time_trace() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let trace = tmp_dir.path().join("trace.json");
+    let result = Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--time-trace",
+        trace.to_str().unwrap(),
+        "--time-report",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let table = result.time_report.unwrap();
+    assert!(table.starts_with("phase "));
+    assert!(table.lines().any(|line| line.starts_with("  parse ")));
+    let trace = fs::read_to_string(trace)?;
+    for phase in ["read", "link", "parse", "layout", "relocate", "write"] {
+        let event = format!("{{\"name\": \"{}\", \"ph\": \"X\"", phase);
+        assert!(trace.contains(&event));
+    }
+    assert!(trace.contains("\"input files\": 2"));
+    Ok(())
+}
+
+#[test]
+fn memory_stats() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let result = Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--stats",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    assert!(result.stats.unwrap().starts_with("memory:\n"));
+    let report = crate::stats::report(&[("input buffers", 100), ("output image", 28)]);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "memory:");
+    assert!(lines[3].starts_with("  total") && lines[3].ends_with(" 128"));
+    assert!(lines[4].starts_with("peak resident set"));
+    Ok(())
+}
+
+#[test]
+fn print_size() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let result = link_objects(&[&main_o, &lib_o], &exe, &["--print-size"])?;
+    let report = result.size_report.unwrap();
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(report.starts_with(&format!("File size: {} bytes\n", buf.len())));
+    // The rows below the heading of the segments, up to the empty line.
+    let segments: Vec<Vec<&str>> = report
+        .lines()
+        .skip_while(|line| *line != "Segments:")
+        .skip(2)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    let loads: Vec<Vec<String>> = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .map(|phdr| {
+            let flags: String = [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')]
+                .iter()
+                .map(|(flag, c)| if phdr.p_flags & flag != 0 { *c } else { '-' })
+                .collect();
+            vec![
+                phdr.p_filesz.to_string(),
+                phdr.p_memsz.to_string(),
+                flags,
+                format!("{:#x}", phdr.p_vaddr),
+            ]
+        })
+        .collect();
+    assert_eq!(segments, loads);
+    Ok(())
+}
+
+#[test]
+fn malformed_inputs() -> Result<(), Box<dyn Error>> {
+    use scroll::{Pread, Pwrite};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = fs::read(gcc(tmp_dir.path(), Path::new("main.c"), &[])?)?;
+    let lib_o = fs::read(gcc(tmp_dir.path(), Path::new("lib.c"), &[])?)?;
+    let macho = |file: &str| llvm_mc(tmp_dir.path(), Path::new(file), "x86_64-apple-macos11");
+    let macho_o = fs::read(macho("macho_x86_64.s")?)?;
+    let macho_helper_o = fs::read(macho("macho_helper.s")?)?;
+    let wasm = |file: &str| llvm_mc(tmp_dir.path(), Path::new(file), "wasm32");
+    let wasm_o = fs::read(wasm("wasm.s")?)?;
+    let wasm_helper_o = fs::read(wasm("wasm_helper.s")?)?;
+    let coff_obj = fs::read(coff(
+        tmp_dir.path(),
+        Path::new("pe.c"),
+        &["-mcmodel=large"],
+    )?)?;
+    let coff_lib_obj = fs::read(coff(tmp_dir.path(), Path::new("pe_lib.c"), &[])?)?;
+    let opts = Opts::parse_from([
+        "toy-linker",
+        "--import",
+        "kernel32.dll:ExitProcess",
+        "-o",
+        "a.out",
+    ]);
+    // Truncated and corrupted files give an error or a link, never a panic.
+    for (object, other) in [
+        (main_o, lib_o),
+        (macho_o, macho_helper_o),
+        (wasm_o, wasm_helper_o),
+        (coff_obj, coff_lib_obj),
+    ] {
+        for offset in (0..object.len()).step_by(3) {
+            let mut corrupted = object.clone();
+            corrupted[offset..]
+                .iter_mut()
+                .take(8)
+                .for_each(|byte| *byte = 0xff);
+            let mut flipped = object.clone();
+            flipped[offset] ^= 0x80;
+            for buf in [&object[..offset], &corrupted, &flipped] {
+                let link = catch_unwind(AssertUnwindSafe(|| {
+                    let _ = crate::link_in_memory(&[buf, &other], &opts);
+                }));
+                assert!(link.is_ok(), "panic with the file changed at {:#x}", offset);
+            }
+        }
+    }
+    // A relocation whose instruction would start before its section.
+    let edge_o = fs::read(gcc(tmp_dir.path(), Path::new("gotpcrelx_edge.s"), &[])?)?;
+    match crate::link_in_memory(&[&edge_o], &opts) {
+        Err(LinkError::MalformedInput { reason, .. }) => {
+            assert_eq!(reason, "Relocation at offset 0x0 is outside of its section")
+        }
+        result => panic!("Expected a malformed input: {:?}", result.map(|_| ())),
+    }
+    // Notes whose name or descriptor would end after their section.
+    for file in ["property_namesz.s", "property_descsz.s"] {
+        let note_o = fs::read(gcc(tmp_dir.path(), Path::new(file), &[])?)?;
+        match crate::link_in_memory(&[&note_o], &opts) {
+            Err(LinkError::MalformedInput { reason, .. }) => {
+                assert_eq!(reason, "Note in .note.gnu.property is truncated")
+            }
+            result => panic!("Expected a malformed input: {:?}", result.map(|_| ())),
+        }
+    }
+    // Inputs that only turn out to be wrong once they are laid out and relocated
+    let expect_malformed =
+        |objects: &[&[u8]], expected: &str| match crate::link_in_memory(objects, &opts) {
+            Err(LinkError::MalformedInput { reason, .. }) => {
+                assert!(reason.starts_with(expected), "{}", reason)
+            }
+            result => panic!("Expected a malformed input: {:?}", result.map(|_| ())),
+        };
+    let eh_frame_o = fs::read(gcc(
+        tmp_dir.path(),
+        Path::new("eh_frame_cie_pointer.s"),
+        &[],
+    )?)?;
+    expect_malformed(&[&eh_frame_o], "Invalid CIE pointer in .eh_frame");
+    let tls_o = fs::read(gcc(tmp_dir.path(), Path::new("tls_below_segment.s"), &[])?)?;
+    expect_malformed(&[&tls_o], "TLS symbol misplaced at ");
+    let abs_entry_o = fs::read(macho("macho_abs_entry.s")?)?;
+    expect_malformed(
+        &[&abs_entry_o],
+        "The entry point _main at 0x1000 is not in __TEXT",
+    );
+    // The last load command turned into a thread command with more state than the file.
+    let mut thread_o = fs::read(macho("macho_x86_64.s")?)?;
+    let mut offset = goblin::mach::header::SIZEOF_HEADER_64;
+    for _ in 1..thread_o.pread_with::<u32>(16, scroll::LE)? {
+        offset += usize::try_from(thread_o.pread_with::<u32>(offset + 4, scroll::LE)?)?;
+    }
+    thread_o.pwrite_with(
+        goblin::mach::load_command::LC_UNIXTHREAD,
+        offset,
+        scroll::LE,
+    )?;
+    thread_o.pwrite_with(0x10000u32, offset + 12, scroll::LE)?;
+    expect_malformed(&[&thread_o], "Load command at ");
+    Ok(())
+}
+
+#[test]
+fn progress_and_cancellation() -> Result<(), Box<dyn Error>> {
+    use crate::{CancellationToken, Phase};
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    // Links that cancel themselves once they reach `cancel_at`
+    let link = |cancel_at: Option<Phase>| {
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let token = CancellationToken::new();
+        let result = Linker::new()
+            .add_archive(main_o.to_str().unwrap())
+            .add_archive(lib_o.to_str().unwrap())
+            .output(exe.to_str().unwrap())
+            .cancellation(token.clone())
+            .on_progress({
+                let reports = reports.clone();
+                move |phase, percent| {
+                    reports.borrow_mut().push((phase, percent));
+                    if Some(phase) == cancel_at {
+                        token.cancel();
+                    }
+                }
+            })
+            .link();
+        let reports = reports.borrow().clone();
+        (result, reports)
+    };
+
+    // The phases come in order and each one only goes forward.
+    let (result, reports) = link(None);
+    result?;
+    assert!(reports.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(reports.first(), Some(&(Phase::Read, 0)));
+    assert!(reports.contains(&(Phase::Parse, 100)));
+    assert!(reports.iter().any(|(phase, _)| *phase == Phase::Relocate));
+    assert_eq!(reports.last(), Some(&(Phase::Write, 100)));
+    fs::remove_file(&exe)?;
+
+    // A link cancelled during layout stops at the next check and leaves no output.
+    let (result, reports) = link(Some(Phase::Layout));
+    assert!(matches!(result, Err(LinkError::Cancelled)));
+    assert_eq!(reports.last(), Some(&(Phase::Layout, 0)));
+    assert!(!exe.exists());
+    Ok(())
+}
+
+#[test]
+fn link_bind_now() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::dynamic::*;
+    use goblin::elf::program_header::PT_DYNAMIC;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    link_objects(&[&main_o, &lib_o], &exe, &["-z", "now"])?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(
+        dynamic_entries(&elf),
+        [
+            (DT_FLAGS, DF_BIND_NOW),
+            (DT_FLAGS_1, DF_1_NOW),
+            (DT_NULL, 0)
+        ]
+    );
+    assert!(elf.program_headers.iter().any(|ph| ph.p_type == PT_DYNAMIC));
+
+    // The last keyword wins and lazy binding needs no dynamic section.
+    for args in [&["-z", "now", "-z", "lazy"][..], &["-z", "lazy"], &[]] {
+        link_objects(&[&main_o, &lib_o], &exe, args)?;
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        assert!(elf.dynamic.is_none(), "dynamic section for {:?}", args);
+        assert!(elf.program_headers.iter().all(|ph| ph.p_type != PT_DYNAMIC));
+    }
+    link_objects(&[&main_o, &lib_o], &exe, &["-z", "lazy", "-z", "now"])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(dynamic_entries(&elf).contains(&(DT_FLAGS, DF_BIND_NOW)));
+
+    assert!(Opts::try_parse_from(["toy-linker", "-z", "eager", "-o", "out"]).is_err());
+    Ok(())
+}
+
+// The relro rounding only happens in memory, the writable segments take up no more of the
+// file than their sections.
+fn assert_rw_unpadded(elf: &goblin::elf::Elf) {
+    use goblin::elf::program_header::*;
+    for ph in elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD && ph.p_flags == PF_R | PF_W)
+    {
+        let contents: u64 = elf
+            .section_headers
+            .iter()
+            .filter(|sec| {
+                sec.sh_type != goblin::elf::section_header::SHT_NOBITS
+                    && ph.p_vaddr <= sec.sh_addr
+                    && sec.sh_addr < ph.p_vaddr + ph.p_memsz
+            })
+            .map(|sec| sec.sh_size)
+            .sum();
+        assert!(ph.p_filesz <= contents + 64, "{:?} is padded", ph);
+    }
+}
+
+#[test]
+fn link_relro() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Calls through GOTPCREL need a .got and -z now adds a .dynamic.
+    let flags = ["-fPIC", "-fno-plt", "-Wa,-mrelax-relocations=no"];
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &flags)?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &flags)?;
+    let exe = tmp_dir.path().join("main");
+    link_objects(&[&main_o, &lib_o], &exe, &["-z", "now"])?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let relros: Vec<_> = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_GNU_RELRO)
+        .collect();
+    assert_eq!(relros.len(), 1);
+    let relro = relros[0];
+    let (start, end) = (relro.p_vaddr, relro.p_vaddr + relro.p_memsz);
+    // The loader rounds the end down to a page, nothing may be left unprotected.
+    assert_eq!(end % u64::try_from(PAGE_SIZE).unwrap(), 0);
+    assert!(relro.p_filesz <= relro.p_memsz);
+    for name in [".dynamic", ".got"] {
+        let sec = elf
+            .section_headers
+            .iter()
+            .find(|sec| &elf.shdr_strtab[sec.sh_name] == name)
+            .unwrap();
+        assert!(
+            start <= sec.sh_addr && sec.sh_addr + sec.sh_size <= end,
+            "{} is outside of PT_GNU_RELRO",
+            name
+        );
+    }
+    assert!(elf.program_headers.iter().any(|ph| {
+        ph.p_type == PT_LOAD
+            && ph.p_flags == PF_R | PF_W
+            && ph.p_offset == relro.p_offset
+            && ph.p_vaddr <= start
+            && end <= ph.p_vaddr + ph.p_memsz
+    }));
+    assert_rw_unpadded(&elf);
+
+    // Writable data after the relro region starts on the next page without padding the file.
+    let init_array_o = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let init_array = tmp_dir.path().join("init_array");
+    link_objects(&[&init_array_o], &init_array, &["-z", "now"])?;
+    let output = Command::new(&init_array).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&init_array)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(elf
+        .program_headers
+        .iter()
+        .any(|ph| ph.p_type == PT_GNU_RELRO));
+    assert_rw_unpadded(&elf);
+
+    link_objects(&[&main_o, &lib_o], &exe, &["-z", "now", "-z", "norelro"])?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(elf
+        .program_headers
+        .iter()
+        .all(|ph| ph.p_type != PT_GNU_RELRO));
+    Ok(())
+}
+
+#[test]
+fn link_rpath() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::dynamic::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    // All directories end up in a single DT_RUNPATH in the order they were given.
+    link_objects(
+        &[&main_o, &lib_o],
+        &exe,
+        &["--rpath", "/opt/lib", "--rpath", "$ORIGIN/../lib"],
+    )?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let entries = dynamic_entries(&elf);
+    let tags: Vec<u64> = entries.iter().map(|(tag, _)| *tag).collect();
+    assert_eq!(tags, [DT_RUNPATH, DT_STRTAB, DT_STRSZ, DT_NULL]);
+    let runpath = usize::try_from(entries[0].1).unwrap();
+    assert_eq!(
+        elf.dynstrtab.get(runpath).transpose()?,
+        Some("/opt/lib:$ORIGIN/../lib")
+    );
+    let dynstr = elf
+        .section_headers
+        .iter()
+        .find(|sec| &elf.shdr_strtab[sec.sh_name] == ".dynstr")
+        .unwrap();
+    assert_eq!(entries[1].1, dynstr.sh_addr);
+    assert_eq!(entries[2].1, dynstr.sh_size);
+    // The string table starts with the empty string.
+    let start = usize::try_from(dynstr.sh_offset).unwrap();
+    let end = start + usize::try_from(dynstr.sh_size).unwrap();
+    assert_eq!(&buf[start..end], b"\0/opt/lib:$ORIGIN/../lib\0");
+
+    // --rpath-link is recognized in both spellings but rejected.
+    let with_rpath_link = tmp_dir.path().join("with_rpath_link");
+    let result = Linker::from_opts(Opts::parse_from(normalize_args(
+        [
+            "toy-linker",
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            "--rpath",
+            "/opt/lib",
+            "--rpath",
+            "$ORIGIN/../lib",
+            "-rpath-link",
+            "/opt/lib",
+            "--rpath-link=/usr/lib",
+            "-o",
+            with_rpath_link.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string()),
+    )))
+    .link();
+    assert!(matches!(result, Err(LinkError::InvalidOptions { .. })));
+    assert!(!with_rpath_link.exists());
+    assert!(Opts::try_parse_from(["toy-linker", "--rpath-link=", "-o", "out"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn link_shared() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::dynamic::*;
+    use goblin::elf::reloc::R_X86_64_RELATIVE;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Without relaxation the counter keeps its GOT entry.
+    let lib_o = gcc(
+        tmp_dir.path(),
+        Path::new("shared_lib.c"),
+        &["-fPIC", "-Wa,-mrelax-relocations=no"],
+    )?;
+    let lib = tmp_dir.path().join("libwuhu.so");
+    link_objects(&[&lib_o], &lib, &["--shared", "--soname", "libwuhu.so.1"])?;
+    let buf = fs::read(&lib)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(elf.header.e_type, goblin::elf::header::ET_DYN);
+    assert_eq!(elf.soname, Some("libwuhu.so.1"));
+    let tags: Vec<u64> = dynamic_entries(&elf).iter().map(|(tag, _)| *tag).collect();
+    assert_eq!(
+        tags,
+        [
+            DT_SONAME, DT_HASH, DT_SYMTAB, DT_SYMENT, DT_RELA, DT_RELASZ, DT_RELAENT, DT_STRTAB,
+            DT_STRSZ, DT_NULL
+        ]
+    );
+    let exports: Vec<&str> = elf
+        .dynsyms
+        .iter()
+        .map(|sym| &elf.dynstrtab[sym.st_name])
+        .collect();
+    assert_eq!(exports, ["", "counter", "extern_call", "handlers"]);
+    // The pointer in the table and the GOT entry of the counter
+    assert_eq!(elf.dynrelas.len(), 2);
+    let handlers = elf.dynsyms.get(3).unwrap();
+    let got = section_header(&elf, ".got");
+    for (reloc, address) in elf.dynrelas.iter().zip([handlers.st_value, got.sh_addr]) {
+        assert_eq!(reloc.r_type, R_X86_64_RELATIVE);
+        assert_eq!(reloc.r_offset, address);
+    }
+
+    // The library works with the system linker and the dynamic loader.
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let output = Command::new("gcc")
+        .args(["-nostdlib", "-o", exe.to_str().unwrap()])
+        .arg(&main_o)
+        .arg(&lib)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let exe_buf = fs::read(&exe)?;
+    assert_eq!(
+        goblin::elf::Elf::parse(&exe_buf)?.libraries,
+        ["libwuhu.so.1"]
+    );
+    fs::rename(&lib, tmp_dir.path().join("libwuhu.so.1"))?;
+    let output = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", tmp_dir.path())
+        .output()?;
+    assert_eq!(output.status.code(), Some(42));
+    assert_eq!(output.stdout, b"Hello world\nwuhu\n");
+
+    // The name of a shared library only makes sense for a shared library.
+    let result = link_objects(&[&lib_o], &lib, &["--soname", "libwuhu.so.1"]);
+    assert!(matches!(result, Err(LinkError::InvalidOptions { .. })));
+    Ok(())
+}
+
+#[test]
+fn link_abs64() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = gcc(tmp_dir.path(), Path::new("abs64.s"), &[])?;
+    let exe = tmp_dir.path().join("abs64");
+    link_objects(&[&object], &exe, &[])?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let data = section_header(&elf, ".data");
+    let pointers = usize::try_from(data.sh_offset).unwrap() + 2;
+    let values = [
+        buf.pread_with::<u64>(pointers, scroll::LE)?,
+        buf.pread_with::<u64>(pointers + 8, scroll::LE)?,
+        buf.pread_with::<u64>(pointers + 16, scroll::LE)?,
+    ];
+    // S + A with the addend from the relocation, negative ones included.
+    assert_eq!(
+        values,
+        [data.sh_addr + 1, elf.header.e_entry, data.sh_addr - 0x100]
+    );
+    Ok(())
+}
+
+#[test]
+fn link_abs32() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = gcc(tmp_dir.path(), Path::new("abs32.s"), &[])?;
+    let exe = tmp_dir.path().join("abs32");
+    link_objects(&[&object], &exe, &[])?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let text = usize::try_from(section_header(&elf, ".text").sh_offset).unwrap();
+    let data = section_header(&elf, ".data");
+    let answer = u32::try_from(data.sh_addr).unwrap();
+    // The immediate of `movq $answer, %rax` and the .long after the answer
+    assert_eq!(buf.pread_with::<u32>(text + 3, scroll::LE)?, answer);
+    let offset = usize::try_from(data.sh_offset).unwrap();
+    assert_eq!(buf.pread_with::<u32>(offset + 1, scroll::LE)?, answer);
+
+    // The largest values that fit, R_X86_64_32 is unsigned and R_X86_64_32S signed.
+    let limits = gcc(tmp_dir.path(), Path::new("abs32_limits.s"), &[])?;
+    let symbols = gcc(tmp_dir.path(), Path::new("big_symbols.s"), &[])?;
+    link_objects(&[&limits, &symbols], &exe, &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let data = section_header(&elf, ".data");
+    let offset = usize::try_from(data.sh_offset).unwrap();
+    assert_eq!(
+        buf[offset..offset + 12],
+        [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x00, 0x00, 0x00, 0x80]
+    );
+
+    // One past them does not.
+    let overflow = gcc(tmp_dir.path(), Path::new("abs32_overflow.s"), &[])?;
+    match link_objects(&[&overflow, &symbols], &exe, &[]) {
+        Err(LinkError::LayoutOverflow { reason }) => {
+            let relocations: Vec<&str> = reason
+                .lines()
+                .map(|line| {
+                    let (_, rest) = line.split_once("truncated to fit: ").unwrap();
+                    rest.split(' ').next().unwrap()
+                })
+                .collect();
+            assert_eq!(
+                relocations,
+                ["X86_64_32S", "X86_64_32S", "X86_64_32", "X86_64_32"]
+            );
+        }
+        result => panic!("Expected the relocations to overflow: {:?}", result),
+    }
+    Ok(())
+}
+
+#[test]
+fn link_pc_relative() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = gcc(tmp_dir.path(), Path::new("pc_relative.s"), &[])?;
+    let exe = tmp_dir.path().join("pc_relative");
+    link_objects(&[&object], &exe, &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let data = section_header(&elf, ".data");
+    let offset = usize::try_from(data.sh_offset).unwrap();
+    let p = |field: u64| i64::try_from(data.sh_addr + field).unwrap();
+    let target = p(1);
+    let start = i64::try_from(elf.header.e_entry).unwrap();
+    // S + A - P for each field
+    assert_eq!(buf.pread_with::<i8>(offset + 2, scroll::LE)?, -1);
+    assert_eq!(
+        i64::from(buf.pread_with::<i16>(offset + 3, scroll::LE)?),
+        target - 2 - p(3)
+    );
+    assert_eq!(buf.pread_with::<i32>(offset + 5, scroll::LE)?, 0);
+    assert_eq!(buf.pread_with::<i64>(offset + 9, scroll::LE)?, start - p(9));
+
+    let overflow = gcc(tmp_dir.path(), Path::new("pc_overflow.s"), &[])?;
+    match link_objects(&[&overflow], &exe, &[]) {
+        Err(LinkError::LayoutOverflow { reason }) => {
+            let lines: Vec<&str> = reason.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].contains(
+                "pc_overflow.o:(.data+0x0): relocation truncated to fit: X86_64_PC8 against `_start'"
+            ));
+            assert!(lines[1].contains(
+                "pc_overflow.o:(.data+0x1): relocation truncated to fit: X86_64_PC16 against `_start'"
+            ));
+        }
+        result => panic!("Expected the relocations to overflow: {:?}", result),
+    }
+    Ok(())
+}
+
+#[test]
+fn relocation_overflow_report() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let overflow = gcc(tmp_dir.path(), Path::new("abs32_overflow.s"), &[])?;
+    let symbols = gcc(tmp_dir.path(), Path::new("big_symbols.s"), &[])?;
+    let far_local = gcc(tmp_dir.path(), Path::new("far_local.s"), &[])?;
+    let exe = tmp_dir.path().join("overflow");
+    let err = match link_objects(&[&overflow, &symbols, &far_local], &exe, &[]) {
+        Err(err @ LinkError::LayoutOverflow { .. }) => err,
+        result => panic!("Expected the relocations to overflow: {:?}", result),
+    };
+    assert!(!exe.exists());
+    // Every overflow of every file is reported in input order, relocations against
+    // section symbols are named after the section.
+    let overflow = overflow.to_str().unwrap();
+    let message = err.to_string();
+    let lines: Vec<&str> = message.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            format!(
+                "{}:(.text+0x3): relocation truncated to fit: X86_64_32S against `above_2g' (0x80000000)",
+                overflow
+            ),
+            format!(
+                "{}:(.text+0xa): relocation truncated to fit: X86_64_32S against `below_2g' (0xffffffff7fffffff)",
+                overflow
+            ),
+            format!(
+                "{}:(.data+0x0): relocation truncated to fit: X86_64_32 against `above_4g' (0x100000000)",
+                overflow
+            ),
+            format!(
+                "{}:(.data+0x4): relocation truncated to fit: X86_64_32 against `below_2g' (0xffffffff7fffffff)",
+                overflow
+            ),
+        ]
+    );
+    assert_eq!(lines.len(), 5);
+    assert!(lines[4].starts_with(&format!(
+        "{}:(.data+0x0): relocation truncated to fit: X86_64_PC8 against `.text' (",
+        far_local.to_str().unwrap()
+    )));
+    Ok(())
+}
+
+#[test]
+fn link_rel_addends() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = gcc(tmp_dir.path(), Path::new("rel_addend.s"), &["-m32"])?;
+    let object_buf = fs::read(&object)?;
+    let object_elf = goblin::elf::Elf::parse(&object_buf)?;
+    assert!(!object_elf.shdr_relocs.is_empty());
+    assert!(object_elf
+        .shdr_relocs
+        .iter()
+        .all(|(_, relocs)| relocs.iter().all(|reloc| reloc.r_addend.is_none())));
+    let exe = tmp_dir.path().join("rel_addend");
+    link_objects(&[&object], &exe, &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let data = section_header(&elf, ".data");
+    let offset = usize::try_from(data.sh_offset).unwrap();
+    let words: Vec<u32> = (0..4)
+        .map(|i| buf.pread_with::<u32>(offset + 4 * i, scroll::LE))
+        .collect::<Result<_, _>>()?;
+    let target = u32::try_from(data.sh_addr).unwrap();
+    let start = u32::try_from(elf.header.e_entry).unwrap();
+    assert_eq!(
+        words,
+        [
+            0,
+            target + 5,
+            target - 0x20,
+            start.wrapping_sub(target + 12).wrapping_add(8)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn unsupported_relocation() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let object = gcc(tmp_dir.path(), Path::new("unsupported_reloc.s"), &[])?;
+    let exe = tmp_dir.path().join("unsupported");
+    match link_objects(&[&object], &exe, &[]) {
+        Err(err @ LinkError::UnsupportedRelocation { .. }) => {
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "{}: unsupported relocation: X86_64_GOTOFF64 (25)",
+                    object.to_str().unwrap()
+                )
+            );
+        }
+        result => panic!("Expected an unsupported relocation: {:?}", result),
+    }
+    assert!(!exe.exists());
+    Ok(())
+}
+
+#[test]
+fn link_tls() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Position independent code uses the general-dynamic and local-dynamic models which
+    // have to be relaxed to local-exec.
+    let tls_o = gcc(tmp_dir.path(), Path::new("tls.c"), &["-O2", "-fPIC"])?;
+    let main_o = gcc(tmp_dir.path(), Path::new("tls_main.c"), &["-O2"])?;
+    let exe = tmp_dir.path().join("tls");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        tls_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(32));
+
+    // The relaxation rewrites the call after the relocation as well.
+    let edge_o = gcc(tmp_dir.path(), Path::new("tlsgd_edge.s"), &[])?;
+    match link_objects(&[&edge_o], &exe, &[]) {
+        Err(LinkError::MalformedInput { reason, .. }) => {
+            assert_eq!(reason, "Relocation at offset 0x4 is outside of its section")
+        }
+        result => panic!("Expected a malformed input: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[test]
+fn link_got() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // With -fno-plt calls go through the GOT. We relax R_X86_64_GOTPCRELX and need a real
+    // GOT entry for the older R_X86_64_GOTPCREL.
+    for (flags, relaxed) in [
+        (&["-fPIC", "-fno-plt"][..], true),
+        (
+            &["-fPIC", "-fno-plt", "-Wa,-mrelax-relocations=no"][..],
+            false,
+        ),
+    ] {
+        let main_o = gcc(tmp_dir.path(), Path::new("main.c"), flags)?;
+        let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), flags)?;
+        let exe = tmp_dir.path().join("main");
+        let result = Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let output = Command::new(exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let out = std::str::from_utf8(&output.stdout).unwrap();
+        assert_eq!(out, "Hello world\nwuhu\n");
+        let elf = goblin::elf::Elf::parse(&result.output)?;
+        let has_got = elf
+            .section_headers
+            .iter()
+            .any(|sec| &elf.shdr_strtab[sec.sh_name] == ".got");
+        assert_eq!(has_got, !relaxed);
+        // `call *extern_call@GOTPCREL(%rip)` becomes `addr32 call extern_call`.
+        let text = section_header(&elf, ".text");
+        let start = usize::try_from(text.sh_offset).unwrap();
+        let code = &result.output[start..start + usize::try_from(text.sh_size).unwrap()];
+        let extern_call = result.layout.unwrap().symbol("extern_call").unwrap();
+        let direct_call = (0..code.len() - 6).any(|i| {
+            let next = text.sh_addr + u64::try_from(i).unwrap() + 6;
+            code[i..i + 2] == [0x67, 0xe8]
+                && code[i + 2..i + 6] == (extern_call.wrapping_sub(next) as u32).to_le_bytes()
+        });
+        assert_eq!(direct_call, relaxed);
+    }
+
+    // `jmp *exit42@GOTPCREL(%rip)` becomes `jmp exit42; nop`.
+    let object = gcc(tmp_dir.path(), Path::new("gotpcrelx_start.s"), &[])?;
+    let exe = tmp_dir.path().join("gotpcrelx_start");
+    let result = link_objects(&[&object], &exe, &[])?;
+    let elf = goblin::elf::Elf::parse(&result.output)?;
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|sec| &elf.shdr_strtab[sec.sh_name] != ".got"));
+    let layout = result.layout.unwrap();
+    let tail = layout
+        .input_section(object.to_str().unwrap(), ".text.tail")
+        .unwrap();
+    // exit42 follows the 5 byte call in _start.
+    let exit42 = layout.symbol("_start").unwrap() + 5;
+    let displacement = (exit42.wrapping_sub(tail.address + 5) as u32).to_le_bytes();
+    let text = section_header(&elf, ".text");
+    let offset = usize::try_from(tail.address - text.sh_addr + text.sh_offset).unwrap();
+    assert_eq!(result.output[offset], 0xe9);
+    assert_eq!(result.output[offset + 1..offset + 5], displacement);
+    assert_eq!(result.output[offset + 5], 0x90);
+    Ok(())
+}
+
+#[test]
+fn link_got_at_section_start() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Without a REX prefix the instruction is only two bytes before the relocation.
+    let object = gcc(tmp_dir.path(), Path::new("gotpcrelx_start.s"), &[])?;
+    let exe = tmp_dir.path().join("gotpcrelx_start");
+    link_objects(&[&object], &exe, &[])?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_got_out_of_range() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Only accesses that reach their symbol are relaxed, the others load from the GOT.
+    let object = gcc(tmp_dir.path(), Path::new("gotpcrelx_far.s"), &[])?;
+    let exe = tmp_dir.path().join("gotpcrelx_far");
+    for (args, far_func) in [
+        (&[][..], None),
+        (&["--section-start=.far=0x100000000"][..], Some(0x100000000)),
+    ] {
+        let result = link_objects(&[&object], &exe, args)?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let elf = goblin::elf::Elf::parse(&result.output)?;
+        let got = section_header(&elf, ".got");
+        let entries = (got.sh_offset..got.sh_offset + got.sh_size)
+            .step_by(8)
+            .map(|offset| {
+                result
+                    .output
+                    .pread_with::<u64>(usize::try_from(offset).unwrap(), scroll::LE)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.contains(&0x7fff00000000));
+        assert!(!entries.contains(&0x1000));
+        // `call *far_func@GOTPCREL(%rip)` stays an indirect call when far_func is at 4 GiB.
+        let text = section_header(&elf, ".text");
+        let start = usize::try_from(text.sh_offset).unwrap();
+        let code = &result.output[start..start + usize::try_from(text.sh_size).unwrap()];
+        let indirect_call = code.windows(2).any(|insn| insn == [0xff, 0x15]);
+        assert_eq!(indirect_call, far_func.is_some());
+        if let Some(far_func) = far_func {
+            assert!(entries.contains(&far_func));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn link_relocatable() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let combined_o = tmp_dir.path().join("combined.o");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-r",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        combined_o.to_str().unwrap(),
+    ]))
+    .link()?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        combined_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let out = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(out, "Hello world\nwuhu\n");
+    Ok(())
+}
+
+#[test]
+fn link_emit_relocs() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--emit-relocs",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let (text_idx, text) = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, sec)| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+        .unwrap();
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|(idx, _)| elf.section_headers[*idx].sh_info == u32::try_from(text_idx).unwrap())
+        .unwrap();
+    // Relocations point into the final addresses of .text.
+    assert!(relocs.iter().all(|reloc| text
+        .vm_range()
+        .contains(&usize::try_from(reloc.r_offset).unwrap())));
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn field_byte_order() -> Result<(), Box<dyn Error>> {
+    use goblin::container::{Container, Endian};
+    for endian in [Endian::Little, Endian::Big] {
+        let ctx = Ctx::new(Container::Little, endian);
+        for (field, value) in [
+            (Field::I16, -2),
+            (Field::U32, 0x1234_5678),
+            (Field::Word64, -0x1234_5678_9abc),
+            (Field::ArmBranch, -8),
+            (Field::ThumbBranch, 0x12_3456),
+        ] {
+            let mut buf = [0; 8];
+            field.write(&mut buf, 0, value, ctx)?;
+            assert_eq!(field.read(&buf, 0, ctx)?, value);
+        }
+        let mut buf = [0; 4];
+        Field::U32.write(&mut buf, 0, 0x1122_3344, ctx)?;
+        let expected = if endian == Endian::Little {
+            [0x44, 0x33, 0x22, 0x11]
+        } else {
+            [0x11, 0x22, 0x33, 0x44]
+        };
+        assert_eq!(buf, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn link_bss() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let bss_o = gcc(tmp_dir.path(), Path::new("bss.c"), &[])?;
+    let exe = tmp_dir.path().join("bss");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        bss_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    // The 1MiB buffer must not end up in the file.
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let data = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_flags & goblin::elf::program_header::PF_W != 0)
+        .unwrap();
+    assert!(data.p_memsz >= data.p_filesz + (1 << 20));
+    assert!(u64::try_from(buf.len()).unwrap() < data.p_offset + data.p_memsz);
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+
+    // With -fcommon `counter` is a tentative definition that only the linker gives space.
+    let bss_o = gcc(tmp_dir.path(), Path::new("bss.c"), &["-fcommon"])?;
+    link_objects(&[&bss_o], &exe, &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let counter = elf
+        .syms
+        .iter()
+        .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some("counter"))
+        .unwrap();
+    let bss = section_header(&elf, ".bss");
+    assert_eq!(
+        counter.st_shndx,
+        elf.section_headers
+            .iter()
+            .position(|sec| sec == bss)
+            .unwrap()
+    );
+    assert!(bss.sh_addr <= counter.st_value && counter.st_value + 4 <= bss.sh_addr + bss.sh_size);
+    assert_eq!(counter.st_value % 4, 0);
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_merge_strings() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Optimizations put the literals into mergeable .rodata.str1.1 sections.
+    let strings_o = gcc(tmp_dir.path(), Path::new("strings.c"), &["-O2"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("strings_lib.c"), &["-O2"])?;
+    let exe = tmp_dir.path().join("strings");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        strings_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let needle = b"shared greeting\n\0";
+    let copies = buf.windows(needle.len()).filter(|w| w == needle).count();
+    assert_eq!(copies, 1);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_merge_constants() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let constants_o = gcc(tmp_dir.path(), Path::new("constants.c"), &["-O2"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("constants_lib.c"), &["-O2"])?;
+    let exe = tmp_dir.path().join("constants");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        constants_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    // 2.25 is in the .rodata.cst8 section of both objects.
+    let buf = fs::read(&exe)?;
+    let needle = 2.25f64.to_le_bytes();
+    let copies = buf.windows(needle.len()).filter(|w| *w == needle).count();
+    assert_eq!(copies, 1);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_gc_sections() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let exe = tmp_dir.path().join("gc");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--gc-sections",
+        "-i",
+        gc_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let needle = b"this string is never used";
+    assert!(!buf.windows(needle.len()).any(|w| w == needle));
+    // The unused table alone takes 16KiB.
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let size: u64 = elf.program_headers.iter().map(|phdr| phdr.p_memsz).sum();
+    assert!(size < 4096 * 4);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn gc_sections_removed() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let buf = fs::read(&gc_o)?;
+    let mut input = Input::new();
+    input.process_object_file("gc.o", &buf)?;
+    let removed: Vec<&str> = input
+        .gc_sections(None, &[])
+        .iter()
+        .map(|sec| input.section_names[sec])
+        .collect();
+    assert!(removed.contains(&".text.unused_function"));
+    assert!(removed.contains(&".data.unused_table"));
+    assert!(!removed.contains(&".text._start"));
+    assert!(!removed.contains(&".data.used"));
+    Ok(())
+}
+
+#[test]
+fn gc_sections_keep_section() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let buf = fs::read(&gc_o)?;
+    let mut input = Input::new();
+    input.process_object_file("gc.o", &buf)?;
+    let removed: Vec<&str> = input
+        .gc_sections(None, &[".text.unused_*".to_string()])
+        .iter()
+        .map(|sec| input.section_names[sec])
+        .collect();
+    // The kept function in turn keeps the table it references alive.
+    assert!(!removed.contains(&".text.unused_function"));
+    assert!(!removed.contains(&".data.unused_table"));
+    assert!(removed.contains(&".data.rel.local.unused_string"));
+    Ok(())
+}
+
+#[test]
+fn link_init_array() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let init_array_o = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let exe = tmp_dir.path().join("init_array");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        init_array_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_eh_frame() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--emit-relocs",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let eh_frame = elf
+        .section_headers
+        .iter()
+        .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".eh_frame"))
+        .unwrap();
+    let contents = &buf[eh_frame.file_range()];
+    // Both objects have the same CIE, every function has an FDE.
+    let mut cies = 0;
+    let mut fdes = 0;
+    let mut offset = 0;
+    loop {
+        let length = usize::try_from(contents.pread::<u32>(offset)?).unwrap();
+        if length == 0 {
+            break;
+        }
+        let cie_pointer = contents.pread::<u32>(offset + 4)?;
+        if cie_pointer == 0 {
+            cies += 1;
+        } else {
+            fdes += 1;
+            assert_eq!(offset + 4 - usize::try_from(cie_pointer).unwrap(), 0);
+        }
+        offset += 4 + length;
+    }
+    assert_eq!((cies, fdes), (1, 5));
+    assert_eq!(offset + 4, contents.len());
+    Ok(())
+}
+
+#[test]
+fn link_eh_frame_hdr() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let phdr = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_type == goblin::elf::program_header::PT_GNU_EH_FRAME)
+        .unwrap();
+    let hdr = usize::try_from(phdr.p_offset).unwrap();
+    assert_eq!(buf[hdr], 1);
+    let fde_count = usize::try_from(buf.pread::<u32>(hdr + 8)?).unwrap();
+    assert_eq!(fde_count, 5);
+    // The table is sorted by the start of the code covered by each FDE.
+    let starts = (0..fde_count)
+        .map(|i| buf.pread::<i32>(hdr + 12 + 8 * i))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert!(starts.windows(2).all(|w| w[0] < w[1]));
+    let entry = i64::try_from(elf.entry).unwrap() - i64::try_from(phdr.p_vaddr).unwrap();
+    assert!(starts.contains(&i32::try_from(entry).unwrap()));
+    Ok(())
+}
+
+#[test]
+fn link_script() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let script_o = gcc(tmp_dir.path(), Path::new("script.c"), &[])?;
+    let exe = tmp_dir.path().join("script");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        script_o.to_str().unwrap(),
+        "-T",
+        "examples/script.ld",
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(elf.entry >= 0x800000);
+    Ok(())
+}
+
+#[test]
+fn link_segment_addresses() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let bss_o = gcc(tmp_dir.path(), Path::new("bss.c"), &[])?;
+    let exe = tmp_dir.path().join("bss");
+    Linker::from_opts(Opts::parse_from(normalize_args(
+        [
+            "toy-linker",
+            "-i",
+            bss_o.to_str().unwrap(),
+            "--image-base=0x100000",
+            "-Ttext=0x200000",
+            "-Tdata",
+            "300000",
+            "-Tbss=0x500000",
+            "-o",
+            exe.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string()),
+    )))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(elf.entry & !0xfff, 0x200000);
+    let starts: Vec<u64> = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|phdr| phdr.p_vaddr)
+        .collect();
+    assert!(starts.contains(&0x500000));
+    // There is no data, only the empty .data section is at the address given.
+    assert!(!starts.contains(&0x300000));
+    assert!(elf
+        .section_headers
+        .iter()
+        .any(|sec| &elf.shdr_strtab[sec.sh_name] == ".data" && sec.sh_addr == 0x300000));
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_section_start() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let section_start_o = gcc(tmp_dir.path(), Path::new("section_start.c"), &[])?;
+    let exe = tmp_dir.path().join("section_start");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        section_start_o.to_str().unwrap(),
+        "--section-start=.boot=0x600000",
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_image_base() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "--image-base=0x10000000",
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    for phdr in &elf.program_headers {
+        assert!(phdr.p_vaddr >= 0x10000000);
+        assert_eq!((phdr.p_vaddr - phdr.p_offset) % 4096, 0);
+    }
+    // Nothing is stored for the addresses below the image base.
+    assert!(buf.len() < 0x10000);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_section_headers() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let names: Vec<&str> = elf
+        .section_headers
+        .iter()
+        .map(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name).unwrap())
+        .collect();
+    assert_eq!(names[usize::from(elf.header.e_shstrndx)], ".shstrtab");
+    let text = &elf.section_headers[names.iter().position(|name| *name == ".text").unwrap()];
+    assert!(text
+        .vm_range()
+        .contains(&usize::try_from(elf.entry).unwrap()));
+    // The table is not loaded.
+    for phdr in &elf.program_headers {
+        assert!(phdr.p_offset + phdr.p_filesz <= elf.header.e_shoff);
+    }
+    Ok(())
+}
+
+#[test]
+fn link_symtab() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let symbol = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some(name))
+            .unwrap()
+    };
+    assert_eq!(symbol("_start").st_value, elf.entry);
+    // Both files have a local `print` which ends up in .text.
+    let text = elf
+        .section_headers
+        .iter()
+        .position(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+        .unwrap();
+    let print = symbol("print");
+    assert!(print.is_function() && print.st_bind() == goblin::elf::sym::STB_LOCAL);
+    assert_eq!(print.st_shndx, text);
+    assert!(elf.section_headers[text]
+        .vm_range()
+        .contains(&usize::try_from(print.st_value).unwrap()));
+    Ok(())
+}
+
+#[test]
+fn link_debug_info() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &["-g"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-g"])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(name))
+            .unwrap()
+    };
+    let loaded_end = elf
+        .program_headers
+        .iter()
+        .map(|phdr| phdr.p_offset + phdr.p_filesz)
+        .max()
+        .unwrap();
+    for name in [
+        ".debug_info",
+        ".debug_abbrev",
+        ".debug_line",
+        ".debug_aranges",
+    ] {
+        let sec = section(name);
+        assert_eq!(sec.sh_addr, 0);
+        assert!(sec.sh_offset >= loaded_end);
+    }
+    // The first address range of each unit starts after a 16 byte header and points into
+    // the relocated .text.
+    let aranges = section(".debug_aranges");
+    let text = section(".text").vm_range();
+    let mut offset = usize::try_from(aranges.sh_offset).unwrap();
+    let end = offset + usize::try_from(aranges.sh_size).unwrap();
+    while offset < end {
+        let length = usize::try_from(buf.pread::<u32>(offset)?).unwrap();
+        let start = buf.pread::<u64>(offset + 16)?;
+        assert!(text.contains(&usize::try_from(start).unwrap()));
+        offset += 4 + length;
+    }
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_build_id() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let build_id = |option: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            option,
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let note = elf
+            .program_headers
+            .iter()
+            .find(|phdr| phdr.p_type == goblin::elf::program_header::PT_NOTE)
+            .unwrap();
+        let offset = usize::try_from(note.p_offset).unwrap();
+        assert_eq!(buf.pread::<u32>(offset + 8)?, NT_GNU_BUILD_ID);
+        assert_eq!(&buf[offset + 12..offset + 16], b"GNU\0");
+        let size = usize::try_from(buf.pread::<u32>(offset + 4)?).unwrap();
+        Ok(buf[offset + 16..offset + 16 + size].to_vec())
+    };
+    assert_eq!(build_id("--build-id=0x0123abcd")?, [0x01, 0x23, 0xab, 0xcd]);
+    let sha1 = build_id("--build-id")?;
+    assert_eq!(sha1.len(), 20);
+    // The same inputs give the same identifier.
+    assert_eq!(build_id("--build-id=sha1")?, sha1);
+    assert_eq!(build_id("--build-id=md5")?.len(), 16);
+    Ok(())
+}
+
+#[test]
+fn link_comment() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let comment = elf
+        .section_headers
+        .iter()
+        .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".comment"))
+        .unwrap();
+    let strings: Vec<&[u8]> = buf[comment.file_range()]
+        .split(|b| *b == 0)
+        .filter(|string| !string.is_empty())
+        .collect();
+    // Both inputs were compiled by the same compiler.
+    assert_eq!(strings.len(), 1);
+    assert!(strings[0].starts_with(b"GCC: "));
+    Ok(())
+}
+
+#[test]
+fn link_notes() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::PT_NOTE;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let note_o = gcc(tmp_dir.path(), Path::new("note.c"), &["-fcf-protection"])?;
+    let exe = tmp_dir.path().join("note");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        note_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    for name in [".note.ABI-tag", ".note.gnu.property"] {
+        let shdr = elf
+            .section_headers
+            .iter()
+            .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(name))
+            .unwrap();
+        // Each note is covered by a PT_NOTE with its alignment.
+        assert!(elf.program_headers.iter().any(|phdr| phdr.p_type == PT_NOTE
+            && phdr.p_vaddr == shdr.sh_addr
+            && phdr.p_filesz == shdr.sh_size
+            && phdr.p_align == shdr.sh_addralign));
+    }
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_gnu_property() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &["-fcf-protection"])?;
+    let lib_cet_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-fcf-protection"])?;
+    let lib_dir = tmp_dir.path().join("plain");
+    fs::create_dir(&lib_dir)?;
+    let lib_o = gcc(&lib_dir, Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let features = |lib_o: &Path| -> Result<Option<u32>, Box<dyn Error>> {
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let notes: Vec<_> = elf
+            .section_headers
+            .iter()
+            .filter(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".note.gnu.property"))
+            .collect();
+        let note = match notes[..] {
+            [] => return Ok(None),
+            [note] => note,
+            _ => panic!("More than one .note.gnu.property section"),
+        };
+        let phdr = elf
+            .program_headers
+            .iter()
+            .find(|phdr| phdr.p_type == PT_GNU_PROPERTY)
+            .unwrap();
+        assert_eq!(phdr.p_vaddr, note.sh_addr);
+        // A single note with a single property
+        let offset = usize::try_from(note.sh_offset).unwrap();
+        assert_eq!(note.sh_size, 32);
+        assert_eq!(buf.pread::<u32>(offset + 8)?, NT_GNU_PROPERTY_TYPE_0);
+        assert_eq!(
+            buf.pread::<u32>(offset + 16)?,
+            GNU_PROPERTY_X86_FEATURE_1_AND
+        );
+        Ok(Some(buf.pread::<u32>(offset + 24)?))
+    };
+    // IBT and SHSTK are only kept if all inputs support them.
+    assert_eq!(features(&lib_cet_o)?, Some(3));
+    assert_eq!(features(&lib_o)?, None);
+    Ok(())
+}
+
+#[test]
+fn link_fill() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    // The code of lib.o has to be aligned which leaves padding after main.o.
+    let lib_o = gcc(
+        tmp_dir.path(),
+        Path::new("lib.c"),
+        &["-falign-functions=64"],
+    )?;
+    let main_text_size = {
+        let buf = fs::read(&main_o)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let text = elf
+            .section_headers
+            .iter()
+            .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+            .unwrap();
+        usize::try_from(text.sh_size).unwrap()
+    };
+    assert_ne!(main_text_size % 64, 0);
+    let exe = tmp_dir.path().join("main");
+    let padding = |options: &[&str]| -> Result<Vec<u8>, Box<dyn Error>> {
+        Linker::from_opts(Opts::parse_from(
+            [
+                "toy-linker",
+                "-i",
+                main_o.to_str().unwrap(),
+                "-i",
+                lib_o.to_str().unwrap(),
+                "-o",
+                exe.to_str().unwrap(),
+            ]
+            .iter()
+            .chain(options),
+        ))
+        .link()?;
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let text = elf
+            .section_headers
+            .iter()
+            .find(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+            .unwrap();
+        let start = usize::try_from(text.sh_offset).unwrap() + main_text_size;
+        Ok(buf[start..align(start, 64)].to_vec())
+    };
+    assert!(padding(&[])?.iter().all(|byte| *byte == 0xcc));
+    assert!(padding(&["--fill=0x90"])?.iter().all(|byte| *byte == 0x90));
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_sort_section() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let sort_o = gcc(tmp_dir.path(), Path::new("sort.c"), &[])?;
+    let exe = tmp_dir.path().join("sort");
+    let order = |options: &[&str]| -> Result<String, Box<dyn Error>> {
+        Linker::from_opts(Opts::parse_from(
+            [
+                "toy-linker",
+                "-i",
+                sort_o.to_str().unwrap(),
+                "-o",
+                exe.to_str().unwrap(),
+            ]
+            .iter()
+            .chain(options),
+        ))
+        .link()?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let mut symbols: Vec<(u64, &str)> = elf
+            .syms
+            .iter()
+            .map(|sym| (sym.st_value, elf.strtab.get_unsafe(sym.st_name).unwrap()))
+            .filter(|(_, name)| ["a", "b", "c"].contains(name))
+            .collect();
+        symbols.sort_unstable();
+        Ok(symbols.into_iter().map(|(_, name)| name).collect())
+    };
+    assert_eq!(order(&[])?, "bca");
+    assert_eq!(order(&["--sort-section=name"])?, "abc");
+    assert_eq!(order(&["--sort-section=alignment"])?, "cab");
+    assert_eq!(order(&["-T", "examples/sort.ld"])?, "cab");
+    // The script's SORT_BY_ALIGNMENT wins over the command line.
+    assert_eq!(
+        order(&["-T", "examples/sort.ld", "--sort-section=name"])?,
+        "cab"
+    );
+    Ok(())
+}
+
+#[test]
+fn link_unique() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let text_sections = |output: &Path, options: &[&str]| -> Result<usize, Box<dyn Error>> {
+        Linker::from_opts(Opts::parse_from(
+            [
+                "toy-linker",
+                "-i",
+                main_o.to_str().unwrap(),
+                "-i",
+                lib_o.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+            ]
+            .iter()
+            .chain(options),
+        ))
+        .link()?;
+        let buf = fs::read(output)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        Ok(elf
+            .section_headers
+            .iter()
+            .filter(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name) == Some(".text"))
+            .count())
+    };
+    let exe = tmp_dir.path().join("main");
+    assert_eq!(text_sections(&exe, &[])?, 1);
+    assert_eq!(text_sections(&exe, &["--unique=.te*"])?, 2);
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let relocatable = tmp_dir.path().join("combined.o");
+    assert_eq!(text_sections(&relocatable, &["-r", "--unique=.text"])?, 2);
+    Ok(())
+}
+
+#[test]
+fn link_symbol_ordering_file() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let exe = tmp_dir.path().join("gc");
+    let ordering_file = tmp_dir.path().join("order.txt");
+    let start_first = |order: &str| -> Result<bool, Box<dyn Error>> {
+        fs::write(&ordering_file, order)?;
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "-i",
+            gc_o.to_str().unwrap(),
+            "--symbol-ordering-file",
+            ordering_file.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let symbol = |name: &str| {
+            elf.syms
+                .iter()
+                .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some(name))
+                .unwrap()
+                .st_value
+        };
+        Ok(symbol("_start") < symbol("unused_function"))
+    };
+    // Without an entry for it a function stays where it is in the input.
+    assert!(!start_first("")?);
+    assert!(start_first("_start\n")?);
+    assert!(start_first("# hot\n_start\nunused_function\n")?);
+    assert!(!start_first("unused_function\n_start\n")?);
+    Ok(())
+}
+
+#[test]
+fn link_compact_file() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::PT_LOAD;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let tls_main_o = gcc(tmp_dir.path(), Path::new("tls_main.c"), &[])?;
+    let tls_o = gcc(tmp_dir.path(), Path::new("tls.c"), &[])?;
+    let exe = tmp_dir.path().join("tls");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        tls_main_o.to_str().unwrap(),
+        "-i",
+        tls_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(32));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let loads: Vec<_> = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .collect();
+    assert!(loads.len() > 1);
+    let page_size = u64::try_from(PAGE_SIZE).unwrap();
+    for phdr in &loads {
+        assert_eq!(phdr.p_offset % page_size, phdr.p_vaddr % page_size);
+    }
+    // Each segment directly follows the previous one in the file.
+    for pair in loads.windows(2) {
+        assert_eq!(pair[1].p_offset, pair[0].p_offset + pair[0].p_filesz);
+    }
+    // The code shares the first page with the headers.
+    assert_eq!(loads[0].p_offset, 0);
+    assert!(section_header(&elf, ".text").sh_offset < page_size);
+    Ok(())
+}
+
+#[test]
+fn link_many_sections() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::section_header::{SHN_LORESERVE, SHN_XINDEX};
+    use std::fmt::Write;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // More sections than fit into e_shnum, _start calls a function in one of the last.
+    let count = usize::try_from(SHN_LORESERVE).unwrap() + 100;
+    let mut asm = format!(
+        ".globl _start\n.section .text._start,\"ax\"\n_start:\ncall f{}\n\
+         movl $42, %edi\nmovq $60, %rax\nsyscall\n",
+        count - 1
+    );
+    for i in 0..count {
+        write!(
+            asm,
+            ".section .text.f{0},\"ax\"\n.globl f{0}\nf{0}:\nret\n",
+            i
+        )
+        .unwrap();
+    }
+    let asm_file = tmp_dir.path().join("many.s");
+    fs::write(&asm_file, asm)?;
+    let many_o = tmp_dir.path().join("many.o");
+    let status = Command::new("gcc")
+        .args([
+            "-c",
+            "-o",
+            many_o.to_str().unwrap(),
+            asm_file.to_str().unwrap(),
+        ])
+        .status()?;
+    assert!(status.success());
+
+    let relocatable = tmp_dir.path().join("combined.o");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-r",
+        "-i",
+        many_o.to_str().unwrap(),
+        "-o",
+        relocatable.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&relocatable)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(elf.header.e_shnum, 0);
+    assert_eq!(u32::from(elf.header.e_shstrndx), SHN_XINDEX);
+    assert!(elf.section_headers.len() > count);
+
+    // Both the assembler's and our extended numbering link.
+    for input in [&many_o, &relocatable] {
+        let exe = tmp_dir.path().join("many");
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+    }
+    Ok(())
+}
+
+#[test]
+fn link_rename_section() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let section_start_o = gcc(tmp_dir.path(), Path::new("section_start.c"), &[])?;
+    let exe = tmp_dir.path().join("section_start");
+    // --section-start only knows the new name.
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        section_start_o.to_str().unwrap(),
+        "--rename-section",
+        ".boot=.init.data",
+        "--section-start=.init.data=0x600000",
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let names: Vec<&str> = elf
+        .section_headers
+        .iter()
+        .map(|sec| elf.shdr_strtab.get_unsafe(sec.sh_name).unwrap())
+        .collect();
+    assert!(names.contains(&".init.data"));
+    assert!(!names.contains(&".boot"));
+    Ok(())
+}
+
+#[test]
+fn link_linkonce() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("linkonce.c"), &["-O2"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("linkonce_lib.c"), &["-O2"])?;
+    let exe = tmp_dir.path().join("linkonce");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    // Both objects call the copy of the first one.
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|sec| !elf.shdr_strtab[sec.sh_name].starts_with(".gnu.linkonce")));
+    Ok(())
+}
+
+#[test]
+fn link_nmagic() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let bss_o = gcc(tmp_dir.path(), Path::new("bss.c"), &[])?;
+    for flag in ["--nmagic", "--omagic"] {
+        let exe = tmp_dir.path().join("bss");
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            flag,
+            "-i",
+            bss_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let loads: Vec<_> = elf
+            .program_headers
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .collect();
+        // Code, read-only data and .bss all share pages.
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].p_flags, PF_R | PF_W | PF_X);
+        assert!(elf
+            .program_headers
+            .iter()
+            .all(|phdr| phdr.p_type != PT_GNU_RELRO));
+    }
+    // The code is writable even when it does not share a page with the data.
+    let exe = tmp_dir.path().join("bss");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--omagic",
+        "--Tdata",
+        "800000",
+        "-i",
+        bss_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let code = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_X != 0)
+        .unwrap();
+    assert_eq!(code.p_flags, PF_R | PF_W | PF_X);
+    Ok(())
+}
+
+#[test]
+fn link_no_rosegment() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--no-rosegment",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let loads: Vec<_> = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .collect();
+    // No segment is only readable.
+    assert!(loads.iter().all(|phdr| phdr.p_flags & (PF_X | PF_W) != 0));
+    assert_eq!(loads[0].p_flags, PF_R | PF_X);
+    let rodata = elf
+        .section_headers
+        .iter()
+        .find(|sec| &elf.shdr_strtab[sec.sh_name] == ".rodata")
+        .unwrap();
+    assert!(loads[0]
+        .vm_range()
+        .contains(&usize::try_from(rodata.sh_addr).unwrap()));
+    Ok(())
+}
+
+#[test]
+fn link_phdr() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let script_o = gcc(tmp_dir.path(), Path::new("script.c"), &[])?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let script = Path::new("examples").join("script.ld");
+    // The script puts the code far from the headers so they need a segment of their own.
+    for args in [
+        [
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+        ],
+        [
+            "-i",
+            script_o.to_str().unwrap(),
+            "-T",
+            script.to_str().unwrap(),
+        ],
+    ] {
+        let exe = tmp_dir.path().join("phdr");
+        let mut opts = vec!["toy-linker", "-o", exe.to_str().unwrap()];
+        opts.extend(args);
+        Linker::from_opts(Opts::parse_from(opts)).link()?;
+        let output = Command::new(&exe).output()?;
+        assert_eq!(output.status.code(), Some(42));
+        let buf = fs::read(&exe)?;
+        let elf = goblin::elf::Elf::parse(&buf)?;
+        let phdr = &elf.program_headers[0];
+        assert_eq!(phdr.p_type, PT_PHDR);
+        assert_eq!(phdr.p_offset, elf.header.e_phoff);
+        assert_eq!(
+            phdr.p_filesz,
+            u64::from(elf.header.e_phnum) * u64::from(elf.header.e_phentsize)
+        );
+        let first = &elf.program_headers[1];
+        assert_eq!(first.p_type, PT_LOAD);
+        assert_eq!(first.p_offset, 0);
+        assert!(first.p_vaddr <= phdr.p_vaddr);
+        assert!(phdr.p_vaddr + phdr.p_filesz <= first.p_vaddr + first.p_filesz);
+    }
+    Ok(())
+}
+
+#[test]
+fn link_no_empty_segments() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Only code and notes, nothing writable.
+    let note_o = gcc(tmp_dir.path(), Path::new("note.c"), &[])?;
+    let exe = tmp_dir.path().join("note");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        note_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(elf
+        .program_headers
+        .iter()
+        .all(|phdr| phdr.p_type != PT_LOAD || phdr.p_memsz > 0 && phdr.p_flags & PF_W == 0));
+    Ok(())
+}
+
+#[test]
+fn link_oformat_binary() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::section_header::*;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let bin = tmp_dir.path().join("main.bin");
+    for (format, out) in [("elf", &exe), ("binary", &bin)] {
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "--oformat",
+            format,
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            lib_o.to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ]))
+        .link()?;
+    }
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let image = fs::read(&bin)?;
+    // Every section is at its address relative to the first one.
+    let sections: Vec<_> = elf
+        .section_headers
+        .iter()
+        .filter(|sec| sec.is_alloc() && sec.sh_type != SHT_NOBITS && sec.sh_size > 0)
+        .collect();
+    let start = sections.iter().map(|sec| sec.sh_addr).min().unwrap();
+    for sec in sections {
+        let offset = usize::try_from(sec.sh_addr - start).unwrap();
+        let range = sec.file_range();
+        assert_eq!(&image[offset..offset + range.len()], &buf[range]);
+    }
+    Ok(())
+}
+
+#[test]
+fn ihex_records() {
+    // A segment crossing into the next 64KiB gets a second extended address record.
+    let data: Vec<u8> = (0..20).collect();
+    let image = ihex_image(&[(0x1_fff8, &data)], 0x1_fff8).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&image).unwrap(),
+        ":020000040001F9\n\
+         :08FFF8000001020304050607E5\n\
+         :020000040002F8\n\
+         :0C00000008090A0B0C0D0E0F1011121352\n\
+         :040000050001FFF8FF\n\
+         :00000001FF\n"
+    );
+}
+
+#[test]
+fn srec_records() {
+    // Checked against objcopy -O srec, which writes no count record.
+    let data: Vec<u8> = (0..20).collect();
+    let image = srec_image(&[(0x1234, &data)], 0x1234).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&image).unwrap(),
+        "S0030000FC\n\
+         S1131234000102030405060708090A0B0C0D0E0F2E\n\
+         S1071244101112135C\n\
+         S5030002FA\n\
+         S9031234B6\n"
+    );
+}
+
+#[test]
+fn link_elf32() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let i386_o = gcc(tmp_dir.path(), Path::new("i386.c"), &["-m32", "-fno-pic"])?;
+    let exe = tmp_dir.path().join("i386");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        i386_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(!elf.is_64);
+    assert_eq!(elf.header.e_machine, goblin::elf::header::EM_386);
+    assert_eq!(usize::from(elf.header.e_phentsize), 32);
+    let output = Command::new(exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_target() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let i386_o = gcc(tmp_dir.path(), Path::new("i386.c"), &["-m32", "-fno-pic"])?;
+    let exe = tmp_dir.path().join("i386");
+    let link = |target: &str| {
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "--target",
+            target,
+            "-i",
+            i386_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()
+    };
+    link("i386")?;
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    match link("x86_64") {
+        Err(LinkError::MalformedInput { reason, .. }) => {
+            assert_eq!(reason, "machine 386 does not match X86_64")
+        }
+        result => panic!("Expected a machine mismatch: {:?}", result),
+    }
+    assert!(Opts::try_parse_from(["toy-linker", "--target", "mips", "-o", "out"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn link_arm_interworking() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Big-endian code is big-endian in the executable too.
+    for (triple, endian) in [
+        ("armv7-none-eabi", scroll::LE),
+        ("armebv7-none-eabi", scroll::BE),
+    ] {
+        let arm_o = llvm_mc(tmp_dir.path(), Path::new("arm_interworking.s"), triple)?;
+        let exe = tmp_dir.path().join(triple);
+        link_objects(&[&arm_o], &exe, &[])?;
+        check_arm_interworking(&fs::read(&exe)?, endian)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn link_armeb() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::dynamic::*;
+    use goblin::elf::header::*;
+    use goblin::elf::program_header::*;
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let triple = "armebv7-none-eabi";
+    let arm_o = llvm_mc(tmp_dir.path(), Path::new("arm_interworking.s"), triple)?;
+    let got_o = llvm_mc(tmp_dir.path(), Path::new("arm_got.s"), triple)?;
+    let exe = tmp_dir.path().join("armeb");
+    link_objects(&[&arm_o, &got_o], &exe, &["--rpath", "/opt/lib"])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    // Every field is read back big-endian, little-endian values would not match.
+    let half = |offset: usize| buf.pread_with::<u16>(offset, scroll::BE).unwrap();
+    let word = |offset: usize| buf.pread_with::<u32>(offset, scroll::BE).unwrap();
+    let words = |offset: usize, count: usize| -> Vec<u32> {
+        (0..count).map(|i| word(offset + 4 * i)).collect()
+    };
+    let symbol = |name: &str| {
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some(name))
+            .unwrap();
+        u32::try_from(sym.st_value).unwrap()
+    };
+    let section = |name: &str| {
+        let sec = section_header(&elf, name);
+        (
+            usize::try_from(sec.sh_offset).unwrap(),
+            u32::try_from(sec.sh_addr).unwrap(),
+        )
+    };
+
+    // The ELF header of a 32 bit big-endian EABI version 5 executable
+    assert_eq!(
+        buf[..16],
+        [
+            0x7f,
+            b'E',
+            b'L',
+            b'F',
+            ELFCLASS32,
+            ELFDATA2MSB,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0
+        ]
+    );
+    assert_eq!([half(16), half(18)], [ET_EXEC, EM_ARM]);
+    let phnum = elf.program_headers.len();
+    let shnum = elf.section_headers.len();
+    assert_eq!(
+        words(20, 5),
+        [
+            1,
+            symbol("_start"),
+            52,
+            u32::try_from(elf.header.e_shoff)?,
+            0x0500_0000
+        ]
+    );
+    assert_eq!(
+        [half(40), half(42), half(44), half(46), half(48), half(50)],
+        [
+            52,
+            32,
+            u16::try_from(phnum)?,
+            40,
+            u16::try_from(shnum)?,
+            u16::try_from(shnum - 1)?
+        ]
+    );
+
+    // The program headers start with PT_PHDR, which covers all of them.
+    let headers: Vec<Vec<u32>> = (0..phnum).map(|i| words(52 + 32 * i, 8)).collect();
+    assert_eq!(
+        headers[0],
+        [
+            PT_PHDR,
+            52,
+            0x40_0034,
+            0x40_0034,
+            u32::try_from(32 * phnum)?,
+            u32::try_from(32 * phnum)?,
+            PF_R,
+            4
+        ]
+    );
+    for (header, ph) in headers.iter().zip(&elf.program_headers) {
+        let expected = [
+            u64::from(ph.p_type),
+            ph.p_offset,
+            ph.p_vaddr,
+            ph.p_paddr,
+            ph.p_filesz,
+            ph.p_memsz,
+            u64::from(ph.p_flags),
+            ph.p_align,
+        ];
+        assert_eq!(
+            header
+                .iter()
+                .map(|field| u64::from(*field))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+    let (dynamic_offset, dynamic_address) = section(".dynamic");
+    let dynamic_header = headers
+        .iter()
+        .find(|header| header[0] == PT_DYNAMIC)
+        .unwrap();
+    assert_eq!(
+        dynamic_header[..4],
+        [
+            PT_DYNAMIC,
+            u32::try_from(dynamic_offset)?,
+            dynamic_address,
+            dynamic_address
+        ]
+    );
+    let (_, text_address) = section(".text");
+    assert!(headers.iter().any(|header| header[0] == PT_LOAD
+        && header[6] == PF_R | PF_X
+        && header[2] <= text_address
+        && text_address < header[2] + header[5]));
+
+    // Tag and value of each entry of .dynamic
+    let (dynstr_offset, dynstr_address) = section(".dynstr");
+    assert_eq!(
+        words(dynamic_offset, 8),
+        [
+            u32::try_from(DT_RUNPATH)?,
+            1,
+            u32::try_from(DT_STRTAB)?,
+            dynstr_address,
+            u32::try_from(DT_STRSZ)?,
+            10,
+            u32::try_from(DT_NULL)?,
+            0
+        ]
+    );
+    assert_eq!(&buf[dynstr_offset..dynstr_offset + 10], b"\0/opt/lib\0");
+
+    // The GOT entry holds the address of `table`, the literal the offset of the entry.
+    let (got_offset, got_address) = section(".got");
+    assert_eq!(words(got_offset, 1), [symbol("table")]);
+    let (text_offset, _) = section(".text");
+    let literal = symbol("got_offset");
+    let literal_offset = text_offset + usize::try_from(literal - text_address)?;
+    assert_eq!(word(literal_offset), got_address - literal);
+    Ok(())
+}
+
+// The calls and addresses between ARM and Thumb code of arm_interworking.s in `buf`.
+fn check_arm_interworking(buf: &[u8], endian: scroll::Endian) -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    let elf = goblin::elf::Elf::parse(buf)?;
+    assert_eq!(elf.header.e_machine, goblin::elf::header::EM_ARM);
+    let symbol = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| elf.strtab.get_unsafe(sym.st_name) == Some(name))
+            .unwrap()
+            .st_value
+    };
+    let text = section_header(&elf, ".text");
+    let read = |address: u64, size: usize| {
+        let offset = usize::try_from(address - text.sh_addr + text.sh_offset).unwrap();
+        &buf[offset..offset + size]
+    };
+    let thumb_func = symbol("thumb_func");
+    assert_eq!(thumb_func & 1, 1);
+    let thumb_func = thumb_func & !1;
+    let arm_func = symbol("arm_func");
+    let thumb_label = symbol("thumb_label");
+
+    // ARM BL to Thumb code becomes BLX, which keeps the halfword of the target in H.
+    let start = symbol("_start");
+    let arm_target = |address: u64| {
+        let insn = read(address, 4).pread_with::<u32>(0, endian).unwrap();
+        let mut imm = i64::from((insn << 8) as i32 >> 6);
+        if insn >> 28 == 0xf {
+            imm += i64::from((insn >> 23) & 2);
+        }
+        (insn >> 24, (address + 8).wrapping_add(imm as u64))
+    };
+    assert_eq!(arm_target(start), (0xfa, thumb_func));
+    assert_eq!(arm_target(start + 4), (0xeb, arm_func));
+
+    // Thumb BL to ARM code becomes BLX, which clears bit 12 of the second halfword and
+    // counts from the PC rounded down to 4 bytes.
+    let thumb_target = |address: u64| {
+        let code = read(address, 4);
+        let hi = u32::from(code.pread_with::<u16>(0, endian).unwrap());
+        let lo = u32::from(code.pread_with::<u16>(2, endian).unwrap());
+        let s = (hi >> 10) & 1;
+        let i1 = !((lo >> 13) ^ s) & 1;
+        let i2 = !((lo >> 11) ^ s) & 1;
+        let imm = s << 24 | i1 << 23 | i2 << 22 | (hi & 0x3ff) << 12 | (lo & 0x7ff) << 1;
+        let imm = i64::from((imm << 7) as i32 >> 7);
+        let blx = lo & 0x1000 == 0;
+        let pc = if blx { (address + 4) & !3 } else { address + 4 };
+        (blx, pc.wrapping_add(imm as u64))
+    };
+    assert_eq!(thumb_target(thumb_func), (true, arm_func));
+    assert_eq!(thumb_target(thumb_func + 4), (false, thumb_label));
+
+    // R_ARM_ABS32 sets the Thumb bit of functions and of labels after $t.
+    let data = section_header(&elf, ".data");
+    let table = usize::try_from(symbol("table") - data.sh_addr + data.sh_offset).unwrap();
+    let words: Vec<u64> = (0..4)
+        .map(|i| u64::from(buf.pread_with::<u32>(table + 4 * i, endian).unwrap()))
+        .collect();
+    assert_eq!(
+        words,
+        [
+            thumb_func | 1,
+            arm_func,
+            thumb_label | 1,
+            symbol("arm_label")
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn link_map() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let exe = tmp_dir.path().join("gc");
+    let map = tmp_dir.path().join("gc.map");
+    Linker::from_opts(Opts::parse_from(normalize_args(
+        [
+            "toy-linker",
+            "--gc-sections",
+            &format!("-Map={}", map.to_str().unwrap()),
+            "-i",
+            gc_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string()),
+    )))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let map = fs::read_to_string(&map)?;
+    let (discarded, memory_map) = map.split_once("Memory map").unwrap();
+    assert!(discarded.contains(" .text.unused_function\n"));
+    assert!(!memory_map.contains("unused"));
+    let start = format!("0x{:016x}                _start\n", elf.entry);
+    assert!(memory_map.contains(&start));
+    assert!(memory_map.contains(" .data.used     0x"));
+    Ok(())
+}
+
+#[test]
+fn link_layout_json() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let gc_o = gcc(
+        tmp_dir.path(),
+        Path::new("gc.c"),
+        &["-ffunction-sections", "-fdata-sections"],
+    )?;
+    let exe = tmp_dir.path().join("gc");
+    let json = tmp_dir.path().join("gc.json");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--gc-sections",
+        "--layout-json",
+        json.to_str().unwrap(),
+        "-i",
+        gc_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let json = fs::read_to_string(&json)?;
+    let (layout, discarded) = json.split_once("\"discarded\"").unwrap();
+    assert!(discarded.contains("\"name\": \".text.unused_function\""));
+    assert!(!layout.contains("unused"));
+    let start = format!("{{\"name\": \"_start\", \"address\": {}}}", elf.entry);
+    assert!(layout.contains(&start));
+    assert!(layout.contains("\"flags\": \"r-x\""));
+    Ok(())
+}
+
+#[test]
+fn link_reproducible() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    // Every link iterates its hash maps in a different order.
+    let tls_o = gcc(tmp_dir.path(), Path::new("tls.c"), &["-g", "-fPIC"])?;
+    let main_o = gcc(tmp_dir.path(), Path::new("tls_main.c"), &["-g"])?;
+    std::env::set_var("SOURCE_DATE_EPOCH", "0");
+    let mut outputs = Vec::new();
+    for i in 0..2 {
+        let exe = tmp_dir.path().join(format!("tls{}", i));
+        let map = tmp_dir.path().join(format!("tls{}.map", i));
+        Linker::from_opts(Opts::parse_from([
+            "toy-linker",
+            "--build-id=uuid",
+            "--emit-relocs",
+            "--Map",
+            map.to_str().unwrap(),
+            "-i",
+            main_o.to_str().unwrap(),
+            "-i",
+            tls_o.to_str().unwrap(),
+            "-o",
+            exe.to_str().unwrap(),
+        ]))
+        .link()?;
+        outputs.push((fs::read(&exe)?, fs::read(&map)?));
+    }
+    assert!(outputs[0] == outputs[1]);
+    Ok(())
+}
+
+#[test]
+fn failed_link_keeps_output() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    fs::write(&exe, b"previous")?;
+    // lib.o is missing so extern_call is undefined.
+    let result = Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link();
+    match result {
+        Err(LinkError::UndefinedSymbol { symbol }) => assert_eq!(symbol, "extern_call"),
+        result => panic!("Expected an undefined symbol error: {:?}", result),
+    }
+    assert_eq!(fs::read(&exe)?, b"previous");
+    // A successful link replaces it with an executable.
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    use std::os::unix::fs::PermissionsExt;
+    assert_eq!(fs::metadata(&exe)?.permissions().mode() & 0o777, 0o755);
+    assert!(!tmp_dir.path().join("main.tmp").exists());
+    Ok(())
+}
+
+#[test]
+fn link_osabi_e_flags() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::header::EI_OSABI;
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--osabi",
+        "freebsd",
+        "--e-flags",
+        "0x5000400",
+        "-i",
+        main_o.to_str().unwrap(),
+        "-i",
+        lib_o.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(elf.header.e_ident[EI_OSABI], 9);
+    assert_eq!(elf.header.e_flags, 0x500_0400);
+    // Linux does not look at either.
+    let output = Command::new(&exe).output()?;
+    assert_eq!(output.status.code(), Some(42));
+    Ok(())
+}
+
+#[test]
+fn link_pe_executable() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+
+    let main_obj = coff(tmp_dir.path(), Path::new("pe.c"), &["-mcmodel=large"])?;
+    let lib_obj = coff(tmp_dir.path(), Path::new("pe_lib.c"), &[])?;
+    let exe = tmp_dir.path().join("main.exe");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "--import",
+        "kernel32.dll:ExitProcess",
+        "-i",
+        main_obj.to_str().unwrap(),
+        "-i",
+        lib_obj.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]))
+    .link()?;
+    let buf = fs::read(&exe)?;
+    let pe = goblin::pe::PE::parse(&buf)?;
+    assert!(pe.is_64);
+    assert_eq!(pe.image_base, usize::try_from(PE_IMAGE_BASE).unwrap());
+    assert_eq!(pe.imports.len(), 1);
+    assert_eq!(pe.imports[0].dll, "kernel32.dll");
+    assert_eq!(pe.imports[0].name, "ExitProcess");
+    let section = |name: &str| {
+        pe.sections
+            .iter()
+            .find(|section| section.name().unwrap() == name)
+            .unwrap()
+    };
+    let rva_offset = |rva: usize| {
+        let text = pe
+            .sections
+            .iter()
+            .find(|s| {
+                let start = usize::try_from(s.virtual_address).unwrap();
+                (start..start + usize::try_from(s.virtual_size).unwrap()).contains(&rva)
+            })
+            .unwrap();
+        rva - usize::try_from(text.virtual_address).unwrap()
+            + usize::try_from(text.pointer_to_raw_data).unwrap()
+    };
+    // `pointer` holds the absolute address of `value`.
+    let data = section(".data");
+    let data_offset = usize::try_from(data.pointer_to_raw_data).unwrap();
+    let pointer = (data_offset..data_offset + usize::try_from(data.virtual_size).unwrap())
+        .step_by(8)
+        .map(|offset| buf.pread_with::<u64>(offset, scroll::LE).unwrap())
+        .find(|word| *word >= PE_IMAGE_BASE)
+        .unwrap();
+    let value_rva = usize::try_from(pointer - PE_IMAGE_BASE).unwrap();
+    assert_eq!(
+        buf.pread_with::<u32>(rva_offset(value_rva), scroll::LE)?,
+        40
+    );
+    // The call in `fail` goes to a thunk jumping through the import address table.
+    let text = section(".text");
+    let text_offset = usize::try_from(text.pointer_to_raw_data).unwrap();
+    let code = &buf[text_offset..text_offset + usize::try_from(text.virtual_size).unwrap()];
+    let thunk = code.windows(2).position(|w| w == [0xff, 0x25]).unwrap();
+    let displacement: i32 = code.pread_with(thunk + 2, scroll::LE)?;
+    let target = i64::from(text.virtual_address)
+        + i64::try_from(thunk).unwrap()
+        + 6
+        + i64::from(displacement);
+    assert_eq!(usize::try_from(target).unwrap(), pe.imports[0].offset);
+    // The absolute addresses get rebased when the image is loaded elsewhere.
+    let reloc = section(".reloc");
+    let relocs = &buf[usize::try_from(reloc.pointer_to_raw_data).unwrap()..];
+    let block_size: u32 = relocs.pread_with(4, scroll::LE)?;
+    assert!(block_size > 8);
+    let entry: u16 = relocs.pread_with(8, scroll::LE)?;
+    assert_eq!(entry >> 12, IMAGE_REL_BASED_DIR64);
+    Ok(())
+}
+
+#[test]
+fn link_macho_executable() -> Result<(), Box<dyn Error>> {
+    use goblin::mach::load_command::CommandVariant;
+    use scroll::Pread;
+    use std::path::Path;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+    let link = |files: &[&str], triple: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut args = vec!["toy-linker".to_string()];
+        for file in files {
+            let obj = llvm_mc(tmp_dir.path(), Path::new(file), triple)?;
+            args.push("-i".to_string());
+            args.push(obj.to_str().unwrap().to_string());
+        }
+        let exe = tmp_dir.path().join(triple);
+        args.push("-o".to_string());
+        args.push(exe.to_str().unwrap().to_string());
+        Linker::from_opts(Opts::parse_from(args)).link()?;
+        Ok(fs::read(&exe)?)
+    };
+    // Returns the symbol addresses and the contents of __text and __data with their
+    // addresses.
+    type Section = (u64, Vec<u8>);
+    let parse =
+        |buf: &[u8]| -> Result<(HashMap<String, u64>, Section, Section), goblin::error::Error> {
+            let macho = goblin::mach::MachO::parse(buf, 0)?;
+            assert_eq!(macho.header.filetype, goblin::mach::header::MH_EXECUTE);
+            let entryoff = macho
+                .load_commands
+                .iter()
+                .find_map(|cmd| match cmd.command {
+                    CommandVariant::Main(main) => Some(main.entryoff),
+                    _ => None,
+                })
+                .unwrap();
+            let symbols: HashMap<_, _> = macho
+                .symbols()
+                .map(|sym| sym.map(|(name, nlist)| (name.to_string(), nlist.n_value)))
+                .collect::<Result<_, _>>()?;
+            assert_eq!(symbols["_main"], MACHO_TEXT_BASE + entryoff);
+            let mut sections = HashMap::new();
+            for segment in macho.segments.iter() {
+                for section in segment {
+                    let (section, data) = section?;
+                    sections.insert(section.name()?.to_string(), (section.addr, data.to_vec()));
+                }
+            }
+            let text = sections.remove("__text").unwrap();
+            let data = sections.remove("__data").unwrap();
+            Ok((symbols, text, data))
+        };
+
+    let buf = link(
+        &["macho_x86_64.s", "macho_helper.s"],
+        "x86_64-apple-macos11",
+    )?;
+    let (symbols, (text_address, text), (data_address, data)) = parse(&buf)?;
+    assert_eq!(symbols["_main"], text_address);
+    let rip_relative = |offset: usize| -> Result<u64, Box<dyn Error>> {
+        let disp = text.pread_with::<i32>(offset + 3, scroll::LE)?;
+        Ok(u64::try_from(
+            i64::try_from(text_address).unwrap()
+                + i64::try_from(offset + 7).unwrap()
+                + i64::from(disp),
+        )
+        .unwrap())
+    };
+    // leaq _value(%rip), %rax
+    assert_eq!(rip_relative(0)?, data_address);
+    // movq _counter@GOTPCREL(%rip), %rcx loads from a GOT entry holding _counter.
+    let got_entry = rip_relative(7)?;
+    let got = buf.pread_with::<u64>(
+        usize::try_from(got_entry - MACHO_TEXT_BASE).unwrap(),
+        scroll::LE,
+    )?;
+    assert_eq!(got, symbols["_counter"]);
+    // callq _helper
+    let call = text.pread_with::<i32>(19, scroll::LE)?;
+    assert_eq!(text[23 + usize::try_from(call).unwrap()], 0xb8);
+    // .quad _value+4 and .quad _pointer - _value
+    assert_eq!(data.pread_with::<u32>(0, scroll::LE)?, 42);
+    assert_eq!(data.pread_with::<u64>(4, scroll::LE)?, data_address + 4);
+    assert_eq!(data.pread_with::<u64>(12, scroll::LE)?, 4);
+
+    let buf = link(&["macho_arm64.s"], "arm64-apple-macos11")?;
+    let (symbols, (text_address, text), (data_address, _)) = parse(&buf)?;
+    assert_eq!(symbols["_main"], text_address);
+    let insn = |i: usize| text.pread_with::<u32>(i * 4, scroll::LE).unwrap();
+    // adrp x8, _value@PAGE; add x8, x8, _value@PAGEOFF
+    let page = |insn: u32| {
+        let imm = u64::from((insn >> 29 & 3) | (insn >> 5 & 0x7_ffff) << 2);
+        (text_address & !0xfff) + (imm << 12)
+    };
+    assert_eq!(
+        page(insn(0)) + u64::from(insn(1) >> 10 & 0xfff),
+        data_address
+    );
+    // b _helper
+    assert_eq!(insn(5), 0x1400_0001);
+    Ok(())
+}
+
+#[test]
+fn link_wasm_module() -> Result<(), Box<dyn Error>> {
+    use std::path::Path;
+    use std::process::Command;
+    use tempdir::TempDir;
+    let tmp_dir = TempDir::new("test")?;
+    let main_obj = llvm_mc(tmp_dir.path(), Path::new("wasm.s"), "wasm32")?;
+    let helper_obj = llvm_mc(tmp_dir.path(), Path::new("wasm_helper.s"), "wasm32")?;
+    let module = tmp_dir.path().join("main.wasm");
+    Linker::from_opts(Opts::parse_from([
+        "toy-linker",
+        "-i",
+        main_obj.to_str().unwrap(),
+        "-i",
+        helper_obj.to_str().unwrap(),
+        "-o",
+        module.to_str().unwrap(),
+    ]))
+    .link()?;
+    // Run _start with an import that collects what gets printed.
+    let script = "const out = [];
+        WebAssembly.instantiate(require('fs').readFileSync(process.argv[1]),
+                                {env: {print: x => out.push(x)}})
+            .then(({instance}) => {
+                instance.exports._start();
+                console.log(out.join(' '));
+            });";
+    let output = Command::new("node")
+        .args(["-e", script, module.to_str().unwrap()])
+        .output()?;
+    assert!(output.status.success());
+    // helper(value) and helper through a function pointer
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "43 8\n");
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! The phases of a link as `tracing` spans, so a program using the library sees them
+//! with its own subscriber and `--log-level` prints them to stderr. With `--time-report`
+//! or `--time-trace` the spans are also recorded with their wall-clock and CPU times.
+
+use crate::emit::json_string;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use tracing::level_filters::LevelFilter;
+use tracing::span::EnteredSpan;
+
+thread_local! {
+    // The number of spans entered on this thread and not left yet
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static RECORDING: RefCell<Option<Report>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.parse().map_err(|_| format!("invalid --log-level: {}", s))
+}
+
+// Enter a debug span for a phase of the link, until the returned guard is dropped.
+macro_rules! span {
+    ($name:literal) => {
+        crate::trace::enter($name, tracing::debug_span!($name))
+    };
+}
+pub(crate) use span;
+
+pub(crate) struct Span {
+    name: &'static str,
+    start: Instant,
+    cpu_start: Duration,
+    _entered: EnteredSpan,
+}
+
+// Disabled spans have no name, so it is passed separately for the recording.
+pub(crate) fn enter(name: &'static str, span: tracing::Span) -> Span {
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    Span {
+        name,
+        start: Instant::now(),
+        cpu_start: cpu_time(),
+        _entered: span.entered(),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            depth.get()
+        });
+        RECORDING.with(|recording| {
+            if let Some(report) = recording.borrow_mut().as_mut() {
+                report.phases.push(Phase {
+                    name: self.name,
+                    depth,
+                    start: self.start.saturating_duration_since(report.start),
+                    wall: self.start.elapsed(),
+                    cpu: cpu_time().saturating_sub(self.cpu_start),
+                });
+            }
+        });
+    }
+}
+
+// The CPU time this thread used so far, links run on one thread.
+fn cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return Duration::default();
+    }
+    Duration::new(
+        u64::try_from(time.tv_sec).unwrap_or(0),
+        u32::try_from(time.tv_nsec).unwrap_or(0),
+    )
+}
+
+#[derive(Debug)]
+struct Phase {
+    name: &'static str,
+    depth: usize,
+    // Since the recording started
+    start: Duration,
+    wall: Duration,
+    cpu: Duration,
+}
+
+// The spans left on this thread while recording, with counts of what the link processed.
+#[derive(Debug)]
+pub(crate) struct Report {
+    start: Instant,
+    phases: Vec<Phase>,
+    counts: Vec<(&'static str, usize)>,
+}
+
+// Record the spans on this thread from now on.
+pub(crate) fn start_recording() {
+    RECORDING.with(|recording| {
+        *recording.borrow_mut() = Some(Report {
+            start: Instant::now(),
+            phases: Vec::new(),
+            counts: Vec::new(),
+        })
+    });
+}
+
+pub(crate) fn finish_recording() -> Option<Report> {
+    RECORDING.with(|recording| recording.borrow_mut().take())
+}
+
+// Add a count to the report if recording.
+pub(crate) fn count(name: &'static str, value: usize) {
+    RECORDING.with(|recording| {
+        if let Some(report) = recording.borrow_mut().as_mut() {
+            report.counts.push((name, value));
+        }
+    });
+}
+
+impl Report {
+    // Spans end before the ones they are in, so sorting by start time puts every span
+    // right after its parent.
+    fn sorted_phases(&self) -> Vec<&Phase> {
+        let mut phases: Vec<&Phase> = self.phases.iter().collect();
+        phases.sort_by_key(|phase| (phase.start, phase.depth));
+        phases
+    }
+    // A table of the phases, nested ones indented, followed by the counts.
+    pub(crate) fn table(&self) -> String {
+        let mut out = format!("{:<20} {:>12} {:>12}\n", "phase", "wall", "cpu");
+        for phase in self.sorted_phases() {
+            let name = format!("{:indent$}{}", "", phase.name, indent = 2 * phase.depth);
+            writeln!(
+                out,
+                "{:<20} {:>12} {:>12}",
+                name,
+                format!("{:.3?}", phase.wall),
+                format!("{:.3?}", phase.cpu)
+            )
+            .unwrap();
+        }
+        for (name, value) in &self.counts {
+            writeln!(out, "{:<20} {:>12}", name, value).unwrap();
+        }
+        out
+    }
+    // The phases as complete events of the Chrome trace event format, which
+    // chrome://tracing and Perfetto show as a flame graph, with the counts as metadata.
+    pub(crate) fn chrome_trace(&self) -> String {
+        let events: Vec<String> = self
+            .sorted_phases()
+            .iter()
+            .map(|phase| {
+                format!(
+                    "{{\"name\": {}, \"ph\": \"X\", \"pid\": 1, \"tid\": 1, \"ts\": {}, \"dur\": {}, \"args\": {{\"cpu_us\": {}}}}}",
+                    json_string(phase.name),
+                    phase.start.as_micros(),
+                    phase.wall.as_micros(),
+                    phase.cpu.as_micros()
+                )
+            })
+            .collect();
+        let counts: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(name, value)| format!("{}: {}", json_string(name), value))
+            .collect();
+        format!(
+            "{{\n  \"traceEvents\": [{}],\n  \"otherData\": {{{}}}\n}}\n",
+            events.join(", "),
+            counts.join(", ")
+        )
+    }
+}
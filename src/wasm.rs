@@ -0,0 +1,1069 @@
+//! Linking wasm32 objects into a WebAssembly module.
+
+use crate::error::LinkError;
+use crate::resolve::{
+    ObjectInput, ObjectRelocation, ObjectSection, ObjectSymbol, RelocationTarget, SymbolDef,
+};
+use crate::{align, Opts};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+const WASM_MAGIC: &[u8] = b"\0asm\x01\0\0\0";
+
+// Where lld puts the data by default, the first KiB is left free to catch null pointers.
+const WASM_GLOBAL_BASE: usize = 1024;
+
+const WASM_STACK_SIZE: usize = 64 * 1024;
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+// Section ids
+const WASM_SEC_CUSTOM: u8 = 0;
+
+const WASM_SEC_TYPE: u8 = 1;
+
+const WASM_SEC_IMPORT: u8 = 2;
+
+const WASM_SEC_FUNCTION: u8 = 3;
+
+const WASM_SEC_TABLE: u8 = 4;
+
+const WASM_SEC_MEMORY: u8 = 5;
+
+const WASM_SEC_GLOBAL: u8 = 6;
+
+const WASM_SEC_EXPORT: u8 = 7;
+
+const WASM_SEC_ELEM: u8 = 9;
+
+const WASM_SEC_CODE: u8 = 10;
+
+const WASM_SEC_DATA: u8 = 11;
+
+// Import and export kinds
+const WASM_EXTERNAL_FUNCTION: u8 = 0;
+
+const WASM_EXTERNAL_TABLE: u8 = 1;
+
+const WASM_EXTERNAL_MEMORY: u8 = 2;
+
+const WASM_EXTERNAL_GLOBAL: u8 = 3;
+
+// Subsections of the linking section
+const WASM_SEGMENT_INFO: u8 = 5;
+
+const WASM_INIT_FUNCS: u8 = 6;
+
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+// Symbol kinds
+const WASM_SYMBOL_TYPE_FUNCTION: u8 = 0;
+
+const WASM_SYMBOL_TYPE_DATA: u8 = 1;
+
+const WASM_SYMBOL_TYPE_GLOBAL: u8 = 2;
+
+const WASM_SYMBOL_TYPE_SECTION: u8 = 3;
+
+const WASM_SYMBOL_TYPE_TABLE: u8 = 5;
+
+// Symbol flags
+const WASM_SYM_BINDING_WEAK: u32 = 0x1;
+
+const WASM_SYM_BINDING_LOCAL: u32 = 0x2;
+
+const WASM_SYM_UNDEFINED: u32 = 0x10;
+
+const WASM_SYM_EXPORTED: u32 = 0x20;
+
+const WASM_SYM_EXPLICIT_NAME: u32 = 0x40;
+
+// Relocation types
+const R_WASM_FUNCTION_INDEX_LEB: u32 = 0;
+
+const R_WASM_TABLE_INDEX_SLEB: u32 = 1;
+
+const R_WASM_TABLE_INDEX_I32: u32 = 2;
+
+const R_WASM_MEMORY_ADDR_LEB: u32 = 3;
+
+const R_WASM_MEMORY_ADDR_SLEB: u32 = 4;
+
+const R_WASM_MEMORY_ADDR_I32: u32 = 5;
+
+const R_WASM_TYPE_INDEX_LEB: u32 = 6;
+
+const R_WASM_GLOBAL_INDEX_LEB: u32 = 7;
+
+const R_WASM_GLOBAL_INDEX_I32: u32 = 13;
+
+const R_WASM_TABLE_NUMBER_LEB: u32 = 20;
+
+pub(crate) fn is_wasm_object(buf: &[u8]) -> bool {
+    buf.starts_with(WASM_MAGIC)
+}
+
+// Whether the relocation type has an addend, which also includes the 64 bit memory
+// addresses and the offsets into debug info.
+fn wasm_reloc_has_addend(r_type: u32) -> bool {
+    matches!(
+        r_type,
+        3 | 4 | 5 | 8 | 9 | 11 | 14 | 15 | 16 | 17 | 21 | 22 | 23 | 25
+    )
+}
+
+fn read_wasm_u32(buf: &[u8], offset: &mut usize) -> Result<u32, LinkError> {
+    let value = scroll::Uleb128::read(buf, offset)?;
+    u32::try_from(value)
+        .map_err(|_| LinkError::malformed("", format!("LEB128 too large: {}", value)))
+}
+
+fn read_wasm_usize(buf: &[u8], offset: &mut usize) -> Result<usize, LinkError> {
+    Ok(usize::try_from(read_wasm_u32(buf, offset)?).unwrap())
+}
+
+fn read_wasm_bytes<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a [u8], LinkError> {
+    let len = read_wasm_usize(buf, offset)?;
+    let bytes = buf
+        .get(*offset..*offset + len)
+        .ok_or_else(|| LinkError::malformed("", format!("Truncated wasm file at {:#x}", offset)))?;
+    *offset += len;
+    Ok(bytes)
+}
+
+fn read_wasm_name<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, LinkError> {
+    let bytes = read_wasm_bytes(buf, offset)?;
+    std::str::from_utf8(bytes).map_err(|err| LinkError::malformed("", err))
+}
+
+// Skip a constant expression like the initializer of a global.
+fn skip_wasm_init_expr(buf: &[u8], offset: &mut usize) -> Result<(), LinkError> {
+    loop {
+        let opcode = buf
+            .get(*offset)
+            .ok_or_else(|| LinkError::malformed("", "Truncated init expression"))?;
+        *offset += 1;
+        match opcode {
+            // end
+            0x0b => return Ok(()),
+            // i32.const, i64.const
+            0x41 | 0x42 => {
+                scroll::Sleb128::read(buf, offset)?;
+            }
+            // f32.const, f64.const
+            0x43 => *offset += 4,
+            0x44 => *offset += 8,
+            // global.get, ref.func
+            0x23 | 0xd2 => {
+                read_wasm_u32(buf, offset)?;
+            }
+            // ref.null
+            0xd0 => *offset += 1,
+            _ => {
+                return Err(LinkError::malformed(
+                    "",
+                    format!("Unsupported opcode in init expression: {:#x}", opcode),
+                ))
+            }
+        }
+    }
+}
+
+fn write_wasm_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = u8::try_from(value & 0x7f).unwrap();
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_wasm_i32(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = u8::try_from(value & 0x7f).unwrap();
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_wasm_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_wasm_u32(out, u32::try_from(bytes.len()).unwrap());
+    out.extend_from_slice(bytes);
+}
+
+// Relocated LEB128 fields are padded to 5 bytes so they can be patched in place.
+fn patch_wasm_leb(
+    buf: &mut [u8],
+    offset: usize,
+    value: i64,
+    signed: bool,
+) -> Result<(), LinkError> {
+    let fits = if signed {
+        i32::try_from(value).is_ok()
+    } else {
+        u32::try_from(value).is_ok()
+    };
+    if !fits {
+        return Err(LinkError::malformed(
+            "",
+            format!("relocation truncated to fit at {:#x}: {:#x}", offset, value),
+        ));
+    }
+    let field = buf
+        .get_mut(offset..offset.saturating_add(5))
+        .ok_or_else(|| {
+            LinkError::malformed("", format!("Relocated field at {:#x} is truncated", offset))
+        })?;
+    for (i, out) in field.iter_mut().enumerate() {
+        let byte = u8::try_from((value >> (7 * i)) & 0x7f).unwrap();
+        *out = if i < 4 { byte | 0x80 } else { byte };
+    }
+    Ok(())
+}
+
+// A section of the output module, its contents are prefixed with their size.
+fn write_wasm_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_wasm_bytes(out, contents);
+}
+
+/// The signature of a wasm function as the encoded parameter and result types.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WasmFuncType<'a> {
+    params: &'a [u8],
+    results: &'a [u8],
+}
+
+/// A function imported by a wasm module.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WasmImport<'a> {
+    module: &'a str,
+    pub(crate) field: &'a str,
+    type_index: u32,
+}
+
+/// What a wasm object file has in addition to the sections and symbols in
+/// `ObjectInput`.
+pub(crate) struct WasmFile<'a> {
+    types: Vec<WasmFuncType<'a>>,
+    // The type of the section of each defined function
+    function_types: HashMap<usize, u32>,
+    // The imports of undefined function symbols by symbol index
+    pub(crate) imports: Vec<(usize, WasmImport<'a>)>,
+    // The symbols with WASM_SYM_EXPORTED
+    exports: Vec<usize>,
+}
+
+/// The wasm object files of a link. Functions, globals and data segments are sections
+/// in `object`, whose segment is CODE, GLOBAL and DATA respectively.
+pub(crate) struct WasmInput<'a> {
+    object: ObjectInput<'a>,
+    files: Vec<WasmFile<'a>>,
+}
+
+// The output data segment of a data segment, `.data.x` goes to `.data`.
+fn wasm_output_segment(name: &str) -> &str {
+    match name.get(1..).and_then(|rest| rest.find('.')) {
+        Some(end) if name.starts_with('.') => &name[..end + 1],
+        _ => name,
+    }
+}
+
+impl<'a> WasmInput<'a> {
+    fn add_file(&mut self, name: &'a str, buf: &'a [u8]) -> Result<(), LinkError> {
+        use scroll::Pread;
+        let object = &mut self.object;
+        let file = object.file_names.len();
+        let first_section = object.sections.len();
+        object.file_names.push(name);
+        let mut wasm = WasmFile {
+            types: Vec::new(),
+            function_types: HashMap::new(),
+            imports: Vec::new(),
+            exports: Vec::new(),
+        };
+        let mut function_imports = Vec::new();
+        let mut global_imports = Vec::new();
+        let mut table_imports = Vec::new();
+        let mut declared_types = Vec::new();
+        let mut functions = Vec::new();
+        let mut globals = Vec::new();
+        let mut data_segments = Vec::new();
+        // The id of each section, including custom sections, which relocation sections
+        // refer to
+        let mut section_ids = Vec::new();
+        // The start of the function bodies and data segments in the payload of their
+        // section
+        let mut code_offsets = Vec::new();
+        let mut data_offsets = Vec::new();
+        let mut linking = None;
+        let mut reloc_sections = Vec::new();
+
+        let mut offset = WASM_MAGIC.len();
+        while offset < buf.len() {
+            let id: u8 = buf.gread(&mut offset)?;
+            let payload = read_wasm_bytes(buf, &mut offset)?;
+            section_ids.push(id);
+            let mut pos = 0;
+            match id {
+                WASM_SEC_CUSTOM => {
+                    let name = read_wasm_name(payload, &mut pos)?;
+                    if name == "linking" {
+                        linking = Some(&payload[pos..]);
+                    } else if name.starts_with("reloc.") {
+                        reloc_sections.push(&payload[pos..]);
+                    }
+                }
+                WASM_SEC_TYPE => {
+                    for _ in 0..read_wasm_u32(payload, &mut pos)? {
+                        let form: u8 = payload.gread(&mut pos)?;
+                        if form != 0x60 {
+                            return Err(LinkError::malformed(
+                                "",
+                                format!("Unsupported type form: {:#x}", form),
+                            ));
+                        }
+                        let params = read_wasm_bytes(payload, &mut pos)?;
+                        let results = read_wasm_bytes(payload, &mut pos)?;
+                        wasm.types.push(WasmFuncType { params, results });
+                    }
+                }
+                WASM_SEC_IMPORT => {
+                    for _ in 0..read_wasm_u32(payload, &mut pos)? {
+                        let module = read_wasm_name(payload, &mut pos)?;
+                        let field = read_wasm_name(payload, &mut pos)?;
+                        let kind: u8 = payload.gread(&mut pos)?;
+                        match kind {
+                            WASM_EXTERNAL_FUNCTION => {
+                                let type_index = read_wasm_u32(payload, &mut pos)?;
+                                function_imports.push(WasmImport {
+                                    module,
+                                    field,
+                                    type_index,
+                                });
+                            }
+                            WASM_EXTERNAL_TABLE | WASM_EXTERNAL_MEMORY => {
+                                if kind == WASM_EXTERNAL_TABLE {
+                                    // The reference type
+                                    pos += 1;
+                                    table_imports.push(field);
+                                }
+                                let flags = read_wasm_u32(payload, &mut pos)?;
+                                read_wasm_u32(payload, &mut pos)?;
+                                if flags & 1 != 0 {
+                                    read_wasm_u32(payload, &mut pos)?;
+                                }
+                            }
+                            WASM_EXTERNAL_GLOBAL => {
+                                // The value type and mutability
+                                pos += 2;
+                                global_imports.push(field);
+                            }
+                            _ => {
+                                return Err(LinkError::malformed(
+                                    "",
+                                    format!("Unsupported import kind: {}", kind),
+                                ))
+                            }
+                        }
+                    }
+                }
+                WASM_SEC_FUNCTION => {
+                    for _ in 0..read_wasm_u32(payload, &mut pos)? {
+                        declared_types.push(read_wasm_u32(payload, &mut pos)?);
+                    }
+                }
+                WASM_SEC_GLOBAL => {
+                    for i in 0..read_wasm_u32(payload, &mut pos)? {
+                        // The global is copied as is, its value type and mutability followed by
+                        // the initializer.
+                        let start = pos;
+                        pos += 2;
+                        skip_wasm_init_expr(payload, &mut pos)?;
+                        globals.push(object.sections.len());
+                        object.sections.push(ObjectSection {
+                            file,
+                            segment: "GLOBAL".to_string(),
+                            name: format!("global {}", i),
+                            flags: 0,
+                            address: 0,
+                            align: 1,
+                            contents: Some(&payload[start..pos]),
+                            size: pos - start,
+                            loaded: true,
+                            relocations: Vec::new(),
+                        });
+                    }
+                }
+                WASM_SEC_CODE => {
+                    for (i, type_index) in
+                        (0..read_wasm_u32(payload, &mut pos)?).zip(&declared_types)
+                    {
+                        let body = read_wasm_bytes(payload, &mut pos)?;
+                        code_offsets.push((pos - body.len(), object.sections.len()));
+                        wasm.function_types
+                            .insert(object.sections.len(), *type_index);
+                        functions.push(object.sections.len());
+                        object.sections.push(ObjectSection {
+                            file,
+                            segment: "CODE".to_string(),
+                            name: format!("function {}", i),
+                            flags: 0,
+                            address: 0,
+                            align: 1,
+                            contents: Some(body),
+                            size: body.len(),
+                            loaded: true,
+                            relocations: Vec::new(),
+                        });
+                    }
+                }
+                WASM_SEC_DATA => {
+                    for i in 0..read_wasm_u32(payload, &mut pos)? {
+                        let flags = read_wasm_u32(payload, &mut pos)?;
+                        if flags != 0 {
+                            return Err(LinkError::malformed(
+                                "",
+                                format!("Unsupported data segment flags: {}", flags),
+                            ));
+                        }
+                        skip_wasm_init_expr(payload, &mut pos)?;
+                        let data = read_wasm_bytes(payload, &mut pos)?;
+                        data_offsets.push((pos - data.len(), object.sections.len()));
+                        data_segments.push(object.sections.len());
+                        object.sections.push(ObjectSection {
+                            file,
+                            segment: "DATA".to_string(),
+                            // Replaced by the name from the segment info
+                            name: format!("segment {}", i),
+                            flags,
+                            address: 0,
+                            align: 1,
+                            contents: Some(data),
+                            size: data.len(),
+                            loaded: true,
+                            relocations: Vec::new(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let type_count = wasm.types.len();
+        let types = function_imports.iter().map(|import| &import.type_index);
+        if let Some(index) = types
+            .chain(&declared_types)
+            .find(|index| usize::try_from(**index).unwrap() >= type_count)
+        {
+            return Err(LinkError::malformed(
+                name,
+                format!("Invalid type index {}", index),
+            ));
+        }
+        let linking = match linking {
+            Some(linking) => linking,
+            None => {
+                return Err(LinkError::malformed(
+                    name,
+                    "Not a relocatable wasm file, it has no linking section",
+                ))
+            }
+        };
+        let mut symbols = Vec::new();
+        let mut pos = 0;
+        let version = read_wasm_u32(linking, &mut pos)?;
+        if version != 2 {
+            return Err(LinkError::malformed(
+                name,
+                format!("Unsupported linking section version: {}", version),
+            ));
+        }
+        while pos < linking.len() {
+            let kind: u8 = linking.gread(&mut pos)?;
+            let subsection = read_wasm_bytes(linking, &mut pos)?;
+            let mut pos = 0;
+            match kind {
+                WASM_SEGMENT_INFO => {
+                    let count = read_wasm_usize(subsection, &mut pos)?;
+                    for segment in data_segments.iter().take(count) {
+                        let section = &mut object.sections[*segment];
+                        section.name = read_wasm_name(subsection, &mut pos)?.to_string();
+                        let align = read_wasm_u32(subsection, &mut pos)?;
+                        if align >= 32 {
+                            return Err(LinkError::malformed(
+                                name,
+                                format!("Alignment 2^{} is too large", align),
+                            ));
+                        }
+                        section.align = 1 << align;
+                        // The segment flags for TLS and strings
+                        read_wasm_u32(subsection, &mut pos)?;
+                    }
+                }
+                WASM_INIT_FUNCS => {
+                    let count = read_wasm_u32(subsection, &mut pos)?;
+                    if count != 0 {
+                        return Err(LinkError::malformed(
+                            name,
+                            "Constructors are not supported for wasm",
+                        ));
+                    }
+                }
+                WASM_SYMBOL_TABLE => {
+                    for _ in 0..read_wasm_u32(subsection, &mut pos)? {
+                        let kind: u8 = subsection.gread(&mut pos)?;
+                        let flags = read_wasm_u32(subsection, &mut pos)?;
+                        let undefined = flags & WASM_SYM_UNDEFINED != 0;
+                        let (name, def) = match kind {
+                            WASM_SYMBOL_TYPE_FUNCTION
+                            | WASM_SYMBOL_TYPE_GLOBAL
+                            | WASM_SYMBOL_TYPE_TABLE => {
+                                let index = read_wasm_usize(subsection, &mut pos)?;
+                                let (imports, definitions): (Vec<&str>, &[usize]) = match kind {
+                                    WASM_SYMBOL_TYPE_FUNCTION => (
+                                        function_imports
+                                            .iter()
+                                            .map(|import| import.field)
+                                            .collect(),
+                                        &functions,
+                                    ),
+                                    WASM_SYMBOL_TYPE_GLOBAL => (global_imports.clone(), &globals),
+                                    _ => (table_imports.clone(), &[]),
+                                };
+                                let invalid = || {
+                                    LinkError::malformed(
+                                        name,
+                                        format!("Symbol refers to invalid index {}", index),
+                                    )
+                                };
+                                if undefined {
+                                    if kind == WASM_SYMBOL_TYPE_FUNCTION {
+                                        let import =
+                                            function_imports.get(index).ok_or_else(invalid)?;
+                                        wasm.imports.push((symbols.len(), *import));
+                                    }
+                                    // Named after the import unless the name is explicit
+                                    let name = if flags & WASM_SYM_EXPLICIT_NAME == 0 {
+                                        imports.get(index).ok_or_else(invalid)?
+                                    } else {
+                                        read_wasm_name(subsection, &mut pos)?
+                                    };
+                                    (name, SymbolDef::Undefined(None))
+                                } else {
+                                    let definition = index.checked_sub(imports.len());
+                                    let section =
+                                        match definition.and_then(|index| definitions.get(index)) {
+                                            Some(section) => *section,
+                                            None => {
+                                                return Err(LinkError::malformed(
+                                                    name,
+                                                    format!(
+                                                        "Unsupported definition of symbol kind {}",
+                                                        kind
+                                                    ),
+                                                ))
+                                            }
+                                        };
+                                    (
+                                        read_wasm_name(subsection, &mut pos)?,
+                                        SymbolDef::Section(section, 0),
+                                    )
+                                }
+                            }
+                            WASM_SYMBOL_TYPE_DATA => {
+                                let name = read_wasm_name(subsection, &mut pos)?;
+                                if undefined {
+                                    (name, SymbolDef::Undefined(None))
+                                } else {
+                                    let segment = read_wasm_usize(subsection, &mut pos)?;
+                                    let offset = read_wasm_u32(subsection, &mut pos)?;
+                                    // The size
+                                    read_wasm_u32(subsection, &mut pos)?;
+                                    let section = data_segments.get(segment).ok_or_else(|| {
+                                        LinkError::malformed(
+                                            name,
+                                            format!("Symbol refers to invalid segment {}", segment),
+                                        )
+                                    })?;
+                                    (name, SymbolDef::Section(*section, u64::from(offset)))
+                                }
+                            }
+                            WASM_SYMBOL_TYPE_SECTION => {
+                                read_wasm_u32(subsection, &mut pos)?;
+                                symbols.push(None);
+                                continue;
+                            }
+                            _ => {
+                                return Err(LinkError::malformed(
+                                    name,
+                                    format!("Unsupported wasm symbol kind: {}", kind),
+                                ))
+                            }
+                        };
+                        if flags & WASM_SYM_EXPORTED != 0 {
+                            wasm.exports.push(symbols.len());
+                        }
+                        symbols.push(Some(ObjectSymbol {
+                            name,
+                            external: flags & WASM_SYM_BINDING_LOCAL == 0,
+                            weak: flags & WASM_SYM_BINDING_WEAK != 0,
+                            def,
+                        }));
+                    }
+                }
+                // COMDAT info, weak definitions already pick one of the copies
+                _ => {}
+            }
+        }
+
+        for relocs in reloc_sections {
+            let mut pos = 0;
+            let target = read_wasm_usize(relocs, &mut pos)?;
+            let target = *section_ids.get(target).ok_or_else(|| {
+                LinkError::malformed(
+                    name,
+                    format!("Relocations for invalid section index {}", target),
+                )
+            })?;
+            // The relocations of debug info are not needed as it is not linked.
+            let offsets = match target {
+                WASM_SEC_CODE => &code_offsets,
+                WASM_SEC_DATA => &data_offsets,
+                _ => continue,
+            };
+            for _ in 0..read_wasm_u32(relocs, &mut pos)? {
+                let r_type = u32::from(relocs.gread::<u8>(&mut pos)?);
+                let offset = read_wasm_usize(relocs, &mut pos)?;
+                let index = read_wasm_usize(relocs, &mut pos)?;
+                let addend = if wasm_reloc_has_addend(r_type) {
+                    scroll::Sleb128::read(relocs, &mut pos)?
+                } else {
+                    0
+                };
+                // The function or segment containing the relocated field
+                let (start, section) = offsets
+                    .partition_point(|(start, _)| *start <= offset)
+                    .checked_sub(1)
+                    .map(|idx| offsets[idx])
+                    .ok_or_else(|| {
+                        LinkError::malformed(
+                            name,
+                            format!("Relocation at {:#x} is outside of the section", offset),
+                        )
+                    })?;
+                let target = if r_type == R_WASM_TYPE_INDEX_LEB {
+                    if index >= type_count {
+                        return Err(LinkError::malformed(
+                            name,
+                            format!("Invalid type index {}", index),
+                        ));
+                    }
+                    RelocationTarget::Type(index)
+                } else {
+                    RelocationTarget::Symbol(index)
+                };
+                object.sections[section].relocations.push(ObjectRelocation {
+                    offset: offset - start,
+                    target,
+                    r_type,
+                    length: 0,
+                    addend,
+                    minus: None,
+                });
+            }
+        }
+        object.add_symbols(symbols);
+        object.check_file(first_section)?;
+        self.files.push(wasm);
+        Ok(())
+    }
+    fn link(&self) -> Result<Vec<u8>, LinkError> {
+        use scroll::Pwrite;
+        let object = &self.object;
+        // The types of all files without duplicates
+        let mut types: Vec<&WasmFuncType> = Vec::new();
+        let type_indices: Vec<Vec<u32>> = self
+            .files
+            .iter()
+            .map(|file| {
+                file.types
+                    .iter()
+                    .map(|ty| {
+                        let index = types.iter().position(|t| *t == ty).unwrap_or_else(|| {
+                            types.push(ty);
+                            types.len() - 1
+                        });
+                        u32::try_from(index).unwrap()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Undefined functions are imported by the module, each import only once.
+        let mut imports: Vec<WasmImport> = Vec::new();
+        let mut import_indices = HashMap::new();
+        for (file, wasm) in self.files.iter().enumerate() {
+            for (sym, import) in &wasm.imports {
+                if let SymbolDef::Undefined(_) = object.resolve(file, *sym).def {
+                    let index = match imports
+                        .iter()
+                        .position(|i| (i.module, i.field) == (import.module, import.field))
+                    {
+                        Some(index) => index,
+                        None => {
+                            imports.push(WasmImport {
+                                type_index: type_indices[file]
+                                    [usize::try_from(import.type_index).unwrap()],
+                                ..*import
+                            });
+                            imports.len() - 1
+                        }
+                    };
+                    import_indices.insert((file, *sym), u32::try_from(index).unwrap());
+                }
+            }
+        }
+        let index_of = |sections: &[usize], first: usize| -> HashMap<usize, u32> {
+            sections
+                .iter()
+                .enumerate()
+                .map(|(i, section)| (*section, u32::try_from(first + i).unwrap()))
+                .collect()
+        };
+        let kind = |kind: &str| -> Vec<usize> {
+            (0..object.sections.len())
+                .filter(|idx| object.sections[*idx].segment == kind)
+                .collect()
+        };
+        let functions = kind("CODE");
+        let function_indices = index_of(&functions, imports.len());
+        // __stack_pointer is the first global.
+        let globals = kind("GLOBAL");
+        let global_indices = index_of(&globals, 1);
+
+        // Data segments are merged by their prefix, with the zero initialized ones last.
+        let mut segment_names = Vec::new();
+        for idx in kind("DATA") {
+            let name = wasm_output_segment(&object.sections[idx].name);
+            if !segment_names.contains(&name) {
+                segment_names.push(name);
+            }
+        }
+        segment_names.sort_by_key(|name| *name == ".bss");
+        let groups = object.groups(segment_names.len() + 1, |section| {
+            if section.segment != "DATA" {
+                return segment_names.len();
+            }
+            let name = wasm_output_segment(&section.name);
+            segment_names.iter().position(|n| *n == name).unwrap()
+        });
+        let mut placements = HashMap::new();
+        let mut segments = Vec::new();
+        let mut address = WASM_GLOBAL_BASE;
+        for (i, name) in segment_names.iter().enumerate() {
+            let (data, size) = object.concatenate(&groups[i], 0, i, &mut placements)?;
+            let alignment = groups[i]
+                .iter()
+                .map(|idx| object.sections[*idx].align)
+                .max()
+                .unwrap();
+            address = align(address, alignment);
+            segments.push((*name, address, data));
+            address += size;
+        }
+        let data_end = address;
+        let stack_pointer = align(data_end, 16) + WASM_STACK_SIZE;
+        let heap_base = stack_pointer;
+        // Addresses are i32 constants in the module.
+        if i32::try_from(heap_base).is_err() {
+            return Err(LinkError::overflow(format!(
+                "{:#x} bytes of data and stack do not fit in the wasm memory",
+                heap_base
+            )));
+        }
+
+        let resolve = |file: usize, sym: usize| object.resolve(file, sym);
+        // Relocations of the wrong kind for their symbol
+        let mismatch = |file: usize, sym: usize, kind: &str| {
+            LinkError::malformed(
+                object.file_names[file],
+                format!("{} is not a {}", resolve(file, sym).name, kind),
+            )
+        };
+        let function_index = |file: usize, sym: usize| {
+            match resolve(file, sym).def {
+                SymbolDef::Section(section, _) => function_indices.get(&section),
+                _ => import_indices.get(&(file, sym)),
+            }
+            .copied()
+            .ok_or_else(|| mismatch(file, sym, "function"))
+        };
+        let global_index = |file: usize, sym: usize| {
+            let sym_def = resolve(file, sym);
+            match sym_def.def {
+                SymbolDef::Section(section, _) => global_indices
+                    .get(&section)
+                    .copied()
+                    .ok_or_else(|| mismatch(file, sym, "global")),
+                _ if sym_def.name == "__stack_pointer" => Ok(0),
+                _ => Err(LinkError::undefined(sym_def.name)),
+            }
+        };
+        let memory_address = |file: usize, sym: usize| {
+            let sym = resolve(file, sym);
+            match sym.def {
+                SymbolDef::Section(section, offset) => {
+                    let (out, start) = object.placement(&placements, section)?;
+                    Ok(segments[out].1 + start + usize::try_from(offset).unwrap())
+                }
+                _ if sym.name == "__data_end" => Ok(data_end),
+                _ if sym.name == "__heap_base" => Ok(heap_base),
+                _ => Err(LinkError::undefined(sym.name)),
+            }
+        };
+
+        // Functions whose address is taken get a slot in the table, starting at 1 so that
+        // a null function pointer traps.
+        let mut table = Vec::new();
+        for section in &object.sections {
+            for reloc in &section.relocations {
+                if let (
+                    R_WASM_TABLE_INDEX_SLEB | R_WASM_TABLE_INDEX_I32,
+                    RelocationTarget::Symbol(sym),
+                ) = (reloc.r_type, reloc.target)
+                {
+                    let index = function_index(section.file, sym)?;
+                    if !table.contains(&index) {
+                        table.push(index);
+                    }
+                }
+            }
+        }
+        let table_index = |index: u32| table.iter().position(|i| *i == index).unwrap() + 1;
+
+        let relocate = |idx: usize| -> Result<Vec<u8>, LinkError> {
+            let section = &object.sections[idx];
+            let file = section.file;
+            let mut data = section.contents.unwrap().to_vec();
+            for reloc in &section.relocations {
+                let sym = match reloc.target {
+                    RelocationTarget::Symbol(sym) => sym,
+                    RelocationTarget::Type(ty) => {
+                        let index = type_indices[file][ty];
+                        patch_wasm_leb(&mut data, reloc.offset, i64::from(index), false)?;
+                        continue;
+                    }
+                    RelocationTarget::Section(_) => unreachable!(),
+                };
+                let value = match reloc.r_type {
+                    R_WASM_FUNCTION_INDEX_LEB => i64::from(function_index(file, sym)?),
+                    R_WASM_TABLE_INDEX_SLEB | R_WASM_TABLE_INDEX_I32 => {
+                        i64::try_from(table_index(function_index(file, sym)?)).unwrap()
+                    }
+                    R_WASM_MEMORY_ADDR_LEB | R_WASM_MEMORY_ADDR_SLEB | R_WASM_MEMORY_ADDR_I32 => {
+                        // The field is checked for overflow instead of the addend.
+                        i64::try_from(memory_address(file, sym)?)
+                            .unwrap()
+                            .wrapping_add(reloc.addend)
+                    }
+                    R_WASM_GLOBAL_INDEX_LEB | R_WASM_GLOBAL_INDEX_I32 => {
+                        i64::from(global_index(file, sym)?)
+                    }
+                    // There is only the indirect function table.
+                    R_WASM_TABLE_NUMBER_LEB => 0,
+                    _ => {
+                        return Err(LinkError::unsupported_relocation(
+                            object.file_names[file],
+                            format!("wasm relocation type {}", reloc.r_type),
+                        ))
+                    }
+                };
+                match reloc.r_type {
+                    R_WASM_TABLE_INDEX_I32 | R_WASM_MEMORY_ADDR_I32 | R_WASM_GLOBAL_INDEX_I32 => {
+                        let value = u32::try_from(value).map_err(|_| {
+                            LinkError::malformed(
+                                "",
+                                format!(
+                                    "relocation truncated to fit at {:#x}: {:#x}",
+                                    reloc.offset, value
+                                ),
+                            )
+                        })?;
+                        data.pwrite_with(value, reloc.offset, scroll::LE)?;
+                    }
+                    R_WASM_TABLE_INDEX_SLEB | R_WASM_MEMORY_ADDR_SLEB => {
+                        patch_wasm_leb(&mut data, reloc.offset, value, true)?
+                    }
+                    _ => patch_wasm_leb(&mut data, reloc.offset, value, false)?,
+                }
+            }
+            Ok(data)
+        };
+
+        let mut out = WASM_MAGIC.to_vec();
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(types.len()).unwrap());
+        for ty in &types {
+            section.push(0x60);
+            write_wasm_bytes(&mut section, ty.params);
+            write_wasm_bytes(&mut section, ty.results);
+        }
+        write_wasm_section(&mut out, WASM_SEC_TYPE, &section);
+
+        if !imports.is_empty() {
+            let mut section = Vec::new();
+            write_wasm_u32(&mut section, u32::try_from(imports.len()).unwrap());
+            for import in &imports {
+                write_wasm_bytes(&mut section, import.module.as_bytes());
+                write_wasm_bytes(&mut section, import.field.as_bytes());
+                section.push(WASM_EXTERNAL_FUNCTION);
+                write_wasm_u32(&mut section, import.type_index);
+            }
+            write_wasm_section(&mut out, WASM_SEC_IMPORT, &section);
+        }
+
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(functions.len()).unwrap());
+        for idx in &functions {
+            let file = object.sections[*idx].file;
+            let ty = self.files[file].function_types[idx];
+            write_wasm_u32(
+                &mut section,
+                type_indices[file][usize::try_from(ty).unwrap()],
+            );
+        }
+        write_wasm_section(&mut out, WASM_SEC_FUNCTION, &section);
+
+        if !table.is_empty() {
+            // A funcref table with a fixed size
+            let size = u32::try_from(table.len() + 1).unwrap();
+            let mut section = vec![1, 0x70, 1];
+            write_wasm_u32(&mut section, size);
+            write_wasm_u32(&mut section, size);
+            write_wasm_section(&mut out, WASM_SEC_TABLE, &section);
+        }
+
+        // One memory large enough for the data and the stack
+        let mut section = vec![1, 0];
+        write_wasm_u32(
+            &mut section,
+            u32::try_from(align(heap_base, WASM_PAGE_SIZE) / WASM_PAGE_SIZE).unwrap(),
+        );
+        write_wasm_section(&mut out, WASM_SEC_MEMORY, &section);
+
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(globals.len() + 1).unwrap());
+        // A mutable i32 initialized with i32.const
+        section.extend_from_slice(&[0x7f, 1, 0x41]);
+        write_wasm_i32(&mut section, i32::try_from(stack_pointer).unwrap());
+        section.push(0x0b);
+        for idx in &globals {
+            section.extend_from_slice(object.sections[*idx].contents.unwrap());
+        }
+        write_wasm_section(&mut out, WASM_SEC_GLOBAL, &section);
+
+        // The memory, _start and the functions marked for export
+        let mut exports: Vec<(&str, u32)> = Vec::new();
+        for (file, wasm) in self.files.iter().enumerate() {
+            let names = wasm.exports.iter().map(|sym| (file, *sym));
+            let start = object.globals.get("_start").copied();
+            for (file, sym) in names.chain(start.filter(|_| file == 0)) {
+                let section = match resolve(file, sym).def {
+                    SymbolDef::Section(section, _) => section,
+                    _ => continue,
+                };
+                let name = resolve(file, sym).name;
+                if object.sections[section].segment == "CODE"
+                    && !exports.iter().any(|(n, _)| *n == name)
+                {
+                    exports.push((name, function_indices[&section]));
+                }
+            }
+        }
+        exports.sort_unstable();
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(exports.len() + 1).unwrap());
+        write_wasm_bytes(&mut section, b"memory");
+        section.extend_from_slice(&[WASM_EXTERNAL_MEMORY, 0]);
+        for (name, index) in &exports {
+            write_wasm_bytes(&mut section, name.as_bytes());
+            section.push(WASM_EXTERNAL_FUNCTION);
+            write_wasm_u32(&mut section, *index);
+        }
+        write_wasm_section(&mut out, WASM_SEC_EXPORT, &section);
+
+        if !table.is_empty() {
+            // An active segment for table 0 at i32.const 1
+            let mut section = vec![1, 0, 0x41, 1, 0x0b];
+            write_wasm_u32(&mut section, u32::try_from(table.len()).unwrap());
+            for index in &table {
+                write_wasm_u32(&mut section, *index);
+            }
+            write_wasm_section(&mut out, WASM_SEC_ELEM, &section);
+        }
+
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(functions.len()).unwrap());
+        for idx in &functions {
+            write_wasm_bytes(&mut section, &relocate(*idx)?);
+        }
+        write_wasm_section(&mut out, WASM_SEC_CODE, &section);
+
+        // The zero initialized data needs no segment as memory starts out zeroed.
+        let mut contents: Vec<Vec<u8>> = segments.iter().map(|(_, _, data)| data.clone()).collect();
+        for (group, data) in groups.iter().zip(contents.iter_mut()) {
+            for idx in group {
+                let start = placements[idx].1;
+                let relocated = relocate(*idx)?;
+                data[start..start + relocated.len()].copy_from_slice(&relocated);
+            }
+        }
+        let initialized: Vec<_> = segments
+            .iter()
+            .zip(&contents)
+            .filter(|((name, _, _), _)| *name != ".bss")
+            .collect();
+        let mut section = Vec::new();
+        write_wasm_u32(&mut section, u32::try_from(initialized.len()).unwrap());
+        for ((_, address, _), data) in initialized {
+            section.extend_from_slice(&[0, 0x41]);
+            write_wasm_i32(&mut section, i32::try_from(*address).unwrap());
+            section.push(0x0b);
+            write_wasm_bytes(&mut section, data);
+        }
+        write_wasm_section(&mut out, WASM_SEC_DATA, &section);
+        Ok(out)
+    }
+}
+
+pub(crate) fn link_wasm(opts: &Opts, inputs: &[(String, Vec<u8>)]) -> Result<Vec<u8>, LinkError> {
+    if opts.relocatable {
+        return Err(LinkError::invalid_options(
+            "-r is not supported for wasm output",
+        ));
+    }
+    let mut input = WasmInput {
+        object: ObjectInput::new(),
+        files: Vec::new(),
+    };
+    for (name, buffer) in inputs {
+        if !is_wasm_object(buffer) {
+            return Err(LinkError::invalid_options(format!(
+                "{}: wasm output needs wasm input files",
+                name
+            )));
+        }
+        input
+            .add_file(name, buffer)
+            .map_err(|err| err.in_file(name))?;
+    }
+    input.link()
+}
@@ -0,0 +1,1317 @@
+// Run the dump tool on outputs of the linker.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempdir::TempDir;
+
+fn gcc(out_dir: &Path, file: &Path, flags: &[&str]) -> Result<PathBuf, Box<dyn Error>> {
+    let out = out_dir.join(file.with_extension("o"));
+    let output = Command::new("gcc")
+        .args(["-nostdlib", "-Wno-main", "-Wall", "-Werror"])
+        .args(flags)
+        .args(["-o", out.to_str().unwrap(), "-c"])
+        .arg(Path::new("examples").join(file))
+        .output()?;
+    assert!(output.status.success());
+    Ok(out)
+}
+
+// Link the objects into `out` with the linker binary.
+fn link(objects: &[&Path], out: &Path, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_toy-linker"));
+    for object in objects {
+        command.arg("-i").arg(object);
+    }
+    let output = command.args(args).arg("-o").arg(out).output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+fn dump(args: &[&str], file: &Path) -> Result<Output, Box<dyn Error>> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_dump"))
+        .args(args)
+        .arg(file)
+        .output()?)
+}
+
+// The output of a successful dump.
+fn dump_stdout(args: &[&str], file: &Path) -> Result<String, Box<dyn Error>> {
+    let output = dump(args, file)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// main.c and lib.c linked into `main` in `dir`.
+fn link_main(dir: &Path, args: &[&str]) -> Result<PathBuf, Box<dyn Error>> {
+    let main_o = gcc(dir, Path::new("main.c"), &[])?;
+    let lib_o = gcc(dir, Path::new("lib.c"), &[])?;
+    let exe = dir.join("main");
+    link(&[&main_o, &lib_o], &exe, args)?;
+    Ok(exe)
+}
+
+#[test]
+fn verify_dynamic_section() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let object = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let exe = tmp_dir.path().join("init_array");
+    // The .init_array in front of it leaves .dynamic 8 but not 16 byte aligned.
+    for args in [&["-z", "now"][..], &["--rpath", "/opt/lib"]] {
+        link(&[&object], &exe, args)?;
+        let output = dump(&["--verify"], &exe)?;
+        assert_eq!(String::from_utf8(output.stdout)?, "");
+        assert!(output.status.success());
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_problems() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::PT_DYNAMIC;
+    use scroll::Pwrite;
+    let tmp_dir = TempDir::new("test")?;
+    let object = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let exe = tmp_dir.path().join("init_array");
+    link(&[&object], &exe, &["-z", "now"])?;
+    let mut buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let idx = elf
+        .section_headers
+        .iter()
+        .position(|sec| &elf.shdr_strtab[sec.sh_name] == ".dynamic")
+        .unwrap();
+    let segment = elf
+        .program_headers
+        .iter()
+        .position(|ph| ph.p_type == PT_DYNAMIC)
+        .unwrap();
+    // sh_link, sh_addralign and p_align of the 64 bit headers
+    let header = &elf.header;
+    let shdr = usize::try_from(header.e_shoff)? + idx * usize::from(header.e_shentsize);
+    let phdr = usize::try_from(header.e_phoff)? + segment * usize::from(header.e_phentsize);
+    let shnum = header.e_shnum;
+    buf.pwrite_with(u32::from(shnum), shdr + 40, scroll::LE)?;
+    buf.pwrite_with(3u64, shdr + 48, scroll::LE)?;
+    buf.pwrite_with(0x18u64, phdr + 48, scroll::LE)?;
+    fs::write(&exe, &buf)?;
+
+    let output = dump(&["--verify"], &exe)?;
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!(
+                "segment {} (DYNAMIC) has an alignment of 0x18, not a power of two",
+                segment
+            ),
+            format!(
+                "section {} (.dynamic) has an alignment of 0x3, not a power of two",
+                idx
+            ),
+            format!(
+                "section {} (.dynamic) links to section {}, which does not exist",
+                idx, shnum
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn file_header() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let header = &elf.header;
+    // Without options only the ELF header is printed.
+    let stdout = dump_stdout(&[], &exe)?;
+    let fields: Vec<(&str, &str)> = block(&stdout, "ELF Header:")
+        .iter()
+        .map(|row| {
+            let (name, value) = row.split_once(':').unwrap();
+            (name.trim(), value.trim())
+        })
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ("Class", "ELF64"),
+            ("Data", "little endian"),
+            ("OS/ABI", "UNIX - System V"),
+            ("Type", "EXEC"),
+            ("Machine", "X86_64"),
+            ("Entry point address", &format!("{:#x}", header.e_entry)),
+            (
+                "Start of program headers",
+                &format!("{} (bytes into file)", header.e_phoff)
+            ),
+            (
+                "Start of section headers",
+                &format!("{} (bytes into file)", header.e_shoff)
+            ),
+            ("Flags", "0x0"),
+            ("Number of program headers", &header.e_phnum.to_string()),
+            ("Number of section headers", &header.e_shnum.to_string()),
+            (
+                "Section header string table index",
+                &header.e_shstrndx.to_string()
+            ),
+        ]
+    );
+    assert_eq!(dump_stdout(&["--file-header"], &exe)?, stdout);
+
+    // --all is the header and the tables one after the other.
+    let tables = ["--file-header", "-S", "-l", "-s", "-r", "-d", "-n", "-V"]
+        .iter()
+        .map(|flag| dump_stdout(&[flag], &exe))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(dump_stdout(&["--all"], &exe)?, tables.join("\n"));
+    assert_eq!(dump_stdout(&["-S", "-l"], &exe)?, tables[1..3].join("\n"));
+    assert!(dump_stdout(&["--debug"], &exe)?.starts_with("Elf {\n"));
+    Ok(())
+}
+
+#[test]
+fn section_headers() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let stdout = dump_stdout(&["-S"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let mut lines = stdout.lines();
+    assert_eq!(
+        lines.next().unwrap().split_whitespace().collect::<Vec<_>>(),
+        ["[Nr]", "Name", "Type", "Flg", "Address", "Off", "Size", "Al"]
+    );
+    for (idx, sec) in elf.section_headers.iter().enumerate() {
+        let line = lines.next().unwrap();
+        let (nr, row) = line.split_once(']').unwrap();
+        assert_eq!(
+            nr.trim_start().trim_start_matches('[').trim(),
+            idx.to_string()
+        );
+        let fields: Vec<&str> = row.split_whitespace().collect();
+        let name = &elf.shdr_strtab[sec.sh_name];
+        if !name.is_empty() {
+            let kind = goblin::elf::section_header::sht_to_str(sec.sh_type);
+            assert_eq!(fields[..2], [name, kind.trim_start_matches("SHT_")]);
+        }
+        let flags = match name {
+            ".text" => "AX",
+            ".rodata" | ".eh_frame" | ".eh_frame_hdr" => "A",
+            ".data" | ".bss" => "WA",
+            ".comment" => "MS",
+            _ => "",
+        };
+        let expected = [
+            format!("{:016x}", sec.sh_addr),
+            format!("{:08x}", sec.sh_offset),
+            format!("{:08x}", sec.sh_size),
+            sec.sh_addralign.to_string(),
+        ];
+        assert_eq!(fields[fields.len() - 4..], expected, "{}", line);
+        if !flags.is_empty() {
+            assert_eq!(fields[fields.len() - 5], flags, "{}", line);
+        }
+    }
+    assert_eq!(lines.next(), Some("Key to Flags:"));
+    Ok(())
+}
+
+// The lines after the heading `from` up to the next empty line, without trailing spaces.
+fn block<'a>(text: &'a str, from: &str) -> Vec<&'a str> {
+    text.lines()
+        .skip_while(|line| !line.contains(from))
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(str::trim_end)
+        .collect()
+}
+
+#[test]
+fn program_headers() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let object = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let exe = tmp_dir.path().join("init_array");
+    // With the relro region, .dynamic is in several segments.
+    link(&[&object], &exe, &["-z", "now"])?;
+    let stdout = dump_stdout(&["-l"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert!(stdout.contains(&format!("Entry point {:#x}\n", elf.entry)));
+    let rows = block(&stdout, "Program Headers:");
+    assert_eq!(rows.len(), elf.program_headers.len() + 1);
+    for (row, ph) in rows[1..].iter().zip(&elf.program_headers) {
+        let fields: Vec<&str> = row.split_whitespace().collect();
+        let kind = goblin::elf::program_header::pt_to_str(ph.p_type);
+        assert_eq!(fields[0], kind.trim_start_matches("PT_"));
+        assert_eq!(
+            fields[1..6],
+            [
+                format!("{:#08x}", ph.p_offset),
+                format!("{:#018x}", ph.p_vaddr),
+                format!("{:#018x}", ph.p_paddr),
+                format!("{:#08x}", ph.p_filesz),
+                format!("{:#08x}", ph.p_memsz),
+            ]
+        );
+    }
+    // The sections of each segment are the ones readelf lists.
+    let readelf = Command::new("readelf").arg("-lW").arg(&exe).output()?;
+    let readelf = String::from_utf8(readelf.stdout)?;
+    let mapping = block(&readelf, "Segment Sections...");
+    assert_eq!(mapping.len(), elf.program_headers.len());
+    assert_eq!(block(&stdout, "Segment Sections..."), mapping);
+    Ok(())
+}
+
+// The names of the listed symbols.
+fn symbol_names(stdout: &str) -> Vec<&str> {
+    block(stdout, "Num:")
+        .iter()
+        .map(|row| row.split_whitespace().nth(7).unwrap_or(""))
+        .collect()
+}
+
+#[test]
+fn symbols() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+
+    let stdout = dump_stdout(&["-s", "--type", "FUNC", "--binding", "GLOBAL"], &exe)?;
+    assert!(stdout.starts_with(&format!(
+        "Symbol table '.symtab' contains {} entries, 2 shown:\n",
+        elf.syms.len()
+    )));
+    assert_eq!(symbol_names(&stdout), ["extern_call", "_start"]);
+    for row in block(&stdout, "Num:") {
+        let fields: Vec<&str> = row.split_whitespace().collect();
+        let sym = elf
+            .syms
+            .get(fields[0].trim_end_matches(':').parse()?)
+            .unwrap();
+        assert_eq!(fields[1], format!("{:016x}", sym.st_value));
+        assert_eq!(fields[2], sym.st_size.to_string());
+    }
+
+    let stdout = dump_stdout(&["-s", "--type", "FUNC", "--sort", "address"], &exe)?;
+    assert_eq!(
+        symbol_names(&stdout),
+        ["print", "main", "_start", "print", "extern_call"]
+    );
+    let stdout = dump_stdout(&["-s", "--name", "^pr"], &exe)?;
+    assert_eq!(symbol_names(&stdout), ["print", "print"]);
+
+    let object = gcc(tmp_dir.path(), Path::new("mangled.s"), &[])?;
+    let mangled = tmp_dir.path().join("mangled");
+    link(&[&object], &mangled, &[])?;
+    let stdout = dump_stdout(&["-s", "--name", "bar"], &mangled)?;
+    assert_eq!(symbol_names(&stdout), ["_ZN3foo3barEi"]);
+    let stdout = dump_stdout(&["-s", "-C", "--name", "^foo::"], &mangled)?;
+    assert!(stdout.lines().any(|line| line.ends_with(" foo::bar(int)")));
+    Ok(())
+}
+
+#[test]
+fn relocations() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::sym::{st_type, STT_SECTION};
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &["--emit-relocs"])?;
+    let stdout = dump_stdout(&["-r"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    assert_eq!(elf.shdr_relocs.len(), 2);
+    for (idx, relocs) in &elf.shdr_relocs {
+        let sec = &elf.section_headers[*idx];
+        let heading = format!(
+            "Relocation section '{}' at offset {:#x} contains {} entries:",
+            &elf.shdr_strtab[sec.sh_name],
+            sec.sh_offset,
+            relocs.len()
+        );
+        let rows = block(&stdout, &heading);
+        assert_eq!(rows.len(), relocs.len() + 1, "{}", heading);
+        for (row, reloc) in rows[1..].iter().zip(relocs.iter()) {
+            let sym = elf.syms.get(reloc.r_sym).unwrap();
+            let name = if st_type(sym.st_info) == STT_SECTION {
+                &elf.shdr_strtab[elf.section_headers[sym.st_shndx].sh_name]
+            } else {
+                &elf.strtab[sym.st_name]
+            };
+            let addend = reloc.r_addend.unwrap();
+            let kind = goblin::elf::reloc::r_to_str(reloc.r_type, elf.header.e_machine);
+            let expected = format!(
+                "{:016x} {} {:016x} {} {} {:#x}",
+                reloc.r_offset,
+                kind.trim_start_matches("R_"),
+                sym.st_value,
+                name,
+                if addend < 0 { '-' } else { '+' },
+                addend.unsigned_abs()
+            );
+            assert_eq!(
+                row.split_whitespace().collect::<Vec<_>>().join(" "),
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn dynamic_section() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let object = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let exe = tmp_dir.path().join("init_array");
+    let args = [
+        "-z",
+        "now",
+        "--rpath",
+        "/opt/lib",
+        "--rpath",
+        "/usr/local/lib",
+    ];
+    link(&[&object], &exe, &args)?;
+    let stdout = dump_stdout(&["-d"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let dynamic = elf.dynamic.as_ref().unwrap();
+    let phdr = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == goblin::elf::program_header::PT_DYNAMIC)
+        .unwrap();
+    let heading = format!(
+        "Dynamic section at offset {:#x} contains {} entries:",
+        phdr.p_offset,
+        dynamic.dyns.len()
+    );
+    assert_eq!(stdout.lines().next(), Some(heading.as_str()));
+    // The tags are the ones readelf decodes, with the same values.
+    let readelf = Command::new("readelf").arg("-dW").arg(&exe).output()?;
+    let readelf = String::from_utf8(readelf.stdout)?;
+    let entries = |text: &str| -> Vec<String> {
+        block(text, "Name/Value")
+            .iter()
+            .map(|row| row.split_whitespace().take(2).collect::<Vec<_>>().join(" "))
+            .collect()
+    };
+    assert_eq!(entries(&stdout).len(), dynamic.dyns.len());
+    assert_eq!(entries(&stdout), entries(&readelf));
+    assert!(stdout.contains("(RUNPATH)            Library runpath: [/opt/lib:/usr/local/lib]\n"));
+    assert!(stdout.contains("(STRSZ)              25 (bytes)\n"));
+
+    // The linker cannot produce DT_NEEDED entries, the system's programs have them.
+    let true_path = Path::new("/bin/true");
+    let readelf = Command::new("readelf").arg("-dW").arg(true_path).output()?;
+    let needed: Vec<String> = String::from_utf8(readelf.stdout)?
+        .lines()
+        .filter(|line| line.contains("(NEEDED)"))
+        .filter_map(|line| Some(line.split_once('[')?.1.trim_end_matches(']').to_string()))
+        .collect();
+    assert!(!needed.is_empty());
+    let stdout = dump_stdout(&["--needed"], true_path)?;
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), needed);
+    assert_eq!(dump_stdout(&["--needed"], &exe)?, "");
+    Ok(())
+}
+
+#[test]
+fn dependency_tree() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let lib_dir = tmp_dir.path().join("lib");
+    fs::create_dir(&lib_dir)?;
+    let wuhu_o = gcc(tmp_dir.path(), Path::new("shared_lib.c"), &["-fPIC"])?;
+    let wuhu = lib_dir.join("libwuhu.so.1");
+    link(&[&wuhu_o], &wuhu, &["--shared", "--soname", "libwuhu.so.1"])?;
+    // A library of the system linker that needs the one of this linker
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-fPIC"])?;
+    let gcc_link = |out: &Path, args: &[&str]| -> Result<(), Box<dyn Error>> {
+        let output = Command::new("gcc")
+            .args(["-nostdlib", "-Wl,--no-as-needed", "-o"])
+            .arg(out)
+            .args(args)
+            .output()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(())
+    };
+    let libb = lib_dir.join("libb.so");
+    gcc_link(
+        &libb,
+        &[
+            "-shared",
+            "-Wl,-soname,libb.so",
+            lib_o.to_str().unwrap(),
+            wuhu.to_str().unwrap(),
+        ],
+    )?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let exe = tmp_dir.path().join("main");
+    let tree = |dtags: &str| -> Result<String, Box<dyn Error>> {
+        gcc_link(
+            &exe,
+            &[
+                main_o.to_str().unwrap(),
+                libb.to_str().unwrap(),
+                wuhu.to_str().unwrap(),
+                "-Wl,-rpath,$ORIGIN/lib",
+                dtags,
+            ],
+        )?;
+        let output = Command::new(env!("CARGO_BIN_EXE_dump"))
+            .arg("--tree")
+            .arg(&exe)
+            .env_remove("LD_LIBRARY_PATH")
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    // The DT_RPATH of the executable is searched for the libraries of its libraries too.
+    let stdout = tree("-Wl,--disable-new-dtags")?;
+    let buf = fs::read(&exe)?;
+    let interpreter = goblin::elf::Elf::parse(&buf)?
+        .interpreter
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        stdout,
+        format!(
+            "{exe}\n  interpreter {interp}\n  libb.so => {dir}/libb.so\n    libwuhu.so.1 => \
+             {dir}/libwuhu.so.1\n  libwuhu.so.1 => {dir}/libwuhu.so.1 (listed above)\n",
+            exe = exe.display(),
+            interp = interpreter,
+            dir = lib_dir.display()
+        )
+    );
+    // DT_RUNPATH is not.
+    assert_eq!(
+        tree("-Wl,--enable-new-dtags")?,
+        format!(
+            "{exe}\n  interpreter {interp}\n  libb.so => {dir}/libb.so\n    libwuhu.so.1 => \
+             not found\n  libwuhu.so.1 => {dir}/libwuhu.so.1\n",
+            exe = exe.display(),
+            interp = interpreter,
+            dir = lib_dir.display()
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn versions() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-fPIC"])?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    // The linker cannot produce symbol versions, the system linker can.
+    let gcc_link = |args: &[&str]| -> Result<(), Box<dyn Error>> {
+        let output = Command::new("gcc")
+            .arg("-nostdlib")
+            .args(args)
+            .current_dir(tmp_dir.path())
+            .output()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(())
+    };
+    let map = std::env::current_dir()?.join("examples/versions.map");
+    gcc_link(&[
+        "-shared",
+        "-Wl,-soname,libv.so",
+        &format!("-Wl,--version-script={}", map.display()),
+        "-o",
+        "libv.so",
+        lib_o.to_str().unwrap(),
+    ])?;
+    gcc_link(&["-o", "main", main_o.to_str().unwrap(), "./libv.so"])?;
+
+    assert_eq!(
+        dump_stdout(&["-V"], &tmp_dir.path().join("libv.so"))?,
+        "Version definition section '.gnu.version_d' contains 3 entries:
+  0x0000: Rev: 1  Flags: BASE  Index: 1  Cnt: 1  Name: libv.so
+  0x001c: Rev: 1  Flags: none  Index: 2  Cnt: 1  Name: VERS_1
+  0x0038: Rev: 1  Flags: WEAK  Index: 3  Cnt: 2  Name: VERS_2
+  0x0054: Parent 1: VERS_1
+
+Dynamic symbol versions:
+   Num: Ver Name
+     0:   0  (*local*)
+     1:   2 VERS_1@VERS_1
+     2:   2 extern_call@@VERS_1
+     3:   3 VERS_2@VERS_2
+"
+    );
+    // The executable binds to the version of the library it was linked against.
+    assert_eq!(
+        dump_stdout(&["-V"], &tmp_dir.path().join("main"))?,
+        "Version needs section '.gnu.version_r' contains 1 entries:
+  0x0000: Version: 1  File: libv.so  Cnt: 1
+  0x0010:   Name: VERS_1  Flags: none  Version: 2
+
+Dynamic symbol versions:
+   Num: Ver Name
+     0:   0  (*local*)
+     1:   2 extern_call@VERS_1 (libv.so)
+"
+    );
+    let exe = link_main(tmp_dir.path(), &[])?;
+    assert_eq!(
+        dump_stdout(&["-V"], &exe)?,
+        "There is no .gnu.version section in this file.\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn hex_dump() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let stdout = dump_stdout(&["-x", ".rodata"], &exe)?;
+    assert!(stdout.contains(" Hello world..wuh\n"));
+    // Same layout as readelf, which surrounds it with empty lines.
+    let readelf = Command::new("readelf")
+        .args(["-x", ".rodata"])
+        .arg(&exe)
+        .output()?;
+    let readelf = String::from_utf8(readelf.stdout)?;
+    assert_eq!(stdout.trim(), readelf.trim());
+
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let idx = elf
+        .section_headers
+        .iter()
+        .position(|sec| &elf.shdr_strtab[sec.sh_name] == ".rodata")
+        .unwrap();
+    assert_eq!(dump_stdout(&["-x", &idx.to_string()], &exe)?, stdout);
+
+    let output = dump(&["-x", ".nope"], &exe)?;
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "dump: there is no section .nope\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn string_dump() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let stdout = dump_stdout(&["-p", ".rodata"], &exe)?;
+    // The strings of both objects are next to each other, control characters are escaped.
+    assert_eq!(
+        stdout,
+        "String dump of section '.rodata':\n  [     0]  Hello world\\n\n  [     d]  wuhu\\n\n"
+    );
+    // The merged .comment has a single copy of the compiler version, like readelf shows.
+    let stdout = dump_stdout(&["-p", ".comment"], &exe)?;
+    let rows = block(&stdout, "String dump of section '.comment':");
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].starts_with("  [     1]  GCC: "), "{}", rows[0]);
+    let readelf = Command::new("readelf")
+        .args(["-p", ".comment"])
+        .arg(&exe)
+        .output()?;
+    let readelf = String::from_utf8(readelf.stdout)?;
+    assert_eq!(block(&readelf, "String dump of section '.comment':"), rows);
+
+    let stdout = dump_stdout(&["-p", ".bss"], &exe)?;
+    assert_eq!(stdout, "Section '.bss' has no data to dump.\n");
+    let output = dump(&["-p", ".nope"], &exe)?;
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "dump: there is no section .nope\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn extract() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let contents = |name: &str| {
+        let sec = elf
+            .section_headers
+            .iter()
+            .find(|sec| &elf.shdr_strtab[sec.sh_name] == name)
+            .unwrap();
+        let start = usize::try_from(sec.sh_offset).unwrap();
+        &buf[start..start + usize::try_from(sec.sh_size).unwrap()]
+    };
+    let text = tmp_dir.path().join("text.bin");
+    let rodata = tmp_dir.path().join("rodata.bin");
+    let bss = tmp_dir.path().join("bss.bin");
+    // Nothing is printed, the sections end up in the files.
+    let stdout = dump_stdout(
+        &[
+            "--extract",
+            &format!(".text={}", text.display()),
+            "--extract",
+            &format!(".rodata={}", rodata.display()),
+            "--extract",
+            &format!(".bss={}", bss.display()),
+        ],
+        &exe,
+    )?;
+    assert_eq!(stdout, "");
+    assert_eq!(fs::read(&text)?, contents(".text"));
+    assert_eq!(fs::read(&rodata)?, b"Hello world\n\0wuhu\n\0");
+    assert_eq!(fs::read(&bss)?, b"");
+    // objcopy writes the same bytes.
+    let objcopy_text = tmp_dir.path().join("objcopy.bin");
+    let objcopy = Command::new("objcopy")
+        .args(["-O", "binary", "--only-section=.text"])
+        .arg(&exe)
+        .arg(&objcopy_text)
+        .output()?;
+    assert!(objcopy.status.success());
+    assert_eq!(fs::read(&objcopy_text)?, fs::read(&text)?);
+
+    let output = dump(&["--extract", ".text"], &exe)?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("invalid --extract, expected SECTION=PATH: .text"));
+    let output = dump(&["--extract", &format!(".nope={}", text.display())], &exe)?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "dump: there is no section .nope\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn disassemble() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let symbol = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .unwrap()
+    };
+
+    let stdout = dump_stdout(&["--disassemble=_start"], &exe)?;
+    let start = symbol("_start");
+    let code = block(&stdout, &format!("{:016x} <_start>:", start.st_value));
+    // The instructions cover the function, each with its bytes.
+    let mut address = start.st_value;
+    for line in &code {
+        let (at, rest) = line.trim_start().split_once(":\t").unwrap();
+        assert_eq!(u64::from_str_radix(at, 16)?, address, "{}", line);
+        let bytes = rest.split('\t').next().unwrap().split_whitespace().count();
+        address += u64::try_from(bytes)?;
+    }
+    assert_eq!(address, start.st_value + start.st_size);
+    // Calls name their target.
+    let main = symbol("main").st_value;
+    assert!(code
+        .iter()
+        .any(|line| line.ends_with(&format!("call {:#x} <main>", main))));
+    assert!(!stdout.contains("<extern_call>:"));
+
+    let stdout = dump_stdout(&["--disassemble"], &exe)?;
+    let functions: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.strip_suffix(">:")?.split_once(" <"))
+        .map(|(_, name)| name)
+        .collect();
+    assert_eq!(
+        functions,
+        ["print", "main", "_start", "print", "extern_call"]
+    );
+    let extern_call = symbol("extern_call").st_value;
+    assert!(stdout.contains(&format!("call {:#x} <extern_call>\n", extern_call)));
+    Ok(())
+}
+
+#[test]
+fn notes() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &["-fcf-protection"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-fcf-protection"])?;
+    let exe = tmp_dir.path().join("main");
+    link(&[&main_o, &lib_o], &exe, &["--build-id"])?;
+    let stdout = dump_stdout(&["-n"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let build_id = elf
+        .iter_note_sections(&buf, Some(".note.gnu.build-id"))
+        .unwrap()
+        .next()
+        .unwrap()?;
+    let hex: String = build_id.desc.iter().map(|b| format!("{:02x}", b)).collect();
+    let rows = |section: &str| {
+        block(&stdout, &format!("Displaying notes found in: {}", section))
+            .iter()
+            .map(|row| row.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        rows(".note.gnu.build-id"),
+        [
+            "Owner Data size Description".to_string(),
+            "GNU 0x00000014 NT_GNU_BUILD_ID (unique build ID bitstring)".to_string(),
+            format!("Build ID: {}", hex),
+        ]
+    );
+    assert_eq!(
+        rows(".note.gnu.property"),
+        [
+            "Owner Data size Description",
+            "GNU 0x00000010 NT_GNU_PROPERTY_TYPE_0",
+            "x86 feature: IBT, SHSTK",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn archive() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let tmp_dir = TempDir::new("test")?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    fs::set_permissions(&main_o, fs::Permissions::from_mode(0o640))?;
+    // 2021-03-04 12:34:56 UTC
+    let touch = Command::new("touch")
+        .args(["-d", "@1614861296"])
+        .arg(&main_o)
+        .output()?;
+    assert!(touch.status.success());
+    let archive = tmp_dir.path().join("lib.a");
+    // Without U, ar leaves out the owner, mode and date.
+    let ar = Command::new("ar")
+        .arg("rcU")
+        .arg(&archive)
+        .args([&main_o, &lib_o])
+        .output()?;
+    assert!(ar.status.success());
+
+    let stdout = dump_stdout(&[], &archive)?;
+    let metadata = fs::metadata(&main_o)?;
+    let rows = block(&stdout, "Archive contains 2 members:");
+    assert_eq!(
+        rows[1].split_whitespace().collect::<Vec<_>>(),
+        [
+            "rw-r-----".to_string(),
+            format!("{}/{}", metadata.uid(), metadata.gid()),
+            metadata.len().to_string(),
+            "2021-03-04".to_string(),
+            "12:34:56".to_string(),
+            "main.o".to_string(),
+        ]
+    );
+    let fields: Vec<&str> = rows[2].split_whitespace().collect();
+    assert_eq!(fields[2], fs::metadata(&lib_o)?.len().to_string());
+    assert_eq!(fields[5], "lib.o");
+    assert_eq!(
+        block(&stdout, "Archive index contains 2 symbols:"),
+        ["  main.o:", "    _start", "  lib.o:", "    extern_call"]
+    );
+
+    // A member is dumped like the object itself.
+    assert_eq!(
+        dump_stdout(&["--member", "lib.o", "-s", "-r"], &archive)?,
+        dump_stdout(&["-s", "-r"], &lib_o)?
+    );
+    for (args, file, message) in [
+        (
+            &["-s"][..],
+            &archive,
+            "the input is an archive, pick a member with --member",
+        ),
+        (
+            &["--member", "nope.o"],
+            &archive,
+            "the archive has no member nope.o",
+        ),
+        (&["--member", "lib.o"], &lib_o, "--member needs an archive"),
+    ] {
+        let output = dump(args, file)?;
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8(output.stderr)?,
+            format!("dump: {}\n", message)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn diff() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let plain = tmp_dir.path().join("plain");
+    fs::copy(link_main(tmp_dir.path(), &[])?, &plain)?;
+    let with_id = link_main(tmp_dir.path(), &["--build-id"])?;
+    let plain_buf = fs::read(&plain)?;
+    let plain_elf = goblin::elf::Elf::parse(&plain_buf)?;
+    let id_buf = fs::read(&with_id)?;
+    let id_elf = goblin::elf::Elf::parse(&id_buf)?;
+    let symbol = |elf: &goblin::elf::Elf, name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .unwrap()
+            .st_value
+    };
+
+    let output = dump(&["diff", plain.to_str().unwrap()], &with_id)?;
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with(&format!(
+        "--- {}\n+++ {}\n",
+        plain.display(),
+        with_id.display()
+    )));
+    for line in [
+        format!(
+            "  Entry point: {:#x} -> {:#x}",
+            plain_elf.entry, id_elf.entry
+        ),
+        format!(
+            "  Number of section headers: {} -> {}",
+            plain_elf.section_headers.len(),
+            id_elf.section_headers.len()
+        ),
+        "  + .note.gnu.build-id (0x24 bytes)".to_string(),
+        format!(
+            "  ~ main: value {:#x} -> {:#x}",
+            symbol(&plain_elf, "main"),
+            symbol(&id_elf, "main")
+        ),
+    ] {
+        assert!(
+            stdout.lines().any(|l| l == line),
+            "{} not in\n{}",
+            line,
+            stdout
+        );
+    }
+    // The code is the same, only moved.
+    assert!(!stdout.contains(".text"));
+
+    let output = dump(&["diff", with_id.to_str().unwrap()], &plain)?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("\n  - .note.gnu.build-id (0x24 bytes)\n"));
+
+    let output = dump(&["diff", plain.to_str().unwrap()], &plain)?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout)?, "");
+    Ok(())
+}
+
+#[test]
+fn graph() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    gcc(tmp_dir.path(), Path::new("main.c"), &[])?;
+    gcc(tmp_dir.path(), Path::new("lib.c"), &[])?;
+    let ar = Command::new("ar")
+        .args(["rc", "lib.a", "lib.o"])
+        .current_dir(tmp_dir.path())
+        .output()?;
+    assert!(ar.status.success());
+    // The nodes are named after the arguments.
+    let graph = |inputs: &[&str]| -> Result<String, Box<dyn Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_dump"))
+            .arg("graph")
+            .args(inputs)
+            .current_dir(tmp_dir.path())
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    assert_eq!(
+        graph(&["main.o", "lib.a"])?,
+        r#"digraph symbols {
+  rankdir=LR;
+  node [shape=box];
+  "main.o";
+  subgraph cluster_0 {
+    label="lib.a";
+    "lib.a(lib.o)";
+  }
+  "main.o" -> "lib.a(lib.o)" [label="extern_call"];
+}
+"#
+    );
+    // Symbols nobody defines point to a node of their own.
+    assert_eq!(
+        graph(&["main.o"])?,
+        r#"digraph symbols {
+  rankdir=LR;
+  node [shape=box];
+  "main.o";
+  "main.o" -> "[undefined]" [label="extern_call"];
+  "[undefined]" [style=dashed];
+}
+"#
+    );
+    Ok(())
+}
+
+// The file and VM sizes of each row of a --size table by name.
+fn size_rows<'a>(stdout: &'a str, table: &str) -> Vec<(&'a str, u64, u64)> {
+    block(stdout, table)
+        .iter()
+        .skip(1)
+        .map(|row| {
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            let name = row[row.rfind('%').unwrap() + 1..].trim();
+            (name, fields[0].parse().unwrap(), fields[2].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn size_report() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::section_header::{SHF_ALLOC, SHT_NOBITS};
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let stdout = dump_stdout(&["--size"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let file_size = u64::try_from(buf.len())?;
+    let vm_size: u64 = elf
+        .section_headers
+        .iter()
+        .filter(|sec| sec.sh_flags & u64::from(SHF_ALLOC) != 0)
+        .map(|sec| sec.sh_size)
+        .sum();
+
+    let sections = size_rows(&stdout, "Sections:");
+    assert_eq!(sections.last(), Some(&("TOTAL", file_size, vm_size)));
+    for sec in elf.section_headers.iter().skip(1) {
+        let name = &elf.shdr_strtab[sec.sh_name];
+        let file = if sec.sh_type == SHT_NOBITS {
+            0
+        } else {
+            sec.sh_size
+        };
+        let vm = if sec.sh_flags & u64::from(SHF_ALLOC) != 0 {
+            sec.sh_size
+        } else {
+            0
+        };
+        if file > 0 || vm > 0 {
+            assert!(sections.contains(&(name, file, vm)), "{}", name);
+        }
+    }
+    // The rows are sorted by their size in the file.
+    assert!(sections[..sections.len() - 1]
+        .windows(2)
+        .all(|pair| pair[0].1 >= pair[1].1));
+
+    let size = |name: &str| -> Vec<u64> {
+        elf.syms
+            .iter()
+            .filter(|sym| &elf.strtab[sym.st_name] == name)
+            .map(|sym| sym.st_size)
+            .collect()
+    };
+    let symbols = size_rows(&stdout, "Symbols:");
+    for name in ["main", "_start", "extern_call"] {
+        let row = symbols.iter().find(|row| row.0 == name).unwrap();
+        assert_eq!(vec![row.1], size(name));
+        assert_eq!(row.1, row.2);
+    }
+    assert_eq!(symbols.last(), Some(&("TOTAL", file_size, vm_size)));
+    // Local symbols belong to the source file before them.
+    let objects = size_rows(&stdout, "Source objects:");
+    let main_c = size("main")[0] + size("print")[0];
+    assert!(objects.contains(&("main.c", main_c, main_c)));
+
+    // The rest of the rows are summed up.
+    let stdout = dump_stdout(&["--size", "--top", "2"], &exe)?;
+    let top = size_rows(&stdout, "Sections:");
+    assert_eq!(top.len(), 4);
+    assert_eq!(top[..2], sections[..2]);
+    assert_eq!(top[2].0, format!("[{} others]", sections.len() - 3));
+    assert_eq!(top.iter().take(3).map(|row| row.1).sum::<u64>(), file_size);
+    Ok(())
+}
+
+// The rows of --checksec by name.
+fn checksec(file: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    Ok(dump_stdout(&["--checksec"], file)?
+        .lines()
+        .map(|line| {
+            let (name, value) = line.split_once(':').unwrap();
+            (name.to_string(), value.trim().to_string())
+        })
+        .collect())
+}
+
+#[test]
+fn checksec_report() -> Result<(), Box<dyn Error>> {
+    use goblin::elf::program_header::{PF_X, PT_GNU_STACK};
+    let tmp_dir = TempDir::new("test")?;
+    let row = |rows: &[(String, String)], name: &str| {
+        rows.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+            .unwrap()
+    };
+
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let rows = checksec(&exe)?;
+    let names: Vec<&str> = rows.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["PIE", "RELRO", "NX stack", "Canary", "BIND_NOW", "Fortify"]
+    );
+    assert_eq!(row(&rows, "RELRO"), "No");
+    assert_eq!(row(&rows, "NX stack"), "No (no PT_GNU_STACK)");
+
+    // .init_array is protected after relocation, with -z now all of the relocations are
+    // done before the program starts.
+    let object = gcc(tmp_dir.path(), Path::new("init_array.c"), &[])?;
+    let init_array = tmp_dir.path().join("init_array");
+    link(&[&object], &init_array, &[])?;
+    let rows = checksec(&init_array)?;
+    assert_eq!(row(&rows, "RELRO"), "Partial");
+    assert_eq!(row(&rows, "BIND_NOW"), "No");
+    link(&[&object], &init_array, &["-z", "now"])?;
+    let rows = checksec(&init_array)?;
+    assert_eq!(row(&rows, "RELRO"), "Full");
+    assert_eq!(row(&rows, "BIND_NOW"), "Yes");
+    assert_eq!(row(&rows, "PIE"), "No");
+
+    // The linker cannot produce PIEs or PT_GNU_STACK, the system's programs have them.
+    let true_path = Path::new("/bin/true");
+    let buf = fs::read(true_path)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    let rows = checksec(true_path)?;
+    let pie = elf.header.e_type == goblin::elf::header::ET_DYN;
+    assert_eq!(row(&rows, "PIE"), if pie { "Yes" } else { "No" });
+    let nx = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_GNU_STACK)
+        .map(|ph| ph.p_flags & PF_X == 0);
+    if nx == Some(true) {
+        assert_eq!(row(&rows, "NX stack"), "Yes");
+    }
+    Ok(())
+}
+
+#[test]
+fn eh_frame() -> Result<(), Box<dyn Error>> {
+    use scroll::Pread;
+    use std::io::{Seek, SeekFrom, Write};
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let stdout = dump_stdout(&["--eh-frame"], &exe)?;
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    // Both objects have the same CIE.
+    let cies = stdout.lines().filter(|line| line.contains(" CIE ")).count();
+    assert_eq!(cies, 1);
+    // Every function has an FDE for its code, which starts with the return address on
+    // the stack.
+    let mut functions: Vec<(u64, u64)> = elf
+        .syms
+        .iter()
+        .filter(|sym| sym.is_function())
+        .map(|sym| (sym.st_value, sym.st_value + sym.st_size))
+        .collect();
+    functions.sort_unstable();
+    let fdes: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.contains(" FDE "))
+        .collect();
+    assert_eq!(fdes.len(), functions.len());
+    for (fde, (start, end)) in fdes.iter().zip(&functions) {
+        assert!(
+            fde.ends_with(&format!(" pc={:016x}..{:016x}", start, end)),
+            "{}",
+            fde
+        );
+        let rules = block(&stdout, fde);
+        assert_eq!(rules[0], format!("  {:#x}: CFA=rsp+8 RA=[CFA-8]", start));
+    }
+
+    // A search table entry that points to the wrong FDE is a problem.
+    let hdr = elf
+        .section_headers
+        .iter()
+        .find(|sec| &elf.shdr_strtab[sec.sh_name] == ".eh_frame_hdr")
+        .unwrap();
+    let entry = usize::try_from(hdr.sh_offset)? + 12;
+    let location = buf.pread_with::<i32>(entry, scroll::LE)?;
+    let mut file = fs::OpenOptions::new().write(true).open(&exe)?;
+    file.seek(SeekFrom::Start(u64::try_from(entry)?))?;
+    file.write_all(&(location + 1).to_le_bytes())?;
+    drop(file);
+    let output = dump(&["--eh-frame"], &exe)?;
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout)?;
+    let problem = format!(
+        ".eh_frame_hdr entry for {:#x} points to the FDE for {:#x}",
+        functions[0].0 + 1,
+        functions[0].0
+    );
+    assert_eq!(stdout.lines().last(), Some(problem.as_str()));
+    Ok(())
+}
+
+#[test]
+fn dwarf() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let main_o = gcc(tmp_dir.path(), Path::new("main.c"), &["-gdwarf-5"])?;
+    let lib_o = gcc(tmp_dir.path(), Path::new("lib.c"), &["-gdwarf-5"])?;
+    let exe = tmp_dir.path().join("main");
+    link(&[&main_o, &lib_o], &exe, &[])?;
+    let stdout = dump_stdout(&["--dwarf"], &exe)?;
+    // One unit for each object at the offsets readelf finds them.
+    let readelf = Command::new("readelf")
+        .arg("--debug-dump=info")
+        .arg(&exe)
+        .output()?;
+    let offsets: Vec<String> = String::from_utf8(readelf.stdout)?
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Compilation Unit @ offset "))
+        .map(|offset| match offset.trim_end_matches(':') {
+            "0" => String::from("0x0"),
+            offset => offset.to_string(),
+        })
+        .collect();
+    assert_eq!(offsets.len(), 2);
+    let dir = std::env::current_dir()?;
+    for (offset, file) in offsets.iter().zip(["main.c", "lib.c"]) {
+        let rows = block(
+            &stdout,
+            &format!("Compilation unit at offset {} (DWARF 5):", offset),
+        );
+        assert_eq!(rows[0], format!("  Name:      examples/{}", file));
+        assert!(rows[1].starts_with("  Producer:  GNU C"), "{}", rows[1]);
+        assert_eq!(rows[2], format!("  Directory: {}", dir.display()));
+        let line_rows: u64 = rows[3].strip_prefix("  Line rows: ").unwrap().parse()?;
+        assert!(line_rows > 0);
+    }
+
+    // Addresses are looked up in the line programs like addr2line does, relative paths are
+    // resolved against the compilation directory.
+    let buf = fs::read(&exe)?;
+    let elf = goblin::elf::Elf::parse(&buf)?;
+    for name in ["_start", "extern_call"] {
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .unwrap();
+        let address = format!("{:#x}", sym.st_value);
+        let addr2line = Command::new("addr2line")
+            .arg("-e")
+            .arg(&exe)
+            .arg(&address)
+            .output()?;
+        let location = String::from_utf8(addr2line.stdout)?;
+        assert_eq!(
+            dump_stdout(&["--addr2line", &address], &exe)?,
+            format!("{}: {}", address, location)
+        );
+    }
+    assert_eq!(dump_stdout(&["--addr2line", "0x10"], &exe)?, "0x10: ??:0\n");
+
+    let plain = link_main(tmp_dir.path(), &[])?;
+    assert_eq!(
+        dump_stdout(&["--dwarf"], &plain)?,
+        "There is no DWARF debug info in this file.\n"
+    );
+    Ok(())
+}
+
+// The output of dump with stdout on a terminal, where it highlights by default.
+fn dump_on_terminal(
+    args: &[&str],
+    file: &Path,
+    envs: &[(&str, &str)],
+) -> Result<String, Box<dyn Error>> {
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+    let (mut master, mut slave) = (0, 0);
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0);
+    let mut master = unsafe { fs::File::from_raw_fd(master) };
+    let slave = unsafe { fs::File::from_raw_fd(slave) };
+    // The command and with it the slave side are gone once the dump is started, so that
+    // reading stops at the end of its output.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dump"))
+        .args(args)
+        .arg("--no-pager")
+        .arg(file)
+        .env_remove("NO_COLOR")
+        .envs(envs.iter().copied())
+        .stdout(slave)
+        .spawn()?;
+    assert!(child.wait()?.success());
+    let mut output = Vec::new();
+    // Linux reports EIO instead of the end of the file once the slave is closed.
+    let _ = master.read_to_end(&mut output);
+    Ok(String::from_utf8(output)?.replace("\r\n", "\n"))
+}
+
+#[test]
+fn colors() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = link_main(tmp_dir.path(), &[])?;
+    let plain = dump_stdout(&["-S"], &exe)?;
+    assert!(!plain.contains('\x1b'));
+
+    let colored = dump_on_terminal(&["-S"], &exe, &[])?;
+    assert!(colored.contains(&format!("\x1b[32m{:<14}\x1b[0m", "PROGBITS")));
+    assert!(colored.contains(&format!("\x1b[34m{:<14}\x1b[0m", "NOBITS")));
+    // Without the escape codes it is the same table.
+    let stripped = regex::Regex::new("\x1b\\[[0-9]+m")?.replace_all(&colored, "");
+    assert_eq!(stripped, plain);
+
+    assert_eq!(dump_on_terminal(&["-S", "--no-color"], &exe, &[])?, plain);
+    assert_eq!(
+        dump_on_terminal(&["-S"], &exe, &[("NO_COLOR", "1")])?,
+        plain
+    );
+    // An empty NO_COLOR does not count.
+    assert_eq!(
+        dump_on_terminal(&["-S"], &exe, &[("NO_COLOR", "")])?,
+        colored
+    );
+    Ok(())
+}
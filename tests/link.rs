@@ -0,0 +1,78 @@
+// Run the linker binary with options that only matter on the command line.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempdir::TempDir;
+
+fn gcc(out_dir: &Path, file: &Path, flags: &[&str]) -> Result<PathBuf, Box<dyn Error>> {
+    let out = out_dir.join(file.with_extension("o"));
+    let output = Command::new("gcc")
+        .args(["-nostdlib", "-Wno-main", "-Wall", "-Werror"])
+        .args(flags)
+        .args(["-o", out.to_str().unwrap(), "-c"])
+        .arg(Path::new("examples").join(file))
+        .output()?;
+    assert!(output.status.success());
+    Ok(out)
+}
+
+// Link main.c and lib.c in `dir`, which is also the working directory of the linker.
+fn link_main(dir: &Path, args: &[&str]) -> Result<Output, Box<dyn Error>> {
+    let main_o = gcc(dir, Path::new("main.c"), &[])?;
+    let lib_o = gcc(dir, Path::new("lib.c"), &[])?;
+    let output = Command::new(env!("CARGO_BIN_EXE_toy-linker"))
+        .current_dir(dir)
+        .arg("-i")
+        .arg(main_o)
+        .arg("-i")
+        .arg(lib_o)
+        .args(args)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(output)
+}
+
+fn dir_entries(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+#[test]
+fn output_to_stdout() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = tmp_dir.path().join("main");
+    link_main(tmp_dir.path(), &["-o", exe.to_str().unwrap()])?;
+    let before = dir_entries(tmp_dir.path())?;
+
+    let output = link_main(tmp_dir.path(), &["-o", "-"])?;
+    assert_eq!(output.stdout, fs::read(&exe)?);
+    assert!(output.stderr.is_empty());
+    assert!(!tmp_dir.path().join("-").exists());
+    assert_eq!(dir_entries(tmp_dir.path())?, before);
+    Ok(())
+}
+
+#[test]
+fn output_to_stdout_with_print_size() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new("test")?;
+    let exe = tmp_dir.path().join("main");
+    link_main(tmp_dir.path(), &["-o", exe.to_str().unwrap()])?;
+
+    // The report goes to stderr, so it does not end up in the output.
+    let output = link_main(tmp_dir.path(), &["-o", "-", "--print-size"])?;
+    let elf = fs::read(&exe)?;
+    assert_eq!(output.stdout, elf);
+    let report = String::from_utf8(output.stderr)?;
+    assert!(report.starts_with(&format!("File size: {} bytes\n", elf.len())));
+    assert!(report.contains("\nSegments:\n"));
+    Ok(())
+}